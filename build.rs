@@ -25,8 +25,11 @@ fn main() {
         .whitelist_function("cvShowImage")
         .whitelist_function("cvWaitKey")
         .whitelist_function("cvCreateCameraCapture")
+        .whitelist_function("cvCreateFileCapture")
         .whitelist_function("cvReleaseCapture")
         .whitelist_function("cvQueryFrame")
+        .whitelist_function("cvGetCaptureProperty")
+        .whitelist_function("cvSetCaptureProperty")
         .prepend_enum_name(false)
         .rustfmt_bindings(false)
         .generate()
@@ -0,0 +1,83 @@
+//! Pinning of the threads of detection to cores, and their priority, so that
+//! the other processes of a busy edge box do not delay the frames (see
+//! `yolo_v2::Config::threads`). Only Linux supports it.
+
+#[cfg(target_os = "linux")]
+use libc;
+use std::io;
+#[cfg(target_os = "linux")]
+use std::mem;
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct Placement {
+    /// Cores the thread runs on, any when empty.
+    #[serde(default)]
+    pub cpus: Vec<usize>,
+    /// Niceness of the thread, from -20, the highest priority, which needs
+    /// privileges, to 19.
+    #[serde(default)]
+    pub nice: Option<i32>,
+}
+
+/// Placements of the threads running a model.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct Threads {
+    /// Resizing the frames to the input, in overlapped detection (see
+    /// `overlap`).
+    #[serde(default)]
+    pub preprocess: Placement,
+    /// Running the network: the thread of `stream` and `serve`, of
+    /// overlapped detection, and the workers of `scheduler::Pool`.
+    #[serde(default)]
+    pub inference: Placement,
+    /// Decoding the outputs, in overlapped detection.
+    #[serde(default)]
+    pub decode: Placement,
+}
+
+impl Placement {
+    pub fn is_empty(&self) -> bool {
+        self.cpus.is_empty() && self.nice.is_none()
+    }
+
+    /// Pins the calling thread to `cpus` and sets its niceness.
+    #[cfg(target_os = "linux")]
+    pub fn apply(&self) -> io::Result<()> {
+        if !self.cpus.is_empty() {
+            let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+            let max = 8 * mem::size_of::<libc::cpu_set_t>();
+            for &cpu in self.cpus.iter() {
+                if cpu >= max {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("no CPU {}", cpu),
+                    ));
+                }
+                unsafe { libc::CPU_SET(cpu, &mut set) };
+            }
+            // 0 for the calling thread
+            if unsafe { libc::sched_setaffinity(0, mem::size_of_val(&set), &set) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        if let Some(nice) = self.nice {
+            // the niceness of a thread is set through its ID on Linux
+            let tid = unsafe { libc::syscall(libc::SYS_gettid) };
+            if unsafe { libc::setpriority(libc::PRIO_PROCESS as _, tid as _, nice) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn apply(&self) -> io::Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "the placement of threads is only supported on Linux",
+        ))
+    }
+}
@@ -0,0 +1,116 @@
+//! Sanity check of the configured anchors against the boxes decoded on sample
+//! images.
+//!
+//! The network predicts each box as its anchor scaled by `exp` of the raw size
+//! outputs, so anchors matching the training keep the scales near 1 and the
+//! boxes within the image. Anchors in pixels instead of grid cells, the most
+//! common mistake, give boxes many times larger than the image.
+
+use image;
+use std::cmp;
+
+use detector;
+use yolo_v2;
+
+/// Statistics of the boxes of an anchor. Sizes are in grid cells.
+#[derive(Clone, Debug)]
+pub struct Stats {
+    pub anchor: usize,
+    pub count: usize,
+    pub median_height: f32,
+    pub median_width: f32,
+    /// Median of the factors the network scales the anchor by.
+    pub median_scale: f32,
+}
+
+/// Decodes the samples and returns the statistics of the anchors with boxes,
+/// on the first output only.
+pub fn collect<'a, I>(
+    model: &mut yolo_v2::YOLOv2,
+    samples: I,
+) -> Result<Vec<Stats>, detector::Error>
+where
+    I: IntoIterator<Item = &'a image::DynamicImage>,
+{
+    let anchors = &model.config().anchors;
+    let mut params = model.params().clone();
+    let saved = params.clone();
+    params.provenance = true;
+    model.set_params(params);
+
+    let mut sizes = vec![Vec::new(); anchors.len()];
+    let mut result = Ok(());
+    for img in samples {
+        match model.predict(img) {
+            Ok(bbox) => {
+                for p in bbox.iter().filter_map(|bb| bb.provenance.as_ref()) {
+                    if p.scale != 0 {
+                        continue;
+                    }
+                    let (h, w) = (p.loc[2].exp(), p.loc[3].exp());
+                    sizes[p.anchor].push((h, w));
+                }
+            }
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    model.set_params(saved);
+    result?;
+
+    Ok(sizes
+        .into_iter()
+        .enumerate()
+        .filter(|(_, s)| !s.is_empty())
+        .map(|(a, s)| Stats {
+            anchor: a,
+            count: s.len(),
+            median_height: median(s.iter().map(|&(h, _)| h * anchors[a].0).collect()),
+            median_width: median(s.iter().map(|&(_, w)| w * anchors[a].1).collect()),
+            median_scale: median(s.iter().map(|&(h, w)| (h * w).sqrt()).collect()),
+        })
+        .collect())
+}
+
+/// Describes the inconsistencies between the anchors and `stats`, on an
+/// output grid of `grid_h` by `grid_w` cells.
+pub fn warnings(
+    config: &yolo_v2::Config,
+    stats: &[Stats],
+    grid_h: usize,
+    grid_w: usize,
+) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let stride = config.insize as f32 / grid_h as f32;
+    for s in stats.iter() {
+        let (h, w) = config.anchors[s.anchor];
+        if s.median_height > 1.5 * grid_h as f32 || s.median_width > 1.5 * grid_w as f32 {
+            warnings.push(format!(
+                "anchor {} ({}, {}): boxes are {:.1}x{:.1} cells on a {}x{} grid; \
+                 the anchors may be in pixels instead of grid cells (divide by {})",
+                s.anchor, h, w, s.median_height, s.median_width, grid_h, grid_w, stride
+            ));
+        } else if s.median_scale > 4. || s.median_scale < 0.25 {
+            warnings.push(format!(
+                "anchor {} ({}, {}): scaled by {:.2} on average; \
+                 the anchors may not match the training (order or units)",
+                s.anchor, h, w, s.median_scale
+            ));
+        }
+    }
+    if config.anchors.iter().all(|&(h, w)| h.max(w) <= 1.) {
+        warnings.push(format!(
+            "all anchors are within one grid cell; they may be normalized \
+             to the image instead of in grid cells (multiply by {})",
+            grid_h
+        ));
+    }
+    warnings
+}
+
+fn median(mut values: Vec<f32>) -> f32 {
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(cmp::Ordering::Equal));
+    values[values.len() / 2]
+}
@@ -0,0 +1,94 @@
+//! Small animated GIFs for sharing clips in notifications.
+//!
+//! Frames are quantized to a fixed 6x7x6 color cube, which is much faster than
+//! adaptive palettes and good enough for previews.
+
+use gif;
+use image;
+use std::io;
+use std::time;
+
+use image::GenericImage;
+
+pub struct Frame {
+    pub img: image::DynamicImage,
+    /// How long the frame is shown.
+    pub delay: time::Duration,
+}
+
+/// Scales `img` down to at most `max_width` pixels wide.
+pub fn shrink(img: &image::DynamicImage, max_width: u32) -> image::DynamicImage {
+    if img.width() <= max_width {
+        img.clone()
+    } else {
+        img.resize(max_width, img.height(), image::FilterType::Triangle)
+    }
+}
+
+/// Writes a looping GIF. All the frames must have the size of the first one.
+pub fn write_gif<W>(w: W, frames: &[Frame]) -> io::Result<()>
+where
+    W: io::Write,
+{
+    let (width, height) = match frames.first() {
+        Some(f) => f.img.dimensions(),
+        None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "no frames")),
+    };
+    if width > 0xffff || height > 0xffff {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "frames are too large",
+        ));
+    }
+
+    let mut encoder = gif::Encoder::new(w, width as _, height as _, &palette())?;
+    encoder.write_extension(gif::ExtensionData::Repetitions(gif::Repeat::Infinite))?;
+    for f in frames.iter() {
+        if f.img.dimensions() != (width, height) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "frames differ in size",
+            ));
+        }
+        let pixels: Vec<_> = f
+            .img
+            .to_rgb()
+            .pixels()
+            .map(|p| index(p.data[0], p.data[1], p.data[2]))
+            .collect();
+        let mut frame = gif::Frame::from_indexed_pixels(width as _, height as _, &pixels, None);
+        let centis = f.delay.as_secs() * 100 + f.delay.subsec_millis() as u64 / 10;
+        // Most viewers slow down delays shorter than 2/100 s.
+        frame.delay = match centis {
+            0..=1 => 2,
+            2..=0xffff => centis as u16,
+            _ => 0xffff,
+        };
+        encoder.write_frame(&frame)?;
+    }
+    Ok(())
+}
+
+const LEVELS: [u8; 3] = [6, 7, 6];
+
+fn palette() -> Vec<u8> {
+    let level = |i: u8, n: u8| (i as u32 * 255 / (n as u32 - 1)) as u8;
+    let mut palette = Vec::with_capacity(256 * 3);
+    for r in 0..LEVELS[0] {
+        for g in 0..LEVELS[1] {
+            for b in 0..LEVELS[2] {
+                palette.push(level(r, LEVELS[0]));
+                palette.push(level(g, LEVELS[1]));
+                palette.push(level(b, LEVELS[2]));
+            }
+        }
+    }
+    palette.resize(256 * 3, 0);
+    palette
+}
+
+fn index(r: u8, g: u8, b: u8) -> u8 {
+    let quantize = |v: u8, n: u8| ((v as u32 * (n as u32 - 1) + 127) / 255) as u8;
+    (quantize(r, LEVELS[0]) * LEVELS[1] + quantize(g, LEVELS[1])) * LEVELS[2]
+        + quantize(b, LEVELS[2])
+}
@@ -0,0 +1,235 @@
+//! Image transforms that map detections back through their inverse, shared by
+//! test-time augmentation, tiling and regions of interest.
+//!
+//! Coordinates are continuous: a pixel `(x, y)` covers `[x, x + 1)`, so that
+//! a box of the whole image is `(0, 0, height, width)` in every space.
+
+use image;
+
+use bb;
+
+use image::GenericImage;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Transform {
+    /// Quarter turns clockwise.
+    Rotate90 {
+        turns: u32,
+    },
+    FlipH,
+    FlipV,
+    Crop {
+        top: u32,
+        left: u32,
+        height: u32,
+        width: u32,
+    },
+    Scale {
+        factor: f32,
+    },
+}
+
+impl Transform {
+    pub fn apply(&self, img: &image::DynamicImage) -> image::DynamicImage {
+        match *self {
+            Transform::Rotate90 { turns } => match turns % 4 {
+                1 => img.rotate90(),
+                2 => img.rotate180(),
+                3 => img.rotate270(),
+                _ => img.clone(),
+            },
+            Transform::FlipH => img.fliph(),
+            Transform::FlipV => img.flipv(),
+            Transform::Crop {
+                top,
+                left,
+                height,
+                width,
+            } => img.clone().crop(left, top, width, height),
+            Transform::Scale { .. } => {
+                let (height, width) = self.dims(img.height(), img.width());
+                img.resize_exact(width, height, image::FilterType::Triangle)
+            }
+        }
+    }
+
+    /// Returns the `(height, width)` of the transformed image.
+    pub fn dims(&self, height: u32, width: u32) -> (u32, u32) {
+        match *self {
+            Transform::Rotate90 { turns } if turns % 2 == 1 => (width, height),
+            Transform::Rotate90 { .. } | Transform::FlipH | Transform::FlipV => (height, width),
+            Transform::Crop {
+                top,
+                left,
+                height: h,
+                width: w,
+            } => (
+                h.min(height.saturating_sub(top)),
+                w.min(width.saturating_sub(left)),
+            ),
+            Transform::Scale { factor } => (
+                ((height as f32 * factor).round() as u32).max(1),
+                ((width as f32 * factor).round() as u32).max(1),
+            ),
+        }
+    }
+
+    /// Maps a box of the transformed image back to the image of `height` and
+    /// `width` the transform was applied to.
+    pub fn unmap(&self, bb: &mut bb::Bb, height: u32, width: u32) {
+        let (h, w) = (height as f32, width as f32);
+        let (top, left, bottom, right) = (bb.top, bb.left, bb.bottom, bb.right);
+        let (top, left, bottom, right) = match *self {
+            Transform::Rotate90 { turns } => match turns % 4 {
+                1 => (h - right, top, h - left, bottom),
+                2 => (h - bottom, w - right, h - top, w - left),
+                3 => (left, w - bottom, right, w - top),
+                _ => (top, left, bottom, right),
+            },
+            Transform::FlipH => (top, w - right, bottom, w - left),
+            Transform::FlipV => (h - bottom, left, h - top, right),
+            Transform::Crop {
+                top: y, left: x, ..
+            } => {
+                let (y, x) = (y as f32, x as f32);
+                (top + y, left + x, bottom + y, right + x)
+            }
+            Transform::Scale { .. } => {
+                let (out_h, out_w) = self.dims(height, width);
+                let (sy, sx) = (h / out_h as f32, w / out_w as f32);
+                (top * sy, left * sx, bottom * sy, right * sx)
+            }
+        };
+        bb.top = top;
+        bb.left = left;
+        bb.bottom = bottom;
+        bb.right = right;
+    }
+}
+
+/// Transforms applied in order.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Chain {
+    pub transforms: Vec<Transform>,
+}
+
+impl Chain {
+    pub fn new(transforms: Vec<Transform>) -> Self {
+        Self { transforms }
+    }
+
+    pub fn apply(&self, img: &image::DynamicImage) -> image::DynamicImage {
+        let mut img = img.clone();
+        for t in self.transforms.iter() {
+            img = t.apply(&img);
+        }
+        img
+    }
+
+    pub fn dims(&self, height: u32, width: u32) -> (u32, u32) {
+        self.transforms
+            .iter()
+            .fold((height, width), |(h, w), t| t.dims(h, w))
+    }
+
+    /// Maps a box of the transformed image back to the original image of
+    /// `height` and `width`.
+    pub fn unmap(&self, bb: &mut bb::Bb, height: u32, width: u32) {
+        let mut dims = vec![(height, width)];
+        for t in self.transforms.iter() {
+            let (h, w) = *dims.last().unwrap();
+            dims.push(t.dims(h, w));
+        }
+        for (t, &(h, w)) in self.transforms.iter().zip(dims.iter()).rev() {
+            t.unmap(bb, h, w);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 7 by 5 image, black but for the white pixel at `(x, y)`.
+    fn dot(x: u32, y: u32) -> image::DynamicImage {
+        let img = image::ImageBuffer::from_fn(5, 7, |px, py| {
+            image::Luma([if (px, py) == (x, y) { 255 } else { 0 }])
+        });
+        image::DynamicImage::ImageLuma8(img)
+    }
+
+    /// The box of the white pixel of `img`.
+    fn find(img: &image::DynamicImage) -> bb::Bb {
+        let (x, y, _) = img
+            .to_luma()
+            .enumerate_pixels()
+            .find(|&(_, _, p)| p[0] == 255)
+            .unwrap();
+        let (x, y) = (x as f32, y as f32);
+        bb::Bb {
+            top: y,
+            left: x,
+            bottom: y + 1.,
+            right: x + 1.,
+            label: 0,
+            score: 1.,
+            truncated: false,
+            sharpness: 0.,
+            provenance: None,
+        }
+    }
+
+    /// `unmap` brings the box of the pixel in the transformed image back to
+    /// the pixel in the image, for pixels of the crops of the transforms.
+    fn check(chain: &Chain) {
+        for &(x, y) in [(1, 2), (3, 5), (2, 3)].iter() {
+            let transformed = chain.apply(&dot(x, y));
+            let (height, width) = chain.dims(7, 5);
+            assert_eq!((transformed.height(), transformed.width()), (height, width));
+            let mut bb = find(&transformed);
+            chain.unmap(&mut bb, 7, 5);
+            let (x, y) = (x as f32, y as f32);
+            assert_eq!(
+                (bb.top, bb.left, bb.bottom, bb.right),
+                (y, x, y + 1., x + 1.),
+                "{:?}",
+                chain.transforms
+            );
+        }
+    }
+
+    #[test]
+    fn unmaps_the_transforms() {
+        for turns in 0..4 {
+            check(&Chain::new(vec![Transform::Rotate90 { turns }]));
+        }
+        check(&Chain::new(vec![Transform::FlipH]));
+        check(&Chain::new(vec![Transform::FlipV]));
+        check(&Chain::new(vec![Transform::Crop {
+            top: 2,
+            left: 1,
+            height: 4,
+            width: 3,
+        }]));
+    }
+
+    #[test]
+    fn unmaps_chains() {
+        check(&Chain::new(vec![
+            Transform::FlipH,
+            Transform::Rotate90 { turns: 1 },
+            Transform::FlipV,
+        ]));
+        check(&Chain::new(vec![
+            Transform::Rotate90 { turns: 3 },
+            Transform::Crop {
+                top: 1,
+                left: 1,
+                height: 4,
+                width: 5,
+            },
+            Transform::FlipH,
+        ]));
+    }
+}
@@ -0,0 +1,76 @@
+//! Typed `backend_config` of the MKL-DNN backends of menoh.
+//!
+//! menoh parses the config itself and ignores the fields it does not know, so
+//! that a typo runs the model with the defaults. The config is checked here
+//! instead, before the model is built.
+
+use serde;
+use serde_json;
+
+use detector;
+
+use serde::Deserialize;
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MkldnnConfig {
+    /// The CPU engine to run on, the first by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_id: Option<u32>,
+    /// Where `mkldnn_with_generic_fallback` logs the backend of each
+    /// operator.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_output: Option<LogOutput>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogOutput {
+    Stdout,
+    File,
+}
+
+impl MkldnnConfig {
+    /// Parses the JSON given to menoh, the defaults when empty.
+    pub fn parse(config: &str) -> Result<Self, detector::Error> {
+        if config.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        serde_json::from_str(config).map_err(invalid)
+    }
+
+    /// The JSON given to menoh, empty for the defaults.
+    pub fn to_json(&self) -> String {
+        if *self == Self::default() {
+            return String::new();
+        }
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/// Checks `config` for `backend`, returning the JSON to build the model with.
+/// The configs of the other backends are passed as is.
+pub fn validate(backend: &str, config: &str) -> Result<String, detector::Error> {
+    if backend.starts_with("mkldnn") {
+        MkldnnConfig::parse(config).map(|c| c.to_json())
+    } else {
+        Ok(config.to_owned())
+    }
+}
+
+/// `deserialize_with` of `backend_config`, given as an object or as the JSON
+/// string of one.
+pub fn deserialize<'de, D>(deserializer: D) -> Result<MkldnnConfig, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let config = match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::String(json) => MkldnnConfig::parse(&json),
+        value => serde_json::from_value(value).map_err(invalid),
+    };
+    config.map_err(|e| serde::de::Error::custom(e.to_string()))
+}
+
+fn invalid(e: serde_json::Error) -> detector::Error {
+    detector::Error::Config(format!("backend_config: {}", e))
+}
@@ -0,0 +1,106 @@
+//! Learned background: objects detected at the same place for a long time,
+//! such as parked cars, are marked as stationary, so that they do not keep
+//! triggering alerts.
+
+use std::time;
+
+use bb;
+
+use rect::Rect;
+
+#[derive(Clone, Deserialize)]
+pub struct Config {
+    /// Seconds at the same place after which an object is stationary.
+    #[serde(default = "default_after")]
+    pub after: f32,
+    /// Seconds without detection after which a place is forgotten. Covers
+    /// missed detections and short occlusions.
+    #[serde(default = "default_forget")]
+    pub forget: f32,
+    /// Minimum IoU of a detection with the place of an object.
+    #[serde(default = "default_iou")]
+    pub iou: f32,
+    /// Drops the stationary detections from the hooks, the recording and the
+    /// sinks, instead of only marking them.
+    #[serde(default)]
+    pub suppress: bool,
+}
+
+fn default_after() -> f32 {
+    600.
+}
+
+fn default_forget() -> f32 {
+    60.
+}
+
+fn default_iou() -> f32 {
+    0.7
+}
+
+/// Where an object was first detected, and since when.
+struct Place {
+    bb: bb::Bb,
+    since: time::SystemTime,
+    last: time::SystemTime,
+}
+
+pub struct Background {
+    after: time::Duration,
+    forget: time::Duration,
+    iou: f32,
+    places: Vec<Place>,
+}
+
+impl Background {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            after: duration(config.after),
+            forget: duration(config.forget),
+            iou: config.iou,
+            places: Vec::new(),
+        }
+    }
+
+    /// Returns whether each of `bbox`, detected at `time`, is stationary.
+    pub fn update(&mut self, bbox: &[bb::Bb], time: time::SystemTime) -> Vec<bool> {
+        let forget = self.forget;
+        self.places
+            .retain(|p| time.duration_since(p.last).map_or(true, |d| d <= forget));
+
+        let mut stationary = Vec::with_capacity(bbox.len());
+        for bb in bbox.iter() {
+            let mut best: Option<(f32, usize)> = None;
+            for (i, place) in self.places.iter().enumerate() {
+                if place.bb.label != bb.label {
+                    continue;
+                }
+                let iou = place.bb.iou(bb);
+                if iou >= self.iou && best.is_none_or(|(best, _)| iou > best) {
+                    best = Some((iou, i));
+                }
+            }
+            match best {
+                Some((_, i)) => {
+                    let place = &mut self.places[i];
+                    place.last = time;
+                    let present = time.duration_since(place.since).unwrap_or_default();
+                    stationary.push(present >= self.after);
+                }
+                None => {
+                    self.places.push(Place {
+                        bb: bb.clone(),
+                        since: time,
+                        last: time,
+                    });
+                    stationary.push(false);
+                }
+            }
+        }
+        stationary
+    }
+}
+
+fn duration(secs: f32) -> time::Duration {
+    time::Duration::from_millis((secs.max(0.) * 1000.) as u64)
+}
@@ -1,5 +1,7 @@
+use nms;
 use rect;
 
+#[derive(Clone, Debug)]
 pub struct Bb {
     pub top: f32,
     pub left: f32,
@@ -7,6 +9,50 @@ pub struct Bb {
     pub right: f32,
     pub label: usize,
     pub score: f32,
+    /// Set when the box touches the image border (see `filter::Border`).
+    pub truncated: bool,
+    /// Variance of the Laplacian of the crop (see `quality::sharpness`).
+    pub sharpness: f32,
+    /// Set when enabled by `Params::provenance`.
+    pub provenance: Option<Provenance>,
+}
+
+/// The output element a box was decoded from, for diagnosing anchors that do
+/// not match the training.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Provenance {
+    /// Index of the output in `yolo_v2::Config::outputs`.
+    #[serde(default)]
+    pub scale: usize,
+    /// Row and column of the grid cell.
+    pub cell: (usize, usize),
+    pub anchor: usize,
+    /// Raw location outputs `(y, x, h, w)`, before activation, whatever the
+    /// `yolo_v2::Config::cell_order`.
+    pub loc: [f32; 4],
+    /// Raw objectness, before the sigmoid.
+    pub obj: f32,
+}
+
+impl nms::BoundingBox for Bb {
+    fn score(&self) -> f32 {
+        self.score
+    }
+
+    fn set_score(&mut self, score: f32) {
+        self.score = score;
+    }
+
+    fn class(&self) -> usize {
+        self.label
+    }
+
+    fn set_rect(&mut self, top: f32, left: f32, bottom: f32, right: f32) {
+        self.top = top;
+        self.left = left;
+        self.bottom = bottom;
+        self.right = right;
+    }
 }
 
 impl rect::Rect<f32> for Bb {
@@ -23,3 +69,54 @@ impl rect::Rect<f32> for Bb {
         self.right
     }
 }
+
+pub fn above<'a, I>(bbox: I, thresh: f32) -> impl Iterator<Item = &'a Bb>
+where
+    I: IntoIterator<Item = &'a Bb>,
+{
+    bbox.into_iter().filter(move |bb| bb.score >= thresh)
+}
+
+/// The boxes scoring at least the threshold of their label in `thresh`.
+pub fn above_each<'a, I>(bbox: I, thresh: &'a [f32]) -> impl Iterator<Item = &'a Bb>
+where
+    I: IntoIterator<Item = &'a Bb>,
+{
+    bbox.into_iter()
+        .filter(move |bb| bb.score >= thresh[bb.label])
+}
+
+/// Snapping of box coordinates to the pixel grid (see
+/// `yolo_v2::Params::rounding`).
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rounding {
+    #[default]
+    None,
+    /// To the nearest integer.
+    Integer,
+    /// To the nearest even integer, as the crops of 4:2:0 video need.
+    Even,
+}
+
+impl Rounding {
+    /// Rounds the coordinates of `bbox`, keeping the boxes at least one step
+    /// high and wide.
+    pub fn apply<'a, I>(self, bbox: I)
+    where
+        I: IntoIterator<Item = &'a mut Bb>,
+    {
+        let step = match self {
+            Rounding::None => return,
+            Rounding::Integer => 1.,
+            Rounding::Even => 2.,
+        };
+        let snap = |c: f32| (c / step).round() * step;
+        for bb in bbox {
+            bb.top = snap(bb.top);
+            bb.left = snap(bb.left);
+            bb.bottom = snap(bb.bottom).max(bb.top + step);
+            bb.right = snap(bb.right).max(bb.left + step);
+        }
+    }
+}
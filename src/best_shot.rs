@@ -0,0 +1,82 @@
+//! Selects the best crop of each tracked object over its lifetime.
+//!
+//! Crops are ranked by `score * sqrt(area) * ln(1 + sharpness)`, favoring
+//! confident, large and sharp views of the object.
+
+use image;
+
+use bb;
+use tracker;
+
+use image::GenericImage;
+use rect::Rect;
+
+pub struct Shot {
+    pub track: u64,
+    pub bb: bb::Bb,
+    pub quality: f32,
+    pub img: image::DynamicImage,
+}
+
+pub fn quality(bb: &bb::Bb) -> f32 {
+    bb.score * (bb.height().max(0.) * bb.width().max(0.)).sqrt() * bb.sharpness.max(0.).ln_1p()
+}
+
+pub struct BestShots {
+    tracker: tracker::Tracker,
+    shots: Vec<Shot>,
+}
+
+impl BestShots {
+    pub fn new(tracker: tracker::Tracker) -> Self {
+        Self {
+            tracker,
+            shots: Vec::new(),
+        }
+    }
+
+    /// Feeds the detections of a frame. Returns the best shots of the tracks
+    /// ended by this frame.
+    pub fn update(&mut self, img: &image::DynamicImage, bbox: &[bb::Bb]) -> Vec<Shot> {
+        let (ids, ended) = self.tracker.update(bbox);
+        for (id, bb) in ids.into_iter().zip(bbox.iter()) {
+            let quality = quality(bb);
+            match self.shots.iter().position(|s| s.track == id) {
+                Some(i) if self.shots[i].quality >= quality => (),
+                Some(i) => self.shots[i] = shot(id, bb, quality, img),
+                None => self.shots.push(shot(id, bb, quality, img)),
+            }
+        }
+        self.take(ended)
+    }
+
+    /// Ends all the tracks, returning their best shots.
+    pub fn finish(&mut self) -> Vec<Shot> {
+        let ended = self.tracker.finish();
+        self.take(ended)
+    }
+
+    fn take(&mut self, ended: Vec<tracker::Track>) -> Vec<Shot> {
+        let (done, shots) = self
+            .shots
+            .drain(..)
+            .partition(|s| ended.iter().any(|t| t.id == s.track));
+        self.shots = shots;
+        done
+    }
+}
+
+fn shot(track: u64, bb: &bb::Bb, quality: f32, img: &image::DynamicImage) -> Shot {
+    let clip = |v: f32, max: u32| v.max(0.).min(max as f32) as u32;
+    let (top, left) = (clip(bb.top, img.height()), clip(bb.left, img.width()));
+    let (bottom, right) = (clip(bb.bottom, img.height()), clip(bb.right, img.width()));
+    let crop = image::ImageBuffer::from_fn(right - left, bottom - top, |x, y| {
+        img.get_pixel(left + x, top + y)
+    });
+    Shot {
+        track,
+        bb: bb.clone(),
+        quality,
+        img: image::DynamicImage::ImageRgba8(crop),
+    }
+}
@@ -0,0 +1,251 @@
+//! Models that output decoded boxes, scores and classes, as the exports of
+//! SSD by the TensorFlow Object Detection API or of Faster R-CNN by
+//! torchvision, usually after their own non-maximum suppression.
+
+use image;
+use menoh;
+use ndarray;
+use std::fs;
+use std::path;
+
+use backend;
+use bb;
+use detector;
+use memory;
+use quality;
+use raw;
+use yolo_v2;
+
+use image::GenericImage;
+use model_ext::ModelExt;
+
+/// Names and layout of the outputs.
+#[derive(Clone, Deserialize)]
+pub struct Outputs {
+    /// `N` boxes, as `[1, N, 4]` or `[N, 4]`.
+    pub boxes: String,
+    /// `N` scores.
+    pub scores: String,
+    /// `N` class indices, as floats.
+    pub classes: String,
+    #[serde(default)]
+    pub order: Order,
+    /// The boxes are in pixels of the input, not in fractions of it.
+    #[serde(default)]
+    pub pixels: bool,
+    /// Subtracted from the classes to index `label_names`, e.g. 1 when the
+    /// class 0 is the background.
+    #[serde(default)]
+    pub class_offset: i32,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Order {
+    /// `(top, left, bottom, right)`, as TensorFlow.
+    #[default]
+    Yxyx,
+    /// `(left, top, right, bottom)`, as torchvision.
+    Xyxy,
+}
+
+pub struct Boxes<'a> {
+    model: menoh::Model,
+    config: &'a yolo_v2::Config,
+    outputs: &'a Outputs,
+    params: yolo_v2::Params,
+    weights_size: usize,
+    low_quality: Option<quality::Issue>,
+    warnings: Vec<detector::Warning>,
+}
+
+impl<'a> Boxes<'a> {
+    pub fn from_onnx<P>(
+        path: P,
+        config: &'a yolo_v2::Config,
+        outputs: &'a Outputs,
+        backend: &str,
+        backend_config: &str,
+    ) -> Result<Self, detector::Error>
+    where
+        P: AsRef<path::Path>,
+    {
+        if !config.input_sizes.is_empty() {
+            return Err(detector::Error::Config(
+                "input_sizes is only supported by YOLO".to_owned(),
+            ));
+        }
+        let weights_size = fs::metadata(path.as_ref())
+            .map(|m| m.len() as usize)
+            .unwrap_or(0);
+        let mut builder = menoh::Builder::from_onnx(path)?
+            .add_input::<f32>(&config.input, &config.input_dims(1))?;
+        for input in config.inputs.iter() {
+            builder = builder.add_input::<f32>(&input.name, &input.shape(1)?)?;
+        }
+        let backend_config = backend::validate(backend, backend_config)?;
+        let model = builder
+            .add_output(&outputs.boxes)?
+            .add_output(&outputs.scores)?
+            .add_output(&outputs.classes)?
+            .build(backend, &backend_config)?;
+
+        let len = |name| {
+            model
+                .get_variable_dims(name)
+                .map(|dims| dims.iter().product::<usize>())
+        };
+        let n = len(&outputs.scores)?;
+        if len(&outputs.boxes)? != n * 4 || len(&outputs.classes)? != n {
+            return Err(detector::Error::Shape(format!(
+                "outputs {:?}, {:?} and {:?} do not hold the same number of boxes",
+                model.get_variable_dims(&outputs.boxes)?,
+                model.get_variable_dims(&outputs.scores)?,
+                model.get_variable_dims(&outputs.classes)?
+            )));
+        }
+        Ok(Self {
+            model,
+            config,
+            outputs,
+            params: config.params.clone(),
+            weights_size,
+            low_quality: None,
+            warnings: Vec::new(),
+        })
+    }
+
+    /// Fills `Config::inputs` and runs the model.
+    fn run(
+        &mut self,
+        height: u32,
+        width: u32,
+        letterbox: yolo_v2::Letterbox,
+    ) -> Result<(), detector::Error> {
+        let image = self.config.input_image(height, width, letterbox);
+        for input in self.config.inputs.iter() {
+            input.fill(self.model.get_view_mut(&input.name)?, 0, &image);
+        }
+        Ok(self.model.run()?)
+    }
+
+    fn decode(&self) -> Result<Vec<bb::Bb>, detector::Error> {
+        let boxes = self.model.get_view(&self.outputs.boxes)?;
+        let scores = self.model.get_view(&self.outputs.scores)?;
+        let classes = self.model.get_view(&self.outputs.classes)?;
+        let boxes: Vec<_> = boxes.iter().cloned().collect();
+        let scale = if self.outputs.pixels {
+            self.config.insize as f32
+        } else {
+            1.
+        };
+        let thresh = self.params.min_score();
+        let corrections = self.params.corrections(&self.config.label_names);
+
+        let mut bbox = Vec::new();
+        for ((b, &score), &class) in boxes.chunks(4).zip(scores.iter()).zip(classes.iter()) {
+            let label = class as i32 - self.outputs.class_offset;
+            if label < 0 || label as usize >= self.config.label_names.len() {
+                continue;
+            }
+            let score = corrections[label as usize].apply(score);
+            if score < thresh {
+                continue;
+            }
+            let (top, left, bottom, right) = match self.outputs.order {
+                Order::Yxyx => (b[0], b[1], b[2], b[3]),
+                Order::Xyxy => (b[1], b[0], b[3], b[2]),
+            };
+            bbox.push(bb::Bb {
+                top: top / scale,
+                left: left / scale,
+                bottom: bottom / scale,
+                right: right / scale,
+                label: label as usize,
+                score,
+                truncated: false,
+                sharpness: 0.,
+                provenance: None,
+            });
+        }
+        Ok(bbox)
+    }
+}
+
+impl<'a> detector::Detector for Boxes<'a> {
+    fn predict(&mut self, img: &image::DynamicImage) -> Result<Vec<bb::Bb>, detector::Error> {
+        self.low_quality = self.params.quality.check(img);
+        self.warnings.clear();
+        if self.low_quality.is_some() {
+            return Ok(Vec::new());
+        }
+        let letterbox = yolo_v2::set_image(
+            self.model
+                .get_view_mut(&self.config.input)?
+                .subview_mut(ndarray::Axis(0), 0),
+            img,
+            self.config,
+        );
+        self.run(img.height(), img.width(), letterbox)?;
+        let mut bbox = self.decode()?;
+        self.warnings = detector::finish(&mut bbox, self.config, &self.params, img, letterbox);
+        Ok(bbox)
+    }
+
+    fn predict_raw(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        format: raw::PixelFormat,
+    ) -> Result<Vec<bb::Bb>, detector::Error> {
+        let frame = raw::Frame::new(data, width, height, format)?;
+        self.low_quality = self.params.quality.check(&frame);
+        self.warnings.clear();
+        if self.low_quality.is_some() {
+            return Ok(Vec::new());
+        }
+        let letterbox = yolo_v2::set_raw(
+            self.model
+                .get_view_mut(&self.config.input)?
+                .subview_mut(ndarray::Axis(0), 0),
+            &frame,
+            self.config,
+        );
+        self.run(frame.height(), frame.width(), letterbox)?;
+        let mut bbox = self.decode()?;
+        self.warnings = detector::finish(&mut bbox, self.config, &self.params, &frame, letterbox);
+        Ok(bbox)
+    }
+
+    fn config(&self) -> &yolo_v2::Config {
+        self.config
+    }
+
+    fn params(&self) -> &yolo_v2::Params {
+        &self.params
+    }
+
+    fn set_params(&mut self, params: yolo_v2::Params) {
+        self.params = params;
+    }
+
+    fn low_quality(&self) -> Option<quality::Issue> {
+        self.low_quality
+    }
+
+    fn memory_usage(&self) -> Result<memory::Usage, detector::Error> {
+        let outputs = &self.outputs;
+        let mut buffers = 0;
+        for name in [&outputs.boxes, &outputs.scores, &outputs.classes] {
+            let dims = self.model.get_variable_dims(name)?;
+            buffers += dims.iter().product::<usize>() * 4;
+        }
+        buffers += 3 * self.config.insize * self.config.insize * 4;
+        Ok(memory::Usage {
+            weights: self.weights_size,
+            buffers,
+            queues: 0,
+        })
+    }
+}
@@ -0,0 +1,69 @@
+//! Detections in the results format of the COCO evaluation, to score a model
+//! with pycocotools or to import predictions into labeling tools.
+
+use std::collections;
+use std::path;
+
+use schema;
+
+/// A detection, with its box as `[x, y, width, height]` in pixels.
+#[derive(Clone, Debug, Serialize)]
+pub struct Detection {
+    pub image_id: u64,
+    pub category_id: u32,
+    pub bbox: [f32; 4],
+    pub score: f32,
+}
+
+/// Maps labels to the IDs of the categories of the annotations.
+#[derive(Clone, Debug)]
+pub struct Categories {
+    ids: collections::HashMap<String, u32>,
+}
+
+impl Categories {
+    /// Numbers `label_names` from 1, as in most datasets converted to COCO,
+    /// except for the labels of `ids`.
+    pub fn new(label_names: &[String], ids: &collections::BTreeMap<String, u32>) -> Self {
+        let mut map: collections::HashMap<_, _> = label_names
+            .iter()
+            .enumerate()
+            .map(|(i, label)| (label.clone(), i as u32 + 1))
+            .collect();
+        map.extend(ids.iter().map(|(label, &id)| (label.clone(), id)));
+        Self { ids: map }
+    }
+
+    pub fn get(&self, label: &str) -> Option<u32> {
+        self.ids.get(label).cloned()
+    }
+}
+
+/// Returns the ID of the image of `source`: the number in its file name, as
+/// in `000000397133.jpg`, or `index` otherwise.
+pub fn image_id(source: Option<&str>, index: u64) -> u64 {
+    source
+        .and_then(|s| path::Path::new(s).file_stem())
+        .and_then(|stem| stem.to_str())
+        .and_then(|stem| {
+            let digits: String = stem.chars().filter(|c| c.is_ascii_digit()).collect();
+            digits.parse().ok()
+        })
+        .unwrap_or(index)
+}
+
+/// Converts the detections of `frame`, skipping the labels without category.
+pub fn results(frame: &schema::Frame, image_id: u64, categories: &Categories) -> Vec<Detection> {
+    frame
+        .detections
+        .iter()
+        .filter_map(|d| {
+            Some(Detection {
+                image_id,
+                category_id: categories.get(&d.label)?,
+                bbox: [d.left, d.top, d.right - d.left, d.bottom - d.top],
+                score: d.score,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,309 @@
+//! Color spaces of the images and of the network input.
+//!
+//! Images tagged with an ICC profile are converted to sRGB when decoded, so
+//! that e.g. Display P3 photos of phones look to the network as they look on
+//! screen. Only the matrix/TRC profiles of RGB images embedded in JPEG files
+//! are supported, which covers the profiles of cameras and phones; images
+//! with other profiles are read as sRGB.
+//!
+//! Networks trained on linear RGB get their input linearized with
+//! `ColorSpace::Linear`.
+
+use image;
+use std::fs;
+use std::path;
+
+/// Encoding of the values of the network input.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorSpace {
+    /// The sRGB values of the pixels.
+    #[default]
+    Srgb,
+    /// The sRGB values converted to linear light.
+    Linear,
+}
+
+impl ColorSpace {
+    /// Returns the input value of every 8-bit sample value.
+    pub fn table(self) -> [f32; 256] {
+        let mut table = [0.; 256];
+        for (v, t) in table.iter_mut().enumerate() {
+            let v = v as f32 / 255.;
+            *t = match self {
+                ColorSpace::Srgb => v,
+                ColorSpace::Linear => srgb_to_linear(v),
+            };
+        }
+        table
+    }
+}
+
+pub fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.003_130_8 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1. / 2.4) - 0.055
+    }
+}
+
+/// `image::open`, converting the image to sRGB if it has a profile.
+pub fn open<P>(path: P) -> image::ImageResult<image::DynamicImage>
+where
+    P: AsRef<path::Path>,
+{
+    load_from_memory(&fs::read(path)?)
+}
+
+/// `image::load_from_memory`, converting the image to sRGB if it has a
+/// profile.
+pub fn load_from_memory(data: &[u8]) -> image::ImageResult<image::DynamicImage> {
+    let mut img = image::load_from_memory(data)?;
+    if let Some(profile) = jpeg_profile(data).and_then(|icc| Profile::parse(&icc)) {
+        profile.to_srgb_mut(&mut img);
+    }
+    Ok(img)
+}
+
+/// Returns the ICC profile of a JPEG file, which is split across `APP2`
+/// segments.
+pub fn jpeg_profile(data: &[u8]) -> Option<Vec<u8>> {
+    const MARKER: &[u8] = b"ICC_PROFILE\0";
+    if !data.starts_with(&[0xff, 0xd8]) {
+        return None;
+    }
+    let mut chunks = Vec::new();
+    let mut pos = 2;
+    while pos + 4 <= data.len() && data[pos] == 0xff {
+        let marker = data[pos + 1];
+        // start of scan: the image data follows
+        if marker == 0xda {
+            break;
+        }
+        let len = (data[pos + 2] as usize) << 8 | data[pos + 3] as usize;
+        let segment = data.get(pos + 4..pos + 2 + len)?;
+        if marker == 0xe2 && segment.len() > MARKER.len() + 2 && segment.starts_with(MARKER) {
+            let seq = segment[MARKER.len()];
+            chunks.push((seq, &segment[MARKER.len() + 2..]));
+        }
+        pos += 2 + len;
+    }
+    if chunks.is_empty() {
+        return None;
+    }
+    chunks.sort_by_key(|&(seq, _)| seq);
+    Some(
+        chunks
+            .iter()
+            .flat_map(|&(_, c)| c.iter().cloned())
+            .collect(),
+    )
+}
+
+/// From D50 XYZ, the connection space of ICC profiles, to linear sRGB, with
+/// the Bradford chromatic adaptation.
+const XYZ_D50_TO_SRGB: [[f32; 3]; 3] = [
+    [3.133_856, -1.616_867, -0.490_615],
+    [-0.978_768, 1.916_142, 0.033_454],
+    [0.071_945, -0.228_991, 1.405_243],
+];
+
+/// Size of the table of `linear_to_srgb`.
+const ENCODE_STEPS: usize = 4096;
+
+/// A matrix/TRC profile, as the conversion of its 8-bit values to sRGB.
+pub struct Profile {
+    /// Linear value of every sample value, per channel.
+    curves: [[f32; 256]; 3],
+    /// From the linear values to linear sRGB.
+    matrix: [[f32; 3]; 3],
+}
+
+impl Profile {
+    /// Returns `None` for the profiles that are not RGB matrix/TRC ones.
+    pub fn parse(icc: &[u8]) -> Option<Self> {
+        if icc.len() < 132 || &icc[16..20] != b"RGB " || &icc[36..40] != b"acsp" {
+            return None;
+        }
+        let count = be_u32(icc, 128)? as usize;
+        let tag = |sig: &[u8]| {
+            (0..count).find_map(|i| {
+                let entry = 132 + 12 * i;
+                if icc.get(entry..entry + 4)? != sig {
+                    return None;
+                }
+                let offset = be_u32(icc, entry + 4)? as usize;
+                let size = be_u32(icc, entry + 8)? as usize;
+                icc.get(offset..offset.checked_add(size)?)
+            })
+        };
+
+        let mut matrix = [[0.; 3]; 3];
+        let mut curves = [[0.; 256]; 3];
+        let channels = [(b"rXYZ", b"rTRC"), (b"gXYZ", b"gTRC"), (b"bXYZ", b"bTRC")];
+        for (c, &(xyz, trc)) in channels.iter().enumerate() {
+            let xyz = tag(xyz)?;
+            if xyz.get(..4)? != b"XYZ " {
+                return None;
+            }
+            for (row, m) in matrix.iter_mut().enumerate() {
+                m[c] = s15_fixed16(xyz, 8 + 4 * row)?;
+            }
+            let trc = Curve::parse(tag(trc)?)?;
+            for (v, linear) in curves[c].iter_mut().enumerate() {
+                *linear = trc.eval(v as f32 / 255.);
+            }
+        }
+        let mut to_srgb = [[0.; 3]; 3];
+        for (i, row) in to_srgb.iter_mut().enumerate() {
+            for (j, t) in row.iter_mut().enumerate() {
+                *t = (0..3).map(|k| XYZ_D50_TO_SRGB[i][k] * matrix[k][j]).sum();
+            }
+        }
+        Some(Self {
+            curves,
+            matrix: to_srgb,
+        })
+    }
+
+    /// Whether the profile is sRGB, up to rounding, so that converting is
+    /// not worth it.
+    pub fn is_srgb(&self) -> bool {
+        let matrix = self.matrix.iter().enumerate().all(|(i, row)| {
+            row.iter()
+                .enumerate()
+                .all(|(j, &t)| (t - if i == j { 1. } else { 0. }).abs() < 0.01)
+        });
+        let curves = self.curves.iter().all(|curve| {
+            curve
+                .iter()
+                .enumerate()
+                .all(|(v, &linear)| (linear_to_srgb(linear) - v as f32 / 255.).abs() < 0.5 / 255.)
+        });
+        matrix && curves
+    }
+
+    /// Converts `img` to sRGB. Only RGB images are converted.
+    pub fn to_srgb_mut(&self, img: &mut image::DynamicImage) {
+        if self.is_srgb() {
+            return;
+        }
+        let mut encode = vec![0; ENCODE_STEPS];
+        for (i, e) in encode.iter_mut().enumerate() {
+            let v = linear_to_srgb(i as f32 / (ENCODE_STEPS - 1) as f32);
+            *e = (v * 255.).round() as u8;
+        }
+        let convert = |p: &mut [u8]| {
+            let linear = [
+                self.curves[0][p[0] as usize],
+                self.curves[1][p[1] as usize],
+                self.curves[2][p[2] as usize],
+            ];
+            for (c, row) in self.matrix.iter().enumerate() {
+                let v = row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2];
+                let i = (v.clamp(0., 1.) * (ENCODE_STEPS - 1) as f32).round() as usize;
+                p[c] = encode[i];
+            }
+        };
+        match *img {
+            image::ImageRgb8(ref mut buf) => buf.chunks_mut(3).for_each(convert),
+            image::ImageRgba8(ref mut buf) => buf.chunks_mut(4).for_each(convert),
+            _ => {}
+        }
+    }
+}
+
+/// A tone reproduction curve, from encoded to linear values.
+enum Curve {
+    Gamma(f32),
+    Table(Vec<f32>),
+    /// The parameters `g, a, b, c, d, e, f` of the parametric curves.
+    Parametric([f32; 7]),
+}
+
+impl Curve {
+    fn parse(tag: &[u8]) -> Option<Self> {
+        match tag.get(..4)? {
+            b"curv" => {
+                let n = be_u32(tag, 8)? as usize;
+                match n {
+                    0 => Some(Curve::Gamma(1.)),
+                    1 => Some(Curve::Gamma(f32::from(be_u16(tag, 12)?) / 256.)),
+                    _ => (0..n)
+                        .map(|i| be_u16(tag, 12 + 2 * i).map(|v| f32::from(v) / 65535.))
+                        .collect::<Option<_>>()
+                        .map(Curve::Table),
+                }
+            }
+            b"para" => {
+                let n = match be_u16(tag, 8)? {
+                    0 => 1,
+                    1 => 3,
+                    2 => 4,
+                    3 => 5,
+                    4 => 7,
+                    _ => return None,
+                };
+                // defaults making the missing segments vanish
+                let mut p = [1., 1., 0., 0., f32::MIN, 0., 0.];
+                for (i, p) in p.iter_mut().enumerate().take(n) {
+                    *p = s15_fixed16(tag, 12 + 4 * i)?;
+                }
+                match n {
+                    // `Y = (aX + b)^g` for `X >= -b / a`, 0 or `c` below
+                    3 | 4 => {
+                        p[4] = -p[2] / p[1];
+                        p[5] = if n == 4 { p[3] } else { 0. };
+                        p[6] = p[5];
+                        p[3] = 0.;
+                    }
+                    // `Y = (aX + b)^g + e` for `X >= d`, `cX + f` below
+                    _ => {}
+                }
+                Some(Curve::Parametric(p))
+            }
+            _ => None,
+        }
+    }
+
+    fn eval(&self, x: f32) -> f32 {
+        match *self {
+            Curve::Gamma(g) => x.powf(g),
+            Curve::Table(ref table) => {
+                let pos = x * (table.len() - 1) as f32;
+                let i = (pos as usize).min(table.len() - 2);
+                let t = pos - i as f32;
+                table[i] * (1. - t) + table[i + 1] * t
+            }
+            Curve::Parametric([g, a, b, c, d, e, f]) => {
+                if x >= d {
+                    (a * x + b).max(0.).powf(g) + e
+                } else {
+                    c * x + f
+                }
+            }
+        }
+    }
+}
+
+fn be_u16(data: &[u8], pos: usize) -> Option<u16> {
+    let b = data.get(pos..pos + 2)?;
+    Some(u16::from(b[0]) << 8 | u16::from(b[1]))
+}
+
+fn be_u32(data: &[u8], pos: usize) -> Option<u32> {
+    let b = data.get(pos..pos + 4)?;
+    Some(u32::from(b[0]) << 24 | u32::from(b[1]) << 16 | u32::from(b[2]) << 8 | u32::from(b[3]))
+}
+
+fn s15_fixed16(data: &[u8], pos: usize) -> Option<f32> {
+    be_u32(data, pos).map(|v| v as i32 as f32 / 65536.)
+}
@@ -0,0 +1,63 @@
+//! Conversions between the coordinate spaces of a prediction.
+//!
+//! - image: pixels of the image passed to `predict`
+//! - network: pixels of the input tensor, with the letterbox padding
+//! - normalized: network coordinates divided by the input size, as decoded
+//!   from the output grid
+//!
+//! Points are `(y, x)`, in the order of `Bb`.
+
+use bb;
+use yolo_v2;
+
+#[derive(Clone, Copy, Debug)]
+pub struct CoordTransform {
+    letterbox: yolo_v2::Letterbox,
+    in_h: f32,
+    in_w: f32,
+}
+
+impl CoordTransform {
+    pub fn new(letterbox: yolo_v2::Letterbox, in_h: usize, in_w: usize) -> Self {
+        Self {
+            letterbox,
+            in_h: in_h as _,
+            in_w: in_w as _,
+        }
+    }
+
+    /// Image to network.
+    pub fn to_network(&self, (y, x): (f32, f32)) -> (f32, f32) {
+        let l = &self.letterbox;
+        (y * l.scale + l.pad_y as f32, x * l.scale + l.pad_x as f32)
+    }
+
+    /// Network to image.
+    pub fn to_image(&self, (y, x): (f32, f32)) -> (f32, f32) {
+        let l = &self.letterbox;
+        (
+            (y - l.pad_y as f32) / l.scale,
+            (x - l.pad_x as f32) / l.scale,
+        )
+    }
+
+    /// Network to normalized.
+    pub fn to_normalized(&self, (y, x): (f32, f32)) -> (f32, f32) {
+        (y / self.in_h, x / self.in_w)
+    }
+
+    /// Normalized to network.
+    pub fn from_normalized(&self, (y, x): (f32, f32)) -> (f32, f32) {
+        (y * self.in_h, x * self.in_w)
+    }
+
+    /// Maps a box from normalized to image coordinates.
+    pub fn bb_to_image(&self, bb: &mut bb::Bb) {
+        let (top, left) = self.to_image(self.from_normalized((bb.top, bb.left)));
+        let (bottom, right) = self.to_image(self.from_normalized((bb.bottom, bb.right)));
+        bb.top = top;
+        bb.left = left;
+        bb.bottom = bottom;
+        bb.right = right;
+    }
+}
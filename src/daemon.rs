@@ -0,0 +1,159 @@
+//! Running as a system service: daemonization, PID files, and the systemd
+//! notification protocol (`sd_notify`).
+
+use libc;
+use std::env;
+use std::ffi;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path;
+use std::process;
+use std::time;
+
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+
+/// Detaches from the terminal. The parent process exits, and the child
+/// continues in a new session with the standard streams on `/dev/null`.
+pub fn daemonize() -> io::Result<()> {
+    match unsafe { libc::fork() } {
+        -1 => return Err(io::Error::last_os_error()),
+        0 => (),
+        _ => process::exit(0),
+    }
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let null = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")?;
+    for fd in 0..3 {
+        if unsafe { libc::dup2(null.as_raw_fd(), fd) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// A file holding the PID of the process, removed on drop.
+pub struct PidFile {
+    path: path::PathBuf,
+}
+
+impl PidFile {
+    pub fn create<P>(path: P) -> io::Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        let mut f = fs::File::create(path.as_ref())?;
+        writeln!(f, "{}", process::id())?;
+        Ok(Self {
+            path: path.as_ref().to_owned(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Sends `state` (e.g. `READY=1`) to the service manager. Returns `false`
+/// when not running under a manager supporting notifications.
+pub fn notify(state: &str) -> io::Result<bool> {
+    let socket = match env::var_os("NOTIFY_SOCKET") {
+        Some(socket) => socket,
+        None => return Ok(false),
+    };
+    let path = socket.as_bytes();
+
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    addr.sun_family = libc::AF_UNIX as _;
+    if path.is_empty() || path.len() >= addr.sun_path.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid NOTIFY_SOCKET",
+        ));
+    }
+    for (d, s) in addr.sun_path.iter_mut().zip(path.iter()) {
+        *d = *s as _;
+    }
+    // A leading '@' denotes an abstract socket.
+    if path[0] == b'@' {
+        addr.sun_path[0] = 0;
+    }
+    let len = mem::size_of::<libc::sa_family_t>() + path.len();
+
+    unsafe {
+        let fd = libc::socket(libc::AF_UNIX, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let sent = libc::sendto(
+            fd,
+            state.as_ptr() as _,
+            state.len(),
+            0,
+            &addr as *const _ as _,
+            len as _,
+        );
+        let err = io::Error::last_os_error();
+        libc::close(fd);
+        if sent == -1 {
+            return Err(err);
+        }
+    }
+    Ok(true)
+}
+
+/// The interval the service manager expects `WATCHDOG=1` within, if enabled
+/// for this process.
+pub fn watchdog_interval() -> Option<time::Duration> {
+    if let Some(pid) = env::var_os("WATCHDOG_PID") {
+        if pid != ffi::OsString::from(process::id().to_string()) {
+            return None;
+        }
+    }
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(time::Duration::from_millis(usec / 1000))
+}
+
+/// Sends `WATCHDOG=1` at most every half of the watchdog interval.
+pub struct Watchdog {
+    interval: Option<time::Duration>,
+    last: Option<time::Instant>,
+}
+
+impl Watchdog {
+    pub fn new() -> Self {
+        Self {
+            interval: watchdog_interval().map(|i| i / 2),
+            last: None,
+        }
+    }
+
+    pub fn ping(&mut self) -> io::Result<()> {
+        let interval = match self.interval {
+            Some(interval) => interval,
+            None => return Ok(()),
+        };
+        let now = time::Instant::now();
+        match self.last {
+            Some(last) if now.duration_since(last) < interval => Ok(()),
+            _ => {
+                self.last = Some(now);
+                notify("WATCHDOG=1").map(|_| ())
+            }
+        }
+    }
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
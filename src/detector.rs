@@ -0,0 +1,310 @@
+//! Detection architectures behind a common interface. The `architecture` of
+//! the config selects one, so that the CLI, the server and the pipelines run
+//! any of them unchanged.
+//!
+//! Every architecture shares the preprocessing of `yolo_v2::set_image` and
+//! the filters of `finish`, and only decodes its own outputs.
+
+use image;
+use menoh;
+use std::error;
+use std::fmt;
+use std::path;
+
+use bb;
+use boxes;
+use coords;
+use filter;
+use memory;
+use quality;
+use raw;
+use yolo_v2;
+
+/// Errors of building and running the detectors.
+#[derive(Debug)]
+pub enum Error {
+    /// Failures of menoh, or of the inference server, to build or run the
+    /// model.
+    Backend(menoh::Error),
+    /// Images that cannot be decoded or converted to the input of the network.
+    Preprocessing(String),
+    /// Configs rejected before the model is built.
+    Config(String),
+    /// Tensors of the model whose shape does not match the config, such as
+    /// outputs with another number of anchors or labels.
+    Shape(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Backend(err) => err.fmt(f),
+            Error::Preprocessing(message) => message.fmt(f),
+            Error::Config(message) => message.fmt(f),
+            Error::Shape(message) => message.fmt(f),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<menoh::Error> for Error {
+    fn from(value: menoh::Error) -> Self {
+        match value {
+            menoh::Error::DimensionMismatch(message) => Error::Shape(message),
+            menoh::Error::InvalidBackendConfigError(message) => Error::Config(message),
+            value => Error::Backend(value),
+        }
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(value: image::ImageError) -> Self {
+        Error::Preprocessing(value.to_string())
+    }
+}
+
+/// Non-fatal issues of a prediction or of a pipeline, left to the caller to
+/// report (see `Detector::warnings` and `pipeline::run`).
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Warning {
+    /// Boxes dropped for NaN or infinite coordinates or scores, as decoded
+    /// from diverging outputs.
+    NonFinite {
+        boxes: usize,
+    },
+    /// Boxes reaching past the image, clipped to it with `Params::clip`.
+    Clipped {
+        boxes: usize,
+    },
+    /// The display threshold raised by `Params::adaptive`, dropping the boxes
+    /// below `to`.
+    ThresholdAdjusted {
+        from: f32,
+        to: f32,
+    },
+    /// Detections the sinks of a pipeline failed to send.
+    SinkFailed {
+        error: String,
+    },
+    /// A dump of a pipeline, on `SIGUSR1` (see `dump`).
+    Dumped {
+        dir: String,
+    },
+    DumpFailed {
+        error: String,
+    },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Warning::NonFinite { boxes } => write!(f, "dropped {} non-finite boxes", boxes),
+            Warning::Clipped { boxes } => write!(f, "clipped {} boxes to the image", boxes),
+            Warning::ThresholdAdjusted { from, to } => {
+                write!(f, "raised the display threshold from {} to {}", from, to)
+            }
+            Warning::SinkFailed { ref error } => write!(f, "failed to send detections: {}", error),
+            Warning::Dumped { ref dir } => write!(f, "dumped the pipeline to {}", dir),
+            Warning::DumpFailed { ref error } => {
+                write!(f, "failed to dump the pipeline: {}", error)
+            }
+        }
+    }
+}
+
+pub trait Detector {
+    fn predict(&mut self, img: &image::DynamicImage) -> Result<Vec<bb::Bb>, Error>;
+
+    /// `predict` on a raw frame of a capture device, letterboxed directly
+    /// into the input (see `raw`).
+    fn predict_raw(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        format: raw::PixelFormat,
+    ) -> Result<Vec<bb::Bb>, Error>;
+
+    /// `predict` on each of `imgs`. Architectures running several images at
+    /// once override it.
+    fn predict_batch(&mut self, imgs: &[image::DynamicImage]) -> Result<Vec<Vec<bb::Bb>>, Error> {
+        imgs.iter().map(|img| self.predict(img)).collect()
+    }
+
+    fn config(&self) -> &yolo_v2::Config;
+
+    fn params(&self) -> &yolo_v2::Params;
+
+    fn set_params(&mut self, params: yolo_v2::Params);
+
+    fn memory_usage(&self) -> Result<memory::Usage, Error>;
+
+    /// Whether the last prediction hit the deadline before decoding every
+    /// box.
+    fn partial(&self) -> bool {
+        false
+    }
+
+    /// The issue of the last image if it failed the quality gate of the
+    /// params, which skips the network.
+    fn low_quality(&self) -> Option<quality::Issue> {
+        None
+    }
+
+    /// The fraction of the members of an ensemble that found each box of the
+    /// last prediction (see `ensemble`).
+    fn agreement(&self) -> Option<&[f32]> {
+        None
+    }
+
+    /// The issues of the last prediction.
+    fn warnings(&self) -> &[Warning] {
+        &[]
+    }
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Architecture {
+    /// Grids of anchor boxes (see `yolo_v2`).
+    #[default]
+    Yolo,
+    /// Boxes decoded by the model itself, as in exports of SSD and Faster
+    /// R-CNN (see `boxes`).
+    Boxes(boxes::Outputs),
+}
+
+pub fn from_onnx<'a, P>(
+    path: P,
+    config: &'a yolo_v2::Config,
+    backend: &str,
+    backend_config: &str,
+) -> Result<Box<dyn Detector + 'a>, Error>
+where
+    P: AsRef<path::Path>,
+{
+    Ok(match config.architecture {
+        Architecture::Yolo => Box::new(yolo_v2::YOLOv2::from_onnx(
+            path,
+            config,
+            backend,
+            backend_config,
+        )?),
+        Architecture::Boxes(ref outputs) => Box::new(boxes::Boxes::from_onnx(
+            path,
+            config,
+            outputs,
+            backend,
+            backend_config,
+        )?),
+    })
+}
+
+/// Remote inference only supports YOLO outputs.
+pub fn from_remote<'a>(
+    url: &str,
+    config: &'a yolo_v2::Config,
+    binary: bool,
+) -> Result<Box<dyn Detector + 'a>, Error> {
+    match config.architecture {
+        Architecture::Yolo => Ok(Box::new(yolo_v2::YOLOv2::from_remote(url, config, binary)?)),
+        Architecture::Boxes(_) => Err(Error::Config(
+            "remote inference requires the yolo architecture".to_owned(),
+        )),
+    }
+}
+
+/// Maps `bbox`, in fractions of the network input, back to `img`, drops the
+/// non-finite boxes, and applies the filters of `params`.
+pub fn finish<I>(
+    bbox: &mut Vec<bb::Bb>,
+    config: &yolo_v2::Config,
+    params: &yolo_v2::Params,
+    img: &I,
+    letterbox: yolo_v2::Letterbox,
+) -> Vec<Warning>
+where
+    I: quality::Luma + ?Sized,
+{
+    let transform = coords::CoordTransform::new(letterbox, letterbox.height, letterbox.width);
+    for bb in bbox.iter_mut() {
+        transform.bb_to_image(bb);
+    }
+    let mut warnings = Vec::new();
+    let n_boxes = bbox.len();
+    bbox.retain(|bb| {
+        [bb.top, bb.left, bb.bottom, bb.right, bb.score]
+            .iter()
+            .all(|v| v.is_finite())
+    });
+    if bbox.len() < n_boxes {
+        warnings.push(Warning::NonFinite {
+            boxes: n_boxes - bbox.len(),
+        });
+    }
+    if params.clip {
+        let (width, height) = img.dimensions();
+        let clipped = clip(bbox, height as f32, width as f32);
+        if clipped > 0 {
+            warnings.push(Warning::Clipped { boxes: clipped });
+        }
+    }
+    apply_filters(bbox, config, params, img);
+    warnings
+}
+
+/// Clips `bbox` to an image of `height * width`, returning the number of
+/// clipped boxes.
+fn clip(bbox: &mut [bb::Bb], height: f32, width: f32) -> usize {
+    let mut clipped = 0;
+    for bb in bbox.iter_mut() {
+        let inside = (
+            bb.top.clamp(0., height),
+            bb.left.clamp(0., width),
+            bb.bottom.clamp(0., height),
+            bb.right.clamp(0., width),
+        );
+        if inside != (bb.top, bb.left, bb.bottom, bb.right) {
+            bb.top = inside.0;
+            bb.left = inside.1;
+            bb.bottom = inside.2;
+            bb.right = inside.3;
+            clipped += 1;
+        }
+    }
+    clipped
+}
+
+/// Applies the filters of `params` to `bbox`, in the pixels of `img`. The
+/// boxes are left unrounded, for the caller to round with `params.rounding`
+/// once in the pixels of the original image.
+pub fn apply_filters<I>(
+    bbox: &mut Vec<bb::Bb>,
+    config: &yolo_v2::Config,
+    params: &yolo_v2::Params,
+    img: &I,
+) where
+    I: quality::Luma + ?Sized,
+{
+    let (width, height) = img.dimensions();
+    if let Some(ref classes) = params.classes {
+        filter::classes(bbox, classes, &config.label_names);
+    }
+    if !params.class_thresholds.is_empty() {
+        let thresh = params.thresholds.min();
+        filter::class_thresholds(bbox, &params.class_thresholds, thresh, &config.label_names);
+    }
+    filter::constrain(bbox, &params.constraints, &config.label_names);
+    filter::border(bbox, &params.border, height as _, width as _);
+    if let Some(max) = params.max_per_class {
+        filter::max_per_class(bbox, max);
+    }
+    if let Some(max) = params.max_detections {
+        filter::max_detections(bbox, max);
+    }
+    for bb in bbox.iter_mut() {
+        bb.sharpness = quality::sharpness(img, bb);
+    }
+}
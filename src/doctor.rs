@@ -0,0 +1,245 @@
+//! Self-test of the runtime environment, the model and the config.
+
+use image;
+use menoh;
+use std::path;
+use std::time;
+
+use anchors;
+use detector;
+use source;
+use yolo_v2;
+
+use source::Source;
+
+pub struct Check {
+    pub name: &'static str,
+    /// A short description of the outcome.
+    pub result: Result<String, String>,
+}
+
+/// Runs the checks in order. Checks depending on a failed one are skipped.
+/// The anchors are checked on the images of `samples` when given.
+pub fn run<P>(
+    model_path: P,
+    config: &yolo_v2::Config,
+    backend: &str,
+    backend_config: &str,
+    samples: Option<&path::Path>,
+) -> Vec<Check>
+where
+    P: AsRef<path::Path>,
+{
+    let mut checks = Vec::new();
+    let mut push = |name, result: Result<String, String>| {
+        let ok = result.is_ok();
+        checks.push(Check { name, result });
+        ok
+    };
+
+    // reaching here means that libmenoh has been loaded
+    push("libmenoh", Ok("linked".to_owned()));
+    push("config", check_config(config));
+
+    let model_path = model_path.as_ref();
+    if !push(
+        "model file",
+        model_path
+            .metadata()
+            .map(|m| format!("{} ({} bytes)", model_path.display(), m.len()))
+            .map_err(|e| format!("{}: {}", model_path.display(), e)),
+    ) {
+        return checks;
+    }
+    if !push(
+        "model load",
+        menoh::Builder::from_onnx(model_path)
+            .map(|_| "parsed".to_owned())
+            .map_err(|e| e.to_string()),
+    ) {
+        return checks;
+    }
+
+    if let detector::Architecture::Boxes(_) = config.architecture {
+        match detector::from_onnx(model_path, config, backend, backend_config) {
+            Ok(mut model) => {
+                push("backend", Ok(format!("{} is available", backend)));
+                push("dummy inference", dummy_inference(&mut *model, config));
+            }
+            Err(detector::Error::Shape(message)) => {
+                push("backend", Ok(format!("{} is available", backend)));
+                push("output shape", Err(message));
+            }
+            Err(e) => {
+                push("backend", Err(format!("{}: {}", backend, e)));
+            }
+        }
+        return checks;
+    }
+
+    let mut model = match yolo_v2::YOLOv2::from_onnx(model_path, config, backend, backend_config) {
+        Ok(model) => {
+            push("backend", Ok(format!("{} is available", backend)));
+            model
+        }
+        // from_onnx verifies the output shape once built
+        Err(detector::Error::Shape(message)) => {
+            push("backend", Ok(format!("{} is available", backend)));
+            push("output shape", Err(message));
+            return checks;
+        }
+        Err(e) => {
+            push("backend", Err(format!("{}: {}", backend, e)));
+            return checks;
+        }
+    };
+
+    push("output shape", check_output_shape(&model));
+    push("dummy inference", dummy_inference(&mut model, config));
+
+    if let Some(samples) = samples {
+        push("anchors", check_anchors(&mut model, samples));
+    }
+
+    checks
+}
+
+/// Times a prediction on a gray image.
+fn dummy_inference(
+    model: &mut dyn detector::Detector,
+    config: &yolo_v2::Config,
+) -> Result<String, String> {
+    let img = image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+        config.insize as _,
+        config.insize as _,
+        image::Rgb {
+            data: [128, 128, 128],
+        },
+    ));
+    let start = time::Instant::now();
+    model
+        .predict(&img)
+        .map(|bbox| {
+            let elapsed = start.elapsed();
+            format!(
+                "{} boxes in {:.1} ms",
+                bbox.len(),
+                elapsed.as_secs() as f64 * 1e3 + elapsed.subsec_nanos() as f64 / 1e6
+            )
+        })
+        .map_err(|e| e.to_string())
+}
+
+fn check_anchors(model: &mut yolo_v2::YOLOv2, samples: &path::Path) -> Result<String, String> {
+    let mut images = source::Images::dir(samples).map_err(|e| e.to_string())?;
+    let mut imgs = Vec::new();
+    while let Some(frame) = images.next_frame() {
+        imgs.push(frame.map_err(|e| e.to_string())?.img);
+    }
+    let dims = model.output_dims().map_err(|e| e.to_string())?;
+    let stats = anchors::collect(model, imgs.iter()).map_err(|e| e.to_string())?;
+    if stats.is_empty() {
+        return Err(format!("no detections on {} samples", imgs.len()));
+    }
+    let warnings = anchors::warnings(model.config(), &stats, dims[2], dims[3]);
+    if warnings.is_empty() {
+        Ok(format!(
+            "consistent with {} boxes on {} samples",
+            stats.iter().map(|s| s.count).sum::<usize>(),
+            imgs.len()
+        ))
+    } else {
+        Err(warnings.join("; "))
+    }
+}
+
+/// Checks that the outputs of `model` match the anchors and the labels of its
+/// config.
+pub fn check_output_shape(model: &yolo_v2::YOLOv2) -> Result<String, String> {
+    let config = model.config();
+    let all_dims = model.all_output_dims().map_err(|e| e.to_string())?;
+    for (dims, (name, anchors)) in all_dims.iter().zip(config.outputs()) {
+        let expected = anchors.len() * (4 + 1 + config.n_class_outputs());
+        if dims.len() != 4 || dims[1] != expected {
+            return Err(format!(
+                "{}: {:?}, expected [1, {}, _, _] for {} anchors and {} labels{}",
+                name,
+                dims,
+                expected,
+                anchors.len(),
+                config.label_names.len(),
+                if config.objectness_only {
+                    " without class outputs"
+                } else {
+                    ""
+                }
+            ));
+        }
+    }
+    let shapes: Vec<_> = all_dims.iter().map(|d| format!("{:?}", d)).collect();
+    Ok(shapes.join(", "))
+}
+
+pub fn check_config(config: &yolo_v2::Config) -> Result<String, String> {
+    if config.label_names.is_empty() {
+        return Err("label_names is empty".to_owned());
+    }
+    if config.objectness_only && config.label_names.len() != 1 {
+        return Err("objectness_only requires a single label".to_owned());
+    }
+    if let detector::Architecture::Boxes(_) = config.architecture {
+        if !config.input_sizes.is_empty() {
+            return Err("input_sizes is only supported by YOLO".to_owned());
+        }
+        if config.insize == 0 {
+            return Err("insize is 0".to_owned());
+        }
+        return Ok(format!(
+            "{} labels, decoded boxes, input {}x{}",
+            config.label_names.len(),
+            config.insize,
+            config.insize
+        ));
+    }
+    if config.output.is_empty() {
+        return Err("no output".to_owned());
+    }
+    for (name, anchors) in config.outputs() {
+        if anchors.is_empty() {
+            return Err(format!("no anchors for {}", name));
+        }
+    }
+    if config.cell_offset.is_nan() || config.cell_offset.abs() > 1. {
+        return Err(format!(
+            "cell_offset {} is not in [-1, 1]",
+            config.cell_offset
+        ));
+    }
+    if config.insize == 0 || config.insize / 32 * 32 != config.insize {
+        return Err(format!("insize {} is not a multiple of 32", config.insize));
+    }
+    for size in config.input_sizes.iter() {
+        if size.height == 0 || size.height % 32 != 0 || size.width == 0 || size.width % 32 != 0 {
+            return Err(format!(
+                "input size {}x{} is not a multiple of 32",
+                size.width, size.height
+            ));
+        }
+    }
+    for (i, label) in config.label_names.iter().enumerate() {
+        if config.label_names[..i].contains(label) {
+            return Err(format!("duplicated label name: {}", label));
+        }
+    }
+    let sizes: Vec<_> = config
+        .sizes()
+        .iter()
+        .map(|s| format!("{}x{}", s.width, s.height))
+        .collect();
+    Ok(format!(
+        "{} labels, {} anchors, input {}",
+        config.label_names.len(),
+        config.outputs().iter().map(|o| o.1.len()).sum::<usize>(),
+        sizes.join(", ")
+    ))
+}
@@ -0,0 +1,220 @@
+//! Caps the size of input images before preprocessing.
+//!
+//! Huge inputs are scaled down right after decoding, so that the full-size
+//! image is released before any other processing, and baseline JPEGs are
+//! scaled down as they are decoded, so that it is never held (see `jpeg`).
+//! Detections on the smaller image are mapped back to the original
+//! coordinates with `restore`, and filtered in them by `predict`.
+
+use image;
+use std::fs;
+use std::path;
+
+use bb;
+use color;
+use detector;
+use jpeg;
+use quality;
+
+use image::GenericImage;
+
+/// Scales `img` down so that its longer edge is at most `max_edge`. Returns
+/// the factor mapping the coordinates of the returned image to those of
+/// `img`.
+pub fn cap(img: image::DynamicImage, max_edge: u32) -> (image::DynamicImage, f32) {
+    let long = img.width().max(img.height());
+    if long <= max_edge {
+        return (img, 1.);
+    }
+    let small = img.resize(max_edge, max_edge, image::FilterType::Triangle);
+    let factor = long as f32 / small.width().max(small.height()) as f32;
+    (small, factor)
+}
+
+/// Opens the image at `path`, capped to `max_edge` if given, along with the
+/// factor to pass to `restore` and the original dimensions.
+pub fn open<P>(
+    path: P,
+    max_edge: Option<u32>,
+) -> image::ImageResult<(image::DynamicImage, f32, (u32, u32))>
+where
+    P: AsRef<path::Path>,
+{
+    if let Some(max_edge) = max_edge {
+        if let Some(opened) = open_jpeg(path.as_ref(), max_edge) {
+            return Ok(opened);
+        }
+    }
+    Ok(prepare(color::open(path)?, max_edge))
+}
+
+/// `open` on a JPEG larger than `max_edge`, decoded by bands of rows into
+/// the scaled-down image. `None` for the other files and the JPEGs the band
+/// decoder rejects, which are decoded whole.
+fn open_jpeg(path: &path::Path, max_edge: u32) -> Option<(image::DynamicImage, f32, (u32, u32))> {
+    let mut decoder = jpeg::Decoder::new(fs::File::open(path).ok()?).ok()?;
+    let (width, height) = decoder.dimensions();
+    let long = width.max(height);
+    if long <= max_edge {
+        return None;
+    }
+    let profile = decoder.icc_profile().and_then(color::Profile::parse);
+    let mut thumbnail = Thumbnail::new(width, height, max_edge);
+    let mut top = 0;
+    while let Some(band) = decoder.next_band().ok()? {
+        let mut band = image::DynamicImage::ImageRgb8(band);
+        if let Some(ref profile) = profile {
+            profile.to_srgb_mut(&mut band);
+        }
+        let band = band.to_rgb();
+        thumbnail.add(top, &band);
+        top += band.height();
+    }
+    let small = thumbnail.image();
+    let factor = long as f32 / small.width().max(small.height()) as f32;
+    Some((small, factor, (width, height)))
+}
+
+/// `open` on an image already decoded.
+pub fn prepare(
+    img: image::DynamicImage,
+    max_edge: Option<u32>,
+) -> (image::DynamicImage, f32, (u32, u32)) {
+    let dims = img.dimensions();
+    let (img, factor) = match max_edge {
+        Some(max_edge) => cap(img, max_edge),
+        None => (img, 1.),
+    };
+    (img, factor, dims)
+}
+
+/// Runs `predict` on `img`, scaled down by `factor` from an image of
+/// `width` by `height`, with the filters of the model applied once the boxes
+/// are restored to the pixels of that image (see `detector::apply_filters`),
+/// so that the sizes of constraints and borders are those of the original.
+/// The boxes are returned in the pixels of `img`.
+pub fn predict<F>(
+    model: &mut dyn detector::Detector,
+    img: &image::DynamicImage,
+    factor: f32,
+    (width, height): (u32, u32),
+    predict: F,
+) -> Result<Vec<bb::Bb>, detector::Error>
+where
+    F: FnOnce(&mut dyn detector::Detector) -> Result<Vec<bb::Bb>, detector::Error>,
+{
+    if factor == 1. {
+        return predict(model);
+    }
+    let params = model.params().clone();
+    model.set_params(params.without_filters());
+    let result = predict(&mut *model).map(|mut bbox| {
+        restore(bbox.iter_mut(), factor);
+        let scaled = Scaled { img, width, height };
+        detector::apply_filters(&mut bbox, model.config(), &params, &scaled);
+        restore(bbox.iter_mut(), 1. / factor);
+        bbox
+    });
+    model.set_params(params);
+    result
+}
+
+/// Maps boxes on a capped image back to the original coordinates.
+pub fn restore<'a, I>(bbox: I, factor: f32)
+where
+    I: IntoIterator<Item = &'a mut bb::Bb>,
+{
+    for bb in bbox {
+        bb.top *= factor;
+        bb.left *= factor;
+        bb.bottom *= factor;
+        bb.right *= factor;
+    }
+}
+
+/// An image scaled down as it is decoded, each pixel the average of the
+/// pixels it covers.
+pub struct Thumbnail {
+    height: u32,
+    width: u32,
+    small_height: u32,
+    small_width: u32,
+    /// Column of the thumbnail of each column of the image.
+    columns: Vec<usize>,
+    sums: Vec<u32>,
+    counts: Vec<u32>,
+}
+
+impl Thumbnail {
+    /// A thumbnail of `edge` pixels on its longer edge of an image of `width`
+    /// by `height`, or of its size when smaller.
+    pub fn new(width: u32, height: u32, edge: u32) -> Self {
+        let long = width.max(height);
+        let scale = |len: u32| ((len as u64 * edge as u64 / long as u64) as u32).clamp(1, len);
+        let (small_width, small_height) = if long <= edge {
+            (width, height)
+        } else {
+            (scale(width), scale(height))
+        };
+        let pixels = small_width as usize * small_height as usize;
+        Self {
+            height,
+            width,
+            small_height,
+            small_width,
+            columns: (0..width as u64)
+                .map(|x| (x * small_width as u64 / width as u64) as usize)
+                .collect(),
+            sums: vec![0; pixels * 3],
+            counts: vec![0; pixels],
+        }
+    }
+
+    /// Adds the rows of `band`, from row `top` of the image.
+    pub fn add(&mut self, top: u32, band: &image::RgbImage) {
+        for (y, row) in band.chunks(self.width as usize * 3).enumerate() {
+            let small_y = (top as u64 + y as u64) * self.small_height as u64 / self.height as u64;
+            let offset = small_y as usize * self.small_width as usize;
+            for (pixel, &x) in row.chunks(3).zip(self.columns.iter()) {
+                let i = offset + x;
+                self.counts[i] += 1;
+                for (sum, &value) in self.sums[i * 3..i * 3 + 3].iter_mut().zip(pixel) {
+                    *sum += value as u32;
+                }
+            }
+        }
+    }
+
+    /// The thumbnail of the rows added.
+    pub fn image(&self) -> image::DynamicImage {
+        let data = self
+            .sums
+            .iter()
+            .enumerate()
+            .map(|(i, &sum)| (sum / self.counts[i / 3].max(1)) as u8)
+            .collect();
+        let small = image::RgbImage::from_raw(self.small_width, self.small_height, data).unwrap();
+        image::DynamicImage::ImageRgb8(small)
+    }
+}
+
+/// A scaled-down image seen at the size of the original one, for the filters
+/// and the sharpness of the boxes in its pixels.
+pub struct Scaled<'a> {
+    pub img: &'a image::DynamicImage,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl<'a> quality::Luma for Scaled<'a> {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn luma(&self, x: u32, y: u32) -> u8 {
+        let (small_width, small_height) = (self.img.width(), self.img.height());
+        let x = x as u64 * small_width as u64 / self.width as u64;
+        let y = y as u64 * small_height as u64 / self.height as u64;
+        self.img.luma(x as u32, y as u32)
+    }
+}
@@ -2,9 +2,14 @@ use image;
 use imageproc;
 use rusttype;
 use std::fmt;
+use std::fs;
+use std::io;
 use std::ops;
+use std::path;
+use std::time;
 
 use bb;
+use rfc3339;
 
 use rect::Rect;
 
@@ -12,6 +17,50 @@ pub fn get_font() -> Result<rusttype::Font<'static>, rusttype::Error> {
     rusttype::Font::from_bytes(include_bytes!("../opensans/ttfs/OpenSans-Regular.ttf") as &[u8])
 }
 
+/// The font of the file at `path`, for labels in the scripts missing from
+/// `get_font`, which only covers the Latin, Greek and Cyrillic ones.
+pub fn load_font<P>(path: P) -> io::Result<rusttype::Font<'static>>
+where
+    P: AsRef<path::Path>,
+{
+    rusttype::Font::from_bytes(fs::read(path)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+}
+
+/// Returns `img` with `bbox` drawn on it, as `draw_bbox_mut`.
+pub fn annotate(
+    img: &image::DynamicImage,
+    bbox: &[bb::Bb],
+    label_names: &[String],
+) -> Result<image::DynamicImage, rusttype::Error> {
+    let font = get_font()?;
+    let mut img = img.clone();
+    draw_bbox_mut(&mut img, bbox.iter(), label_names, &font);
+    Ok(img)
+}
+
+/// Color of the boxes of `label`. Hues are spread by the golden ratio, so
+/// that labels close in the list get distinct colors.
+pub fn class_color(label: usize) -> image::Rgba<u8> {
+    let h = (label as f32 * 0.618_034).fract() * 6.;
+    let (s, v) = (0.75, 0.95);
+    let f = h.fract();
+    let (p, q, t) = (v * (1. - s), v * (1. - s * f), v * (1. - s * (1. - f)));
+    let (r, g, b) = match h as u32 {
+        0 => (v, t, p),
+        1 => (q, v, p),
+        2 => (p, v, t),
+        3 => (p, q, v),
+        4 => (t, p, v),
+        _ => (v, p, q),
+    };
+    image::Rgba {
+        data: [(r * 255.) as u8, (g * 255.) as u8, (b * 255.) as u8, 255],
+    }
+}
+
+/// Draws the boxes with their labels and scores, in the color of their label
+/// (see `class_color`).
 pub fn draw_bbox_mut<'a, I, B, L, S>(image: &mut I, bbox: B, label_names: &L, font: &rusttype::Font)
 where
     I: image::GenericImage<Pixel = image::Rgba<u8>>,
@@ -22,6 +71,12 @@ where
     let scale = 32.;
 
     for bb in bbox {
+        let color = class_color(bb.label);
+        // black text on light colors
+        let luma = 0.299 * color.data[0] as f32
+            + 0.587 * color.data[1] as f32
+            + 0.114 * color.data[2] as f32;
+        let text_color = if luma > 128. { 0 } else { 255 };
         let text = format!("{}: {:.2}", label_names[bb.label], bb.score);
         let text_width =
             font.layout(
@@ -37,13 +92,13 @@ where
             image,
             imageproc::rect::Rect::at(bb.left() as _, (bb.top() - scale) as _)
                 .of_size(text_width as _, scale as _),
-            image::Rgba {
-                data: [255, 255, 255, 0],
-            },
+            color,
         );
         imageproc::drawing::draw_text_mut(
             image,
-            image::Rgba { data: [0, 0, 0, 0] },
+            image::Rgba {
+                data: [text_color, text_color, text_color, 255],
+            },
             bb.left() as _,
             (bb.top() - scale) as _,
             rusttype::Scale::uniform(scale),
@@ -54,10 +109,85 @@ where
             image,
             imageproc::rect::Rect::at(bb.left() as _, bb.top() as _)
                 .of_size(bb.width() as _, bb.height() as _),
+            color,
+            5,
+        );
+    }
+}
+
+/// Metadata rendered on annotated frames. Missing fields are skipped.
+#[derive(Default)]
+pub struct Overlay<'a> {
+    pub time: Option<time::SystemTime>,
+    pub stream: Option<&'a str>,
+    pub model: Option<&'a str>,
+    pub fps: Option<f64>,
+}
+
+#[derive(Clone, Copy)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+pub fn draw_overlay_mut<I>(image: &mut I, overlay: &Overlay, corner: Corner, font: &rusttype::Font)
+where
+    I: image::GenericImage<Pixel = image::Rgba<u8>>,
+{
+    let scale = 24.;
+
+    let mut lines = Vec::new();
+    if let Some(t) = overlay.time {
+        lines.push(rfc3339::format(t));
+    }
+    if let Some(stream) = overlay.stream {
+        lines.push(stream.to_owned());
+    }
+    if let Some(model) = overlay.model {
+        lines.push(model.to_owned());
+    }
+    if let Some(fps) = overlay.fps {
+        lines.push(format!("{:.2} FPS", fps));
+    }
+
+    let (width, height) = image.dimensions();
+    let n = lines.len() as u32;
+    for (i, line) in lines.iter().enumerate() {
+        let text_width = font
+            .layout(
+                line,
+                rusttype::Scale::uniform(scale),
+                rusttype::Point { x: 0., y: 0. },
+            ).filter_map(|l| l.pixel_bounding_box())
+            .last()
+            .map_or(0, |b| b.max.x as u32 + 2);
+        let x = match corner {
+            Corner::TopLeft | Corner::BottomLeft => 0,
+            Corner::TopRight | Corner::BottomRight => width.saturating_sub(text_width),
+        };
+        let y = match corner {
+            Corner::TopLeft | Corner::TopRight => i as u32 * scale as u32,
+            Corner::BottomLeft | Corner::BottomRight => {
+                height.saturating_sub((n - i as u32) * scale as u32)
+            }
+        };
+        imageproc::drawing::draw_filled_rect_mut(
+            image,
+            imageproc::rect::Rect::at(x as _, y as _).of_size(text_width.max(1), scale as _),
             image::Rgba {
-                data: [255, 0, 0, 0],
+                data: [255, 255, 255, 0],
             },
-            5,
+        );
+        imageproc::drawing::draw_text_mut(
+            image,
+            image::Rgba { data: [0, 0, 0, 0] },
+            x,
+            y,
+            rusttype::Scale::uniform(scale),
+            font,
+            line,
         );
     }
 }
@@ -0,0 +1,33 @@
+//! Debug dump requests, raised by SIGUSR1 or by `request` (see
+//! `pipeline::Dump`).
+
+use std::io;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+pub fn listen() -> io::Result<()> {
+    use libc;
+
+    extern "C" fn handle(_: libc::c_int) {
+        REQUESTED.store(true, Ordering::SeqCst);
+    }
+
+    let handler = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    if unsafe { libc::signal(libc::SIGUSR1, handler) } == libc::SIG_ERR {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+pub fn request() {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Returns whether a dump has been requested since the last call.
+pub fn requested() -> bool {
+    REQUESTED.swap(false, Ordering::SeqCst)
+}
@@ -0,0 +1,128 @@
+//! Ensembles of detectors with the same labels. The boxes of the members are
+//! merged when they overlap, and each merged box records the fraction of the
+//! members that found it, so that the boxes found by only some of them can
+//! be reviewed rather than trusted.
+
+use image;
+
+use bb;
+use detector;
+use memory;
+use nms;
+use quality;
+use raw;
+use yolo_v2;
+
+pub struct Ensemble<'a> {
+    members: Vec<Box<dyn detector::Detector + 'a>>,
+    iou: f32,
+    agreement: Vec<f32>,
+    warnings: Vec<detector::Warning>,
+}
+
+impl<'a> Ensemble<'a> {
+    /// Boxes of the same label overlapping with an IoU of at least `iou` are
+    /// merged. The first member gives the config and the params.
+    pub fn new(members: Vec<Box<dyn detector::Detector + 'a>>, iou: f32) -> Self {
+        assert!(!members.is_empty(), "an ensemble needs members");
+        Self {
+            members,
+            iou,
+            agreement: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    fn merge(&mut self, bboxes: Vec<Vec<bb::Bb>>) -> Vec<bb::Bb> {
+        self.warnings = self
+            .members
+            .iter()
+            .flat_map(|m| m.warnings().iter().cloned())
+            .collect();
+        let merged = merge(&bboxes, self.iou);
+        self.agreement = merged.iter().map(|&(_, found)| found).collect();
+        merged.into_iter().map(|(bb, _)| bb).collect()
+    }
+}
+
+/// Merges the boxes found by each member by `nms::fuse`, returning each
+/// merged box with the fraction of the members that found it.
+pub fn merge(bboxes: &[Vec<bb::Bb>], iou: f32) -> Vec<(bb::Bb, f32)> {
+    let n = bboxes.len().max(1) as f32;
+    nms::fuse(bboxes, iou)
+        .into_iter()
+        .map(|c| {
+            let merged = bb::Bb {
+                truncated: c.members.iter().any(|(_, bb)| bb.truncated),
+                ..c.fused
+            };
+            (merged, c.members.len() as f32 / n)
+        })
+        .collect()
+}
+
+impl<'a> detector::Detector for Ensemble<'a> {
+    fn predict(&mut self, img: &image::DynamicImage) -> Result<Vec<bb::Bb>, detector::Error> {
+        let bboxes = self
+            .members
+            .iter_mut()
+            .map(|m| m.predict(img))
+            .collect::<Result<_, _>>()?;
+        Ok(self.merge(bboxes))
+    }
+
+    fn predict_raw(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        format: raw::PixelFormat,
+    ) -> Result<Vec<bb::Bb>, detector::Error> {
+        let bboxes = self
+            .members
+            .iter_mut()
+            .map(|m| m.predict_raw(data, width, height, format))
+            .collect::<Result<_, _>>()?;
+        Ok(self.merge(bboxes))
+    }
+
+    fn config(&self) -> &yolo_v2::Config {
+        self.members[0].config()
+    }
+
+    fn params(&self) -> &yolo_v2::Params {
+        self.members[0].params()
+    }
+
+    /// Sets the params of every member.
+    fn set_params(&mut self, params: yolo_v2::Params) {
+        for member in self.members.iter_mut() {
+            member.set_params(params.clone());
+        }
+    }
+
+    fn memory_usage(&self) -> Result<memory::Usage, detector::Error> {
+        let mut usage = memory::Usage::default();
+        for member in self.members.iter() {
+            usage = usage + member.memory_usage()?;
+        }
+        Ok(usage)
+    }
+
+    fn partial(&self) -> bool {
+        self.members.iter().any(|m| m.partial())
+    }
+
+    fn low_quality(&self) -> Option<quality::Issue> {
+        self.members[0].low_quality()
+    }
+
+    fn agreement(&self) -> Option<&[f32]> {
+        Some(&self.agreement)
+    }
+
+    /// The issues of the members.
+    fn warnings(&self) -> &[detector::Warning] {
+        &self.warnings
+    }
+}
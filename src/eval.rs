@@ -0,0 +1,546 @@
+//! Mean average precision of a model against ground truth annotations, in
+//! Pascal VOC XML files or a COCO JSON file, to check that a converted model
+//! still detects as the original one.
+//!
+//! AP is the area under the precision envelope sampled at 101 recall points,
+//! as in the COCO evaluation, at the IoU thresholds from 0.5 to 0.95 by 0.05.
+//! Objects marked `difficult` (VOC) or `iscrowd` (COCO) are not required to
+//! be detected, and the detections matching them are not counted.
+
+use image;
+use serde_json;
+use std::cmp;
+use std::collections;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path;
+
+use bb;
+use color;
+use detector;
+use downscale;
+use rect;
+use yolo_v2;
+
+use image::GenericImage;
+use rect::Rect;
+
+/// The thresholds `run` decodes with, low enough for the precision at high
+/// recalls.
+pub const MIN_SCORE: f32 = 0.005;
+
+/// An annotated object, in pixels.
+#[derive(Clone, Debug)]
+pub struct Object {
+    pub top: f32,
+    pub left: f32,
+    pub bottom: f32,
+    pub right: f32,
+    pub label: usize,
+    /// Set for the `difficult` and `iscrowd` objects.
+    pub ignored: bool,
+}
+
+impl rect::Rect<f32> for Object {
+    fn top(&self) -> f32 {
+        self.top
+    }
+    fn left(&self) -> f32 {
+        self.left
+    }
+    fn bottom(&self) -> f32 {
+        self.bottom
+    }
+    fn right(&self) -> f32 {
+        self.right
+    }
+}
+
+pub struct Image {
+    pub path: path::PathBuf,
+    pub objects: Vec<Object>,
+}
+
+/// Annotated images. The objects of labels the model does not know are left
+/// out.
+pub struct Dataset {
+    pub images: Vec<Image>,
+}
+
+impl Dataset {
+    /// Loads `annotations`, a directory of VOC XML files or a COCO JSON file,
+    /// of the images in `images`.
+    pub fn load<P, Q>(annotations: P, images: Q, label_names: &[String]) -> Result<Self, Error>
+    where
+        P: AsRef<path::Path>,
+        Q: AsRef<path::Path>,
+    {
+        let (annotations, images) = (annotations.as_ref(), images.as_ref());
+        if annotations.is_dir() {
+            Self::voc(annotations, images, label_names)
+        } else {
+            Self::coco(annotations, images, label_names)
+        }
+    }
+
+    /// Loads the XML files of `dir`, as the `Annotations` directory of VOC.
+    pub fn voc<P, Q>(dir: P, images: Q, label_names: &[String]) -> Result<Self, Error>
+    where
+        P: AsRef<path::Path>,
+        Q: AsRef<path::Path>,
+    {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "xml") {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        let mut dataset = Self { images: Vec::new() };
+        for path in paths {
+            let xml = fs::read_to_string(&path)?;
+            let invalid = |what: &str| Error::Invalid(format!("{}: {}", path.display(), what));
+            let file_name = match elements(&xml, "filename").first() {
+                Some(name) => name.trim().to_owned(),
+                None => format!("{}.jpg", path.file_stem().unwrap().to_string_lossy()),
+            };
+            let mut objects = Vec::new();
+            for object in elements(&xml, "object") {
+                let name = first(object, "name").ok_or_else(|| invalid("object without name"))?;
+                let label = match label_names.iter().position(|l| l == name) {
+                    Some(label) => label,
+                    None => continue,
+                };
+                let bndbox =
+                    first(object, "bndbox").ok_or_else(|| invalid("object without box"))?;
+                let coord = |tag| {
+                    first(bndbox, tag)
+                        .and_then(|v| v.parse::<f32>().ok())
+                        .ok_or_else(|| invalid(&format!("invalid {}", tag)))
+                };
+                // VOC numbers the pixels from 1, both ends included
+                objects.push(Object {
+                    top: coord("ymin")? - 1.,
+                    left: coord("xmin")? - 1.,
+                    bottom: coord("ymax")?,
+                    right: coord("xmax")?,
+                    label,
+                    ignored: first(object, "difficult") == Some("1"),
+                });
+            }
+            dataset.images.push(Image {
+                path: images.as_ref().join(file_name),
+                objects,
+            });
+        }
+        Ok(dataset)
+    }
+
+    /// Loads a COCO instances file. Categories are matched to labels by name.
+    pub fn coco<P, Q>(path: P, images: Q, label_names: &[String]) -> Result<Self, Error>
+    where
+        P: AsRef<path::Path>,
+        Q: AsRef<path::Path>,
+    {
+        #[derive(Deserialize)]
+        struct Instances {
+            images: Vec<CocoImage>,
+            annotations: Vec<Annotation>,
+            categories: Vec<Category>,
+        }
+        #[derive(Deserialize)]
+        struct CocoImage {
+            id: u64,
+            file_name: String,
+        }
+        #[derive(Deserialize)]
+        struct Annotation {
+            image_id: u64,
+            category_id: u32,
+            bbox: [f32; 4],
+            #[serde(default)]
+            iscrowd: u8,
+        }
+        #[derive(Deserialize)]
+        struct Category {
+            id: u32,
+            name: String,
+        }
+
+        let instances: Instances =
+            serde_json::from_reader(io::BufReader::new(fs::File::open(path)?))?;
+        let labels: collections::HashMap<_, _> = instances
+            .categories
+            .iter()
+            .filter_map(|c| Some((c.id, label_names.iter().position(|l| *l == c.name)?)))
+            .collect();
+        let index: collections::HashMap<_, _> = instances
+            .images
+            .iter()
+            .enumerate()
+            .map(|(i, img)| (img.id, i))
+            .collect();
+        let mut dataset = Self {
+            images: instances
+                .images
+                .iter()
+                .map(|img| Image {
+                    path: images.as_ref().join(&img.file_name),
+                    objects: Vec::new(),
+                })
+                .collect(),
+        };
+        for a in instances.annotations {
+            let label = match labels.get(&a.category_id) {
+                Some(&label) => label,
+                None => continue,
+            };
+            let i = *index.get(&a.image_id).ok_or_else(|| {
+                Error::Invalid(format!("annotation of unknown image {}", a.image_id))
+            })?;
+            let [x, y, w, h] = a.bbox;
+            dataset.images[i].objects.push(Object {
+                top: y,
+                left: x,
+                bottom: y + h,
+                right: x + w,
+                label,
+                ignored: a.iscrowd != 0,
+            });
+        }
+        Ok(dataset)
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Evaluation {
+    /// The labels with objects to detect.
+    pub classes: Vec<ClassAp>,
+    /// mAP@0.5.
+    pub map50: f32,
+    /// mAP@[.5:.95].
+    pub map: f32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ClassAp {
+    pub label: String,
+    pub objects: usize,
+    pub detections: usize,
+    pub ap50: f32,
+    pub ap: f32,
+}
+
+/// Runs `model` on the images of `dataset`, with its thresholds lowered to
+/// `MIN_SCORE`, and evaluates the detections.
+pub fn run(model: &mut dyn detector::Detector, dataset: &Dataset) -> Result<Evaluation, Error> {
+    let saved = model.params().clone();
+    let mut params = saved.clone();
+    params.thresholds = yolo_v2::Thresholds {
+        track: MIN_SCORE,
+        display: MIN_SCORE,
+        alert: MIN_SCORE,
+    };
+    params.class_thresholds.clear();
+    model.set_params(params);
+    let detections = predict(model, dataset);
+    model.set_params(saved);
+    Ok(evaluate(dataset, &detections?, &model.config().label_names))
+}
+
+fn predict(
+    model: &mut dyn detector::Detector,
+    dataset: &Dataset,
+) -> Result<Vec<Vec<bb::Bb>>, Error> {
+    let max_edge = model.config().max_edge;
+    let mut detections = Vec::with_capacity(dataset.images.len());
+    for img in dataset.images.iter() {
+        let decoded = color::open(&img.path).map_err(|err| Error::Image(img.path.clone(), err))?;
+        let mut bbox = match max_edge {
+            Some(max_edge) => {
+                let dimensions = decoded.dimensions();
+                let (decoded, factor) = downscale::cap(decoded, max_edge);
+                let mut bbox = downscale::predict(model, &decoded, factor, dimensions, |m| {
+                    m.predict(&decoded)
+                })?;
+                downscale::restore(bbox.iter_mut(), factor);
+                bbox
+            }
+            None => model.predict(&decoded)?,
+        };
+        model.params().rounding.apply(bbox.iter_mut());
+        detections.push(bbox);
+    }
+    Ok(detections)
+}
+
+/// Evaluates `detections`, those of every image of `dataset` in order.
+pub fn evaluate(
+    dataset: &Dataset,
+    detections: &[Vec<bb::Bb>],
+    label_names: &[String],
+) -> Evaluation {
+    let mut classes = Vec::new();
+    for (label, name) in label_names.iter().enumerate() {
+        let objects = dataset
+            .images
+            .iter()
+            .flat_map(|img| img.objects.iter())
+            .filter(|o| o.label == label && !o.ignored)
+            .count();
+        if objects == 0 {
+            continue;
+        }
+        let mut ranked: Vec<_> = detections
+            .iter()
+            .enumerate()
+            .flat_map(|(i, bbox)| {
+                bbox.iter()
+                    .filter(|bb| bb.label == label)
+                    .map(move |bb| (i, bb))
+            })
+            .collect();
+        ranked.sort_by(|a, b| {
+            b.1.score
+                .partial_cmp(&a.1.score)
+                .unwrap_or(cmp::Ordering::Equal)
+        });
+        let aps: Vec<_> = (0..10)
+            .map(|k| average_precision(dataset, &ranked, label, objects, 0.5 + 0.05 * k as f32))
+            .collect();
+        classes.push(ClassAp {
+            label: name.clone(),
+            objects,
+            detections: ranked.len(),
+            ap50: aps[0],
+            ap: mean(aps.iter().cloned()),
+        });
+    }
+    Evaluation {
+        map50: mean(classes.iter().map(|c| c.ap50)),
+        map: mean(classes.iter().map(|c| c.ap)),
+        classes,
+    }
+}
+
+/// AP of the detections of `label` ranked by score, matched greedily to the
+/// objects they overlap most.
+fn average_precision(
+    dataset: &Dataset,
+    ranked: &[(usize, &bb::Bb)],
+    label: usize,
+    objects: usize,
+    iou: f32,
+) -> f32 {
+    let mut matched: Vec<_> = dataset
+        .images
+        .iter()
+        .map(|img| vec![false; img.objects.len()])
+        .collect();
+    let (mut tp, mut fp) = (0, 0);
+    // recall and precision after each counted detection
+    let mut curve = Vec::with_capacity(ranked.len());
+    for &(i, bb) in ranked {
+        let mut best: Option<(usize, f32)> = None;
+        let mut ignored = false;
+        for (j, o) in dataset.images[i].objects.iter().enumerate() {
+            if o.label != label {
+                continue;
+            }
+            let overlap = o.iou(bb);
+            if overlap < iou {
+                continue;
+            }
+            if o.ignored {
+                ignored = true;
+            } else if !matched[i][j] && best.is_none_or(|(_, b)| overlap > b) {
+                best = Some((j, overlap));
+            }
+        }
+        match best {
+            Some((j, _)) => {
+                matched[i][j] = true;
+                tp += 1;
+            }
+            None if ignored => continue,
+            None => fp += 1,
+        }
+        curve.push((tp as f32 / objects as f32, tp as f32 / (tp + fp) as f32));
+    }
+    // the precision envelope, non-increasing in recall
+    for k in (1..curve.len()).rev() {
+        curve[k - 1].1 = curve[k - 1].1.max(curve[k].1);
+    }
+    let sampled = (0..=100).map(|r| {
+        let recall = r as f32 / 100.;
+        curve
+            .iter()
+            .find(|&&(rc, _)| rc >= recall)
+            .map_or(0., |&(_, p)| p)
+    });
+    mean(sampled)
+}
+
+fn mean<I>(values: I) -> f32
+where
+    I: Iterator<Item = f32>,
+{
+    let (sum, n) = values.fold((0., 0), |(sum, n), v| (sum + v, n + 1));
+    if n == 0 {
+        0.
+    } else {
+        sum / n as f32
+    }
+}
+
+/// The contents of the elements `tag` of `xml`, which is enough for the
+/// plain annotations of VOC.
+fn elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        let content = &rest[start + open.len()..];
+        match content.find(&close) {
+            Some(end) => {
+                found.push(&content[..end]);
+                rest = &content[end + close.len()..];
+            }
+            None => break,
+        }
+    }
+    found
+}
+
+/// The trimmed contents of the first element `tag`, the one of the object
+/// rather than of its parts.
+fn first<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    elements(xml, tag).first().map(|s| s.trim())
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Image(path::PathBuf, image::ImageError),
+    Detector(detector::Error),
+    Annotations(serde_json::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err) => err.fmt(f),
+            Error::Image(path, err) => write!(f, "{}: {}", path.display(), err),
+            Error::Detector(err) => err.fmt(f),
+            Error::Annotations(err) => err.fmt(f),
+            Error::Invalid(message) => message.fmt(f),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        Error::Io(value)
+    }
+}
+
+impl From<detector::Error> for Error {
+    fn from(value: detector::Error) -> Self {
+        Error::Detector(value)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Self {
+        Error::Annotations(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(top: f32, left: f32, ignored: bool) -> Object {
+        Object {
+            top,
+            left,
+            bottom: top + 10.,
+            right: left + 10.,
+            label: 0,
+            ignored,
+        }
+    }
+
+    fn bb(top: f32, left: f32, score: f32) -> bb::Bb {
+        bb::Bb {
+            top,
+            left,
+            bottom: top + 10.,
+            right: left + 10.,
+            label: 0,
+            score,
+            truncated: false,
+            sharpness: 0.,
+            provenance: None,
+        }
+    }
+
+    fn dataset(objects: Vec<Object>) -> Dataset {
+        Dataset {
+            images: vec![Image {
+                path: path::PathBuf::from("a.jpg"),
+                objects,
+            }],
+        }
+    }
+
+    /// Two objects, found by the first and the third detections: the
+    /// precision is 1 up to a recall of 0.5, then 2 / 3 up to 1, which gives
+    /// (51 * 1 + 50 * 2 / 3) / 101 over the 101 recalls sampled.
+    #[test]
+    fn averages_the_precision() {
+        let dataset = dataset(vec![object(0., 0., false), object(50., 50., false)]);
+        let detections = vec![vec![
+            bb(0., 0., 0.9),
+            bb(100., 100., 0.8),
+            bb(50., 50., 0.7),
+        ]];
+        let evaluation = evaluate(&dataset, &detections, &["a".to_owned()]);
+        let expected = (51. + 50. * 2. / 3.) / 101.;
+        assert_eq!(evaluation.classes.len(), 1);
+        let class = &evaluation.classes[0];
+        assert_eq!((class.objects, class.detections), (2, 3));
+        assert!((class.ap50 - expected).abs() < 1e-5, "{}", class.ap50);
+        assert!((class.ap - expected).abs() < 1e-5, "{}", class.ap);
+        assert!((evaluation.map50 - expected).abs() < 1e-5);
+    }
+
+    /// A box shifted by 2 pixels has an IoU of 80 / 120 with its object: a
+    /// match at the thresholds from 0.5 to 0.65, a false positive above.
+    #[test]
+    fn matches_by_iou() {
+        let dataset = dataset(vec![object(0., 0., false)]);
+        let detections = vec![vec![bb(0., 2., 0.9)]];
+        let evaluation = evaluate(&dataset, &detections, &["a".to_owned()]);
+        let class = &evaluation.classes[0];
+        assert!((class.ap50 - 1.).abs() < 1e-6);
+        assert!((class.ap - 0.4).abs() < 1e-6, "{}", class.ap);
+    }
+
+    #[test]
+    fn skips_the_ignored_objects() {
+        let dataset = dataset(vec![object(0., 0., false), object(50., 50., true)]);
+        let detections = vec![vec![bb(50., 50., 0.9), bb(0., 0., 0.8)]];
+        let evaluation = evaluate(&dataset, &detections, &["a".to_owned()]);
+        let class = &evaluation.classes[0];
+        assert_eq!(class.objects, 1);
+        assert!((class.ap50 - 1.).abs() < 1e-6);
+    }
+}
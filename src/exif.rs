@@ -0,0 +1,288 @@
+//! Reads the capture time, the camera and the GPS position from the EXIF of
+//! JPEG and TIFF files.
+
+use std::fs;
+use std::io;
+use std::path;
+
+use schema;
+
+use std::io::Read;
+
+const MAKE: u16 = 0x010f;
+const MODEL: u16 = 0x0110;
+const DATE_TIME: u16 = 0x0132;
+const EXIF_IFD: u16 = 0x8769;
+const GPS_IFD: u16 = 0x8825;
+const DATE_TIME_ORIGINAL: u16 = 0x9003;
+const OFFSET_TIME_ORIGINAL: u16 = 0x9011;
+const FOCAL_LENGTH_IN_35MM_FILM: u16 = 0xa405;
+const GPS_LATITUDE_REF: u16 = 1;
+const GPS_LATITUDE: u16 = 2;
+const GPS_LONGITUDE_REF: u16 = 3;
+const GPS_LONGITUDE: u16 = 4;
+const GPS_ALTITUDE_REF: u16 = 5;
+const GPS_ALTITUDE: u16 = 6;
+const GPS_IMG_DIRECTION: u16 = 17;
+
+/// Returns the metadata found in `data`, the content of an image file. Files
+/// without EXIF, or with a malformed one, give empty metadata.
+pub fn read(data: &[u8]) -> schema::Metadata {
+    let tiff = if data.starts_with(&[0xff, 0xd8]) {
+        match app1(data) {
+            Some(tiff) => tiff,
+            None => return schema::Metadata::default(),
+        }
+    } else {
+        data
+    };
+    Tiff::new(tiff).map(|t| t.metadata()).unwrap_or_default()
+}
+
+/// `read` on the file at `path`, if a JPEG or a TIFF by its extension.
+pub fn read_file<P>(path: P) -> io::Result<schema::Metadata>
+where
+    P: AsRef<path::Path>,
+{
+    let path = path.as_ref();
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+    // The EXIF segment of a JPEG comes first and fits in 64 KiB, while the
+    // IFDs of a TIFF may be anywhere.
+    let limit = match extension.as_deref() {
+        Some("jpg") | Some("jpeg") => 1 << 16,
+        Some("tif") | Some("tiff") => u64::MAX,
+        _ => return Ok(schema::Metadata::default()),
+    };
+    let mut data = Vec::new();
+    fs::File::open(path)?.take(limit).read_to_end(&mut data)?;
+    Ok(read(&data))
+}
+
+/// Returns the TIFF structure in the EXIF segment of a JPEG.
+fn app1(data: &[u8]) -> Option<&[u8]> {
+    let mut i = 2;
+    while i + 4 <= data.len() && data[i] == 0xff {
+        let marker = data[i + 1];
+        // start of scan: the segments are over
+        if marker == 0xda {
+            return None;
+        }
+        let len = (data[i + 2] as usize) << 8 | data[i + 3] as usize;
+        let segment = data.get(i + 4..i + 2 + len)?;
+        if marker == 0xe1 && segment.starts_with(b"Exif\0\0") {
+            return Some(&segment[6..]);
+        }
+        i += 2 + len;
+    }
+    None
+}
+
+struct Tiff<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    tag: u16,
+    kind: u16,
+    count: usize,
+    /// Offset of the value, inline or not.
+    offset: usize,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let little_endian = match data.get(..2)? {
+            b"II" => true,
+            b"MM" => false,
+            _ => return None,
+        };
+        let tiff = Self {
+            data,
+            little_endian,
+        };
+        if tiff.u16(2)? != 42 {
+            return None;
+        }
+        Some(tiff)
+    }
+
+    fn metadata(&self) -> schema::Metadata {
+        let mut metadata = schema::Metadata::default();
+        let ifd0 = match self.u32(4).and_then(|o| self.ifd(o as usize)) {
+            Some(ifd) => ifd,
+            None => return metadata,
+        };
+        let make = self.find_str(&ifd0, MAKE);
+        let model = self.find_str(&ifd0, MODEL);
+        metadata.camera = match (make, model) {
+            // models often repeat the make
+            (Some(make), Some(model)) if model.starts_with(&make) => Some(model),
+            (Some(make), Some(model)) => Some(format!("{} {}", make, model)),
+            (make, model) => make.or(model),
+        };
+
+        let exif = self.sub_ifd(&ifd0, EXIF_IFD).unwrap_or_default();
+        let taken = self
+            .find_str(&exif, DATE_TIME_ORIGINAL)
+            .or_else(|| self.find_str(&ifd0, DATE_TIME));
+        metadata.taken = taken.and_then(|t| {
+            let offset = self
+                .find_str(&exif, OFFSET_TIME_ORIGINAL)
+                .unwrap_or_default();
+            date_time(&t).map(|t| t + &offset)
+        });
+        metadata.focal_length_35mm = self
+            .find(&exif, FOCAL_LENGTH_IN_35MM_FILM)
+            .filter(|e| e.kind == 3)
+            .and_then(|e| self.u16(e.offset))
+            .filter(|&f| f != 0)
+            .map(f64::from);
+
+        if let Some(gps) = self.sub_ifd(&ifd0, GPS_IFD) {
+            let coordinate = |tag, ref_tag, negative| {
+                let v = self.find_rationals(&gps, tag)?;
+                if v.len() != 3 {
+                    return None;
+                }
+                let degrees = v[0] + v[1] / 60. + v[2] / 3600.;
+                if self.find_str(&gps, ref_tag)? == negative {
+                    Some(-degrees)
+                } else {
+                    Some(degrees)
+                }
+            };
+            let latitude = coordinate(GPS_LATITUDE, GPS_LATITUDE_REF, "S");
+            let longitude = coordinate(GPS_LONGITUDE, GPS_LONGITUDE_REF, "W");
+            if let (Some(latitude), Some(longitude)) = (latitude, longitude) {
+                let below_sea = self
+                    .find(&gps, GPS_ALTITUDE_REF)
+                    .and_then(|e| self.data.get(e.offset))
+                    == Some(&1);
+                let altitude = self
+                    .find_rationals(&gps, GPS_ALTITUDE)
+                    .and_then(|v| v.first().cloned())
+                    .map(|a| if below_sea { -a } else { a });
+                // true or magnetic north, which is close enough for maps
+                let direction = self
+                    .find_rationals(&gps, GPS_IMG_DIRECTION)
+                    .and_then(|v| v.first().cloned());
+                metadata.gps = Some(schema::Gps {
+                    latitude,
+                    longitude,
+                    altitude,
+                    direction,
+                });
+            }
+        }
+        metadata
+    }
+
+    fn ifd(&self, offset: usize) -> Option<Vec<Entry>> {
+        let n = self.u16(offset)? as usize;
+        let mut entries = Vec::with_capacity(n);
+        for i in 0..n {
+            let at = offset + 2 + 12 * i;
+            let kind = self.u16(at + 2)?;
+            let count = self.u32(at + 4)? as usize;
+            let size = match kind {
+                1 | 2 | 6 | 7 => 1,
+                3 | 8 => 2,
+                4 | 9 => 4,
+                5 | 10 => 8,
+                _ => continue,
+            } * count;
+            let offset = if size <= 4 {
+                at + 8
+            } else {
+                self.u32(at + 8)? as usize
+            };
+            entries.push(Entry {
+                tag: self.u16(at)?,
+                kind,
+                count,
+                offset,
+            });
+        }
+        Some(entries)
+    }
+
+    fn find(&self, ifd: &[Entry], tag: u16) -> Option<Entry> {
+        ifd.iter().find(|e| e.tag == tag).cloned()
+    }
+
+    fn sub_ifd(&self, ifd: &[Entry], tag: u16) -> Option<Vec<Entry>> {
+        let entry = self.find(ifd, tag)?;
+        self.ifd(self.u32(entry.offset)? as usize)
+    }
+
+    fn find_str(&self, ifd: &[Entry], tag: u16) -> Option<String> {
+        let entry = self.find(ifd, tag)?;
+        if entry.kind != 2 {
+            return None;
+        }
+        let bytes = self.data.get(entry.offset..entry.offset + entry.count)?;
+        let s = String::from_utf8_lossy(bytes);
+        let s = s.trim_end_matches('\0').trim();
+        if s.is_empty() {
+            None
+        } else {
+            Some(s.to_owned())
+        }
+    }
+
+    fn find_rationals(&self, ifd: &[Entry], tag: u16) -> Option<Vec<f64>> {
+        let entry = self.find(ifd, tag)?;
+        if entry.kind != 5 {
+            return None;
+        }
+        (0..entry.count)
+            .map(|i| {
+                let at = entry.offset + 8 * i;
+                let (n, d) = (self.u32(at)?, self.u32(at + 4)?);
+                if d == 0 {
+                    None
+                } else {
+                    Some(n as f64 / d as f64)
+                }
+            })
+            .collect()
+    }
+
+    fn u16(&self, at: usize) -> Option<u16> {
+        let b = self.data.get(at..at + 2)?;
+        Some(if self.little_endian {
+            b[0] as u16 | (b[1] as u16) << 8
+        } else {
+            (b[0] as u16) << 8 | b[1] as u16
+        })
+    }
+
+    fn u32(&self, at: usize) -> Option<u32> {
+        let (a, b) = (self.u16(at)? as u32, self.u16(at + 2)? as u32);
+        Some(if self.little_endian {
+            a | b << 16
+        } else {
+            a << 16 | b
+        })
+    }
+}
+
+/// `YYYY:MM:DD HH:MM:SS` to `YYYY-MM-DDTHH:MM:SS`.
+fn date_time(s: &str) -> Option<String> {
+    let b = s.as_bytes();
+    if !s.is_ascii() || b.len() < 19 || b[4] != b':' || b[7] != b':' || b[10] != b' ' {
+        return None;
+    }
+    Some(format!(
+        "{}-{}-{}T{}",
+        &s[..4],
+        &s[5..7],
+        &s[8..10],
+        &s[11..19]
+    ))
+}
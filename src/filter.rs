@@ -0,0 +1,214 @@
+use std::cmp;
+use std::collections;
+
+use bb;
+
+use rect::Rect;
+
+/// Drops the boxes whose label is not in `classes`.
+pub fn classes(bbox: &mut Vec<bb::Bb>, classes: &[String], label_names: &[String]) {
+    let keep: Vec<_> = label_names.iter().map(|l| classes.contains(l)).collect();
+    bbox.retain(|bb| keep[bb.label]);
+}
+
+/// Drops the boxes scoring below the threshold of their label in
+/// `thresholds`, or below `default` for the other labels.
+pub fn class_thresholds(
+    bbox: &mut Vec<bb::Bb>,
+    thresholds: &collections::HashMap<String, f32>,
+    default: f32,
+    label_names: &[String],
+) {
+    let thresh: Vec<_> = label_names
+        .iter()
+        .map(|l| thresholds.get(l).cloned().unwrap_or(default))
+        .collect();
+    bbox.retain(|bb| bb.score >= thresh[bb.label]);
+}
+
+/// Correction of the scores of a label, `score * scale + offset` clamped to
+/// [0, 1], for labels known to be over- or under-confident.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct ScoreCorrection {
+    pub scale: f32,
+    pub offset: f32,
+}
+
+impl Default for ScoreCorrection {
+    fn default() -> Self {
+        Self {
+            scale: 1.,
+            offset: 0.,
+        }
+    }
+}
+
+impl ScoreCorrection {
+    pub fn apply(&self, score: f32) -> f32 {
+        (score * self.scale + self.offset).clamp(0., 1.)
+    }
+}
+
+/// Geometric constraints on the boxes of a class, in image pixels.
+/// `aspect` is width / height.
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Constraint {
+    pub min_width: f32,
+    pub min_height: f32,
+    pub min_aspect: Option<f32>,
+    pub max_aspect: Option<f32>,
+}
+
+impl Constraint {
+    pub fn accepts<R>(&self, r: &R) -> bool
+    where
+        R: Rect<f32>,
+    {
+        let (h, w) = (r.height(), r.width());
+        if w < self.min_width || h < self.min_height {
+            return false;
+        }
+        let aspect = w / h;
+        match (self.min_aspect, self.max_aspect) {
+            (Some(min), _) if aspect < min => false,
+            (_, Some(max)) if aspect > max => false,
+            _ => true,
+        }
+    }
+}
+
+pub fn constrain(
+    bbox: &mut Vec<bb::Bb>,
+    constraints: &collections::HashMap<String, Constraint>,
+    label_names: &[String],
+) {
+    if constraints.is_empty() {
+        return;
+    }
+    let constraints: Vec<_> = label_names.iter().map(|l| constraints.get(l)).collect();
+    bbox.retain(|bb| match constraints[bb.label] {
+        Some(c) => c.accepts(bb),
+        None => true,
+    });
+}
+
+#[derive(Clone, Copy, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BorderPolicy {
+    Keep,
+    Flag,
+    Drop,
+}
+
+/// What to do with boxes that reach within `margin` pixels of the image
+/// border, which are often truncated objects.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Border {
+    pub policy: BorderPolicy,
+    pub margin: f32,
+}
+
+impl Default for Border {
+    fn default() -> Self {
+        Self {
+            policy: BorderPolicy::Keep,
+            margin: 1.,
+        }
+    }
+}
+
+impl Border {
+    pub fn touches<R>(&self, r: &R, height: f32, width: f32) -> bool
+    where
+        R: Rect<f32>,
+    {
+        r.top() <= self.margin
+            || r.left() <= self.margin
+            || r.bottom() >= height - self.margin
+            || r.right() >= width - self.margin
+    }
+}
+
+pub fn border(bbox: &mut Vec<bb::Bb>, border: &Border, height: f32, width: f32) {
+    match border.policy {
+        BorderPolicy::Keep => (),
+        BorderPolicy::Flag => {
+            for bb in bbox.iter_mut() {
+                bb.truncated = border.touches(bb, height, width);
+            }
+        }
+        BorderPolicy::Drop => bbox.retain(|bb| !border.touches(bb, height, width)),
+    }
+}
+
+/// Keeps the `max` highest-scoring boxes of each label.
+pub fn max_per_class(bbox: &mut Vec<bb::Bb>, max: usize) {
+    bbox.sort_by(|a, b| {
+        a.label.cmp(&b.label).then(
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(cmp::Ordering::Equal),
+        )
+    });
+    let mut label = None;
+    let mut count = 0;
+    bbox.retain(|bb| {
+        if label != Some(bb.label) {
+            label = Some(bb.label);
+            count = 0;
+        }
+        count += 1;
+        count <= max
+    });
+}
+
+/// Raising of the display threshold in cluttered scenes, bounding the boxes
+/// reported per frame (see `adapt`).
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Adaptive {
+    /// Boxes at or above the display threshold beyond which it is raised.
+    pub max_boxes: usize,
+    /// The highest threshold, so that a crowd of confident boxes is still
+    /// reported in full.
+    #[serde(default = "default_ceiling")]
+    pub ceiling: f32,
+}
+
+fn default_ceiling() -> f32 {
+    0.9
+}
+
+/// The threshold keeping `adaptive.max_boxes` of `bbox`, from `display` up
+/// to `adaptive.ceiling`, along with the boxes tied with the last one. When
+/// raised, the boxes below it are dropped, whatever their consumer.
+pub fn adapt(bbox: &mut Vec<bb::Bb>, adaptive: &Adaptive, display: f32) -> f32 {
+    let mut scores: Vec<_> = bbox
+        .iter()
+        .map(|bb| bb.score)
+        .filter(|&score| score >= display)
+        .collect();
+    if scores.len() <= adaptive.max_boxes {
+        return display;
+    }
+    scores.sort_by(|a, b| b.partial_cmp(a).unwrap_or(cmp::Ordering::Equal));
+    let threshold = match adaptive.max_boxes.checked_sub(1) {
+        Some(last) => scores[last].min(adaptive.ceiling),
+        None => adaptive.ceiling,
+    }
+    .max(display);
+    bbox.retain(|bb| bb.score >= threshold);
+    threshold
+}
+
+/// Keeps the `max` highest-scoring boxes.
+pub fn max_detections(bbox: &mut Vec<bb::Bb>, max: usize) {
+    bbox.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(cmp::Ordering::Equal)
+    });
+    bbox.truncate(max);
+}
@@ -0,0 +1,81 @@
+//! Checks that detections are consistent with those on the flipped image.
+//! Large differences usually mean that the preprocessing or the coordinate
+//! decoding does not match the model: a swapped `cell_order` or a wrong
+//! `cell_offset` of `yolo_v2::Config` shifts the boxes along both axes.
+
+use image;
+
+use augment;
+use bb;
+use detector;
+
+use image::GenericImage;
+use rect::Rect;
+
+pub struct Match {
+    pub bb: bb::Bb,
+    /// The best matching box of the same label on the flipped image, mirrored
+    /// back into the coordinates of the original image.
+    pub mirrored: Option<bb::Bb>,
+    pub iou: f32,
+    pub score_diff: f32,
+}
+
+/// Flips of each axis, the names of the axes, checked by `--flip-check`.
+pub const FLIPS: [(&str, augment::Transform); 2] = [
+    ("horizontal", augment::Transform::FlipH),
+    ("vertical", augment::Transform::FlipV),
+];
+
+/// Mean IoU of the matches below which the decoding likely does not match the
+/// model.
+pub const MIN_MEAN_IOU: f32 = 0.7;
+
+/// Matches the detections of `img` with those of `img` flipped by `flip`.
+pub fn check(
+    model: &mut dyn detector::Detector,
+    img: &image::DynamicImage,
+    flip: augment::Transform,
+) -> Result<Vec<Match>, detector::Error> {
+    let bbox = model.predict(img)?;
+    let mut mirrored = model.predict(&flip.apply(img))?;
+    for bb in mirrored.iter_mut() {
+        flip.unmap(bb, img.height(), img.width());
+    }
+
+    Ok(bbox
+        .into_iter()
+        .map(|bb| {
+            let best = mirrored
+                .iter()
+                .filter(|m| m.label == bb.label)
+                .map(|m| (bb.iou(m), m))
+                .fold(None, |best: Option<(f32, &bb::Bb)>, (iou, m)| match best {
+                    Some((best_iou, _)) if best_iou >= iou => best,
+                    _ => Some((iou, m)),
+                });
+            match best {
+                Some((iou, m)) => Match {
+                    score_diff: m.score - bb.score,
+                    mirrored: Some(m.clone()),
+                    iou,
+                    bb,
+                },
+                None => Match {
+                    score_diff: -bb.score,
+                    mirrored: None,
+                    iou: 0.,
+                    bb,
+                },
+            }
+        })
+        .collect())
+}
+
+/// Mean IoU of `matches`, `None` if empty.
+pub fn mean_iou(matches: &[Match]) -> Option<f32> {
+    if matches.is_empty() {
+        return None;
+    }
+    Some(matches.iter().map(|m| m.iou).sum::<f32>() / matches.len() as f32)
+}
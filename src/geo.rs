@@ -0,0 +1,186 @@
+//! Georeferencing of detections, to export them as GeoJSON for GIS tools.
+//!
+//! Either the camera points straight down over flat ground, as in drone
+//! surveys, and is placed by the GPS position in the EXIF, or a homography
+//! maps the pixels of a fixed camera to longitudes and latitudes.
+
+use serde_json;
+use std::f64::consts::PI;
+
+use schema;
+
+/// Equatorial radius of WGS 84, in meters.
+const EARTH_RADIUS: f64 = 6_378_137.;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Projection {
+    Nadir {
+        /// Height of the camera above the ground, in meters. Defaults to the
+        /// GPS altitude above `ground_elevation`.
+        #[serde(default)]
+        height: Option<f64>,
+        #[serde(default)]
+        ground_elevation: f64,
+        /// Horizontal field of view, in degrees. Defaults to the one of the
+        /// 35 mm equivalent focal length, assuming a 3:2 sensor.
+        #[serde(default)]
+        fov: Option<f64>,
+        /// Direction of the top of the image, in degrees clockwise from
+        /// north. Defaults to the GPS image direction, or north.
+        #[serde(default)]
+        heading: Option<f64>,
+    },
+    /// Row-major matrix from homogeneous pixel coordinates to longitudes and
+    /// latitudes.
+    Homography { matrix: [[f64; 3]; 3] },
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Projection::Nadir {
+            height: None,
+            ground_elevation: 0.,
+            fov: None,
+            heading: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Geometry {
+    /// The center of the box, or its bottom center with a homography, where
+    /// upright objects touch the ground.
+    #[default]
+    Point,
+    /// The box.
+    Polygon,
+}
+
+impl Projection {
+    /// Returns the homography from the pixels of `frame` to longitudes and
+    /// latitudes.
+    pub fn homography(&self, frame: &schema::Frame) -> Result<[[f64; 3]; 3], String> {
+        let (height, ground_elevation, fov, heading) = match *self {
+            Projection::Homography { matrix } => return Ok(matrix),
+            Projection::Nadir {
+                height,
+                ground_elevation,
+                fov,
+                heading,
+            } => (height, ground_elevation, fov, heading),
+        };
+        let metadata = &frame.metadata;
+        let gps = metadata.gps.as_ref().ok_or("no GPS position in the EXIF")?;
+        let height = match (height, gps.altitude) {
+            (Some(height), _) => height,
+            (None, Some(altitude)) => altitude - ground_elevation,
+            (None, None) => return Err("no GPS altitude in the EXIF, set height".to_owned()),
+        };
+        if height <= 0. {
+            return Err(format!("the camera is {} m above the ground", height));
+        }
+        let fov = match (fov, metadata.focal_length_35mm) {
+            (Some(fov), _) => fov.to_radians(),
+            (None, Some(focal_length)) => 2. * (18. / focal_length).atan(),
+            (None, None) => return Err("no focal length in the EXIF, set fov".to_owned()),
+        };
+        let (sin, cos) = heading
+            .or(gps.direction)
+            .unwrap_or(0.)
+            .to_radians()
+            .sin_cos();
+
+        // Meters east and north of the camera, with y pointing down.
+        let scale = 2. * height * (fov / 2.).tan() / f64::from(frame.width);
+        let east = [scale * cos, -scale * sin];
+        let north = [-scale * sin, -scale * cos];
+        let (cx, cy) = (f64::from(frame.width) / 2., f64::from(frame.height) / 2.);
+        let latitude_per_meter = 180. / PI / EARTH_RADIUS;
+        let longitude_per_meter = latitude_per_meter / gps.latitude.to_radians().cos();
+        Ok([
+            [
+                longitude_per_meter * east[0],
+                longitude_per_meter * east[1],
+                gps.longitude - longitude_per_meter * (east[0] * cx + east[1] * cy),
+            ],
+            [
+                latitude_per_meter * north[0],
+                latitude_per_meter * north[1],
+                gps.latitude - latitude_per_meter * (north[0] * cx + north[1] * cy),
+            ],
+            [0., 0., 1.],
+        ])
+    }
+}
+
+/// Returns a GeoJSON feature per detection of `frame`.
+pub fn features(
+    frame: &schema::Frame,
+    projection: &Projection,
+    geometry: Geometry,
+) -> Result<Vec<serde_json::Value>, String> {
+    let h = projection.homography(frame)?;
+    let bottom_anchored = match *projection {
+        Projection::Homography { .. } => true,
+        Projection::Nadir { .. } => false,
+    };
+    Ok(frame
+        .detections
+        .iter()
+        .map(|d| {
+            let (top, left, bottom, right) = (
+                f64::from(d.top),
+                f64::from(d.left),
+                f64::from(d.bottom),
+                f64::from(d.right),
+            );
+            let geometry = match geometry {
+                Geometry::Point => {
+                    let y = if bottom_anchored {
+                        bottom
+                    } else {
+                        (top + bottom) / 2.
+                    };
+                    json!({
+                        "type": "Point",
+                        "coordinates": apply(&h, (left + right) / 2., y),
+                    })
+                }
+                Geometry::Polygon => json!({
+                    "type": "Polygon",
+                    "coordinates": [[
+                        apply(&h, left, top),
+                        apply(&h, left, bottom),
+                        apply(&h, right, bottom),
+                        apply(&h, right, top),
+                        apply(&h, left, top),
+                    ]],
+                }),
+            };
+            json!({
+                "type": "Feature",
+                "geometry": geometry,
+                "properties": {
+                    "id": d.id,
+                    "label": d.label,
+                    "score": d.score,
+                    "track": d.track,
+                    "source": frame.source,
+                    "model": frame.model,
+                    "time": frame.time,
+                    "taken": frame.metadata.taken,
+                },
+            })
+        })
+        .collect())
+}
+
+fn apply(h: &[[f64; 3]; 3], x: f64, y: f64) -> [f64; 2] {
+    let w = h[2][0] * x + h[2][1] * y + h[2][2];
+    [
+        (h[0][0] * x + h[0][1] * y + h[0][2]) / w,
+        (h[1][0] * x + h[1][1] * y + h[1][2]) / w,
+    ]
+}
@@ -0,0 +1,111 @@
+//! Unary calls of the gRPC protocol over HTTP/2 (see `h2`), for `remote`.
+//! Messages are protobuf bytes (see `proto`) and are never compressed.
+
+use std::io;
+
+use h2;
+use http;
+
+/// The codes of the statuses mapped to an `io::ErrorKind`.
+const NOT_FOUND: u32 = 5;
+const DEADLINE_EXCEEDED: u32 = 4;
+const UNAVAILABLE: u32 = 14;
+
+/// Calls `method` (`/package.Service/Method`) of the server at `url` with
+/// `request`, returning the response message, of at most `max_response`
+/// bytes.
+pub fn call(
+    url: &http::Url,
+    method: &str,
+    request: &[u8],
+    max_response: usize,
+) -> io::Result<Vec<u8>> {
+    let mut url = url.clone();
+    url.path = method.to_owned();
+    // the message with its prefix: not compressed, and its length
+    let mut body = Vec::with_capacity(5 + request.len());
+    body.push(0);
+    body.extend_from_slice(&(request.len() as u32).to_be_bytes());
+    body.extend_from_slice(request);
+    let headers = [("content-type", "application/grpc"), ("te", "trailers")];
+    let response = h2::send("POST", &url, &headers, &body, max_response + 5)?;
+    let failed = |kind, message: String| io::Error::new(kind, format!("{}: {}", method, message));
+    let status = response.header(":status").unwrap_or("");
+    if status != "200" {
+        return Err(failed(
+            io::ErrorKind::InvalidData,
+            format!("HTTP status {}", status),
+        ));
+    }
+    // in the trailers, or in the headers of a response without a body
+    let code: u32 = response
+        .header("grpc-status")
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| failed(io::ErrorKind::InvalidData, "no grpc-status".to_owned()))?;
+    if code != 0 {
+        let kind = match code {
+            NOT_FOUND => io::ErrorKind::NotFound,
+            DEADLINE_EXCEEDED => io::ErrorKind::TimedOut,
+            UNAVAILABLE => io::ErrorKind::ConnectionRefused,
+            _ => io::ErrorKind::InvalidData,
+        };
+        let message = percent_decode(response.header("grpc-message").unwrap_or(""));
+        return Err(failed(kind, format!("status {} {}", code, message)));
+    }
+    message(response.body).map_err(|e| failed(io::ErrorKind::InvalidData, e.to_owned()))
+}
+
+/// The single message of a response body.
+fn message(mut body: Vec<u8>) -> Result<Vec<u8>, &'static str> {
+    if body.len() < 5 {
+        return Err("no message in the response");
+    }
+    if body[0] != 0 {
+        return Err("compressed message");
+    }
+    let len = u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize;
+    if body.len() != 5 + len {
+        return Err("malformed message length");
+    }
+    Ok(body.split_off(5))
+}
+
+/// Decodes the percent-encoding of `grpc-message`.
+fn percent_decode(s: &str) -> String {
+    let s = s.as_bytes();
+    let mut decoded = Vec::with_capacity(s.len());
+    let mut i = 0;
+    while i < s.len() {
+        let hex = s
+            .get(i + 1..i + 3)
+            .and_then(|h| ::std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match hex {
+            Some(v) if s[i] == b'%' => {
+                decoded.push(v);
+                i += 3;
+            }
+            _ => {
+                decoded.push(s[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_messages() {
+        assert_eq!(message(vec![0, 0, 0, 0, 2, 8, 1]).unwrap(), [8, 1]);
+        assert!(message(vec![0, 0, 0, 0, 3, 8, 1]).is_err());
+        assert!(message(vec![1, 0, 0, 0, 0]).is_err());
+        assert_eq!(
+            percent_decode("no model %22yolo%22%e2%9c%93 100%"),
+            "no model \"yolo\"✓ 100%"
+        );
+    }
+}
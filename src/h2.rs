@@ -0,0 +1,482 @@
+//! Minimal HTTP/2 client (RFC 9113), over cleartext TCP with prior knowledge
+//! (h2c), for `grpc`. As with `http`, each request has its own connection.
+//!
+//! The flow control of both directions is honored: the body is sent as the
+//! windows of the server allow, and the data received is acknowledged as it
+//! arrives. Server push is disabled.
+
+use std::cmp;
+use std::io;
+use std::mem;
+use std::time;
+
+use std::io::{Read, Write};
+
+use hpack;
+use http;
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY: u8 = 0x20;
+
+const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+
+/// The initial window and the frame size of both sides, until changed.
+const DEFAULT_WINDOW: i64 = 65_535;
+const DEFAULT_FRAME_SIZE: usize = 16_384;
+const MAX_WINDOW: i64 = (1 << 31) - 1;
+const HEADER_TABLE_SIZE: usize = 4096;
+
+const TIMEOUT: time::Duration = time::Duration::from_secs(30);
+
+/// The only stream of a connection.
+const STREAM: u32 = 1;
+
+#[derive(Default)]
+pub struct Response {
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// The headers following the body, if any.
+    pub trailers: Vec<(String, String)>,
+}
+
+impl Response {
+    /// The value of `name` in the headers or the trailers.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .chain(self.trailers.iter())
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Sends a request and reads the whole response. Header names must be
+/// lowercase. Bodies longer than `max_body` are errors.
+pub fn send(
+    method: &str,
+    url: &http::Url,
+    headers: &[(&str, &str)],
+    body: &[u8],
+    max_body: usize,
+) -> io::Result<Response> {
+    let stream = http::connect(url, Some(TIMEOUT))?;
+    let authority = if url.host.contains(':') {
+        format!("[{}]:{}", url.host, url.port)
+    } else {
+        format!("{}:{}", url.host, url.port)
+    };
+    let scheme = if url.tls { "https" } else { "http" };
+    let mut all = vec![
+        (":method", method),
+        (":scheme", scheme),
+        (":path", url.path.as_str()),
+        (":authority", authority.as_str()),
+    ];
+    all.extend_from_slice(headers);
+    Connection::new(stream).request(&all, body, max_body)
+}
+
+struct Frame {
+    kind: u8,
+    flags: u8,
+    stream: u32,
+    payload: Vec<u8>,
+}
+
+struct Connection<S> {
+    stream: S,
+    decoder: hpack::Decoder,
+    /// The largest frame the server accepts.
+    max_frame_size: usize,
+    initial_window: i64,
+    /// How much data the server accepts on the connection and on the stream.
+    connection_window: i64,
+    stream_window: i64,
+    /// The response so far, complete once `ended`.
+    response: Response,
+    ended: bool,
+}
+
+impl<S> Connection<S>
+where
+    S: Read + Write,
+{
+    fn new(stream: S) -> Self {
+        Self {
+            stream,
+            decoder: hpack::Decoder::new(HEADER_TABLE_SIZE),
+            max_frame_size: DEFAULT_FRAME_SIZE,
+            initial_window: DEFAULT_WINDOW,
+            connection_window: DEFAULT_WINDOW,
+            stream_window: DEFAULT_WINDOW,
+            response: Response::default(),
+            ended: false,
+        }
+    }
+
+    fn request(
+        &mut self,
+        headers: &[(&str, &str)],
+        body: &[u8],
+        max_body: usize,
+    ) -> io::Result<Response> {
+        self.stream.write_all(PREFACE)?;
+        let mut settings = Vec::new();
+        settings.extend_from_slice(&SETTINGS_ENABLE_PUSH.to_be_bytes());
+        settings.extend_from_slice(&0u32.to_be_bytes());
+        self.write_frame(SETTINGS, 0, 0, &settings)?;
+
+        // the header block, split into frames
+        let block = hpack::encode(headers);
+        let mut chunks = block.chunks(self.max_frame_size).peekable();
+        let mut kind = HEADERS;
+        let mut flags = if body.is_empty() { END_STREAM } else { 0 };
+        while let Some(chunk) = chunks.next() {
+            if chunks.peek().is_none() {
+                flags |= END_HEADERS;
+            }
+            self.write_frame(kind, flags, STREAM, chunk)?;
+            kind = CONTINUATION;
+            flags = 0;
+        }
+
+        let mut sent = 0;
+        // a server may answer, e.g. with an error, before the end of the body
+        while sent < body.len() && !self.ended {
+            let window = cmp::min(self.connection_window, self.stream_window);
+            let len = cmp::min(body.len() - sent, self.max_frame_size);
+            let len = cmp::min(len as i64, window);
+            if len <= 0 {
+                self.receive(max_body)?;
+                continue;
+            }
+            let len = len as usize;
+            let flags = if sent + len == body.len() {
+                END_STREAM
+            } else {
+                0
+            };
+            self.write_frame(DATA, flags, STREAM, &body[sent..sent + len])?;
+            sent += len;
+            self.connection_window -= len as i64;
+            self.stream_window -= len as i64;
+        }
+        while !self.ended {
+            self.receive(max_body)?;
+        }
+        // the connection closes with the stream
+        let _ = self.write_frame(GOAWAY, 0, 0, &[0; 8]);
+        if self.response.headers.is_empty() {
+            return Err(invalid_data("response without headers".to_owned()));
+        }
+        Ok(mem::take(&mut self.response))
+    }
+
+    /// Reads and handles a frame from the server.
+    fn receive(&mut self, max_body: usize) -> io::Result<()> {
+        let frame = self.read_frame()?;
+        if frame.stream != 0 && frame.stream != STREAM {
+            return Err(invalid_data(format!(
+                "frame on the unknown stream {}",
+                frame.stream
+            )));
+        }
+        match frame.kind {
+            DATA => {
+                let len = frame.payload.len();
+                let data = unpad(&frame)?;
+                if self.response.body.len() + data.len() > max_body {
+                    return Err(invalid_data(format!("body larger than {} bytes", max_body)));
+                }
+                self.response.body.extend_from_slice(data);
+                self.end(&frame);
+                // the padding counts in the windows too
+                if len > 0 {
+                    let increment = (len as u32).to_be_bytes();
+                    self.write_frame(WINDOW_UPDATE, 0, 0, &increment)?;
+                    if !self.ended {
+                        self.write_frame(WINDOW_UPDATE, 0, STREAM, &increment)?;
+                    }
+                }
+            }
+            HEADERS => {
+                let mut fragment = unpad(&frame)?;
+                if frame.flags & PRIORITY != 0 {
+                    fragment = fragment
+                        .get(5..)
+                        .ok_or_else(|| invalid_data("malformed HEADERS frame".to_owned()))?;
+                }
+                let mut block = fragment.to_vec();
+                let mut flags = frame.flags;
+                while flags & END_HEADERS == 0 {
+                    let continuation = self.read_frame()?;
+                    if continuation.kind != CONTINUATION || continuation.stream != frame.stream {
+                        return Err(invalid_data("expected a CONTINUATION frame".to_owned()));
+                    }
+                    block.extend_from_slice(&continuation.payload);
+                    flags = continuation.flags;
+                }
+                let headers = self.decoder.decode(&block)?;
+                if self.response.headers.is_empty() {
+                    self.response.headers = headers;
+                } else {
+                    self.response.trailers = headers;
+                }
+                self.end(&frame);
+            }
+            RST_STREAM => {
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionReset,
+                    format!("stream reset with error code {}", error_code(&frame)?),
+                ));
+            }
+            SETTINGS if frame.flags & ACK == 0 => {
+                if frame.payload.len() % 6 != 0 {
+                    return Err(invalid_data("malformed SETTINGS frame".to_owned()));
+                }
+                for setting in frame.payload.chunks(6) {
+                    let id = u16::from_be_bytes([setting[0], setting[1]]);
+                    let value =
+                        u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+                    self.set(id, value)?;
+                }
+                self.write_frame(SETTINGS, ACK, 0, &[])?;
+            }
+            PUSH_PROMISE => return Err(invalid_data("PUSH_PROMISE, push is disabled".to_owned())),
+            PING if frame.flags & ACK == 0 => self.write_frame(PING, ACK, 0, &frame.payload)?,
+            GOAWAY => {
+                let code = u32::from_be_bytes(frame_bytes(&frame, 4, 8)?);
+                let last = u32::from_be_bytes(frame_bytes(&frame, 0, 4)?) & 0x7fff_ffff;
+                // a graceful shutdown still completes the stream
+                if code != 0 || last < STREAM {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        format!(
+                            "connection closed with error code {}: {}",
+                            code,
+                            String::from_utf8_lossy(&frame.payload[8..])
+                        ),
+                    ));
+                }
+            }
+            WINDOW_UPDATE => {
+                let increment =
+                    i64::from(u32::from_be_bytes(frame_bytes(&frame, 0, 4)?) & 0x7fff_ffff);
+                if increment == 0 {
+                    return Err(invalid_data("WINDOW_UPDATE of 0".to_owned()));
+                }
+                let window = if frame.stream == 0 {
+                    &mut self.connection_window
+                } else {
+                    &mut self.stream_window
+                };
+                *window += increment;
+                if *window > MAX_WINDOW {
+                    return Err(invalid_data("flow-control window overflow".to_owned()));
+                }
+            }
+            CONTINUATION => return Err(invalid_data("unexpected CONTINUATION frame".to_owned())),
+            // PRIORITY, acknowledgements and unknown frames
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, id: u16, value: u32) -> io::Result<()> {
+        match id {
+            SETTINGS_INITIAL_WINDOW_SIZE => {
+                let value = i64::from(value);
+                if value > MAX_WINDOW {
+                    return Err(invalid_data("initial window too large".to_owned()));
+                }
+                // the window of the open stream moves by the difference
+                self.stream_window += value - self.initial_window;
+                self.initial_window = value;
+            }
+            SETTINGS_MAX_FRAME_SIZE => {
+                if value < DEFAULT_FRAME_SIZE as u32 || value >= 1 << 24 {
+                    return Err(invalid_data("invalid maximum frame size".to_owned()));
+                }
+                self.max_frame_size = value as usize;
+            }
+            // the encoder indexes nothing, whatever the table size of the server
+            SETTINGS_HEADER_TABLE_SIZE => {}
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn end(&mut self, frame: &Frame) {
+        if frame.flags & END_STREAM != 0 {
+            self.ended = true;
+        }
+    }
+
+    fn read_frame(&mut self) -> io::Result<Frame> {
+        let mut header = [0; 9];
+        self.stream.read_exact(&mut header)?;
+        let len = (header[0] as usize) << 16 | (header[1] as usize) << 8 | header[2] as usize;
+        // the frame size of the client is never raised
+        if len > DEFAULT_FRAME_SIZE {
+            return Err(invalid_data(format!("frame of {} bytes", len)));
+        }
+        let mut payload = vec![0; len];
+        self.stream.read_exact(&mut payload)?;
+        Ok(Frame {
+            kind: header[3],
+            flags: header[4],
+            stream: u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff,
+            payload,
+        })
+    }
+
+    fn write_frame(&mut self, kind: u8, flags: u8, stream: u32, payload: &[u8]) -> io::Result<()> {
+        let len = (payload.len() as u32).to_be_bytes();
+        let mut frame = Vec::with_capacity(9 + payload.len());
+        frame.extend_from_slice(&len[1..]);
+        frame.push(kind);
+        frame.push(flags);
+        frame.extend_from_slice(&stream.to_be_bytes());
+        frame.extend_from_slice(payload);
+        self.stream.write_all(&frame)
+    }
+}
+
+/// The payload of a DATA or HEADERS frame, without its padding.
+fn unpad(frame: &Frame) -> io::Result<&[u8]> {
+    if frame.flags & PADDED == 0 {
+        return Ok(&frame.payload);
+    }
+    let padding = *frame.payload.first().unwrap_or(&0) as usize;
+    if frame.payload.is_empty() || padding >= frame.payload.len() {
+        return Err(invalid_data("malformed padding".to_owned()));
+    }
+    Ok(&frame.payload[1..frame.payload.len() - padding])
+}
+
+fn frame_bytes(frame: &Frame, start: usize, end: usize) -> io::Result<[u8; 4]> {
+    match frame.payload.get(start..end) {
+        Some(b) if b.len() == 4 => Ok([b[0], b[1], b[2], b[3]]),
+        _ => Err(invalid_data("truncated frame".to_owned())),
+    }
+}
+
+fn error_code(frame: &Frame) -> io::Result<u32> {
+    frame_bytes(frame, 0, 4).map(u32::from_be_bytes)
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A server answering from a script, and recording the frames of the
+    /// client.
+    struct Script {
+        server: io::Cursor<Vec<u8>>,
+        client: Vec<u8>,
+    }
+
+    impl Read for Script {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.server.read(buf)
+        }
+    }
+
+    impl Write for Script {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.client.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn frame(kind: u8, flags: u8, stream: u32, payload: &[u8]) -> Vec<u8> {
+        let mut connection = Connection::new(Script {
+            server: io::Cursor::new(Vec::new()),
+            client: Vec::new(),
+        });
+        connection
+            .write_frame(kind, flags, stream, payload)
+            .unwrap();
+        connection.stream.client
+    }
+
+    #[test]
+    fn follows_flow_control() {
+        let mut server = Vec::new();
+        server.extend(frame(SETTINGS, 0, 0, &[0, 5, 0, 0, 0x80, 0]));
+        // once the body fills the initial windows
+        server.extend(frame(WINDOW_UPDATE, 0, 0, &[0, 0, 0x27, 0x10]));
+        server.extend(frame(WINDOW_UPDATE, 0, 1, &[0, 0, 0x27, 0x10]));
+        let headers = hpack::encode(&[(":status", "200"), ("content-type", "application/grpc")]);
+        server.extend(frame(HEADERS, END_HEADERS, 1, &headers));
+        server.extend(frame(DATA, PADDED, 1, &[2, b'o', b'k', 0, 0]));
+        let trailers = hpack::encode(&[("grpc-status", "0")]);
+        server.extend(frame(HEADERS, END_HEADERS | END_STREAM, 1, &trailers));
+        let mut connection = Connection::new(Script {
+            server: io::Cursor::new(server),
+            client: Vec::new(),
+        });
+        let response = connection
+            .request(&[(":method", "POST")], &[0; 70_000], 10)
+            .unwrap();
+        assert_eq!(response.header(":status"), Some("200"));
+        assert_eq!(response.header("grpc-status"), Some("0"));
+        assert_eq!(response.body, b"ok");
+
+        let client = &connection.stream.client;
+        assert!(client.starts_with(PREFACE));
+        let mut frames = Vec::new();
+        let mut r = &client[PREFACE.len()..];
+        while !r.is_empty() {
+            let len = (r[1] as usize) << 8 | r[2] as usize;
+            frames.push((r[3], r[4], r[8], r[9..9 + len].to_vec()));
+            r = &r[9 + len..];
+        }
+        let data: Vec<_> = frames
+            .iter()
+            .filter(|f| f.0 == DATA)
+            .map(|f| (f.3.len(), f.1))
+            .collect();
+        // the windows of 65535 bytes, then the rest in a larger frame
+        assert_eq!(
+            data,
+            [
+                (16384, 0),
+                (16384, 0),
+                (16384, 0),
+                (16383, 0),
+                (4465, END_STREAM)
+            ]
+        );
+        assert!(frames.contains(&(SETTINGS, ACK, 0, Vec::new())));
+        // the padded DATA frame, acknowledged on the connection and the stream
+        for &stream in &[0, 1] {
+            assert!(frames.contains(&(WINDOW_UPDATE, 0, stream, vec![0, 0, 0, 5])));
+        }
+    }
+}
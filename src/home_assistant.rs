@@ -0,0 +1,157 @@
+//! Publishes detection states to Home Assistant over MQTT.
+//!
+//! Every label gets a `binary_sensor` (present or not) and a `sensor` (number
+//! of boxes), and the node gets a `camera` showing the last frame with
+//! detections. All of them are announced with MQTT discovery, so they show up
+//! in Home Assistant without manual configuration.
+
+use image;
+use serde_json;
+use std::io;
+use std::net;
+
+use bb;
+use mqtt;
+
+const DISCOVERY_PREFIX: &str = "homeassistant";
+
+pub struct HomeAssistant {
+    client: mqtt::Client,
+    node_id: String,
+    label_names: Vec<String>,
+}
+
+impl HomeAssistant {
+    pub fn connect<A>(addr: A, node_id: &str, label_names: &[String]) -> io::Result<Self>
+    where
+        A: net::ToSocketAddrs,
+    {
+        let availability = format!("menoh-yolo/{}/availability", node_id);
+        let client = mqtt::Client::connect(
+            addr,
+            &format!("menoh-yolo-{}", node_id),
+            Some(mqtt::Will {
+                topic: &availability,
+                message: b"offline",
+                retain: true,
+            }),
+        )?;
+        let mut ha = Self {
+            client,
+            node_id: node_id.to_owned(),
+            label_names: label_names.to_owned(),
+        };
+        ha.announce()?;
+        ha.client.publish(&availability, b"online", true)?;
+        Ok(ha)
+    }
+
+    pub fn publish(&mut self, bbox: &[bb::Bb], img: &image::DynamicImage) -> io::Result<()> {
+        let mut counts = vec![0; self.label_names.len()];
+        for bb in bbox.iter() {
+            counts[bb.label] += 1;
+        }
+
+        for (lb, count) in counts.iter().enumerate() {
+            let base = self.topic(&self.label_names[lb]);
+            self.client.publish(
+                &format!("{}/state", base),
+                if *count > 0 { b"ON" } else { b"OFF" },
+                true,
+            )?;
+            self.client.publish(
+                &format!("{}/count", base),
+                count.to_string().as_bytes(),
+                true,
+            )?;
+        }
+
+        if !bbox.is_empty() {
+            let img = img.to_rgb();
+            let mut jpeg = Vec::new();
+            image::jpeg::JPEGEncoder::new(&mut jpeg).encode(
+                &img,
+                img.width(),
+                img.height(),
+                image::ColorType::RGB(8),
+            )?;
+            let topic = format!("menoh-yolo/{}/image", self.node_id);
+            self.client.publish(&topic, &jpeg, true)?;
+        }
+
+        Ok(())
+    }
+
+    fn announce(&mut self) -> io::Result<()> {
+        let device = json!({
+            "identifiers": [format!("menoh-yolo-{}", self.node_id)],
+            "name": self.node_id,
+            "model": "menoh-yolo",
+        });
+        let availability_topic = format!("menoh-yolo/{}/availability", self.node_id);
+
+        for label in self.label_names.clone().iter() {
+            let object_id = object_id(label);
+            let base = self.topic(label);
+            self.announce_entity(
+                "binary_sensor",
+                &object_id,
+                &json!({
+                    "name": format!("{} detected", label),
+                    "unique_id": format!("{}_{}", self.node_id, object_id),
+                    "state_topic": format!("{}/state", base),
+                    "device_class": "occupancy",
+                    "availability_topic": availability_topic,
+                    "device": device,
+                }),
+            )?;
+            self.announce_entity(
+                "sensor",
+                &format!("{}_count", object_id),
+                &json!({
+                    "name": format!("{} count", label),
+                    "unique_id": format!("{}_{}_count", self.node_id, object_id),
+                    "state_topic": format!("{}/count", base),
+                    "availability_topic": availability_topic,
+                    "device": device,
+                }),
+            )?;
+        }
+        self.announce_entity(
+            "camera",
+            "last_detection",
+            &json!({
+                "name": "last detection",
+                "unique_id": format!("{}_last_detection", self.node_id),
+                "topic": format!("menoh-yolo/{}/image", self.node_id),
+                "availability_topic": availability_topic,
+                "device": device,
+            }),
+        )
+    }
+
+    fn announce_entity(
+        &mut self,
+        component: &str,
+        object_id: &str,
+        config: &serde_json::Value,
+    ) -> io::Result<()> {
+        let topic = format!(
+            "{}/{}/{}/{}/config",
+            DISCOVERY_PREFIX, component, self.node_id, object_id
+        );
+        self.client
+            .publish(&topic, config.to_string().as_bytes(), true)
+    }
+
+    fn topic(&self, label: &str) -> String {
+        format!("menoh-yolo/{}/{}", self.node_id, object_id(label))
+    }
+}
+
+fn object_id(label: &str) -> String {
+    label
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
@@ -0,0 +1,292 @@
+//! Runs shell commands when detections match a rule, for simple local
+//! automation (e.g. `aplay alert.wav`).
+//!
+//! In the command, `{class}`, `{score}`, `{top}`, `{left}`, `{bottom}` and
+//! `{right}` are replaced with the shell-quoted values of the best-scoring
+//! matching box, `{count}` with the number of matching boxes, `{zone}` with
+//! the name of the zone of the hook, and `{summary}` with a sentence
+//! describing the boxes (see `summary`). The values are not expanded again.
+//!
+//! A hook fires again once its cooldown is over, and, with `rearm` of type
+//! `clear`, once nothing has matched for a while, so that an ongoing event
+//! fires once however long it lasts.
+
+use std::cmp;
+use std::io;
+use std::process;
+use std::time;
+
+use bb;
+use rank;
+use summary;
+
+#[derive(Clone, Deserialize)]
+pub struct Hook {
+    pub classes: Vec<String>,
+    #[serde(default = "default_threshold")]
+    pub threshold: f32,
+    pub command: String,
+    /// Seconds during which the hook does not fire again.
+    #[serde(default = "default_cooldown")]
+    pub cooldown: f32,
+    #[serde(default)]
+    pub rearm: Rearm,
+    /// Keeps the cooldown and the re-arm state per class, so that an event
+    /// of a class does not mute the others.
+    #[serde(default)]
+    pub per_class: bool,
+    /// Limits the hook to the boxes whose center is in the zone. The state is
+    /// kept per hook, so that hooks of different zones do not mute each other.
+    #[serde(default)]
+    pub zone: Option<rank::Zone>,
+}
+
+/// When a hook that fired can fire again, besides the cooldown.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Rearm {
+    /// As soon as the cooldown is over.
+    #[default]
+    Cooldown,
+    /// After `after` seconds without matching boxes.
+    Clear {
+        #[serde(default)]
+        after: f32,
+    },
+}
+
+fn default_threshold() -> f32 {
+    0.5
+}
+
+fn default_cooldown() -> f32 {
+    10.
+}
+
+struct State {
+    hook: Hook,
+    /// Whether each label matches the hook.
+    labels: Vec<bool>,
+    cooldown: time::Duration,
+    clear: Option<time::Duration>,
+    /// Per label with `per_class`, or a single one.
+    arms: Vec<Arm>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Arm {
+    /// When the hook last fired.
+    fired: Option<time::Instant>,
+    /// When boxes last matched.
+    seen: Option<time::Instant>,
+}
+
+impl Arm {
+    fn ready(
+        &self,
+        now: time::Instant,
+        cooldown: time::Duration,
+        clear: Option<time::Duration>,
+    ) -> bool {
+        let elapsed = |t: Option<time::Instant>, d| t.is_none_or(|t| now.duration_since(t) >= d);
+        elapsed(self.fired, cooldown) && clear.is_none_or(|clear| elapsed(self.seen, clear))
+    }
+}
+
+pub struct Hooks {
+    states: Vec<State>,
+    label_names: Vec<String>,
+    summary: summary::Template,
+    /// With the index of their hook.
+    children: Vec<(usize, process::Child)>,
+}
+
+impl Hooks {
+    pub fn new(hooks: &[Hook], label_names: &[String]) -> Self {
+        Self {
+            states: hooks
+                .iter()
+                .map(|h| State {
+                    hook: h.clone(),
+                    labels: label_names.iter().map(|l| h.classes.contains(l)).collect(),
+                    cooldown: duration(h.cooldown),
+                    clear: match h.rearm {
+                        Rearm::Cooldown => None,
+                        Rearm::Clear { after } => Some(duration(after)),
+                    },
+                    arms: vec![Arm::default(); if h.per_class { label_names.len() } else { 1 }],
+                })
+                .collect(),
+            label_names: label_names.to_owned(),
+            summary: summary::Template::default(),
+            children: Vec::new(),
+        }
+    }
+
+    /// Describes the matching boxes in `{summary}` with `template`.
+    pub fn with_summary(mut self, template: &summary::Template) -> Self {
+        self.summary = template.clone();
+        self
+    }
+
+    /// Starts the commands of the hooks matched by `bbox`, in an image of
+    /// `height` by `width`, without waiting for them.
+    pub fn fire(&mut self, bbox: &[bb::Bb], height: u32, width: u32) -> io::Result<()> {
+        self.reap();
+        let now = time::Instant::now();
+        let (label_names, summary) = (&self.label_names, &self.summary);
+        for (hook, state) in self.states.iter_mut().enumerate() {
+            let zone = state.hook.zone.as_ref();
+            let matched: Vec<_> = bb::above(bbox, state.hook.threshold)
+                .filter(|bb| {
+                    state.labels[bb.label] && zone.is_none_or(|z| z.contains_bb(bb, height, width))
+                })
+                .collect();
+            for (i, arm) in state.arms.iter_mut().enumerate() {
+                let matched: Vec<_> = if state.hook.per_class {
+                    matched.iter().filter(|bb| bb.label == i).cloned().collect()
+                } else {
+                    matched.clone()
+                };
+                let best = match matched.iter().max_by(|a, b| {
+                    a.score
+                        .partial_cmp(&b.score)
+                        .unwrap_or(cmp::Ordering::Equal)
+                }) {
+                    Some(best) => best,
+                    None => continue,
+                };
+                let ready = arm.ready(now, state.cooldown, state.clear);
+                arm.seen = Some(now);
+                if !ready {
+                    continue;
+                }
+                arm.fired = Some(now);
+                let command = expand(&state.hook.command, |name| match name {
+                    "class" => Some(quote(&label_names[best.label])),
+                    "score" => Some(quote(&best.score.to_string())),
+                    "top" => Some(quote(&best.top.to_string())),
+                    "left" => Some(quote(&best.left.to_string())),
+                    "bottom" => Some(quote(&best.bottom.to_string())),
+                    "right" => Some(quote(&best.right.to_string())),
+                    "count" => Some(matched.len().to_string()),
+                    "zone" => Some(quote(
+                        zone.and_then(|z| z.name.as_deref()).unwrap_or_default(),
+                    )),
+                    "summary" => Some(quote(
+                        &summary.describe(matched.iter().cloned(), label_names),
+                    )),
+                    _ => None,
+                });
+                self.children.push((hook, shell(&command).spawn()?));
+            }
+        }
+        Ok(())
+    }
+
+    /// The commands of the hooks, with the number of their commands still
+    /// running.
+    pub fn depths(&mut self) -> Vec<(&str, usize)> {
+        self.reap();
+        let mut depths: Vec<_> = self
+            .states
+            .iter()
+            .map(|s| (&s.hook.command[..], 0))
+            .collect();
+        for &(hook, _) in self.children.iter() {
+            depths[hook].1 += 1;
+        }
+        depths
+    }
+
+    /// Waits for the started commands to finish.
+    pub fn wait(&mut self) -> io::Result<()> {
+        for (_, mut child) in self.children.drain(..) {
+            child.wait()?;
+        }
+        Ok(())
+    }
+
+    /// Forgets the finished commands.
+    fn reap(&mut self) {
+        self.children
+            .retain_mut(|&mut (_, ref mut child)| matches!(child.try_wait(), Ok(None)));
+    }
+}
+
+fn duration(secs: f32) -> time::Duration {
+    time::Duration::from_millis((secs.max(0.) * 1000.) as u64)
+}
+
+/// `command` with each `{name}` replaced by `value(name)`, in a single pass
+/// so that the values, which may come from the labels, are not expanded
+/// again. Unknown names are kept.
+fn expand<F>(command: &str, mut value: F) -> String
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    let mut expanded = String::with_capacity(command.len());
+    let mut rest = command;
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let replaced = rest
+            .find('}')
+            .and_then(|end| value(&rest[1..end]).map(|v| (end, v)));
+        match replaced {
+            Some((end, v)) => {
+                expanded.push_str(&v);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                expanded.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+#[cfg(unix)]
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+#[cfg(not(unix))]
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+/// `command` run by the shell.
+#[cfg(unix)]
+pub fn shell(command: &str) -> process::Command {
+    let mut c = process::Command::new("sh");
+    c.arg("-c").arg(command);
+    c
+}
+
+#[cfg(not(unix))]
+pub fn shell(command: &str) -> process::Command {
+    let mut c = process::Command::new("cmd");
+    c.arg("/C").arg(command);
+    c
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_once() {
+        let value = |name: &str| match name {
+            "class" => Some("{count}".to_owned()),
+            "count" => Some("2".to_owned()),
+            _ => None,
+        };
+        assert_eq!(
+            expand("{{class}} {count} {other} {", value),
+            "{{count}} 2 {other} {"
+        );
+    }
+}
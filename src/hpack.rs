@@ -0,0 +1,527 @@
+//! HPACK, the header compression of HTTP/2 (RFC 7541), for `h2`.
+//!
+//! Headers are encoded as literals without indexing nor Huffman coding, which
+//! every decoder accepts and which keeps no state; decoding supports the
+//! whole format.
+
+use std::collections;
+use std::io;
+
+/// The entries of the static table, from index 1.
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// The bit lengths of the Huffman codes of the octets, then of EOS. The
+/// codes are canonical: those of a length follow each other in the order of
+/// their symbols, after the shorter ones (RFC 7541 Appendix B).
+#[rustfmt::skip]
+const LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28,
+    28, 28, 28, 28, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 28,
+    6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6,
+    5, 5, 5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10,
+    13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6,
+    15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6, 6, 5,
+    6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28,
+    20, 22, 20, 20, 22, 22, 22, 23, 22, 23, 23, 23, 23, 23, 24, 23,
+    24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24,
+    22, 21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23,
+    21, 21, 22, 21, 23, 22, 23, 23, 20, 22, 22, 22, 23, 22, 22, 23,
+    26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25,
+    19, 21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27,
+    20, 24, 20, 21, 22, 21, 21, 23, 22, 22, 25, 25, 24, 24, 26, 23,
+    26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26,
+    30,
+];
+
+const MAX_LENGTH: usize = 30;
+const EOS: u16 = 256;
+
+/// The number of codes of each length.
+const COUNTS: [u16; MAX_LENGTH + 1] = counts();
+/// The symbols in the order of their codes.
+const SYMBOLS: [u16; 257] = symbols();
+
+const fn counts() -> [u16; MAX_LENGTH + 1] {
+    let mut counts = [0; MAX_LENGTH + 1];
+    let mut i = 0;
+    while i < LENGTHS.len() {
+        counts[LENGTHS[i] as usize] += 1;
+        i += 1;
+    }
+    counts
+}
+
+const fn symbols() -> [u16; 257] {
+    let mut symbols = [0; 257];
+    let mut n = 0;
+    let mut len = 1;
+    while len <= MAX_LENGTH {
+        let mut i = 0;
+        while i < LENGTHS.len() {
+            if LENGTHS[i] as usize == len {
+                symbols[n] = i as u16;
+                n += 1;
+            }
+            i += 1;
+        }
+        len += 1;
+    }
+    symbols
+}
+
+/// The size of an entry in the dynamic table.
+fn entry_size(name: &str, value: &str) -> usize {
+    name.len() + value.len() + 32
+}
+
+/// A decoder, with the dynamic table of the header blocks of a connection.
+pub struct Decoder {
+    table: collections::VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+    /// The largest `max_size` the encoder may set, `SETTINGS_HEADER_TABLE_SIZE`.
+    capacity: usize,
+}
+
+impl Decoder {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            table: collections::VecDeque::new(),
+            size: 0,
+            max_size: capacity,
+            capacity,
+        }
+    }
+
+    /// Decodes a header block, in order.
+    pub fn decode(&mut self, block: &[u8]) -> io::Result<Vec<(String, String)>> {
+        let mut headers = Vec::new();
+        let mut r = block;
+        while let Some(&first) = r.first() {
+            if first & 0x80 != 0 {
+                let index = integer(&mut r, 7)?;
+                let (name, value) = self.get(index)?;
+                headers.push((name.to_owned(), value.to_owned()));
+            } else if first & 0xe0 == 0x20 {
+                // allowed before the first header only
+                if !headers.is_empty() {
+                    return Err(invalid_data("misplaced dynamic table size update"));
+                }
+                let max_size = integer(&mut r, 5)?;
+                if max_size > self.capacity {
+                    return Err(invalid_data("dynamic table size update too large"));
+                }
+                self.max_size = max_size;
+                self.evict(0);
+            } else {
+                // incremental indexing, or not (never indexed is the same here)
+                let indexing = first & 0xc0 == 0x40;
+                let index = integer(&mut r, if indexing { 6 } else { 4 })?;
+                let name = match index {
+                    0 => string(&mut r)?,
+                    _ => self.get(index)?.0.to_owned(),
+                };
+                let value = string(&mut r)?;
+                if indexing {
+                    self.insert(name.clone(), value.clone());
+                }
+                headers.push((name, value));
+            }
+        }
+        Ok(headers)
+    }
+
+    fn get(&self, index: usize) -> io::Result<(&str, &str)> {
+        match index {
+            0 => Err(invalid_data("header index 0")),
+            1..=61 => Ok(STATIC_TABLE[index - 1]),
+            _ => self
+                .table
+                .get(index - 62)
+                .map(|(n, v)| (n.as_str(), v.as_str()))
+                .ok_or_else(|| invalid_data("header index out of the tables")),
+        }
+    }
+
+    fn insert(&mut self, name: String, value: String) {
+        let size = entry_size(&name, &value);
+        // an entry larger than the table empties it
+        self.evict(size);
+        if size <= self.max_size {
+            self.size += size;
+            self.table.push_front((name, value));
+        }
+    }
+
+    /// Evicts the oldest entries until `size` more bytes fit, or none is left.
+    fn evict(&mut self, size: usize) {
+        while self.size + size > self.max_size {
+            match self.table.pop_back() {
+                Some((name, value)) => self.size -= entry_size(&name, &value),
+                None => break,
+            }
+        }
+    }
+}
+
+/// Encodes a header block. Names must be lowercase.
+pub fn encode(headers: &[(&str, &str)]) -> Vec<u8> {
+    let mut block = Vec::new();
+    for &(name, value) in headers {
+        if let Some(i) = STATIC_TABLE.iter().position(|&h| h == (name, value)) {
+            write_integer(&mut block, 0x80, 7, i + 1);
+            continue;
+        }
+        match STATIC_TABLE.iter().position(|h| h.0 == name) {
+            Some(i) => write_integer(&mut block, 0, 4, i + 1),
+            None => {
+                block.push(0);
+                write_string(&mut block, name);
+            }
+        }
+        write_string(&mut block, value);
+    }
+    block
+}
+
+fn write_integer(block: &mut Vec<u8>, flags: u8, prefix: u32, mut v: usize) {
+    let max = (1 << prefix) - 1;
+    if v < max {
+        block.push(flags | v as u8);
+        return;
+    }
+    block.push(flags | max as u8);
+    v -= max;
+    while v >= 0x80 {
+        block.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    block.push(v as u8);
+}
+
+fn write_string(block: &mut Vec<u8>, s: &str) {
+    write_integer(block, 0, 7, s.len());
+    block.extend_from_slice(s.as_bytes());
+}
+
+/// Reads an integer with a `prefix`-bit prefix.
+fn integer(r: &mut &[u8], prefix: u32) -> io::Result<usize> {
+    let truncated = || invalid_data("truncated header block");
+    let (&first, rest) = r.split_first().ok_or_else(truncated)?;
+    *r = rest;
+    let max = (1 << prefix) - 1;
+    let mut v = first as usize & max;
+    if v < max {
+        return Ok(v);
+    }
+    let mut shift = 0;
+    loop {
+        let (&b, rest) = r.split_first().ok_or_else(truncated)?;
+        *r = rest;
+        if shift > 28 {
+            return Err(invalid_data("header integer too large"));
+        }
+        v += ((b & 0x7f) as usize) << shift;
+        shift += 7;
+        if b & 0x80 == 0 {
+            return Ok(v);
+        }
+    }
+}
+
+fn string(r: &mut &[u8]) -> io::Result<String> {
+    let huffman_coded = r.first().map(|&b| b & 0x80 != 0).unwrap_or(false);
+    let len = integer(r, 7)?;
+    if len > r.len() {
+        return Err(invalid_data("truncated header block"));
+    }
+    let (data, rest) = r.split_at(len);
+    *r = rest;
+    let data = if huffman_coded {
+        huffman(data)?
+    } else {
+        data.to_vec()
+    };
+    String::from_utf8(data).map_err(|_| invalid_data("header not in UTF-8"))
+}
+
+/// Decodes a Huffman-coded string.
+fn huffman(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut s = Vec::with_capacity(data.len() * 8 / 5);
+    // the bits read of a code, of `len` bits; the codes of this length start
+    // at `first`, whose symbol is `SYMBOLS[index]`
+    let (mut code, mut len, mut first, mut index) = (0u32, 0, 0u32, 0);
+    for &byte in data {
+        for bit in (0..8).rev() {
+            code = code << 1 | u32::from(byte >> bit & 1);
+            len += 1;
+            let count = u32::from(COUNTS[len]);
+            if code < first + count {
+                match SYMBOLS[index + (code - first) as usize] {
+                    EOS => return Err(invalid_data("EOS in a Huffman-coded string")),
+                    symbol => s.push(symbol as u8),
+                }
+                code = 0;
+                len = 0;
+                first = 0;
+                index = 0;
+            } else if len == MAX_LENGTH {
+                return Err(invalid_data("malformed Huffman code"));
+            } else {
+                index += count as usize;
+                first = (first + count) << 1;
+            }
+        }
+    }
+    // padded with the first bits of EOS, all ones
+    if len >= 8 || code != (1 << len) - 1 {
+        return Err(invalid_data("malformed Huffman padding"));
+    }
+    Ok(s)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        let digits: Vec<_> = s.chars().filter(|c| !c.is_whitespace()).collect();
+        digits
+            .chunks(2)
+            .map(|d| u8::from_str_radix(&d.iter().collect::<String>(), 16).unwrap())
+            .collect()
+    }
+
+    fn owned(headers: &[(&str, &str)]) -> Vec<(String, String)> {
+        headers
+            .iter()
+            .map(|&(n, v)| (n.to_owned(), v.to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn decodes_integers() {
+        // RFC 7541 C.1
+        assert_eq!(integer(&mut &[0xea][..], 5).unwrap(), 10);
+        assert_eq!(integer(&mut &[0x1f, 0x9a, 0x0a][..], 5).unwrap(), 1337);
+        assert_eq!(integer(&mut &[0x2a][..], 8).unwrap(), 42);
+        let mut block = Vec::new();
+        write_integer(&mut block, 0, 5, 1337);
+        assert_eq!(block, [0x1f, 0x9a, 0x0a]);
+    }
+
+    #[test]
+    fn encodes_without_indexing() {
+        // RFC 7541 C.2.2 and C.2.4
+        assert_eq!(
+            encode(&[(":path", "/sample/path")]),
+            hex("040c 2f73 616d 706c 652f 7061 7468")
+        );
+        assert_eq!(encode(&[(":method", "GET")]), [0x82]);
+        let headers = [
+            (":method", "POST"),
+            ("te", "trailers"),
+            ("content-type", "application/grpc"),
+        ];
+        assert_eq!(
+            Decoder::new(0).decode(&encode(&headers)).unwrap(),
+            owned(&headers)
+        );
+    }
+
+    #[test]
+    fn decodes_requests() {
+        // RFC 7541 C.3, then C.4 coded with Huffman
+        let requests = [
+            (
+                "8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d",
+                "8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff",
+                vec![
+                    (":method", "GET"),
+                    (":scheme", "http"),
+                    (":path", "/"),
+                    (":authority", "www.example.com"),
+                ],
+                57,
+            ),
+            (
+                "8286 84be 5808 6e6f 2d63 6163 6865",
+                "8286 84be 5886 a8eb 1064 9cbf",
+                vec![
+                    (":method", "GET"),
+                    (":scheme", "http"),
+                    (":path", "/"),
+                    (":authority", "www.example.com"),
+                    ("cache-control", "no-cache"),
+                ],
+                110,
+            ),
+            (
+                "8287 85bf 400a 6375 7374 6f6d 2d6b 6579 0c63 7573 746f 6d2d 7661 6c75 65",
+                "8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf",
+                vec![
+                    (":method", "GET"),
+                    (":scheme", "https"),
+                    (":path", "/index.html"),
+                    (":authority", "www.example.com"),
+                    ("custom-key", "custom-value"),
+                ],
+                164,
+            ),
+        ];
+        let mut plain = Decoder::new(4096);
+        let mut huffman = Decoder::new(4096);
+        for &(block, huffman_block, ref headers, size) in requests.iter() {
+            assert_eq!(plain.decode(&hex(block)).unwrap(), owned(headers));
+            assert_eq!(huffman.decode(&hex(huffman_block)).unwrap(), owned(headers));
+            assert_eq!((plain.size, huffman.size), (size, size));
+        }
+        assert_eq!(
+            plain.table[0],
+            ("custom-key".to_owned(), "custom-value".to_owned())
+        );
+    }
+
+    #[test]
+    fn decodes_responses() {
+        // RFC 7541 C.5, then C.6 coded with Huffman, evicting from a table of
+        // 256 bytes
+        let cookie = "foo=ASDJKHQKBZXOQWEOPIUAXQWEOIU; max-age=3600; version=1";
+        let responses = [
+            (
+                "4803 3330 3258 0770 7269 7661 7465 611d 4d6f 6e2c 2032 3120 4f63 7420 3230 3133
+                 2032 303a 3133 3a32 3120 474d 546e 1768 7474 7073 3a2f 2f77 7777 2e65 7861 6d70
+                 6c65 2e63 6f6d",
+                "4882 6402 5885 aec3 771a 4b61 96d0 7abe 9410 54d4 44a8 2005 9504 0b81 66e0 82a6
+                 2d1b ff6e 919d 29ad 1718 63c7 8f0b 97c8 e9ae 82ae 43d3",
+                vec![
+                    (":status", "302"),
+                    ("cache-control", "private"),
+                    ("date", "Mon, 21 Oct 2013 20:13:21 GMT"),
+                    ("location", "https://www.example.com"),
+                ],
+                222,
+            ),
+            (
+                "4803 3330 37c1 c0bf",
+                "4883 640e ffc1 c0bf",
+                vec![
+                    (":status", "307"),
+                    ("cache-control", "private"),
+                    ("date", "Mon, 21 Oct 2013 20:13:21 GMT"),
+                    ("location", "https://www.example.com"),
+                ],
+                222,
+            ),
+            (
+                "88c1 611d 4d6f 6e2c 2032 3120 4f63 7420 3230 3133 2032 303a 3133 3a32 3220 474d
+                 54c0 5a04 677a 6970 7738 666f 6f3d 4153 444a 4b48 514b 425a 584f 5157 454f 5049
+                 5541 5851 5745 4f49 553b 206d 6178 2d61 6765 3d33 3630 303b 2076 6572 7369 6f6e
+                 3d31",
+                "88c1 6196 d07a be94 1054 d444 a820 0595 040b 8166 e084 a62d 1bff c05a 839b d9ab
+                 77ad 94e7 821d d7f2 e6c7 b335 dfdf cd5b 3960 d5af 2708 7f36 72c1 ab27 0fb5 291f
+                 9587 3160 65c0 03ed 4ee5 b106 3d50 07",
+                vec![
+                    (":status", "200"),
+                    ("cache-control", "private"),
+                    ("date", "Mon, 21 Oct 2013 20:13:22 GMT"),
+                    ("location", "https://www.example.com"),
+                    ("content-encoding", "gzip"),
+                    ("set-cookie", cookie),
+                ],
+                215,
+            ),
+        ];
+        let mut plain = Decoder::new(256);
+        let mut huffman = Decoder::new(256);
+        for &(block, huffman_block, ref headers, size) in responses.iter() {
+            assert_eq!(plain.decode(&hex(block)).unwrap(), owned(headers));
+            assert_eq!(huffman.decode(&hex(huffman_block)).unwrap(), owned(headers));
+            assert_eq!((plain.size, huffman.size), (size, size));
+        }
+        assert_eq!(huffman.table.len(), 3);
+        assert_eq!(
+            huffman.table[0],
+            ("set-cookie".to_owned(), cookie.to_owned())
+        );
+    }
+
+    #[test]
+    fn refuses_malformed_blocks() {
+        let mut decoder = Decoder::new(4096);
+        // an index past the tables, a truncated string, EOS, padding of zeros
+        for block in ["be", "0403 2f61", "0084 ffff ffff 0161", "0081 0001 61"].iter() {
+            assert!(decoder.decode(&hex(block)).is_err(), "{}", block);
+        }
+        // a size update above SETTINGS_HEADER_TABLE_SIZE
+        assert!(decoder.decode(&hex("3fe2 1f")).is_err());
+        assert!(decoder.decode(&hex("3fe1 1f")).is_ok());
+    }
+}
@@ -0,0 +1,438 @@
+//! Minimal HTTP/1.1 primitives: one request per connection on the server
+//! side, and just enough of a client to open streams and fetch images.
+//!
+//! The client speaks to `https://` URLs only with the `tls` feature.
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::net;
+use std::time;
+
+use std::io::{BufRead, Read, Write};
+
+#[cfg(feature = "tls")]
+use tls;
+
+/// The longest request, status or header line read.
+const MAX_LINE: usize = 8 << 10;
+const MAX_HEADERS: usize = 100;
+
+/// A request that cannot be read, answered with the status.
+#[derive(Debug)]
+pub struct Refused(pub u16, &'static str);
+
+impl fmt::Display for Refused {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.1)
+    }
+}
+
+impl error::Error for Refused {}
+
+/// The status answering a request whose reading failed with `err`: 408 if
+/// the client was too slow, 400 unless refused otherwise.
+pub fn status(err: &io::Error) -> u16 {
+    match err.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => 408,
+        _ => err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<Refused>())
+            .map_or(400, |refused| refused.0),
+    }
+}
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+pub fn read_request<R>(r: R, max_body: usize) -> io::Result<Request>
+where
+    R: io::Read,
+{
+    let mut r = io::BufReader::new(r);
+
+    let mut line = String::new();
+    read_line(&mut r, &mut line, 414)?;
+    let (method, path) = {
+        let mut parts = line.split_whitespace();
+        match (parts.next(), parts.next()) {
+            (Some(method), Some(path)) => (method.to_owned(), path.to_owned()),
+            _ => return Err(invalid_data("malformed request line")),
+        }
+    };
+
+    let headers = read_headers(&mut r)?;
+
+    let mut request = Request {
+        method,
+        path,
+        headers,
+        body: Vec::new(),
+    };
+    let len = match request.header("Content-Length") {
+        Some(len) => len
+            .parse::<usize>()
+            .map_err(|_| invalid_data("malformed Content-Length"))?,
+        None => 0,
+    };
+    if len > max_body {
+        return Err(refused(413, "request body is too large"));
+    }
+    request.body.resize(len, 0);
+    r.read_exact(&mut request.body)?;
+    Ok(request)
+}
+
+pub fn write_response<W>(w: &mut W, status: u16, content_type: &str, body: &[u8]) -> io::Result<()>
+where
+    W: io::Write,
+{
+    write!(
+        w,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        reason(status),
+        content_type,
+        body.len()
+    )?;
+    w.write_all(body)?;
+    w.flush()
+}
+
+pub fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Payload Too Large",
+        414 => "URI Too Long",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+/// An `http://` or `https://` URL.
+#[derive(Clone)]
+pub struct Url {
+    pub tls: bool,
+    pub host: String,
+    pub port: u16,
+    /// Includes the query.
+    pub path: String,
+    /// `user:password`, for basic authentication.
+    pub userinfo: Option<String>,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> io::Result<Self> {
+        let (tls, rest, default_port) = if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest, 80)
+        } else if let Some(rest) = url.strip_prefix("https://") {
+            if !cfg!(feature = "tls") {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("https:// URLs require the tls feature: {}", url),
+                ));
+            }
+            (true, rest, 443)
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("only http:// and https:// URLs are supported: {}", url),
+            ));
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let (userinfo, hostport) = match authority.rfind('@') {
+            Some(i) => (Some(authority[..i].to_owned()), &authority[i + 1..]),
+            None => (None, authority),
+        };
+        let (host, port) = match hostport.rfind(':') {
+            Some(i) if !hostport.ends_with(']') => (
+                &hostport[..i],
+                hostport[i + 1..]
+                    .parse()
+                    .map_err(|_| invalid_data("malformed port"))?,
+            ),
+            _ => (hostport, default_port),
+        };
+        Ok(Self {
+            tls,
+            host: host.trim_matches(|c| c == '[' || c == ']').to_owned(),
+            port,
+            path: path.to_owned(),
+            userinfo,
+        })
+    }
+}
+
+/// A client connection, over TLS for `https://` URLs.
+pub enum Connection {
+    Tcp(net::TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(tls::Stream),
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Connection::Tcp(ref mut s) => s.read(buf),
+            #[cfg(feature = "tls")]
+            Connection::Tls(ref mut s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Connection::Tcp(ref mut s) => s.write(buf),
+            #[cfg(feature = "tls")]
+            Connection::Tls(ref mut s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Connection::Tcp(ref mut s) => s.flush(),
+            #[cfg(feature = "tls")]
+            Connection::Tls(ref mut s) => s.flush(),
+        }
+    }
+}
+
+/// Connects to the server of `url`, with `timeout` for reads and writes.
+pub fn connect(url: &Url, timeout: Option<time::Duration>) -> io::Result<Connection> {
+    let stream = net::TcpStream::connect((url.host.as_str(), url.port))?;
+    stream.set_read_timeout(timeout)?;
+    stream.set_write_timeout(timeout)?;
+    #[cfg(feature = "tls")]
+    {
+        if url.tls {
+            return Ok(Connection::Tls(tls::Stream::connect(stream, &url.host)?));
+        }
+    }
+    Ok(Connection::Tcp(stream))
+}
+
+/// Reads the status line and the headers of a response.
+pub fn read_response_head<R>(r: &mut R) -> io::Result<(u16, Vec<(String, String)>)>
+where
+    R: io::BufRead,
+{
+    let mut line = String::new();
+    read_line(r, &mut line, 400)?;
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| invalid_data("malformed status line"))?;
+    Ok((status, read_headers(r)?))
+}
+
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Fetches `url`. Statuses other than 200 and bodies longer than `max_body`
+/// are errors.
+pub fn get(url: &str, max_body: usize) -> io::Result<Response> {
+    let url = Url::parse(url)?;
+    let response = send("GET", &url, &[("Accept", "image/*")], &[], max_body)?;
+    if response.status != 200 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unexpected status {}", response.status),
+        ));
+    }
+    Ok(response)
+}
+
+/// Sends a request and reads the whole response. Bodies longer than
+/// `max_body` are errors.
+pub fn send(
+    method: &str,
+    url: &Url,
+    headers: &[(&str, &str)],
+    body: &[u8],
+    max_body: usize,
+) -> io::Result<Response> {
+    let mut stream = connect(url, Some(time::Duration::from_secs(30)))?;
+    // HTTP/1.0 keeps servers from using chunked transfer encoding.
+    let mut request = format!("{} {} HTTP/1.0\r\nHost: {}\r\n", method, url.path, url.host);
+    if let Some(ref userinfo) = url.userinfo {
+        request += &format!("Authorization: Basic {}\r\n", base64(userinfo.as_bytes()));
+    }
+    if !body.is_empty() {
+        request += &format!("Content-Length: {}\r\n", body.len());
+    }
+    for &(name, value) in headers.iter() {
+        request += &format!("{}: {}\r\n", name, value);
+    }
+    request += "\r\n";
+    stream.write_all(request.as_bytes())?;
+    stream.write_all(body)?;
+
+    let mut reader = io::BufReader::new(stream);
+    let (status, headers) = read_response_head(&mut reader)?;
+    let mut response = Response {
+        status,
+        headers,
+        body: Vec::new(),
+    };
+    let too_large = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("body larger than {} bytes", max_body),
+        )
+    };
+    let length = response
+        .header("Content-Length")
+        .and_then(|v| v.parse::<usize>().ok());
+    if length.map(|l| l > max_body).unwrap_or(false) {
+        return Err(too_large());
+    }
+    reader
+        .take(max_body as u64 + 1)
+        .read_to_end(&mut response.body)?;
+    if response.body.len() > max_body {
+        return Err(too_large());
+    }
+    Ok(response)
+}
+
+pub fn base64(data: &[u8]) -> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut s = String::with_capacity(data.len() / 3 * 4 + 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).cloned().unwrap_or(0),
+            chunk.get(2).cloned().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                s.push(TABLE[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                s.push('=');
+            }
+        }
+    }
+    s
+}
+
+fn read_headers<R>(r: &mut R) -> io::Result<Vec<(String, String)>>
+where
+    R: io::BufRead,
+{
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if read_line(r, &mut line, 431)? == 0 {
+            return Err(invalid_data("unexpected end of headers"));
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        match line.find(':') {
+            Some(_) if headers.len() == MAX_HEADERS => {
+                return Err(refused(431, "too many headers"))
+            }
+            Some(i) => headers.push((line[..i].trim().to_owned(), line[i + 1..].trim().to_owned())),
+            None => return Err(invalid_data("malformed header")),
+        }
+    }
+}
+
+/// Reads a line of at most `MAX_LINE` bytes, refusing longer ones with
+/// `status`.
+fn read_line<R>(r: &mut R, line: &mut String, status: u16) -> io::Result<usize>
+where
+    R: io::BufRead,
+{
+    let len = r.take(MAX_LINE as u64).read_line(line)?;
+    if len == MAX_LINE && !line.ends_with('\n') {
+        return Err(refused(status, "line too long"));
+    }
+    Ok(len)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn refused(status: u16, message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, Refused(status, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_long_lines() {
+        let line = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE));
+        let err = read_request(line.as_bytes(), 0).err().unwrap();
+        assert_eq!(status(&err), 414);
+        let header = format!("GET / HTTP/1.1\r\nA: {}\r\n\r\n", "a".repeat(MAX_LINE));
+        let err = read_request(header.as_bytes(), 0).err().unwrap();
+        assert_eq!(status(&err), 431);
+        let headers = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "A: a\r\n".repeat(MAX_HEADERS + 1)
+        );
+        let err = read_request(headers.as_bytes(), 0).err().unwrap();
+        assert_eq!(status(&err), 431);
+        let body = "POST / HTTP/1.1\r\nContent-Length: 1\r\n\r\na";
+        let err = read_request(body.as_bytes(), 0).err().unwrap();
+        assert_eq!(status(&err), 413);
+
+        let request = format!("GET / HTTP/1.1\r\nA: {}\r\n\r\n", "a".repeat(MAX_LINE - 6));
+        assert_eq!(
+            read_request(request.as_bytes(), 0).unwrap().headers.len(),
+            1
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "tls")]
+    fn parses_https_urls() {
+        let url = Url::parse("https://user:pass@[::1]/a?b").unwrap();
+        assert!(url.tls);
+        assert_eq!((url.host.as_str(), url.port), ("::1", 443));
+        assert_eq!(url.path, "/a?b");
+        assert!(!Url::parse("http://host:8080").unwrap().tls);
+    }
+}
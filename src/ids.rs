@@ -0,0 +1,90 @@
+//! Identifiers of detections and tracks, so that downstream systems can
+//! reference and deduplicate them.
+//!
+//! The ID of a detection is a hash of its frame, its label and its box, so
+//! that processing the same frame again gives the same IDs. Tracks are
+//! numbered per tracker, or get UUIDs that are unique across sources and runs.
+
+use std::collections::hash_map;
+use std::sync::atomic;
+use std::time;
+
+use std::hash::{BuildHasher, Hasher};
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TrackIds {
+    /// Numbers from 0, in `track`.
+    #[default]
+    Sequence,
+    /// Random UUIDs, in `track_uuid`.
+    Uuid,
+}
+
+/// Returns the ID of a detection. `frame` identifies the frame, e.g. by its
+/// source and time, and `bb` is `(top, left, bottom, right)` in pixels.
+pub fn detection_id(frame: &str, label: &str, bb: [f32; 4]) -> String {
+    let mut hash = Fnv::default();
+    hash.write(frame.as_bytes());
+    hash.write(&[0]);
+    hash.write(label.as_bytes());
+    hash.write(&[0]);
+    for c in bb.iter() {
+        // to a hundredth of a pixel, as printed
+        hash.write(&((c * 100.).round() as i64).to_le_bytes());
+    }
+    format!("{:016x}", hash.finish())
+}
+
+/// Returns a random (version 4) UUID.
+pub fn uuid() -> String {
+    static COUNTER: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
+    // The keys of the std hasher are random, which avoids a dependency on a
+    // random number generator.
+    let state = hash_map::RandomState::new();
+    let count = COUNTER.fetch_add(1, atomic::Ordering::Relaxed);
+    let now = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut bytes = [0; 16];
+    for (i, half) in bytes.chunks_mut(8).enumerate() {
+        let mut hasher = state.build_hasher();
+        hasher.write_usize(count);
+        hasher.write_u64(now.as_secs());
+        hasher.write_u32(now.subsec_nanos());
+        hasher.write_usize(i);
+        half.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+    bytes[6] = bytes[6] & 0x0f | 0x40;
+    bytes[8] = bytes[8] & 0x3f | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// 64-bit FNV-1a, which unlike the std hasher is stable across Rust versions.
+struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
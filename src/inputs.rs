@@ -0,0 +1,87 @@
+//! Inputs of the network besides the image, as the `image_shape` of the
+//! YOLOv3 models of the ONNX model zoo, filled for every image.
+
+use ndarray;
+
+use detector;
+
+/// An input of `dims` (without the batch dimension) holding `values`, in
+/// row-major order.
+#[derive(Clone, Deserialize)]
+pub struct Input {
+    pub name: String,
+    /// Defaults to `[values.len()]`.
+    #[serde(default)]
+    pub dims: Option<Vec<usize>>,
+    pub values: Vec<Value>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum Value {
+    Constant(f32),
+    Variable(Variable),
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Variable {
+    /// Size of the image given to `predict`, in pixels.
+    ImageHeight,
+    ImageWidth,
+    /// Size of the image input (see `yolo_v2::Config::sizes`).
+    InputHeight,
+    InputWidth,
+    /// Factor from image to input pixels (see `yolo_v2::Letterbox`).
+    Scale,
+}
+
+/// What the variables are for an image.
+pub struct Image {
+    pub height: u32,
+    pub width: u32,
+    pub input_height: usize,
+    pub input_width: usize,
+    pub scale: f32,
+}
+
+impl Input {
+    /// Shape of the input for `batch` images. Fails if `values` does not
+    /// fill `dims`.
+    pub fn shape(&self, batch: usize) -> Result<Vec<usize>, detector::Error> {
+        let dims = self.dims.clone().unwrap_or_else(|| vec![self.values.len()]);
+        if dims.iter().product::<usize>() != self.values.len() {
+            return Err(detector::Error::Config(format!(
+                "input {}: {} values do not fill {:?}",
+                self.name,
+                self.values.len(),
+                dims
+            )));
+        }
+        Ok(Some(batch).into_iter().chain(dims).collect())
+    }
+
+    /// Sets the values for `image`, the image `index` of the batch, in
+    /// `view`, the whole input.
+    pub fn fill(&self, view: ndarray::ArrayViewMutD<f32>, index: usize, image: &Image) {
+        let values = self.eval(image);
+        let mut view = view.into_subview(ndarray::Axis(0), index);
+        for (v, value) in view.iter_mut().zip(values) {
+            *v = value;
+        }
+    }
+
+    pub fn eval(&self, image: &Image) -> Vec<f32> {
+        self.values
+            .iter()
+            .map(|v| match *v {
+                Value::Constant(c) => c,
+                Value::Variable(Variable::ImageHeight) => image.height as f32,
+                Value::Variable(Variable::ImageWidth) => image.width as f32,
+                Value::Variable(Variable::InputHeight) => image.input_height as f32,
+                Value::Variable(Variable::InputWidth) => image.input_width as f32,
+                Value::Variable(Variable::Scale) => image.scale,
+            })
+            .collect()
+    }
+}
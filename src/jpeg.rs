@@ -0,0 +1,842 @@
+//! Decoding of baseline JPEGs by bands of rows, for images too large to be
+//! held decoded at once (see `tiling::predict_jpeg` and `downscale::open`).
+//!
+//! A single row of MCUs is decoded at a time, so that the memory does not
+//! grow with the height of the image. Progressive and arithmetic-coded files,
+//! and the files with a scan per component, need the whole image and are
+//! rejected. Chroma is upsampled by replication.
+
+use image;
+use std::f32;
+use std::io;
+
+use color;
+
+use std::io::Read;
+
+/// Natural order of the coefficients stored in zigzag order.
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// Bits of the codes found in a single lookup.
+const LOOKUP_BITS: u32 = 9;
+
+pub struct Decoder<R> {
+    bits: Bits<io::BufReader<R>>,
+    width: u32,
+    height: u32,
+    components: Vec<Component>,
+    quant: [[u16; 64]; 4],
+    dc: [Option<Huffman>; 4],
+    ac: [Option<Huffman>; 4],
+    /// MCUs between restart markers, or 0.
+    restart_interval: usize,
+    /// Whether the components are YCbCr rather than RGB.
+    ycbcr: bool,
+    icc: Option<Vec<u8>>,
+    h_max: usize,
+    v_max: usize,
+    mcus_x: usize,
+    mcus_y: usize,
+    /// Rows of MCUs decoded.
+    row: usize,
+    idct: [[f32; 8]; 8],
+}
+
+struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    quant: usize,
+    dc: usize,
+    ac: usize,
+    pred: i32,
+    /// Samples of the current row of MCUs, `8 * h` per MCU and `8 * v`
+    /// rows.
+    samples: Vec<u8>,
+}
+
+impl Component {
+    fn stride(&self, mcus_x: usize) -> usize {
+        mcus_x * self.h * 8
+    }
+}
+
+/// A canonical Huffman table.
+struct Huffman {
+    /// Length and value of the codes of at most `LOOKUP_BITS` bits, by
+    /// their first bits; a length of 0 for the longer codes.
+    lookup: Vec<(u8, u8)>,
+    /// Largest code of each length, or -1.
+    max_code: [i32; 17],
+    min_code: [i32; 17],
+    /// Index in `values` of the first code of each length.
+    first: [usize; 17],
+    values: Vec<u8>,
+}
+
+impl Huffman {
+    fn new(counts: &[u8; 16], values: Vec<u8>) -> image::ImageResult<Self> {
+        let mut table = Self {
+            lookup: vec![(0, 0); 1 << LOOKUP_BITS],
+            max_code: [-1; 17],
+            min_code: [0; 17],
+            first: [0; 17],
+            values,
+        };
+        let (mut code, mut k) = (0i32, 0usize);
+        for len in 1..17 {
+            let count = counts[len - 1] as usize;
+            table.first[len] = k;
+            table.min_code[len] = code;
+            for _ in 0..count {
+                // more codes than the length has
+                if code >= 1 << len {
+                    return Err(format_error("overfull Huffman table"));
+                }
+                if len as u32 <= LOOKUP_BITS {
+                    let shift = LOOKUP_BITS - len as u32;
+                    let start = (code as usize) << shift;
+                    let value = *table
+                        .values
+                        .get(k)
+                        .ok_or_else(|| format_error("short table"))?;
+                    for entry in table.lookup[start..start + (1 << shift)].iter_mut() {
+                        *entry = (len as u8, value);
+                    }
+                }
+                code += 1;
+                k += 1;
+            }
+            if count > 0 {
+                table.max_code[len] = code - 1;
+            }
+            code <<= 1;
+        }
+        if k > table.values.len() {
+            return Err(format_error("short Huffman table"));
+        }
+        Ok(table)
+    }
+}
+
+/// The entropy-coded data, without the stuffed bytes, up to the next marker.
+struct Bits<R> {
+    reader: R,
+    /// The next bits, from the most significant one.
+    acc: u32,
+    len: u32,
+    /// The marker ending the data, once read.
+    marker: Option<u8>,
+}
+
+impl<R: Read> Bits<R> {
+    fn fill(&mut self) -> io::Result<()> {
+        while self.len <= 24 {
+            let byte = match self.marker {
+                // zeros past the marker
+                Some(_) => 0,
+                None => match byte(&mut self.reader)? {
+                    0xff => {
+                        let mut next = byte(&mut self.reader)?;
+                        while next == 0xff {
+                            next = byte(&mut self.reader)?;
+                        }
+                        if next == 0 {
+                            0xff
+                        } else {
+                            self.marker = Some(next);
+                            0
+                        }
+                    }
+                    byte => byte,
+                },
+            };
+            self.acc |= (byte as u32) << (24 - self.len);
+            self.len += 8;
+        }
+        Ok(())
+    }
+
+    fn consume(&mut self, n: u32) {
+        self.acc <<= n;
+        self.len -= n;
+    }
+
+    /// `n` bits, at most 16, as an unsigned value.
+    fn receive(&mut self, n: u32) -> io::Result<u32> {
+        if n == 0 {
+            return Ok(0);
+        }
+        self.fill()?;
+        let value = self.acc >> (32 - n);
+        self.consume(n);
+        Ok(value)
+    }
+
+    fn decode(&mut self, table: &Huffman) -> image::ImageResult<u8> {
+        self.fill()?;
+        let (len, value) = table.lookup[(self.acc >> (32 - LOOKUP_BITS)) as usize];
+        if len > 0 {
+            self.consume(len as u32);
+            return Ok(value);
+        }
+        for len in LOOKUP_BITS as usize + 1..17 {
+            let code = (self.acc >> (32 - len)) as i32;
+            if code <= table.max_code[len] {
+                self.consume(len as u32);
+                let index = table.first[len] + (code - table.min_code[len]) as usize;
+                return table
+                    .values
+                    .get(index)
+                    .cloned()
+                    .ok_or_else(|| format_error("invalid Huffman code"));
+            }
+        }
+        Err(format_error("invalid Huffman code"))
+    }
+
+    /// Skips to the restart marker ending the interval.
+    fn restart(&mut self) -> image::ImageResult<()> {
+        self.acc = 0;
+        self.len = 0;
+        let marker = match self.marker.take() {
+            Some(marker) => marker,
+            None => loop {
+                if byte(&mut self.reader)? != 0xff {
+                    continue;
+                }
+                let mut next = byte(&mut self.reader)?;
+                while next == 0xff {
+                    next = byte(&mut self.reader)?;
+                }
+                if next != 0 {
+                    break next;
+                }
+            },
+        };
+        match marker {
+            0xd0..=0xd7 => Ok(()),
+            _ => Err(format_error("missing restart marker")),
+        }
+    }
+}
+
+impl<R: Read> Decoder<R> {
+    /// Reads the headers, up to the start of the scan.
+    pub fn new(reader: R) -> image::ImageResult<Self> {
+        let mut reader = io::BufReader::new(reader);
+        if byte(&mut reader)? != 0xff || byte(&mut reader)? != 0xd8 {
+            return Err(format_error("not a JPEG"));
+        }
+        // the segments before the scan, for `color::jpeg_profile`
+        let mut head = vec![0xff, 0xd8];
+        let mut frame = None;
+        let mut quant = [[0u16; 64]; 4];
+        let mut dc: [Option<Huffman>; 4] = [None, None, None, None];
+        let mut ac: [Option<Huffman>; 4] = [None, None, None, None];
+        let mut restart_interval = 0;
+        let mut adobe_rgb = false;
+        let scan = loop {
+            let mut marker = byte(&mut reader)?;
+            if marker != 0xff {
+                return Err(format_error("expected a marker"));
+            }
+            while marker == 0xff {
+                marker = byte(&mut reader)?;
+            }
+            // without a length
+            if let 0x01 | 0xd0..=0xd7 = marker {
+                continue;
+            }
+            let mut len = [0; 2];
+            reader.read_exact(&mut len)?;
+            let len = (len[0] as usize) << 8 | len[1] as usize;
+            if len < 2 {
+                return Err(format_error("invalid segment length"));
+            }
+            let mut data = vec![0; len - 2];
+            reader.read_exact(&mut data)?;
+            head.extend_from_slice(&[0xff, marker, (len >> 8) as u8, len as u8]);
+            head.extend_from_slice(&data);
+            match marker {
+                0xc0 | 0xc1 => frame = Some(data),
+                0xc2 | 0xc6 | 0xca | 0xce => {
+                    return Err(unsupported("progressive JPEGs cannot be decoded by bands"))
+                }
+                0xc3 | 0xc5 | 0xc7 | 0xc9 | 0xcb | 0xcd | 0xcf => {
+                    return Err(unsupported("only baseline JPEGs can be decoded by bands"))
+                }
+                0xc4 => read_huffman(&data, &mut dc, &mut ac)?,
+                0xdb => read_quant(&data, &mut quant)?,
+                0xdd if data.len() >= 2 => {
+                    restart_interval = (data[0] as usize) << 8 | data[1] as usize
+                }
+                // the color transform of Adobe, 0 for RGB
+                0xee if data.len() >= 12 && data.starts_with(b"Adobe") => adobe_rgb = data[11] == 0,
+                0xda => break data,
+                0xd9 => return Err(format_error("no image data")),
+                _ => {}
+            }
+        };
+        let frame = frame.ok_or_else(|| format_error("no frame header"))?;
+        if frame.len() < 6 || frame[0] != 8 {
+            return Err(unsupported("only 8-bit JPEGs can be decoded by bands"));
+        }
+        let height = (frame[1] as u32) << 8 | frame[2] as u32;
+        let width = (frame[3] as u32) << 8 | frame[4] as u32;
+        let count = frame[5] as usize;
+        if height == 0 || width == 0 || frame.len() < 6 + 3 * count {
+            return Err(format_error("invalid frame header"));
+        }
+        if count != 1 && count != 3 {
+            return Err(unsupported(
+                "only grayscale and color JPEGs can be decoded by bands",
+            ));
+        }
+        let mut components: Vec<_> = frame[6..6 + 3 * count]
+            .chunks(3)
+            .map(|c| Component {
+                id: c[0],
+                h: (c[1] >> 4).max(1) as usize,
+                v: (c[1] & 15).max(1) as usize,
+                quant: (c[2] & 3) as usize,
+                dc: 0,
+                ac: 0,
+                pred: 0,
+                samples: Vec::new(),
+            })
+            .collect();
+        if count == 1 {
+            // a single component is not interleaved: an MCU is one block
+            components[0].h = 1;
+            components[0].v = 1;
+        }
+
+        if scan.is_empty() || scan[0] as usize != count || scan.len() < 1 + 2 * count {
+            return Err(unsupported(
+                "JPEGs with a scan per component cannot be decoded by bands",
+            ));
+        }
+        for c in scan[1..1 + 2 * count].chunks(2) {
+            let component = components
+                .iter_mut()
+                .find(|component| component.id == c[0])
+                .ok_or_else(|| format_error("unknown component in the scan"))?;
+            component.dc = (c[1] >> 4 & 3) as usize;
+            component.ac = (c[1] & 3) as usize;
+        }
+        for component in components.iter() {
+            if dc[component.dc].is_none() || ac[component.ac].is_none() {
+                return Err(format_error("missing Huffman table"));
+            }
+        }
+
+        let h_max = components.iter().map(|c| c.h).max().unwrap_or(1);
+        let v_max = components.iter().map(|c| c.v).max().unwrap_or(1);
+        let mcus_x = (width as usize).div_ceil(8 * h_max);
+        let mcus_y = (height as usize).div_ceil(8 * v_max);
+        for component in components.iter_mut() {
+            component.samples = vec![0; component.stride(mcus_x) * component.v * 8];
+        }
+        let ids: Vec<_> = components.iter().map(|c| c.id).collect();
+        let mut idct = [[0.; 8]; 8];
+        for (x, row) in idct.iter_mut().enumerate() {
+            for (u, t) in row.iter_mut().enumerate() {
+                let c = if u == 0 {
+                    f32::consts::FRAC_1_SQRT_2
+                } else {
+                    1.
+                };
+                *t = c / 2. * ((2 * x + 1) as f32 * u as f32 * f32::consts::PI / 16.).cos();
+            }
+        }
+        Ok(Self {
+            bits: Bits {
+                reader,
+                acc: 0,
+                len: 0,
+                marker: None,
+            },
+            width,
+            height,
+            components,
+            quant,
+            dc,
+            ac,
+            restart_interval,
+            ycbcr: count == 3 && !adobe_rgb && ids != b"RGB",
+            icc: color::jpeg_profile(&head),
+            h_max,
+            v_max,
+            mcus_x,
+            mcus_y,
+            row: 0,
+            idct,
+        })
+    }
+
+    /// `(width, height)`
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// The ICC profile of the image, if any.
+    pub fn icc_profile(&self) -> Option<&[u8]> {
+        self.icc.as_deref()
+    }
+
+    /// The next band of rows, of the height of an MCU but for the last one,
+    /// or `None` once the image is decoded.
+    pub fn next_band(&mut self) -> image::ImageResult<Option<image::RgbImage>> {
+        if self.row == self.mcus_y {
+            return Ok(None);
+        }
+        for x in 0..self.mcus_x {
+            let index = self.row * self.mcus_x + x;
+            if self.restart_interval > 0 && index > 0 && index.is_multiple_of(self.restart_interval)
+            {
+                self.bits.restart()?;
+                for component in self.components.iter_mut() {
+                    component.pred = 0;
+                }
+            }
+            self.decode_mcu(x)?;
+        }
+
+        let top = self.row * 8 * self.v_max;
+        let rows = (8 * self.v_max).min(self.height as usize - top);
+        let width = self.width as usize;
+        let mut band = Vec::with_capacity(width * rows * 3);
+        for y in 0..rows {
+            let sample = |c: &Component, x: usize| {
+                let (cx, cy) = (x * c.h / self.h_max, y * c.v / self.v_max);
+                c.samples[cy * c.stride(self.mcus_x) + cx]
+            };
+            match self.components[..] {
+                [ref luma] => {
+                    for x in 0..width {
+                        let luma = sample(luma, x);
+                        band.extend_from_slice(&[luma, luma, luma]);
+                    }
+                }
+                [ref a, ref b, ref c] => {
+                    for x in 0..width {
+                        let (a, b, c) = (sample(a, x), sample(b, x), sample(c, x));
+                        if self.ycbcr {
+                            band.extend_from_slice(&ycbcr_to_rgb(a, b, c));
+                        } else {
+                            band.extend_from_slice(&[a, b, c]);
+                        }
+                    }
+                }
+                _ => unreachable!("checked by new"),
+            }
+        }
+        self.row += 1;
+        Ok(image::RgbImage::from_raw(self.width, rows as u32, band))
+    }
+
+    fn decode_mcu(&mut self, mcu_x: usize) -> image::ImageResult<()> {
+        for c in 0..self.components.len() {
+            let (h, v) = (self.components[c].h, self.components[c].v);
+            for by in 0..v {
+                for bx in 0..h {
+                    let block = self.decode_block(c)?;
+                    let component = &mut self.components[c];
+                    let stride = component.stride(self.mcus_x);
+                    let origin = by * 8 * stride + (mcu_x * h + bx) * 8;
+                    for (y, row) in block.chunks(8).enumerate() {
+                        let start = origin + y * stride;
+                        component.samples[start..start + 8].copy_from_slice(row);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn decode_block(&mut self, c: usize) -> image::ImageResult<[u8; 64]> {
+        let component = &mut self.components[c];
+        let quant = &self.quant[component.quant];
+        let dc = self.dc[component.dc].as_ref().unwrap();
+        let ac = self.ac[component.ac].as_ref().unwrap();
+        let mut coefs = [0f32; 64];
+
+        let size = self.bits.decode(dc)? as u32;
+        if size > 11 {
+            return Err(format_error("invalid DC coefficient"));
+        }
+        let diff = extend(self.bits.receive(size)?, size);
+        component.pred += diff;
+        coefs[0] = (component.pred * quant[0] as i32) as f32;
+        let mut dc_only = true;
+        let mut k = 1;
+        while k < 64 {
+            let rs = self.bits.decode(ac)?;
+            let (run, size) = ((rs >> 4) as usize, (rs & 15) as u32);
+            if size == 0 {
+                if run == 15 {
+                    k += 16;
+                    continue;
+                }
+                break;
+            }
+            k += run;
+            if k > 63 {
+                return Err(format_error("invalid AC coefficients"));
+            }
+            let value = extend(self.bits.receive(size)?, size);
+            coefs[ZIGZAG[k]] = (value * quant[k] as i32) as f32;
+            dc_only = false;
+            k += 1;
+        }
+
+        if dc_only {
+            let value = clamp(coefs[0] / 8. + 128.);
+            return Ok([value; 64]);
+        }
+        // rows, then columns
+        let mut block = [0u8; 64];
+        let t = &self.idct;
+        let mut rows = [0f32; 64];
+        for v in 0..8 {
+            for x in 0..8 {
+                rows[v * 8 + x] = (0..8).map(|u| t[x][u] * coefs[v * 8 + u]).sum();
+            }
+        }
+        for y in 0..8 {
+            for x in 0..8 {
+                let value: f32 = (0..8).map(|v| t[y][v] * rows[v * 8 + x]).sum();
+                block[y * 8 + x] = clamp(value + 128.);
+            }
+        }
+        Ok(block)
+    }
+}
+
+fn read_huffman(
+    mut data: &[u8],
+    dc: &mut [Option<Huffman>; 4],
+    ac: &mut [Option<Huffman>; 4],
+) -> image::ImageResult<()> {
+    while !data.is_empty() {
+        if data.len() < 17 {
+            return Err(format_error("short Huffman table"));
+        }
+        let (class, id) = (data[0] >> 4, (data[0] & 3) as usize);
+        let mut counts = [0; 16];
+        counts.copy_from_slice(&data[1..17]);
+        let total: usize = counts.iter().map(|&c| c as usize).sum();
+        let values = data
+            .get(17..17 + total)
+            .ok_or_else(|| format_error("short Huffman table"))?
+            .to_vec();
+        let table = Some(Huffman::new(&counts, values)?);
+        match class {
+            0 => dc[id] = table,
+            _ => ac[id] = table,
+        }
+        data = &data[17 + total..];
+    }
+    Ok(())
+}
+
+fn read_quant(mut data: &[u8], quant: &mut [[u16; 64]; 4]) -> image::ImageResult<()> {
+    while !data.is_empty() {
+        let (precision, id) = (data[0] >> 4, (data[0] & 3) as usize);
+        let len = if precision == 0 { 64 } else { 128 };
+        let values = data
+            .get(1..1 + len)
+            .ok_or_else(|| format_error("short quantization table"))?;
+        for (k, q) in quant[id].iter_mut().enumerate() {
+            *q = match precision {
+                0 => values[k] as u16,
+                _ => (values[2 * k] as u16) << 8 | values[2 * k + 1] as u16,
+            };
+        }
+        data = &data[1 + len..];
+    }
+    Ok(())
+}
+
+/// The signed value of the `size` bits `value`.
+fn extend(value: u32, size: u32) -> i32 {
+    if size == 0 {
+        0
+    } else if value < 1 << (size - 1) {
+        value as i32 - (1 << size) + 1
+    } else {
+        value as i32
+    }
+}
+
+fn ycbcr_to_rgb(y: u8, cb: u8, cr: u8) -> [u8; 3] {
+    let (y, cb, cr) = (y as f32, cb as f32 - 128., cr as f32 - 128.);
+    [
+        clamp(y + 1.402 * cr),
+        clamp(y - 0.344_136 * cb - 0.714_136 * cr),
+        clamp(y + 1.772 * cb),
+    ]
+}
+
+fn clamp(v: f32) -> u8 {
+    v.round().clamp(0., 255.) as u8
+}
+
+fn byte<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn format_error(message: &str) -> image::ImageError {
+    image::ImageError::FormatError(message.to_owned())
+}
+
+fn unsupported(message: &str) -> image::ImageError {
+    image::ImageError::UnsupportedError(message.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decodes `data` by bands, and checks each band against the rows of the
+    /// image decoded whole by `image`, to within `tolerance` per sample.
+    fn check_bands(data: &[u8], band_height: u32, tolerance: u8) {
+        let whole = image::load_from_memory(data).unwrap().to_rgb();
+        let mut decoder = Decoder::new(data).unwrap();
+        assert_eq!(decoder.dimensions(), whole.dimensions());
+        let mut top = 0;
+        while let Some(band) = decoder.next_band().unwrap() {
+            assert_eq!(band.width(), whole.width());
+            assert_eq!(band.height(), band_height.min(whole.height() - top));
+            for (x, y, p) in band.enumerate_pixels() {
+                let q = whole.get_pixel(x, top + y);
+                for c in 0..3 {
+                    let diff = (p[c] as i32 - q[c] as i32).unsigned_abs();
+                    assert!(
+                        diff <= tolerance as u32,
+                        "{:?} at ({}, {}), not {:?}",
+                        p,
+                        x,
+                        top + y,
+                        q
+                    );
+                }
+            }
+            top += band.height();
+        }
+        assert_eq!(top, whole.height());
+    }
+
+    fn encode(width: u32, height: u32, color: image::ColorType) -> Vec<u8> {
+        let channels = if color == image::ColorType::Gray(8) {
+            1
+        } else {
+            3
+        };
+        let pixels: Vec<u8> = (0..height)
+            .flat_map(|y| {
+                (0..width * channels).map(move |x| ((x * 7 + y * 3) % 256) as u8 / 2 + 64)
+            })
+            .collect();
+        let mut data = Vec::new();
+        image::jpeg::JPEGEncoder::new_with_quality(&mut data, 95)
+            .encode(&pixels, width, height, color)
+            .unwrap();
+        data
+    }
+
+    /// The codes of the canonical Huffman table of `counts`, by value.
+    fn codes(counts: &[u8; 16], values: &[u8]) -> Vec<(u8, u16, u8)> {
+        let (mut code, mut k, mut codes) = (0u16, 0, Vec::new());
+        for len in 1..17 {
+            for _ in 0..counts[len - 1] {
+                codes.push((values[k], code, len as u8));
+                code += 1;
+                k += 1;
+            }
+            code <<= 1;
+        }
+        codes
+    }
+
+    struct Writer {
+        data: Vec<u8>,
+        acc: u32,
+        len: u32,
+    }
+
+    impl Writer {
+        fn put(&mut self, bits: u32, len: u32) {
+            for i in (0..len).rev() {
+                self.acc = self.acc << 1 | (bits >> i & 1);
+                self.len += 1;
+                if self.len == 8 {
+                    self.data.push(self.acc as u8);
+                    if self.acc == 0xff {
+                        self.data.push(0);
+                    }
+                    self.acc = 0;
+                    self.len = 0;
+                }
+            }
+        }
+
+        /// Pads the last byte with ones.
+        fn flush(&mut self) {
+            while self.len > 0 {
+                self.put(1, 1);
+            }
+        }
+    }
+
+    const DC_COUNTS: [u8; 16] = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+    const DC_VALUES: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+
+    /// A JPEG of flat blocks, with only DC coefficients: `sampling` gives the
+    /// sampling factors of the components, and `value` the value of the block
+    /// of a component at `(x, y)` of the image.
+    fn encode_flat<F>(
+        width: u32,
+        height: u32,
+        sampling: &[(usize, usize)],
+        restart_interval: usize,
+        value: F,
+    ) -> Vec<u8>
+    where
+        F: Fn(usize, usize, usize) -> u8,
+    {
+        let segment = |data: &mut Vec<u8>, marker: u8, body: &[u8]| {
+            let len = body.len() + 2;
+            data.extend_from_slice(&[0xff, marker, (len >> 8) as u8, len as u8]);
+            data.extend_from_slice(body);
+        };
+        let mut data = vec![0xff, 0xd8];
+        segment(&mut data, 0xdb, &[[0].as_ref(), &[1; 64]].concat());
+        let mut frame = vec![
+            8,
+            (height >> 8) as u8,
+            height as u8,
+            (width >> 8) as u8,
+            width as u8,
+            sampling.len() as u8,
+        ];
+        for (i, &(h, v)) in sampling.iter().enumerate() {
+            frame.extend_from_slice(&[i as u8 + 1, (h << 4 | v) as u8, 0]);
+        }
+        segment(&mut data, 0xc0, &frame);
+        segment(
+            &mut data,
+            0xc4,
+            &[[0].as_ref(), &DC_COUNTS, &DC_VALUES].concat(),
+        );
+        let mut ac_counts = [0; 16];
+        ac_counts[0] = 1;
+        segment(
+            &mut data,
+            0xc4,
+            &[[0x10].as_ref(), &ac_counts, &[0]].concat(),
+        );
+        if restart_interval > 0 {
+            let n = restart_interval;
+            segment(&mut data, 0xdd, &[(n >> 8) as u8, n as u8]);
+        }
+        let mut scan = vec![sampling.len() as u8];
+        for i in 0..sampling.len() {
+            scan.extend_from_slice(&[i as u8 + 1, 0]);
+        }
+        scan.extend_from_slice(&[0, 63, 0]);
+        segment(&mut data, 0xda, &scan);
+
+        let dc_codes = codes(&DC_COUNTS, &DC_VALUES);
+        let h_max = sampling.iter().map(|s| s.0).max().unwrap();
+        let v_max = sampling.iter().map(|s| s.1).max().unwrap();
+        let mcus_x = (width as usize).div_ceil(8 * h_max);
+        let mcus_y = (height as usize).div_ceil(8 * v_max);
+        let mut writer = Writer {
+            data,
+            acc: 0,
+            len: 0,
+        };
+        let mut preds = vec![0i32; sampling.len()];
+        for mcu in 0..mcus_x * mcus_y {
+            if restart_interval > 0 && mcu > 0 && mcu % restart_interval == 0 {
+                writer.flush();
+                let n = (mcu / restart_interval - 1) % 8;
+                writer.data.extend_from_slice(&[0xff, 0xd0 + n as u8]);
+                preds.iter_mut().for_each(|p| *p = 0);
+            }
+            let (mcu_x, mcu_y) = (mcu % mcus_x, mcu / mcus_x);
+            for (c, &(h, v)) in sampling.iter().enumerate() {
+                for by in 0..v {
+                    for bx in 0..h {
+                        // the center of the block, in the pixels of the image
+                        let x = ((mcu_x * h + bx) * 8 + 4) * h_max / h;
+                        let y = ((mcu_y * v + by) * 8 + 4) * v_max / v;
+                        let dc = 8 * (value(c, x, y) as i32 - 128);
+                        let diff = dc - preds[c];
+                        preds[c] = dc;
+                        let size = 32 - diff.unsigned_abs().leading_zeros();
+                        let &(_, code, len) = dc_codes.iter().find(|d| d.0 == size as u8).unwrap();
+                        writer.put(code as u32, len as u32);
+                        let bits = if diff < 0 { diff - 1 } else { diff };
+                        writer.put(bits as u32 & ((1 << size) - 1), size);
+                        // end of block
+                        writer.put(0, 1);
+                    }
+                }
+            }
+        }
+        writer.flush();
+        let mut data = writer.data;
+        data.extend_from_slice(&[0xff, 0xd9]);
+        data
+    }
+
+    /// Luma varying by block, chroma slowly, so that the upsampling of
+    /// `image` stays close to the replication.
+    fn flat_value(c: usize, x: usize, y: usize) -> u8 {
+        match c {
+            0 => ((x / 8 * 37 + y / 8 * 23) % 160 + 40) as u8,
+            1 => (120 + x / 8 % 8) as u8,
+            _ => (136 - y / 8 % 8) as u8,
+        }
+    }
+
+    #[test]
+    fn decodes_444() {
+        check_bands(&encode(37, 29, image::ColorType::RGB(8)), 8, 4);
+    }
+
+    #[test]
+    fn decodes_grayscale() {
+        check_bands(&encode(37, 29, image::ColorType::Gray(8)), 8, 2);
+    }
+
+    #[test]
+    fn decodes_420() {
+        let data = encode_flat(45, 35, &[(2, 2), (1, 1), (1, 1)], 0, flat_value);
+        check_bands(&data, 16, 6);
+    }
+
+    #[test]
+    fn decodes_restart_intervals() {
+        let data = encode_flat(45, 35, &[(2, 2), (1, 1), (1, 1)], 2, flat_value);
+        check_bands(&data, 16, 6);
+        let data = encode_flat(70, 20, &[(1, 1)], 3, flat_value);
+        check_bands(&data, 8, 1);
+    }
+
+    #[test]
+    fn rejects_overfull_huffman_tables() {
+        let mut counts = [0; 16];
+        counts[0] = 3;
+        assert!(Huffman::new(&counts, vec![0, 1, 2]).is_err());
+    }
+}
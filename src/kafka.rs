@@ -0,0 +1,475 @@
+//! Minimal Kafka producer (acks = 1, no compression).
+//!
+//! Records are sent with Produce v3 as v2 record batches, which brokers
+//! support since 0.11. Keys are assigned to partitions with murmur2, as the
+//! Java client does, so that the records of a key keep their order and land
+//! on the same partitions as those of other producers.
+
+use std::collections::hash_map;
+use std::collections::HashMap;
+use std::io;
+use std::net;
+use std::thread;
+use std::time;
+
+use std::io::{Read, Write};
+
+const API_PRODUCE: i16 = 0;
+const API_METADATA: i16 = 3;
+const CLIENT_ID: &str = "menoh-yolo";
+const TIMEOUT: time::Duration = time::Duration::from_secs(10);
+/// Errors after which the metadata is refreshed before retrying.
+const NOT_LEADER: [i16; 3] = [3, 5, 6];
+
+struct Partition {
+    id: i32,
+    leader: i32,
+}
+
+pub struct Producer {
+    bootstrap: String,
+    topic: String,
+    brokers: HashMap<i32, String>,
+    partitions: Vec<Partition>,
+    connections: HashMap<i32, net::TcpStream>,
+    correlation_id: i32,
+}
+
+impl Producer {
+    /// Connects to `bootstrap` (`host:port`) and fetches the partitions of
+    /// `topic`.
+    pub fn connect(bootstrap: &str, topic: &str) -> io::Result<Self> {
+        let mut producer = Self {
+            bootstrap: bootstrap.to_owned(),
+            topic: topic.to_owned(),
+            brokers: HashMap::new(),
+            partitions: Vec::new(),
+            connections: HashMap::new(),
+            correlation_id: 0,
+        };
+        // Topics created on the first request have no leader for a moment.
+        let mut attempt = 0;
+        loop {
+            match producer.refresh() {
+                Err(ref e) if attempt < 5 && e.kind() == io::ErrorKind::NotFound => {
+                    thread::sleep(time::Duration::from_millis(500));
+                    attempt += 1;
+                }
+                result => return result.map(|_| producer),
+            }
+        }
+    }
+
+    /// Sends a record and waits for the leader to acknowledge it. Records
+    /// without a key go to the partitions in turn.
+    pub fn send(&mut self, key: Option<&[u8]>, value: &[u8]) -> io::Result<()> {
+        match self.try_send(key, value) {
+            Err(ref e) if e.kind() != io::ErrorKind::InvalidInput => {
+                self.connections.clear();
+                self.refresh()?;
+                self.try_send(key, value)
+            }
+            result => result,
+        }
+    }
+
+    fn try_send(&mut self, key: Option<&[u8]>, value: &[u8]) -> io::Result<()> {
+        let index = match key {
+            Some(key) => (murmur2(key) & 0x7fff_ffff) as usize % self.partitions.len(),
+            None => self.correlation_id as usize % self.partitions.len(),
+        };
+        let (partition, leader) = (self.partitions[index].id, self.partitions[index].leader);
+        let timestamp = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64 * 1000 + d.subsec_millis() as i64)
+            .unwrap_or(0);
+        let batch = record_batch(key, value, timestamp);
+
+        let mut body = Vec::new();
+        // no transactional ID
+        put_i16(&mut body, -1);
+        // acks
+        put_i16(&mut body, 1);
+        put_i32(&mut body, TIMEOUT.as_secs() as i32 * 1000);
+        put_i32(&mut body, 1);
+        put_str(&mut body, &self.topic);
+        put_i32(&mut body, 1);
+        put_i32(&mut body, partition);
+        put_i32(&mut body, batch.len() as i32);
+        body.extend_from_slice(&batch);
+        let response = self.request(Some(leader), API_PRODUCE, 3, &body)?;
+
+        let mut r = Reader(&response);
+        for _ in 0..r.i32()? {
+            r.str()?;
+            for _ in 0..r.i32()? {
+                r.i32()?;
+                let error = r.i16()?;
+                if error != 0 {
+                    let kind = if NOT_LEADER.contains(&error) {
+                        io::ErrorKind::Other
+                    } else {
+                        io::ErrorKind::InvalidInput
+                    };
+                    return Err(io::Error::new(
+                        kind,
+                        format!("{}: produce failed with error code {}", self.topic, error),
+                    ));
+                }
+                // base offset, log append time
+                r.i64()?;
+                r.i64()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn refresh(&mut self) -> io::Result<()> {
+        let mut body = Vec::new();
+        put_i32(&mut body, 1);
+        put_str(&mut body, &self.topic);
+        // allow auto topic creation
+        body.push(1);
+        let response = self.request(None, API_METADATA, 4, &body)?;
+
+        let mut r = Reader(&response);
+        // throttle time
+        r.i32()?;
+        self.brokers.clear();
+        for _ in 0..r.i32()? {
+            let id = r.i32()?;
+            let host = r.str()?;
+            let port = r.i32()?;
+            // rack
+            r.str()?;
+            self.brokers.insert(id, format!("{}:{}", host, port));
+        }
+        // cluster ID, controller ID
+        r.str()?;
+        r.i32()?;
+        self.partitions.clear();
+        for _ in 0..r.i32()? {
+            let error = r.i16()?;
+            r.str()?;
+            // internal
+            r.bytes(1)?;
+            if error != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{}: metadata failed with error code {}", self.topic, error),
+                ));
+            }
+            for _ in 0..r.i32()? {
+                r.i16()?;
+                let id = r.i32()?;
+                let leader = r.i32()?;
+                // replicas, in-sync replicas
+                for _ in 0..2 {
+                    let n = r.i32()?;
+                    r.bytes(4 * n.max(0) as usize)?;
+                }
+                self.partitions.push(Partition { id, leader });
+            }
+        }
+        if self.partitions.iter().any(|p| p.leader < 0) || self.partitions.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{}: no leader for some partitions", self.topic),
+            ));
+        }
+        self.partitions.sort_by_key(|p| p.id);
+        Ok(())
+    }
+
+    /// Sends a request to the broker `node`, or to the bootstrap broker, and
+    /// returns the body of the response.
+    fn request(
+        &mut self,
+        node: Option<i32>,
+        api_key: i16,
+        api_version: i16,
+        body: &[u8],
+    ) -> io::Result<Vec<u8>> {
+        self.correlation_id = self.correlation_id.wrapping_add(1);
+        let mut request = Vec::with_capacity(body.len() + 32);
+        put_i32(&mut request, 0);
+        put_i16(&mut request, api_key);
+        put_i16(&mut request, api_version);
+        put_i32(&mut request, self.correlation_id);
+        put_str(&mut request, CLIENT_ID);
+        request.extend_from_slice(body);
+        let size = request.len() as i32 - 4;
+        request[..4].copy_from_slice(&be32(size as u32));
+
+        let addr = match node {
+            Some(node) => self.brokers.get(&node).cloned().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("unknown broker {}", node))
+            })?,
+            None => self.bootstrap.clone(),
+        };
+        let key = node.unwrap_or(-1);
+        let stream = match self.connections.entry(key) {
+            hash_map::Entry::Occupied(e) => e.into_mut(),
+            hash_map::Entry::Vacant(e) => {
+                let stream = net::TcpStream::connect(addr.as_str())?;
+                stream.set_read_timeout(Some(TIMEOUT))?;
+                stream.set_write_timeout(Some(TIMEOUT))?;
+                e.insert(stream)
+            }
+        };
+        let result = exchange(stream, &request, self.correlation_id);
+        if result.is_err() {
+            self.connections.remove(&key);
+        }
+        result
+    }
+}
+
+fn exchange(
+    stream: &mut net::TcpStream,
+    request: &[u8],
+    correlation_id: i32,
+) -> io::Result<Vec<u8>> {
+    stream.write_all(request)?;
+    let mut size = [0; 4];
+    stream.read_exact(&mut size)?;
+    let mut response = vec![0; Reader(&size).i32()?.max(0) as usize];
+    stream.read_exact(&mut response)?;
+    if response.len() < 4 || response[..4] != be32(correlation_id as u32) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unexpected correlation ID",
+        ));
+    }
+    Ok(response.split_off(4))
+}
+
+/// A batch of one record, `timestamp` being in milliseconds since the epoch.
+fn record_batch(key: Option<&[u8]>, value: &[u8], timestamp: i64) -> Vec<u8> {
+    let mut record = Vec::new();
+    // attributes, timestamp delta, offset delta
+    record.extend_from_slice(&[0, 0, 0]);
+    match key {
+        Some(key) => {
+            put_varint(&mut record, key.len() as i64);
+            record.extend_from_slice(key);
+        }
+        None => put_varint(&mut record, -1),
+    }
+    put_varint(&mut record, value.len() as i64);
+    record.extend_from_slice(value);
+    // headers
+    put_varint(&mut record, 0);
+
+    // the part covered by the CRC
+    let mut tail = Vec::new();
+    // attributes
+    put_i16(&mut tail, 0);
+    // last offset delta
+    put_i32(&mut tail, 0);
+    put_i64(&mut tail, timestamp);
+    put_i64(&mut tail, timestamp);
+    // producer ID, producer epoch, base sequence
+    put_i64(&mut tail, -1);
+    put_i16(&mut tail, -1);
+    put_i32(&mut tail, -1);
+    put_i32(&mut tail, 1);
+    put_varint(&mut tail, record.len() as i64);
+    tail.extend_from_slice(&record);
+
+    let mut batch = Vec::with_capacity(tail.len() + 21);
+    // base offset
+    put_i64(&mut batch, 0);
+    // batch length, from the partition leader epoch on
+    put_i32(&mut batch, tail.len() as i32 + 9);
+    // partition leader epoch
+    put_i32(&mut batch, -1);
+    // magic
+    batch.push(2);
+    batch.extend_from_slice(&be32(crc32c(&tail)));
+    batch.extend_from_slice(&tail);
+    batch
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "truncated response",
+            ));
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn i16(&mut self) -> io::Result<i16> {
+        let b = self.bytes(2)?;
+        Ok((b[0] as i16) << 8 | b[1] as i16)
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        let b = self.bytes(4)?;
+        Ok((b[0] as i32) << 24 | (b[1] as i32) << 16 | (b[2] as i32) << 8 | b[3] as i32)
+    }
+
+    fn i64(&mut self) -> io::Result<i64> {
+        Ok((self.i32()? as i64) << 32 | self.i32()? as u32 as i64)
+    }
+
+    /// A nullable string, empty if null.
+    fn str(&mut self) -> io::Result<String> {
+        let n = self.i16()?;
+        Ok(String::from_utf8_lossy(self.bytes(n.max(0) as usize)?).into_owned())
+    }
+}
+
+fn be32(n: u32) -> [u8; 4] {
+    [(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8]
+}
+
+fn put_i16(buf: &mut Vec<u8>, n: i16) {
+    buf.extend_from_slice(&[(n >> 8) as u8, n as u8]);
+}
+
+fn put_i32(buf: &mut Vec<u8>, n: i32) {
+    buf.extend_from_slice(&be32(n as u32));
+}
+
+fn put_i64(buf: &mut Vec<u8>, n: i64) {
+    put_i32(buf, (n >> 32) as i32);
+    put_i32(buf, n as i32);
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    put_i16(buf, s.len() as i16);
+    buf.extend_from_slice(s.as_bytes());
+}
+
+/// Zigzag-encoded variable-length integer.
+fn put_varint(buf: &mut Vec<u8>, n: i64) {
+    let mut n = ((n << 1) ^ (n >> 63)) as u64;
+    while n >= 0x80 {
+        buf.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 0 {
+                crc >> 1
+            } else {
+                crc >> 1 ^ 0x82f6_3b78
+            };
+        }
+    }
+    !crc
+}
+
+fn murmur2(data: &[u8]) -> u32 {
+    const M: u32 = 0x5bd1_e995;
+    let mut h = 0x9747_b28c ^ data.len() as u32;
+    for chunk in data.chunks(4) {
+        if chunk.len() < 4 {
+            let mut tail = 0;
+            for (i, &b) in chunk.iter().enumerate() {
+                tail |= (b as u32) << (8 * i);
+            }
+            h ^= tail;
+            h = h.wrapping_mul(M);
+            break;
+        }
+        let mut k = chunk[0] as u32
+            | (chunk[1] as u32) << 8
+            | (chunk[2] as u32) << 16
+            | (chunk[3] as u32) << 24;
+        k = k.wrapping_mul(M);
+        k ^= k >> 24;
+        k = k.wrapping_mul(M);
+        h = h.wrapping_mul(M);
+        h ^= k;
+    }
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^ h >> 15
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_varints() {
+        for &(n, expected) in &[
+            (0, &[0x00][..]),
+            (-1, &[0x01]),
+            (1, &[0x02]),
+            (-64, &[0x7f]),
+            (64, &[0x80, 0x01]),
+            (300, &[0xd8, 0x04]),
+            (
+                i64::MIN,
+                &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01],
+            ),
+        ] {
+            let mut buf = Vec::new();
+            put_varint(&mut buf, n);
+            assert_eq!(buf, expected, "{}", n);
+        }
+    }
+
+    #[test]
+    fn computes_crc32c() {
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+        assert_eq!(crc32c(&[0; 32]), 0x8a91_36aa);
+    }
+
+    #[test]
+    fn encodes_record_batches() {
+        let timestamp: &[u8] = &[0, 0, 0x01, 0x74, 0x87, 0x6e, 0x80, 0];
+        let expected = [
+            // base offset, batch length, partition leader epoch, magic
+            &[0; 8][..],
+            &[0, 0, 0, 0x3a],
+            &[0xff; 4],
+            &[2],
+            // CRC
+            &[0x1d, 0x10, 0x83, 0xaf],
+            // attributes, last offset delta
+            &[0, 0, 0, 0, 0, 0],
+            // first and max timestamps
+            timestamp,
+            timestamp,
+            // producer ID, producer epoch, base sequence
+            &[0xff; 14],
+            // records
+            &[0, 0, 0, 1],
+            // length, attributes, deltas, key, value, headers
+            &[0x10, 0, 0, 0, 0x02, b'k', 0x02, b'v', 0],
+        ]
+        .concat();
+        assert_eq!(record_batch(Some(b"k"), b"v", 1_600_000_000_000), expected);
+
+        let batch = record_batch(None, b"", 0);
+        // key length -1, empty value
+        assert_eq!(batch[batch.len() - 7..], [0x0c, 0, 0, 0, 0x01, 0, 0][..]);
+    }
+
+    #[test]
+    fn partitions_as_the_java_client() {
+        // the cases of the tests of the Java client, as unsigned
+        assert_eq!(murmur2(b"21"), 0xc5f2_f8ec);
+        assert_eq!(murmur2(b"foobar"), 0xd0e4_7bbe);
+        assert_eq!(murmur2(b"a-little-bit-long-string"), 0xc53b_1da0);
+        assert_eq!(murmur2(b"a-little-bit-longer-string"), 0xa768_c9c3);
+        assert_eq!(murmur2(b"abc"), 0x1c94_221b);
+    }
+}
@@ -0,0 +1,155 @@
+//! Label sets of `label_names`, given in the config as a list, as the name of
+//! a built-in set (`voc20`, `coco80`) or as the path of a file with one label
+//! per line.
+
+use serde;
+use std::fs;
+
+use serde::Deserialize;
+
+/// PASCAL VOC, in the order of ChainerCV.
+pub const VOC20: [&str; 20] = [
+    "aeroplane",
+    "bicycle",
+    "bird",
+    "boat",
+    "bottle",
+    "bus",
+    "car",
+    "cat",
+    "chair",
+    "cow",
+    "diningtable",
+    "dog",
+    "horse",
+    "motorbike",
+    "person",
+    "pottedplant",
+    "sheep",
+    "sofa",
+    "train",
+    "tvmonitor",
+];
+
+/// MS COCO detection categories, in the order of ChainerCV.
+pub const COCO80: [&str; 80] = [
+    "person",
+    "bicycle",
+    "car",
+    "motorcycle",
+    "airplane",
+    "bus",
+    "train",
+    "truck",
+    "boat",
+    "traffic light",
+    "fire hydrant",
+    "stop sign",
+    "parking meter",
+    "bench",
+    "bird",
+    "cat",
+    "dog",
+    "horse",
+    "sheep",
+    "cow",
+    "elephant",
+    "bear",
+    "zebra",
+    "giraffe",
+    "backpack",
+    "umbrella",
+    "handbag",
+    "tie",
+    "suitcase",
+    "frisbee",
+    "skis",
+    "snowboard",
+    "sports ball",
+    "kite",
+    "baseball bat",
+    "baseball glove",
+    "skateboard",
+    "surfboard",
+    "tennis racket",
+    "bottle",
+    "wine glass",
+    "cup",
+    "fork",
+    "knife",
+    "spoon",
+    "bowl",
+    "banana",
+    "apple",
+    "sandwich",
+    "orange",
+    "broccoli",
+    "carrot",
+    "hot dog",
+    "pizza",
+    "donut",
+    "cake",
+    "chair",
+    "couch",
+    "potted plant",
+    "bed",
+    "dining table",
+    "toilet",
+    "tv",
+    "laptop",
+    "mouse",
+    "remote",
+    "keyboard",
+    "cell phone",
+    "microwave",
+    "oven",
+    "toaster",
+    "sink",
+    "refrigerator",
+    "book",
+    "clock",
+    "vase",
+    "scissors",
+    "teddy bear",
+    "hair drier",
+    "toothbrush",
+];
+
+/// Returns a built-in label set by name.
+pub fn builtin(name: &str) -> Option<Vec<String>> {
+    let labels: &[&str] = match name {
+        "voc20" => &VOC20,
+        "coco80" => &COCO80,
+        _ => return None,
+    };
+    Some(labels.iter().map(|l| l.to_string()).collect())
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Labels {
+    List(Vec<String>),
+    Name(String),
+}
+
+/// `deserialize_with` of `label_names`.
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    match Labels::deserialize(deserializer)? {
+        Labels::List(labels) => Ok(labels),
+        Labels::Name(name) => match builtin(&name) {
+            Some(labels) => Ok(labels),
+            None => fs::read_to_string(&name)
+                .map(|text| {
+                    text.lines()
+                        .map(|l| l.trim())
+                        .filter(|l| !l.is_empty())
+                        .map(|l| l.to_owned())
+                        .collect()
+                })
+                .map_err(|e| serde::de::Error::custom(format!("{}: {}", name, e))),
+        },
+    }
+}
@@ -0,0 +1,107 @@
+extern crate gif;
+extern crate image;
+extern crate imageproc;
+#[cfg(unix)]
+extern crate libc;
+extern crate menoh;
+#[macro_use]
+extern crate ndarray;
+extern crate num_traits;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+extern crate rusttype;
+extern crate serde;
+#[allow(unused_imports)]
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+
+pub mod affinity;
+pub mod anchors;
+pub mod animation;
+pub mod augment;
+pub mod backend;
+pub mod background;
+pub mod bb;
+pub mod best_shot;
+pub mod boxes;
+pub mod coco;
+pub mod color;
+pub mod coords;
+#[cfg(unix)]
+pub mod daemon;
+pub mod detector;
+pub mod digest;
+pub mod doctor;
+pub mod downscale;
+pub mod drawing;
+pub mod dump;
+pub mod ensemble;
+pub mod eval;
+pub mod exif;
+pub mod filter;
+pub mod flip_check;
+pub mod geo;
+pub mod grpc;
+pub mod h2;
+pub mod home_assistant;
+pub mod hook;
+pub mod hpack;
+pub mod http;
+pub mod ids;
+pub mod inputs;
+pub mod jpeg;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod labels;
+pub mod memory;
+pub mod mjpeg;
+mod model_ext;
+pub mod mosaic;
+pub mod mqtt;
+pub mod nms;
+pub mod onnx;
+pub mod onvif;
+#[cfg(feature = "opencv")]
+pub mod opencv;
+pub mod overlap;
+pub mod pages;
+mod partial_cmp;
+#[cfg(feature = "pdf")]
+pub mod pdf;
+pub mod pipeline;
+pub mod profile;
+pub mod proto;
+pub mod quality;
+pub mod rank;
+pub mod raw;
+pub mod recorder;
+pub mod rect;
+pub mod redis;
+pub mod reload;
+pub mod remote;
+pub mod results;
+mod rfc3339;
+#[cfg(feature = "s3")]
+pub mod s3;
+pub mod scene;
+pub mod scheduler;
+pub mod schema;
+pub mod screen;
+pub mod server;
+pub mod severity;
+pub mod sink;
+pub mod soak;
+pub mod source;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod summary;
+pub mod template;
+pub mod thumbnail;
+pub mod tiling;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod tracker;
+pub mod yaml;
+pub mod yolo_v2;
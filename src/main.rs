@@ -1,132 +1,1273 @@
 extern crate docopt;
 extern crate image;
+#[cfg(feature = "opencv")]
 extern crate imageproc;
-extern crate menoh;
-#[macro_use]
-extern crate ndarray;
-extern crate num_traits;
+extern crate menoh_yolo;
+#[cfg(feature = "opencv")]
 extern crate rusttype;
-#[allow(unused_imports)]
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
 
+#[cfg(not(feature = "opencv"))]
+use std::collections;
 use std::error;
+use std::fmt;
 use std::fs;
 use std::path;
+use std::process;
 
-mod bb;
-mod drawing;
-mod model_ext;
+use menoh_yolo::bb;
+#[cfg(feature = "opencv")]
+use menoh_yolo::best_shot;
+use menoh_yolo::coco;
+#[cfg(unix)]
+use menoh_yolo::daemon;
+use menoh_yolo::detector;
+use menoh_yolo::doctor;
+#[cfg(not(feature = "opencv"))]
+use menoh_yolo::downscale;
+use menoh_yolo::drawing;
+#[cfg(not(feature = "opencv"))]
+use menoh_yolo::eval;
+#[cfg(not(feature = "opencv"))]
+use menoh_yolo::exif;
+#[cfg(not(feature = "opencv"))]
+use menoh_yolo::flip_check;
+use menoh_yolo::home_assistant;
+use menoh_yolo::hook;
+#[cfg(not(feature = "opencv"))]
+use menoh_yolo::onvif;
+#[cfg(feature = "opencv")]
+use menoh_yolo::opencv;
+use menoh_yolo::pipeline;
+#[cfg(feature = "opencv")]
+use menoh_yolo::recorder;
+#[cfg(any(unix, feature = "opencv"))]
+use menoh_yolo::reload;
+#[cfg(not(feature = "opencv"))]
+use menoh_yolo::scheduler;
+use menoh_yolo::schema;
+#[cfg(not(feature = "opencv"))]
+use menoh_yolo::server;
+use menoh_yolo::sink;
+#[cfg(not(feature = "opencv"))]
+use menoh_yolo::soak;
+use menoh_yolo::source;
+#[cfg(not(feature = "opencv"))]
+use menoh_yolo::tiling;
 #[cfg(feature = "opencv")]
-mod opencv;
-mod partial_cmp;
-mod rect;
-mod yolo_v2;
+use menoh_yolo::tracker;
+use menoh_yolo::yolo_v2;
 
+/// Exit status of the errors of the config, the pipeline and the arguments.
 #[cfg(not(feature = "opencv"))]
-pub fn main() -> Result<(), Box<dyn error::Error>> {
-    use rect::Rect;
+const EXIT_CONFIG: i32 = 2;
+/// Exit status of the `stream` runs that skipped failed frames.
+#[cfg(not(feature = "opencv"))]
+const EXIT_PARTIAL: i32 = 3;
+/// Failures in a row after which `stream` stops, as of a broken source.
+#[cfg(not(feature = "opencv"))]
+const MAX_CONSECUTIVE_FAILURES: usize = 10;
+
+#[derive(Debug)]
+struct ConfigError(String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl error::Error for ConfigError {}
+
+/// Frames skipped by `stream`.
+#[cfg(not(feature = "opencv"))]
+#[derive(Debug)]
+struct PartialFailure(schema::Summary);
+
+#[cfg(not(feature = "opencv"))]
+impl fmt::Display for PartialFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.0.processed + self.0.failed;
+        write!(f, "{} of {} frames failed", self.0.failed, total)?;
+        if self.0.stopped {
+            write!(f, ", stopped after {} in a row", MAX_CONSECUTIVE_FAILURES)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "opencv"))]
+impl error::Error for PartialFailure {}
+
+#[cfg(not(feature = "opencv"))]
+pub fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        process::exit(exit_code(&*e));
+    }
+}
+
+/// `EXIT_CONFIG` for the errors of the config, `EXIT_PARTIAL` for
+/// `PartialFailure`, 1 for the others.
+#[cfg(not(feature = "opencv"))]
+fn exit_code(e: &(dyn error::Error + 'static)) -> i32 {
+    if e.is::<PartialFailure>() {
+        return EXIT_PARTIAL;
+    }
+    let config = e.is::<ConfigError>()
+        || matches!(
+            e.downcast_ref::<detector::Error>(),
+            Some(detector::Error::Config(_))
+        )
+        || matches!(
+            e.downcast_ref::<pipeline::Error>(),
+            Some(pipeline::Error::Config(_)) | Some(pipeline::Error::Invalid(_))
+        );
+    if config {
+        EXIT_CONFIG
+    } else {
+        1
+    }
+}
+
+#[cfg(not(feature = "opencv"))]
+fn run() -> Result<(), Box<dyn error::Error>> {
+    use image::GenericImage;
+    use menoh_yolo::rect::Rect;
+    use std::iter;
+    use std::sync;
+    use std::time;
 
     const USAGE: &'static str = r#"
 YOLO on Menoh
 
-Usage: menoh-yolo [options] <src> <dest>
+Usage:
+  menoh-yolo doctor [options]
+  menoh-yolo eval [options] <images> <annotations>
+  menoh-yolo serve [options]
+  menoh-yolo soak [options] <source>
+  menoh-yolo stream [options] <source>
+  menoh-yolo run [options] <pipeline>
+  menoh-yolo [options] --export-preprocessing PATH
+  menoh-yolo [options] <src> <dest>
 
 Options:
   --model PATH   onnx model path [default: yolo_v2_voc0712.onnx]
   --config PATH  config file path [default: yolo_v2_voc0712.json]
+  --listen ADDR  address to serve HTTP on [default: 127.0.0.1:8080]
+  --daemon       detach from the terminal
+  --pid-file PATH  write the process ID to PATH
+  --onvif PATH   append ONVIF analytics metadata to PATH
+  --mqtt ADDR    publish states to Home Assistant via the MQTT broker at ADDR
+  --node-id ID   Home Assistant node ID [default: menoh_yolo]
+  --export-preprocessing PATH  write the preprocessing parameters to PATH as JSON
+  --flip-check   report the consistency with detections on the flipped image
+  --overlay      render the time, the source and the model name on the output
+  --json         print the detections, or the evaluation, as JSON (see the schema module)
+  --coco PATH    write the detections to PATH as COCO results, for evaluation
+  --provenance   include the grid cell, the anchor and the raw outputs of each box in the JSON
+  --check        validate the pipeline without running it
+  --samples DIR  check the anchors on the images of DIR (doctor)
+  --max-download BYTES  largest image to download when <src> is a URL [default: 20000000]
+  --remote URL   run the network on a KServe v2 server instead of --model
+  --binary       send tensors to --remote with the binary extension of Triton
+  --duty-cycle MS  work and sleep by turns, e.g. 200/800 in ms (stream, run)
+  --meta FIELDS  attach KEY=VALUE,... to the results, alongside the EXIF
+  --tile SIZE    run on overlapping crops of SIZE pixels, for large images
+  --tile-overlap PX  overlap of the crops of --tile [default: 64]
+  --tile-stream  decode baseline JPEGs for --tile by bands of rows, never whole
+  --report PATH  write the totals of stream to PATH as JSON
+  --start TIME   start <source> of stream at TIME, as HH:MM:SS
+  --end TIME     stop <source> of stream at TIME, excluded
+  --start-frame N  start <source> of stream at the frame of index N
+  --end-frame N  stop <source> of stream at the frame of index N, excluded
+  --debug-preprocess PATH  save the input tensor of <src> to PATH as an image, padding hatched
+  --lang LANG    show the labels in LANG, from display_names in the config
+  --font PATH    TrueType font of the labels, for scripts other than Latin, Greek and Cyrillic
+  --summary      print a sentence describing the detections instead of the boxes
+  --duration SECS  how long to soak for [default: 3600]
+  --interval SECS  seconds between the samples of soak [default: 60]
 "#;
 
     #[derive(Debug, Deserialize)]
     struct Args {
-        arg_src: path::PathBuf,
-        arg_dest: path::PathBuf,
+        cmd_doctor: bool,
+        cmd_eval: bool,
+        cmd_serve: bool,
+        cmd_soak: bool,
+        cmd_stream: bool,
+        cmd_run: bool,
+        arg_pipeline: Option<path::PathBuf>,
+        arg_source: Option<String>,
+        arg_images: Option<path::PathBuf>,
+        arg_annotations: Option<path::PathBuf>,
+        arg_src: Option<path::PathBuf>,
+        arg_dest: Option<path::PathBuf>,
         flag_model: path::PathBuf,
         flag_config: path::PathBuf,
+        flag_listen: String,
+        flag_daemon: bool,
+        flag_pid_file: Option<path::PathBuf>,
+        flag_onvif: Option<path::PathBuf>,
+        flag_mqtt: Option<String>,
+        flag_node_id: String,
+        flag_export_preprocessing: Option<path::PathBuf>,
+        flag_flip_check: bool,
+        flag_overlay: bool,
+        flag_json: bool,
+        flag_coco: Option<path::PathBuf>,
+        flag_provenance: bool,
+        flag_check: bool,
+        flag_samples: Option<path::PathBuf>,
+        flag_max_download: usize,
+        flag_remote: Option<String>,
+        flag_binary: bool,
+        flag_duty_cycle: Option<String>,
+        flag_meta: Option<String>,
+        flag_lang: Option<String>,
+        flag_font: Option<path::PathBuf>,
+        flag_summary: bool,
+        flag_tile: Option<u32>,
+        flag_tile_overlap: u32,
+        flag_tile_stream: bool,
+        flag_debug_preprocess: Option<path::PathBuf>,
+        flag_report: Option<path::PathBuf>,
+        flag_start: Option<String>,
+        flag_end: Option<String>,
+        flag_start_frame: Option<u64>,
+        flag_end_frame: Option<u64>,
+        flag_duration: u64,
+        flag_interval: u64,
     }
 
     let args: Args = docopt::Docopt::new(USAGE)
         .and_then(|d| d.deserialize())
         .unwrap_or_else(|e| e.exit());
+    let duty_cycle = match args.flag_duty_cycle {
+        Some(ref s) => Some(s.parse::<scheduler::Quota>().map_err(ConfigError)?),
+        None => None,
+    };
+    let fields = match args.flag_meta {
+        Some(ref s) => parse_fields(s).map_err(|e| ConfigError(e.to_string()))?,
+        None => collections::BTreeMap::new(),
+    };
 
-    let config = serde_json::from_reader(fs::File::open(args.flag_config)?)?;
-    let mut model = yolo_v2::YOLOv2::from_onnx(args.flag_model, &config, "mkldnn", "")?;
-    let font = drawing::get_font()?;
+    if args.cmd_doctor {
+        run_doctor(
+            &args.flag_model,
+            &args.flag_config,
+            args.flag_samples.as_ref(),
+        );
+    }
+    if args.cmd_run {
+        let path = args.arg_pipeline.unwrap();
+        let mut pipeline =
+            pipeline::load(&path).map_err(|e| ConfigError(format!("{}: {}", path.display(), e)))?;
+        if duty_cycle.is_some() {
+            pipeline.duty_cycle = duty_cycle;
+        }
+        pipeline.metadata.extend(fields);
+        if args.flag_check {
+            report(pipeline::check(&pipeline));
+        }
+        let _pid_file = start_service(args.flag_daemon, args.flag_pid_file.as_ref())?;
+        // the stages are built once, so SIGHUP would only terminate
+        #[cfg(unix)]
+        reload::ignore()?;
+        pipeline::run(pipeline, |warning| eprintln!("{}", warning))?;
+        return Ok(());
+    }
 
-    let mut img = image::open(args.arg_src)?;
-    let bbox = model.predict(&img)?;
-    drawing::draw_bbox_mut(&mut img, bbox.iter(), &config.label_names, &font);
-    for bb in bbox.iter() {
+    let mut config = load_config(&args.flag_config)?;
+    if let Some(path) = args.flag_export_preprocessing {
+        serde_json::to_writer_pretty(fs::File::create(path)?, &config.preprocessing())?;
+        return Ok(());
+    }
+
+    let find_output = |config: &mut yolo_v2::Config| match args.flag_remote {
+        Some(ref url) => config.find_remote_output(url),
+        None => config.find_onnx_output(&args.flag_model),
+    };
+    find_output(&mut config)?;
+    let mut display_names = display_names(&config, args.flag_lang.as_ref())?;
+    let _pid_file = start_service(args.flag_daemon, args.flag_pid_file.as_ref())?;
+    let mut model = open_model(
+        &config,
+        args.flag_remote.as_ref(),
+        &args.flag_model,
+        args.flag_binary,
+        args.flag_provenance,
+    )?;
+    if args.cmd_eval {
+        let dataset = eval::Dataset::load(
+            args.arg_annotations.unwrap(),
+            args.arg_images.unwrap(),
+            &config.label_names,
+        )?;
+        let evaluation = eval::run(&mut *model, &dataset)?;
+        if args.flag_json {
+            println!("{}", serde_json::to_string(&evaluation)?);
+        } else {
+            for c in evaluation.classes.iter() {
+                println!(
+                    "{}: AP@0.5 {:.4}, AP@[.5:.95] {:.4} ({} objects, {} detections)",
+                    c.label, c.ap50, c.ap, c.objects, c.detections
+                );
+            }
+            println!("mAP@0.5: {:.4}", evaluation.map50);
+            println!("mAP@[.5:.95]: {:.4}", evaluation.map);
+        }
+        return Ok(());
+    }
+    if args.cmd_soak {
+        let spec = args.arg_source.as_ref().unwrap();
+        let duration = time::Duration::from_secs(args.flag_duration);
+        let interval = time::Duration::from_secs(args.flag_interval.max(1));
+        let mut monitor = soak::Monitor::new(duration / 10);
+        let mut source = source::open(spec)?;
+        let mut reopened = true;
+        let mut iterations = 0;
+        let start = time::Instant::now();
+        let mut next_sample = start;
+        loop {
+            let done = start.elapsed() >= duration;
+            if done || time::Instant::now() >= next_sample {
+                let sample = monitor.sample(iterations)?;
+                if args.flag_json {
+                    println!("{}", serde_json::to_string(&sample)?);
+                } else {
+                    println!(
+                        "{:.0}s: {} inferences, RSS {} MiB, {} handles",
+                        sample.elapsed,
+                        sample.iterations,
+                        sample.usage.rss >> 20,
+                        sample.usage.handles
+                    );
+                }
+                next_sample += interval;
+            }
+            if done {
+                break;
+            }
+            // image files are looped over
+            let frame = match source.next_frame() {
+                Some(frame) => frame?,
+                None if reopened => return Err(format!("{} has no frames", spec).into()),
+                None => {
+                    source = source::open(spec)?;
+                    reopened = true;
+                    continue;
+                }
+            };
+            reopened = false;
+            model.predict(&frame.img)?;
+            iterations += 1;
+        }
+        let growth = monitor.growth();
+        if !growth.is_empty() {
+            return Err(growth.join(", ").into());
+        }
         println!(
-            "{}, ({}, {}, {}, {}) {}",
-            config.label_names[bb.label],
-            bb.top(),
-            bb.left(),
-            bb.bottom(),
-            bb.right(),
-            bb.score
+            "no growth after the warm-up, over {} samples",
+            monitor.samples().len()
+        );
+        return Ok(());
+    }
+    if args.cmd_serve || args.cmd_stream {
+        config
+            .threads
+            .inference
+            .apply()
+            .map_err(|e| format!("threads: {}", e))?;
+    }
+    if args.cmd_serve {
+        // the params are changed with PUT /config instead
+        #[cfg(unix)]
+        reload::ignore()?;
+        let mut server =
+            server::Server::bind(args.flag_listen.as_str(), model, &config.label_names)?;
+        if let Some(pool) = config.pool {
+            if args.flag_remote.is_some() {
+                return Err(Box::new(ConfigError(
+                    "a pool of detectors requires --model, not --remote".to_owned(),
+                )));
+            }
+            // the workers build their models on a config of their own
+            let mut pool_config = load_config(&args.flag_config)?;
+            pool_config.find_onnx_output(&args.flag_model)?;
+            let pool = scheduler::Pool::new(
+                &args.flag_model,
+                sync::Arc::new(pool_config),
+                "mkldnn",
+                "",
+                pool.workers,
+                pool.weights,
+                pool.limits,
+            )?;
+            pool.on_watermark(|alert| eprintln!("{}", alert));
+            server = server.with_pool(pool);
+        }
+        println!("listening on {}", server.local_addr()?);
+        #[cfg(unix)]
+        {
+            daemon::notify("READY=1")?;
+            // from the loop, so that a hung request stops the pings
+            let mut watchdog = daemon::Watchdog::new();
+            server.run_with(|| watchdog.ping())?;
+        }
+        #[cfg(not(unix))]
+        server.run()?;
+        return Ok(());
+    }
+    let run = match args.flag_remote {
+        Some(ref url) => schema::Run::new(url, &config, "remote", ""),
+        None => schema::Run::new(&args.flag_model.to_string_lossy(), &config, "mkldnn", ""),
+    }
+    .with_config_file(&args.flag_config);
+    let mut sinks = open_sinks(
+        &config,
+        &run,
+        args.flag_json,
+        args.flag_coco.as_ref(),
+        args.flag_mqtt.as_ref(),
+        &args.flag_node_id,
+    )?;
+    if args.cmd_stream {
+        let mut source = select_range(
+            source::open(args.arg_source.as_ref().unwrap())?,
+            (args.flag_start.as_ref(), args.flag_start_frame),
+            (args.flag_end.as_ref(), args.flag_end_frame),
+        )?;
+        let mut hooks =
+            hook::Hooks::new(&config.hooks, &config.label_names).with_summary(&config.summary);
+        let mut duty_cycle = scheduler::DutyCycle::new(duty_cycle);
+        let mut n_frame = 0;
+        let start = time::Instant::now();
+        let mut summary = schema::Summary::default();
+        let mut failures = 0;
+        let mut display = config
+            .params
+            .label_thresholds(config.params.thresholds.display, &config.label_names);
+        let mut onvif = match args.flag_onvif {
+            Some(ref path) => Some(onvif::Stream::open(path)?),
+            None => None,
+        };
+        #[cfg(unix)]
+        reload::listen()?;
+        while !summary.stopped {
+            #[cfg(unix)]
+            {
+                if reload::requested() {
+                    daemon::notify("RELOADING=1")?;
+                    let reloaded = load_config(&args.flag_config).and_then(|mut reloaded| {
+                        find_output(&mut reloaded)?;
+                        let names = self::display_names(&reloaded, args.flag_lang.as_ref())?;
+                        Ok((reloaded, names))
+                    });
+                    match reloaded {
+                        Ok((reloaded, names)) => {
+                            // the model borrows the config it replaces; the sinks
+                            // stay open, so that their files are not truncated
+                            drop(model);
+                            hooks.wait()?;
+                            config = reloaded;
+                            display_names = names;
+                            model = open_model(
+                                &config,
+                                args.flag_remote.as_ref(),
+                                &args.flag_model,
+                                args.flag_binary,
+                                args.flag_provenance,
+                            )?;
+                            hooks = hook::Hooks::new(&config.hooks, &config.label_names)
+                                .with_summary(&config.summary);
+                            display = config.params.label_thresholds(
+                                config.params.thresholds.display,
+                                &config.label_names,
+                            );
+                        }
+                        Err(e) => eprintln!("failed to reload config: {}", e),
+                    }
+                    daemon::notify("READY=1")?;
+                }
+            }
+            let frame = match source.next_frame() {
+                Some(frame) => frame,
+                None => break,
+            };
+            let source::Frame {
+                img,
+                time,
+                name,
+                mut metadata,
+                recorded,
+            } = match frame {
+                Ok(frame) => frame,
+                Err(e) => {
+                    eprintln!("frame {}: {}", n_frame, e);
+                    skip(&mut summary, &mut failures);
+                    n_frame += 1;
+                    continue;
+                }
+            };
+            if recorded.is_some() {
+                return Err("replayed results only run through pipelines".into());
+            }
+            metadata.fields.extend(fields.clone());
+            let (width, height) = img.dimensions();
+            let (img, factor) = match config.max_edge {
+                Some(max_edge) => downscale::cap(img, max_edge),
+                None => (img, 1.),
+            };
+            let (tile, overlap) = (args.flag_tile, args.flag_tile_overlap);
+            let scaled = (factor, (width, height));
+            let predicted = duty_cycle.run(|| predict(&mut *model, &img, scaled, tile, overlap));
+            let mut bbox = match predicted {
+                Ok(bbox) => bbox,
+                Err(e) => {
+                    eprintln!("{} {}: {}", name, n_frame, e);
+                    skip(&mut summary, &mut failures);
+                    n_frame += 1;
+                    continue;
+                }
+            };
+            failures = 0;
+            let mut warnings = model.warnings().to_vec();
+            let threshold = model.params().adapt(&mut bbox, &mut warnings);
+            if let Some(issue) = model.low_quality() {
+                eprintln!("{} {}: {} frame, not detected", name, n_frame, issue.name());
+            }
+            for warning in warnings {
+                eprintln!("{} {}: {}", name, n_frame, warning);
+            }
+            downscale::restore(bbox.iter_mut(), factor);
+            model.params().rounding.apply(bbox.iter_mut());
+            let shown = bb::above_each(&bbox, &display);
+            if args.flag_summary && !args.flag_json {
+                println!(
+                    "{} {}: {}",
+                    name,
+                    n_frame,
+                    config.summary.describe(shown, &display_names)
+                );
+            } else if !args.flag_json {
+                for bb in shown {
+                    println!(
+                        "{} {}: {}, ({}, {}, {}, {}) {}",
+                        name,
+                        n_frame,
+                        display_names[bb.label],
+                        bb.top(),
+                        bb.left(),
+                        bb.bottom(),
+                        bb.right(),
+                        bb.score
+                    );
+                }
+            }
+            let frame = schema::Frame::new(&bbox, &config.label_names, height, width)
+                .with_source(&name)
+                .with_time(time)
+                .with_metadata(&metadata)
+                .with_partial(model.partial())
+                .with_low_quality(model.low_quality())
+                .with_threshold(threshold)
+                .with_severities(&config.severities)
+                .with_ids();
+            if let Err(e) = sinks.send(&frame, &img) {
+                eprintln!("failed to send detections: {}", e);
+            }
+            hooks.fire(&bbox, height, width)?;
+            if let Some(ref mut onvif) = onvif {
+                let params = &config.params;
+                let alert = params.label_thresholds(params.thresholds.alert, &config.label_names);
+                onvif.write(
+                    time,
+                    bb::above_each(&bbox, &alert),
+                    &config.label_names,
+                    height,
+                    width,
+                )?;
+            }
+            summary.processed += 1;
+            summary.detections += frame.detections.len() as u64;
+            n_frame += 1;
+        }
+        sinks.finish()?;
+        hooks.wait()?;
+        let elapsed = start.elapsed();
+        summary.wall_secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
+        if let Some(ref path) = args.flag_report {
+            serde_json::to_writer_pretty(fs::File::create(path)?, &summary)?;
+        }
+        if summary.failed > 0 {
+            return Err(Box::new(PartialFailure(summary)));
+        }
+        return Ok(());
+    }
+    let font = match args.flag_font {
+        Some(ref path) => drawing::load_font(path)?,
+        None => drawing::get_font()?,
+    };
+
+    let (src, dest) = match (args.arg_src.clone(), args.arg_dest.clone()) {
+        (Some(src), Some(dest)) => (src, dest),
+        _ => {
+            return Err(Box::new(ConfigError(
+                "<src> and <dest> are required".to_owned(),
+            )))
+        }
+    };
+    if args.flag_tile_stream && args.flag_tile.is_none() {
+        return Err(Box::new(ConfigError(
+            "--tile-stream requires --tile".to_owned(),
+        )));
+    }
+    // the boxes of --tile-stream, found while decoding
+    let mut streamed = None;
+    let (mut metadata, (mut img, factor, (width, height))) = match src.to_str() {
+        Some(url) if url.starts_with("http://") || url.starts_with("https://") => {
+            let frame = source::fetch(url, args.flag_max_download)?;
+            (
+                frame.metadata,
+                downscale::prepare(frame.img, config.max_edge),
+            )
+        }
+        _ if args.flag_tile_stream => {
+            let jpeg = tiling::predict_jpeg(
+                &mut *model,
+                fs::File::open(&src)?,
+                args.flag_tile.unwrap(),
+                args.flag_tile_overlap,
+                config.max_edge.unwrap_or(tiling::THUMBNAIL_EDGE),
+            )?;
+            streamed = Some(jpeg.bbox);
+            (
+                exif::read_file(&src)?,
+                (jpeg.img, jpeg.factor, jpeg.dimensions),
+            )
+        }
+        _ => (
+            exif::read_file(&src)?,
+            downscale::open(&src, config.max_edge)?,
+        ),
+    };
+    metadata.fields.extend(fields);
+    if args.flag_flip_check {
+        for &(axis, flip) in flip_check::FLIPS.iter() {
+            let mut matches = flip_check::check(&mut *model, &img, flip)?;
+            for m in matches.iter_mut() {
+                downscale::restore(iter::once(&mut m.bb), factor);
+                println!(
+                    "{} flip: {}, ({}, {}, {}, {}) {}: IoU {}, score difference {}",
+                    axis,
+                    display_names[m.bb.label],
+                    m.bb.top(),
+                    m.bb.left(),
+                    m.bb.bottom(),
+                    m.bb.right(),
+                    m.bb.score,
+                    m.iou,
+                    m.score_diff
+                );
+            }
+            if let Some(iou) = flip_check::mean_iou(&matches) {
+                println!("{} flip: mean IoU {}", axis, iou);
+                if iou < flip_check::MIN_MEAN_IOU {
+                    eprintln!(
+                        "the detections do not follow the {} flip: check cell_order and cell_offset of the config",
+                        axis
+                    );
+                }
+            }
+        }
+    }
+    if let Some(ref path) = args.flag_debug_preprocess {
+        if args.flag_tile.is_some() {
+            return Err("--debug-preprocess cannot be used with --tile".into());
+        }
+        yolo_v2::render_input(&img, &config).save(path)?;
+    }
+    let mut bbox = match streamed {
+        Some(bbox) => bbox,
+        None => predict(
+            &mut *model,
+            &img,
+            (factor, (width, height)),
+            args.flag_tile,
+            args.flag_tile_overlap,
+        )?,
+    };
+    let mut warnings = model.warnings().to_vec();
+    let threshold = model.params().adapt(&mut bbox, &mut warnings);
+    if let Some(issue) = model.low_quality() {
+        eprintln!("{}: {} image, not detected", src.display(), issue.name());
+    }
+    for warning in warnings {
+        eprintln!("{}: {}", src.display(), warning);
+    }
+    // drawn in the pixels of img, once the sinks got it undrawn
+    let params = &config.params;
+    let display = params.label_thresholds(params.thresholds.display, &config.label_names);
+    let alert = params.label_thresholds(params.thresholds.alert, &config.label_names);
+    let drawn: Vec<_> = bb::above_each(&bbox, &display).cloned().collect();
+    downscale::restore(bbox.iter_mut(), factor);
+    model.params().rounding.apply(bbox.iter_mut());
+    let alerts: Vec<_> = bb::above_each(&bbox, &alert).cloned().collect();
+    let shown = bb::above_each(&bbox, &display);
+    if args.flag_summary && !args.flag_json {
+        println!("{}", config.summary.describe(shown, &display_names));
+    } else if !args.flag_json {
+        for bb in shown {
+            println!(
+                "{}, ({}, {}, {}, {}) {}{}",
+                display_names[bb.label],
+                bb.top(),
+                bb.left(),
+                bb.bottom(),
+                bb.right(),
+                bb.score,
+                if bb.truncated { " (truncated)" } else { "" }
+            );
+        }
+    }
+    let frame = schema::Frame::new(&bbox, &config.label_names, height, width)
+        .with_source(&src.to_string_lossy())
+        .with_time(time::SystemTime::now())
+        .with_metadata(&metadata)
+        .with_partial(model.partial())
+        .with_low_quality(model.low_quality())
+        .with_threshold(threshold)
+        .with_severities(&config.severities)
+        .with_ids();
+    sinks.send(&frame, &img)?;
+    sinks.finish()?;
+    drawing::draw_bbox_mut(&mut img, drawn.iter(), &display_names, &font);
+    if args.flag_overlay {
+        drawing::draw_overlay_mut(
+            &mut img,
+            &drawing::Overlay {
+                time: Some(time::SystemTime::now()),
+                stream: src.to_str(),
+                model: args.flag_model.file_name().and_then(|n| n.to_str()),
+                fps: None,
+            },
+            drawing::Corner::BottomLeft,
+            &font,
         );
     }
-    img.save(args.arg_dest)?;
+    if let Some(path) = args.flag_onvif {
+        onvif::Stream::open(path)?.write(
+            time::SystemTime::now(),
+            alerts.iter(),
+            &config.label_names,
+            height,
+            width,
+        )?;
+    }
+    if !config.hooks.is_empty() {
+        let mut hooks =
+            hook::Hooks::new(&config.hooks, &config.label_names).with_summary(&config.summary);
+        hooks.fire(&bbox, height, width)?;
+        hooks.wait()?;
+    }
+    img.save(dest)?;
 
     Ok(())
 }
 
 #[cfg(feature = "opencv")]
 pub fn main() -> Result<(), Box<dyn error::Error>> {
+    use image::GenericImage;
     use std::time;
 
     const USAGE: &'static str = r#"
 YOLO on Menoh
 
-Usage: menoh-yolo [options]
+Usage:
+  menoh-yolo doctor [options]
+  menoh-yolo run [options] <pipeline>
+  menoh-yolo [options]
 
 Options:
   --model PATH   onnx model path [default: yolo_v2_voc0712.onnx]
   --config PATH  config file path [default: yolo_v2_voc0712.json]
   --camera ID    camera ID [default: 0]
+  --source SPEC  read frames from SPEC instead of the camera (see the source module)
+  --record DIR   save clips triggered by the recording rules to DIR
+  --gif WIDTH    also save the clips as GIFs at most WIDTH pixels wide
+  --best-shots DIR  save the best crop of each tracked object to DIR
+  --mqtt ADDR    publish states to Home Assistant via the MQTT broker at ADDR
+  --node-id ID   Home Assistant node ID [default: menoh_yolo]
+  --overlay      render the time, the camera and the model name with the FPS
+  --daemon       detach from the terminal
+  --pid-file PATH  write the process ID to PATH
+  --check        validate the pipeline without running it
+  --samples DIR  check the anchors on the images of DIR (doctor)
+  --lang LANG    show the labels in LANG, from display_names in the config
+  --font PATH    TrueType font of the labels, for scripts other than Latin, Greek and Cyrillic
+  --start TIME   start --source at TIME, as HH:MM:SS
+  --end TIME     stop --source at TIME, excluded
+  --start-frame N  start --source at the frame of index N
+  --end-frame N  stop --source at the frame of index N, excluded
 "#;
 
     #[derive(Debug, Deserialize)]
     struct Args {
+        cmd_doctor: bool,
+        cmd_run: bool,
+        arg_pipeline: Option<path::PathBuf>,
         flag_model: path::PathBuf,
         flag_config: path::PathBuf,
         flag_camera: usize,
+        flag_source: Option<String>,
+        flag_record: Option<path::PathBuf>,
+        flag_gif: Option<u32>,
+        flag_best_shots: Option<path::PathBuf>,
+        flag_mqtt: Option<String>,
+        flag_node_id: String,
+        flag_overlay: bool,
+        flag_daemon: bool,
+        flag_pid_file: Option<path::PathBuf>,
+        flag_check: bool,
+        flag_samples: Option<path::PathBuf>,
+        flag_lang: Option<String>,
+        flag_font: Option<path::PathBuf>,
+        flag_start: Option<String>,
+        flag_end: Option<String>,
+        flag_start_frame: Option<u64>,
+        flag_end_frame: Option<u64>,
     }
 
     let args: Args = docopt::Docopt::new(USAGE)
         .and_then(|d| d.deserialize())
         .unwrap_or_else(|e| e.exit());
 
-    let config = serde_json::from_reader(fs::File::open(args.flag_config)?)?;
-    let mut model = yolo_v2::YOLOv2::from_onnx(args.flag_model, &config, "mkldnn", "")?;
-    let font = drawing::get_font()?;
+    if args.cmd_doctor {
+        run_doctor(
+            &args.flag_model,
+            &args.flag_config,
+            args.flag_samples.as_ref(),
+        );
+    }
+    if args.cmd_run {
+        let pipeline = pipeline::load(args.arg_pipeline.unwrap())?;
+        if args.flag_check {
+            report(pipeline::check(&pipeline));
+        }
+        let _pid_file = start_service(args.flag_daemon, args.flag_pid_file.as_ref())?;
+        #[cfg(unix)]
+        reload::ignore()?;
+        pipeline::run(pipeline, |warning| eprintln!("{}", warning))?;
+        return Ok(());
+    }
+
+    let mut config = load_model_config(&args.flag_config, &args.flag_model)?;
+    let font = match args.flag_font {
+        Some(ref path) => drawing::load_font(path)?,
+        None => drawing::get_font()?,
+    };
+    let _pid_file = start_service(args.flag_daemon, args.flag_pid_file.as_ref())?;
+    #[cfg(unix)]
+    reload::listen()?;
+    #[cfg(unix)]
+    let mut watchdog = daemon::Watchdog::new();
 
-    let mut cap = opencv::Capture::open_camera(args.flag_camera).unwrap();
+    let mut source = match args.flag_source {
+        Some(ref spec) => select_range(
+            source::open(spec)?,
+            (args.flag_start.as_ref(), args.flag_start_frame),
+            (args.flag_end.as_ref(), args.flag_end_frame),
+        )?,
+        None => Box::new(source::Capture::open_camera(args.flag_camera)?),
+    };
+    let mut best_shots = match args.flag_best_shots {
+        Some(ref dir) => {
+            fs::create_dir_all(dir)?;
+            Some(best_shot::BestShots::new(tracker::Tracker::new(0.3, 15)))
+        }
+        None => None,
+    };
     let start = time::Instant::now();
     let mut n_frame = 0;
 
-    while opencv::wait_key(Some(10)) != Some('q') {
-        let mut img = cap.query_frame().unwrap().into_image();
-        let bbox = model.predict(&img)?;
-        drawing::draw_bbox_mut(&mut img, bbox.iter(), &config.label_names, &font);
+    loop {
+        let reloaded = {
+            let mut model = detector::from_onnx(&args.flag_model, &config, "mkldnn", "")?;
+            #[cfg(unix)]
+            daemon::notify("READY=1")?;
+            let run = schema::Run::new(&args.flag_model.to_string_lossy(), &config, "mkldnn", "")
+                .with_config_file(&args.flag_config);
+            let mut sinks = open_sinks(
+                &config,
+                &run,
+                false,
+                None,
+                args.flag_mqtt.as_ref(),
+                &args.flag_node_id,
+            )?;
+            let mut hooks =
+                hook::Hooks::new(&config.hooks, &config.label_names).with_summary(&config.summary);
+            let display_names = display_names(&config, args.flag_lang.as_ref())?;
+            let mut recorder = match args.flag_record {
+                Some(_) if config.recording.is_empty() => {
+                    return Err("--record requires \"recording\" in the config".into())
+                }
+                Some(ref dir) => {
+                    let mut recorder =
+                        recorder::Recorder::new(dir, &config.recording, &config.label_names)?;
+                    recorder.set_gif(args.flag_gif);
+                    Some(recorder)
+                }
+                None => None,
+            };
 
-        n_frame += 1;
-        imageproc::drawing::draw_text_mut(
-            &mut img,
-            image::Rgba { data: [0, 0, 0, 0] },
-            0,
-            0,
-            rusttype::Scale::uniform(32.),
-            &font,
-            &format!(
-                "{:.2} FPS",
-                n_frame as f64 / start.elapsed().as_secs() as f64
-            ),
-        );
-        opencv::show_image("result", &opencv::IplImage::from_image(img))?;
+            let mut reloaded = None;
+            while opencv::wait_key(Some(10)) != Some('q') {
+                #[cfg(unix)]
+                watchdog.ping()?;
+                if reload::requested() {
+                    #[cfg(unix)]
+                    daemon::notify("RELOADING=1")?;
+                    match load_model_config(&args.flag_config, &args.flag_model) {
+                        Ok(config) => {
+                            reloaded = Some(config);
+                            break;
+                        }
+                        Err(e) => eprintln!("failed to reload config: {}", e),
+                    }
+                }
+
+                let source::Frame {
+                    mut img,
+                    time,
+                    name,
+                    ..
+                } = match source.next_frame() {
+                    Some(frame) => frame?,
+                    None => break,
+                };
+                let mut bbox = model.predict(&img)?;
+                model.params().rounding.apply(bbox.iter_mut());
+                let mut warnings = model.warnings().to_vec();
+                let threshold = model.params().adapt(&mut bbox, &mut warnings);
+                for warning in warnings {
+                    eprintln!("{}: {}", name, warning);
+                }
+                let params = &config.params;
+                let track = params.label_thresholds(params.thresholds.track, &config.label_names);
+                let display =
+                    params.label_thresholds(params.thresholds.display, &config.label_names);
+                if let Some(ref mut recorder) = recorder {
+                    recorder.record(&img, &bbox)?;
+                }
+                hooks.fire(&bbox, img.height(), img.width())?;
+                if let Some(ref mut best_shots) = best_shots {
+                    let tracked: Vec<_> = bb::above_each(&bbox, &track).cloned().collect();
+                    for shot in best_shots.update(&img, &tracked) {
+                        save_shot(args.flag_best_shots.as_ref().unwrap(), &shot, &config)?;
+                    }
+                }
+                let (width, height) = img.dimensions();
+                let frame = schema::Frame::new(&bbox, &config.label_names, height, width)
+                    .with_source(&name)
+                    .with_time(time)
+                    .with_partial(model.partial())
+                    .with_low_quality(model.low_quality())
+                    .with_threshold(threshold)
+                    .with_severities(&config.severities)
+                    .with_ids();
+                if let Err(e) = sinks.send(&frame, &img) {
+                    eprintln!("failed to send detections: {}", e);
+                }
+                drawing::draw_bbox_mut(
+                    &mut img,
+                    bb::above_each(&bbox, &display),
+                    &display_names,
+                    &font,
+                );
+
+                n_frame += 1;
+                let fps = n_frame as f64 / start.elapsed().as_secs() as f64;
+                if args.flag_overlay {
+                    let overlay = drawing::Overlay {
+                        time: Some(time),
+                        stream: Some(&name),
+                        model: args.flag_model.file_name().and_then(|n| n.to_str()),
+                        fps: Some(fps),
+                    };
+                    drawing::draw_overlay_mut(&mut img, &overlay, drawing::Corner::TopLeft, &font);
+                } else {
+                    imageproc::drawing::draw_text_mut(
+                        &mut img,
+                        image::Rgba { data: [0, 0, 0, 0] },
+                        0,
+                        0,
+                        rusttype::Scale::uniform(32.),
+                        &font,
+                        &format!("{:.2} FPS", fps),
+                    );
+                }
+                opencv::show_image("result", &opencv::IplImage::from_image(img))?;
+            }
+            if let Some(ref mut recorder) = recorder {
+                recorder.finish()?;
+            }
+            reloaded
+        };
+        match reloaded {
+            Some(reloaded) => config = reloaded,
+            None => break,
+        }
     }
+    if let Some(ref mut best_shots) = best_shots {
+        for shot in best_shots.finish() {
+            save_shot(args.flag_best_shots.as_ref().unwrap(), &shot, &config)?;
+        }
+    }
+
+    Ok(())
+}
 
+#[cfg(feature = "opencv")]
+fn save_shot(
+    dir: &path::Path,
+    shot: &best_shot::Shot,
+    config: &yolo_v2::Config,
+) -> Result<(), Box<dyn error::Error>> {
+    let name = format!("{}-{}.jpg", shot.track, config.label_names[shot.bb.label]);
+    shot.img.save(dir.join(name))?;
     Ok(())
 }
+
+#[cfg(unix)]
+fn start_service(
+    detach: bool,
+    pid_file: Option<&path::PathBuf>,
+) -> Result<Option<daemon::PidFile>, Box<dyn error::Error>> {
+    if detach {
+        daemon::daemonize()?;
+    }
+    match pid_file {
+        Some(path) => Ok(Some(daemon::PidFile::create(path)?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(not(unix))]
+fn start_service(
+    detach: bool,
+    pid_file: Option<&path::PathBuf>,
+) -> Result<Option<()>, Box<dyn error::Error>> {
+    if detach || pid_file.is_some() {
+        Err("--daemon and --pid-file are only supported on Unix".into())
+    } else {
+        Ok(None)
+    }
+}
+
+/// Opens the sinks of the config, along with those enabled on the command
+/// line, and writes their headers.
+fn open_sinks(
+    config: &yolo_v2::Config,
+    run: &schema::Run,
+    json: bool,
+    coco: Option<&path::PathBuf>,
+    mqtt: Option<&String>,
+    node_id: &str,
+) -> Result<sink::Sinks, Box<dyn error::Error>> {
+    let params = &config.params;
+    let thresholds = &params.thresholds;
+    let mut sinks = sink::Sinks::open(
+        &config.sinks,
+        &config.label_names,
+        thresholds.display,
+        &params.class_thresholds,
+    )?;
+    if json {
+        sinks.attach(Box::new(sink::Stdout), None, thresholds.display);
+    }
+    if let Some(path) = coco {
+        // evaluation ranks every box kept by the model
+        let categories = coco::Categories::new(&config.label_names, &Default::default());
+        sinks.attach(
+            Box::new(sink::Coco::create(path, categories, false)?),
+            None,
+            params.min_score(),
+        );
+    }
+    if let Some(addr) = mqtt {
+        let ha =
+            home_assistant::HomeAssistant::connect(addr.as_str(), node_id, &config.label_names)?;
+        sinks.attach(
+            Box::new(sink::HomeAssistant::new(ha, &config.label_names)),
+            None,
+            thresholds.alert,
+        );
+    }
+    sinks.header(run)?;
+    Ok(sinks)
+}
+
+/// Limits `source` to the frames from `start` to `end`, each given as a time
+/// or as the index of a frame.
+fn select_range(
+    source: Box<dyn source::Source>,
+    start: (Option<&String>, Option<u64>),
+    end: (Option<&String>, Option<u64>),
+) -> Result<Box<dyn source::Source>, Box<dyn error::Error>> {
+    let position = |flag, p: (Option<&String>, Option<u64>)| match p {
+        (Some(_), Some(_)) => Err(ConfigError(format!(
+            "--{} and --{}-frame are exclusive",
+            flag, flag
+        ))),
+        (Some(time), None) => source::Position::parse_time(time)
+            .map(Some)
+            .map_err(|e| ConfigError(format!("--{}: {}", flag, e))),
+        (None, Some(frame)) => Ok(Some(source::Position::Frame(frame))),
+        (None, None) => Ok(None),
+    };
+    let (start, end) = (position("start", start)?, position("end", end)?);
+    if start.is_none() && end.is_none() {
+        return Ok(source);
+    }
+    Ok(Box::new(source::Range::new(source, start, end)?))
+}
+
+/// Counts a failed frame of `stream`, stopping after
+/// `MAX_CONSECUTIVE_FAILURES` in a row.
+#[cfg(not(feature = "opencv"))]
+fn skip(summary: &mut schema::Summary, failures: &mut usize) {
+    summary.failed += 1;
+    *failures += 1;
+    summary.stopped = *failures >= MAX_CONSECUTIVE_FAILURES;
+}
+
+/// Parses `KEY=VALUE,...`.
+#[cfg(not(feature = "opencv"))]
+fn parse_fields(s: &str) -> Result<collections::BTreeMap<String, String>, Box<dyn error::Error>> {
+    s.split(',')
+        .filter(|field| !field.is_empty())
+        .map(|field| {
+            let mut kv = field.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some(key), Some(value)) if !key.is_empty() => {
+                    Ok((key.to_owned(), value.to_owned()))
+                }
+                _ => Err(format!("{}: expected KEY=VALUE", field).into()),
+            }
+        })
+        .collect()
+}
+
+/// `predict`, on the crops of `tile` pixels when set (see `tiling`), of
+/// `img` scaled down by `factor` from an image of `dimensions` (see
+/// `downscale::predict`).
+#[cfg(not(feature = "opencv"))]
+fn predict(
+    model: &mut dyn detector::Detector,
+    img: &image::DynamicImage,
+    (factor, dimensions): (f32, (u32, u32)),
+    tile: Option<u32>,
+    overlap: u32,
+) -> Result<Vec<bb::Bb>, Box<dyn error::Error>> {
+    Ok(downscale::predict(
+        model,
+        img,
+        factor,
+        dimensions,
+        |model| match tile {
+            Some(size) => tiling::predict(model, img, size, overlap),
+            None => model.predict(img),
+        },
+    )?)
+}
+
+/// The labels in `lang` (see `Config::display_names`), or as is.
+fn display_names(
+    config: &yolo_v2::Config,
+    lang: Option<&String>,
+) -> Result<Vec<String>, Box<dyn error::Error>> {
+    match lang {
+        Some(lang) => config
+            .display_names(lang)
+            .ok_or_else(|| format!("no display_names for {} in the config", lang).into()),
+        None => Ok(config.label_names.clone()),
+    }
+}
+
+#[cfg(not(feature = "opencv"))]
+fn open_model<'a>(
+    config: &'a yolo_v2::Config,
+    remote: Option<&String>,
+    model: &path::Path,
+    binary: bool,
+    provenance: bool,
+) -> Result<Box<dyn detector::Detector + 'a>, detector::Error> {
+    let mut model = match remote {
+        Some(url) => detector::from_remote(url, config, binary)?,
+        None => detector::from_onnx(model, config, "mkldnn", "")?,
+    };
+    if provenance {
+        let mut params = model.params().clone();
+        params.provenance = true;
+        model.set_params(params);
+    }
+    Ok(model)
+}
+
+fn load_config(path: &path::Path) -> Result<yolo_v2::Config, Box<dyn error::Error>> {
+    let config = fs::File::open(path)
+        .map_err(|e| e.to_string())
+        .and_then(|file| serde_json::from_reader(file).map_err(|e| e.to_string()));
+    Ok(config.map_err(|e| ConfigError(format!("{}: {}", path.display(), e)))?)
+}
+
+/// `load_config`, finding the output in the model when omitted.
+#[cfg(feature = "opencv")]
+fn load_model_config(
+    path: &path::Path,
+    model: &path::Path,
+) -> Result<yolo_v2::Config, Box<dyn error::Error>> {
+    let mut config = load_config(path)?;
+    config.find_onnx_output(model)?;
+    Ok(config)
+}
+
+fn run_doctor(model: &path::Path, config: &path::Path, samples: Option<&path::PathBuf>) -> ! {
+    let mut config = match load_config(config) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("[FAIL] config: {}", e);
+            process::exit(1);
+        }
+    };
+    if let Err(e) = config.find_onnx_output(model) {
+        println!("[FAIL] output: {}", e);
+        process::exit(1);
+    }
+    report(doctor::run(
+        model,
+        &config,
+        "mkldnn",
+        "",
+        samples.map(|p| p.as_path()),
+    ))
+}
+
+/// Prints the checks and exits, with 1 if any failed.
+fn report(checks: Vec<doctor::Check>) -> ! {
+    let mut ok = true;
+    for check in checks {
+        match check.result {
+            Ok(message) => println!("[ OK ] {}: {}", check.name, message),
+            Err(message) => {
+                println!("[FAIL] {}: {}", check.name, message);
+                ok = false;
+            }
+        }
+    }
+    process::exit(if ok { 0 } else { 1 })
+}
@@ -0,0 +1,44 @@
+//! Approximate memory accounting.
+
+use image;
+use std::ops;
+
+use image::GenericImage;
+
+/// Bytes held by the different parts of the detector. The figures are
+/// estimates: the weights are approximated by the size of the ONNX file, and
+/// backend internals (e.g. workspace of MKL-DNN) are not included.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct Usage {
+    pub weights: usize,
+    pub buffers: usize,
+    pub queues: usize,
+}
+
+impl Usage {
+    pub fn total(&self) -> usize {
+        self.weights + self.buffers + self.queues
+    }
+}
+
+impl ops::Add for Usage {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self {
+            weights: self.weights + rhs.weights,
+            buffers: self.buffers + rhs.buffers,
+            queues: self.queues + rhs.queues,
+        }
+    }
+}
+
+pub fn image_size(img: &image::DynamicImage) -> usize {
+    let channels = match img.color() {
+        image::ColorType::Gray(_) => 1,
+        image::ColorType::GrayA(_) => 2,
+        image::ColorType::RGB(_) => 3,
+        image::ColorType::RGBA(_) => 4,
+        image::ColorType::Palette(_) => 1,
+    };
+    img.height() as usize * img.width() as usize * channels
+}
@@ -0,0 +1,176 @@
+//! MJPEG over HTTP (`multipart/x-mixed-replace`) source, as served by most IP
+//! cameras.
+
+use image;
+use std::io;
+
+use http;
+
+use std::io::{BufRead, Read, Write};
+
+pub struct Stream {
+    reader: io::BufReader<http::Connection>,
+    boundary: String,
+    /// Whether the delimiter of the next part has already been consumed.
+    at_part: bool,
+}
+
+impl Stream {
+    /// Connects to `url` (`http[s]://[user:password@]host[:port]/path`).
+    pub fn open(url: &str) -> io::Result<Self> {
+        let url = http::Url::parse(url)?;
+        let mut stream = http::connect(&url, None)?;
+        // HTTP/1.0 keeps servers from using chunked transfer encoding.
+        let mut request = format!("GET {} HTTP/1.0\r\nHost: {}\r\n", url.path, url.host);
+        if let Some(ref userinfo) = url.userinfo {
+            request += &format!(
+                "Authorization: Basic {}\r\n",
+                http::base64(userinfo.as_bytes())
+            );
+        }
+        request += "\r\n";
+        stream.write_all(request.as_bytes())?;
+
+        let mut reader = io::BufReader::new(stream);
+        let (status, headers) = http::read_response_head(&mut reader)?;
+        if status != 200 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unexpected status {}", status),
+            ));
+        }
+        let content_type = headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("Content-Type"))
+            .map(|(_, v)| v.as_str())
+            .unwrap_or("");
+        let boundary = boundary(content_type).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("not a multipart stream: {}", content_type),
+            )
+        })?;
+        Ok(Self {
+            reader,
+            boundary,
+            at_part: false,
+        })
+    }
+
+    /// Returns the JPEG data of the next frame.
+    pub fn next_jpeg(&mut self) -> io::Result<Vec<u8>> {
+        if !self.at_part {
+            loop {
+                let mut line = String::new();
+                if self.reader.read_line(&mut line)? == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                if self.is_delimiter(line.trim().as_bytes()) {
+                    break;
+                }
+            }
+        }
+        self.at_part = false;
+
+        let mut len = None;
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let line = line.trim();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(i) = line.find(':') {
+                if line[..i].trim().eq_ignore_ascii_case("Content-Length") {
+                    len = line[i + 1..].trim().parse::<usize>().ok();
+                }
+            }
+        }
+
+        match len {
+            Some(len) => {
+                let mut data = vec![0; len];
+                self.reader.read_exact(&mut data)?;
+                Ok(data)
+            }
+            // Without Content-Length, the part ends at the next delimiter.
+            None => {
+                let mut data = Vec::new();
+                loop {
+                    let start = data.len();
+                    if self.reader.read_until(b'\n', &mut data)? == 0 {
+                        if data.is_empty() {
+                            return Err(io::ErrorKind::UnexpectedEof.into());
+                        }
+                        break;
+                    }
+                    if self.is_delimiter(trim_line(&data[start..])) {
+                        data.truncate(start);
+                        self.at_part = true;
+                        break;
+                    }
+                }
+                let len = trim_line(&data).len();
+                data.truncate(len);
+                Ok(data)
+            }
+        }
+    }
+
+    /// The delimiter is `--` followed by the boundary. Some cameras already
+    /// include the dashes in the boundary and omit them in the delimiter.
+    fn is_delimiter(&self, line: &[u8]) -> bool {
+        let b = self.boundary.as_bytes();
+        (line.len() == b.len() + 2 && line.starts_with(b"--") && &line[2..] == b)
+            || (b.starts_with(b"--") && line == b)
+    }
+
+    pub fn next_frame(&mut self) -> io::Result<image::DynamicImage> {
+        let data = self.next_jpeg()?;
+        image::load_from_memory_with_format(&data, image::ImageFormat::JPEG)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
+    }
+}
+
+impl Iterator for Stream {
+    type Item = io::Result<image::DynamicImage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_frame() {
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => None,
+            r => Some(r),
+        }
+    }
+}
+
+fn boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    if !params
+        .next()?
+        .trim()
+        .eq_ignore_ascii_case("multipart/x-mixed-replace")
+    {
+        return None;
+    }
+    for param in params {
+        let param = param.trim();
+        if let Some(i) = param.find('=') {
+            if param[..i].trim().eq_ignore_ascii_case("boundary") {
+                return Some(param[i + 1..].trim().trim_matches('"').to_owned());
+            }
+        }
+    }
+    None
+}
+
+fn trim_line(mut line: &[u8]) -> &[u8] {
+    while let Some((&c, rest)) = line.split_last() {
+        if c != b'\n' && c != b'\r' && c != b' ' {
+            break;
+        }
+        line = rest;
+    }
+    line
+}
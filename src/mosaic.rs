@@ -0,0 +1,89 @@
+//! Mosaic inference: several small images are packed into one canvas, run
+//! through the network at once and the detections are split back per image.
+//! This trades some accuracy (the images are downscaled together and boxes
+//! near the seams may interact in suppression) for throughput.
+
+use image;
+
+use bb;
+use detector;
+use partial_cmp;
+
+use image::GenericImage;
+
+/// Placement of an image on the canvas.
+#[derive(Clone, Copy, Debug)]
+pub struct Tile {
+    pub top: u32,
+    pub left: u32,
+    pub height: u32,
+    pub width: u32,
+}
+
+/// Packs the images into a near-square grid of equally sized cells.
+pub fn pack(imgs: &[image::DynamicImage]) -> (image::DynamicImage, Vec<Tile>) {
+    let cols = (imgs.len() as f64).sqrt().ceil().max(1.) as u32;
+    let rows = (imgs.len() as f64 / cols as f64).ceil().max(1.) as u32;
+    let cell_h = imgs.iter().map(|img| img.height()).max().unwrap_or(0);
+    let cell_w = imgs.iter().map(|img| img.width()).max().unwrap_or(0);
+
+    let mut canvas = image::RgbImage::from_pixel(
+        cols * cell_w,
+        rows * cell_h,
+        image::Rgb {
+            data: [128, 128, 128],
+        },
+    );
+    let mut tiles = Vec::with_capacity(imgs.len());
+    for (i, img) in imgs.iter().enumerate() {
+        let tile = Tile {
+            top: i as u32 / cols * cell_h,
+            left: i as u32 % cols * cell_w,
+            height: img.height(),
+            width: img.width(),
+        };
+        canvas.copy_from(&img.to_rgb(), tile.left, tile.top);
+        tiles.push(tile);
+    }
+    (image::DynamicImage::ImageRgb8(canvas), tiles)
+}
+
+/// Splits detections on the canvas back to the tiles. Each box goes to the
+/// tile containing its center, and is clipped to it.
+pub fn split(bbox: Vec<bb::Bb>, tiles: &[Tile]) -> Vec<Vec<bb::Bb>> {
+    let mut split = vec![Vec::new(); tiles.len()];
+    for bb in bbox {
+        let (y, x) = ((bb.top + bb.bottom) / 2., (bb.left + bb.right) / 2.);
+        let tile = tiles.iter().position(|t| {
+            y >= t.top as f32
+                && y < (t.top + t.height) as f32
+                && x >= t.left as f32
+                && x < (t.left + t.width) as f32
+        });
+        if let Some(i) = tile {
+            let t = &tiles[i];
+            let clip = |v: f32, max: u32| {
+                partial_cmp::min(partial_cmp::max(v, 0.).unwrap(), max as f32).unwrap()
+            };
+            split[i].push(bb::Bb {
+                top: clip(bb.top - t.top as f32, t.height),
+                left: clip(bb.left - t.left as f32, t.width),
+                bottom: clip(bb.bottom - t.top as f32, t.height),
+                right: clip(bb.right - t.left as f32, t.width),
+                ..bb
+            });
+        }
+    }
+    split
+}
+
+pub fn predict(
+    model: &mut dyn detector::Detector,
+    imgs: &[image::DynamicImage],
+) -> Result<Vec<Vec<bb::Bb>>, detector::Error> {
+    if imgs.is_empty() {
+        return Ok(Vec::new());
+    }
+    let (canvas, tiles) = pack(imgs);
+    Ok(split(model.predict(&canvas)?, &tiles))
+}
@@ -0,0 +1,122 @@
+//! Minimal MQTT 3.1.1 publisher (QoS 0 only).
+
+use std::io;
+use std::net;
+
+use std::io::Read;
+
+pub struct Will<'a> {
+    pub topic: &'a str,
+    pub message: &'a [u8],
+    pub retain: bool,
+}
+
+pub struct Client {
+    stream: net::TcpStream,
+    /// Topic, message and retain flag of the will, published when the client
+    /// is dropped since the broker discards it on DISCONNECT.
+    will: Option<(String, Vec<u8>, bool)>,
+}
+
+impl Client {
+    pub fn connect<A>(addr: A, client_id: &str, will: Option<Will>) -> io::Result<Self>
+    where
+        A: net::ToSocketAddrs,
+    {
+        let mut stream = net::TcpStream::connect(addr)?;
+
+        let mut body = Vec::new();
+        put_str(&mut body, "MQTT");
+        // protocol level 4 (3.1.1)
+        body.push(4);
+        // clean session
+        let mut flags = 0x02;
+        if let Some(ref will) = will {
+            flags |= 0x04;
+            if will.retain {
+                flags |= 0x20;
+            }
+        }
+        body.push(flags);
+        // keep alive is disabled since we never send PINGREQ
+        body.extend_from_slice(&[0, 0]);
+        put_str(&mut body, client_id);
+        if let Some(ref will) = will {
+            put_str(&mut body, will.topic);
+            put_bytes(&mut body, will.message);
+        }
+        write_packet(&mut stream, 0x10, &body)?;
+
+        let mut connack = [0; 4];
+        stream.read_exact(&mut connack)?;
+        if connack[0] != 0x20 || connack[1] != 0x02 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected response to CONNECT",
+            ));
+        }
+        if connack[3] != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("connection refused (return code {})", connack[3]),
+            ));
+        }
+
+        Ok(Self {
+            stream,
+            will: will.map(|w| (w.topic.to_owned(), w.message.to_owned(), w.retain)),
+        })
+    }
+
+    pub fn publish(&mut self, topic: &str, payload: &[u8], retain: bool) -> io::Result<()> {
+        let mut body = Vec::with_capacity(2 + topic.len() + payload.len());
+        put_str(&mut body, topic);
+        body.extend_from_slice(payload);
+        write_packet(
+            &mut self.stream,
+            0x30 | if retain { 0x01 } else { 0x00 },
+            &body,
+        )
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        if let Some((topic, message, retain)) = self.will.take() {
+            let _ = self.publish(&topic, &message, retain);
+        }
+        let _ = write_packet(&mut self.stream, 0xe0, &[]);
+    }
+}
+
+fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.push((bytes.len() >> 8) as u8);
+    buf.push(bytes.len() as u8);
+    buf.extend_from_slice(bytes);
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    put_bytes(buf, s.as_bytes())
+}
+
+fn write_packet<W>(w: &mut W, header: u8, body: &[u8]) -> io::Result<()>
+where
+    W: io::Write,
+{
+    let mut packet = Vec::with_capacity(5 + body.len());
+    packet.push(header);
+    let mut len = body.len();
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    w.write_all(&packet)
+}
@@ -0,0 +1,229 @@
+//! Suppression and fusion of overlapping boxes, generic over the type of the
+//! boxes so that applications with their own detection structs can use them
+//! without converting to `bb::Bb`.
+
+use std::cmp;
+
+use yolo_v2;
+
+use rect::Rect;
+
+/// A box with a score and a class, in any coordinates shared by the boxes
+/// compared.
+pub trait BoundingBox: Rect<f32> {
+    fn score(&self) -> f32;
+    /// Lowers the score, in Soft-NMS.
+    fn set_score(&mut self, score: f32);
+    fn class(&self) -> usize;
+    /// Moves the box to the fused coordinates, in `fuse`.
+    fn set_rect(&mut self, top: f32, left: f32, bottom: f32, right: f32);
+}
+
+/// Sorts `boxes` by class and descending score, and suppresses the boxes
+/// overlapping a higher-scoring box of their class, as `nms`, dropping the
+/// ones falling below `thresh`. Boxes of nearly equal scores are ordered by
+/// `ties`, then keep their order in `boxes`.
+pub fn suppress<B>(boxes: &mut Vec<B>, nms: yolo_v2::Nms, ties: yolo_v2::Ties, thresh: f32)
+where
+    B: BoundingBox,
+{
+    boxes.sort_by(|a, b| a.class().cmp(&b.class()).then_with(|| ties.cmp(a, b)));
+    match nms {
+        yolo_v2::Nms::Greedy { iou } => {
+            let mut kept: Vec<B> = Vec::with_capacity(boxes.len());
+            for b in boxes.drain(..) {
+                // the kept boxes of the class are the last ones
+                let suppressed = kept
+                    .iter()
+                    .rev()
+                    .take_while(|k| k.class() == b.class())
+                    .any(|k| k.iou(&b) > iou);
+                if !suppressed {
+                    kept.push(b);
+                }
+            }
+            *boxes = kept;
+        }
+        yolo_v2::Nms::Soft { sigma } => {
+            let mut start = 0;
+            while start < boxes.len() {
+                let class = boxes[start].class();
+                let end = start
+                    + boxes[start..]
+                        .iter()
+                        .take_while(|b| b.class() == class)
+                        .count();
+                let n = soft_suppress(&mut boxes[start..end], sigma, ties, thresh);
+                boxes.drain(start + n..end);
+                start += n;
+            }
+        }
+    }
+}
+
+/// Soft-NMS of boxes of a single class. The kept boxes are moved to the
+/// start, in descending score, and their number is returned.
+fn soft_suppress<B>(boxes: &mut [B], sigma: f32, ties: yolo_v2::Ties, thresh: f32) -> usize
+where
+    B: BoundingBox,
+{
+    for i in 0..boxes.len() {
+        let best = match (i..boxes.len())
+            .filter(|&j| boxes[j].score() >= thresh)
+            .min_by(|&a, &b| ties.cmp(&boxes[a], &boxes[b]))
+        {
+            Some(best) => best,
+            None => return i,
+        };
+        boxes.swap(i, best);
+        let (kept, rest) = boxes.split_at_mut(i + 1);
+        for b in rest {
+            let iou = kept[i].iou(b);
+            let score = b.score() * (-iou * iou / sigma).exp();
+            b.set_score(score);
+        }
+    }
+    boxes.len()
+}
+
+/// Boxes fused by `fuse`.
+pub struct Cluster<'a, B: 'a> {
+    pub fused: B,
+    /// The boxes fused, with the index of their group.
+    pub members: Vec<(usize, &'a B)>,
+}
+
+/// Weighted box fusion of `groups` of boxes, e.g. those of the members of an
+/// ensemble. Boxes of the same class overlapping with an IoU of at least
+/// `iou` are matched greedily, highest scores first, with at most one box
+/// per group; a fused box is the highest-scoring one moved to the average
+/// coordinates of its members weighted by their scores, with their average
+/// score.
+pub fn fuse<B>(groups: &[Vec<B>], iou: f32) -> Vec<Cluster<'_, B>>
+where
+    B: BoundingBox + Clone,
+{
+    let mut boxes: Vec<_> = groups
+        .iter()
+        .enumerate()
+        .flat_map(|(g, boxes)| boxes.iter().map(move |b| (g, b)))
+        .collect();
+    boxes.sort_by(|a, b| {
+        b.1.score()
+            .partial_cmp(&a.1.score())
+            .unwrap_or(cmp::Ordering::Equal)
+    });
+
+    // the highest-scoring box of each cluster, and the boxes with their group
+    let mut clusters: Vec<(&B, Vec<(usize, &B)>)> = Vec::new();
+    for (g, b) in boxes {
+        let cluster = clusters.iter_mut().find(|(best, members)| {
+            best.class() == b.class()
+                && members.iter().all(|&(other, _)| other != g)
+                && best.iou(b) >= iou
+        });
+        match cluster {
+            Some(cluster) => cluster.1.push((g, b)),
+            None => clusters.push((b, vec![(g, b)])),
+        }
+    }
+
+    clusters
+        .into_iter()
+        .map(|(best, members)| {
+            let sum: f32 = members.iter().map(|(_, b)| b.score()).sum();
+            let weighted =
+                |f: fn(&B) -> f32| members.iter().map(|(_, b)| f(b) * b.score()).sum::<f32>() / sum;
+            let mut fused = best.clone();
+            fused.set_rect(
+                weighted(|b| b.top()),
+                weighted(|b| b.left()),
+                weighted(|b| b.bottom()),
+                weighted(|b| b.right()),
+            );
+            fused.set_score(sum / members.len() as f32);
+            Cluster { fused, members }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bb;
+
+    fn bb(top: f32, left: f32, bottom: f32, right: f32, label: usize, score: f32) -> bb::Bb {
+        bb::Bb {
+            top,
+            left,
+            bottom,
+            right,
+            label,
+            score,
+            truncated: false,
+            sharpness: 0.,
+            provenance: None,
+        }
+    }
+
+    /// Two boxes of label 0 overlapping with an IoU of 90 / 110, one of label
+    /// 0 apart and one of label 1 on the first.
+    fn boxes() -> Vec<bb::Bb> {
+        vec![
+            bb(20., 20., 30., 30., 0, 0.7),
+            bb(0., 1., 10., 11., 0, 0.8),
+            bb(0., 0., 10., 10., 1, 0.6),
+            bb(0., 0., 10., 10., 0, 0.9),
+        ]
+    }
+
+    fn scores(boxes: &[bb::Bb]) -> Vec<(usize, f32)> {
+        boxes.iter().map(|b| (b.label, b.score)).collect()
+    }
+
+    #[test]
+    fn suppresses_greedily() {
+        let mut boxes = boxes();
+        let nms = yolo_v2::Nms::Greedy { iou: 0.45 };
+        suppress(&mut boxes, nms, yolo_v2::Ties::default(), 0.5);
+        assert_eq!(scores(&boxes), [(0, 0.9), (0, 0.7), (1, 0.6)]);
+
+        // below the IoU of the overlapping boxes
+        let mut boxes = self::boxes();
+        let nms = yolo_v2::Nms::Greedy { iou: 0.85 };
+        suppress(&mut boxes, nms, yolo_v2::Ties::default(), 0.5);
+        assert_eq!(scores(&boxes), [(0, 0.9), (0, 0.8), (0, 0.7), (1, 0.6)]);
+    }
+
+    #[test]
+    fn lowers_the_scores_in_soft_nms() {
+        let nms = yolo_v2::Nms::Soft { sigma: 0.5 };
+        let mut boxes = boxes();
+        suppress(&mut boxes, nms, yolo_v2::Ties::default(), 0.1);
+        // 0.8 * exp(-(90 / 110)^2 / 0.5)
+        let lowered = 0.8 * (-(90f32 / 110.).powi(2) / 0.5).exp();
+        assert!((lowered - 0.2097).abs() < 1e-3);
+        assert_eq!(scores(&boxes), [(0, 0.9), (0, 0.7), (0, lowered), (1, 0.6)]);
+
+        let mut boxes = self::boxes();
+        suppress(&mut boxes, nms, yolo_v2::Ties::default(), 0.3);
+        assert_eq!(scores(&boxes), [(0, 0.9), (0, 0.7), (1, 0.6)]);
+    }
+
+    #[test]
+    fn fuses_by_score() {
+        let groups = vec![
+            vec![bb(0., 0., 10., 10., 0, 0.9)],
+            vec![bb(2., 2., 12., 12., 0, 0.3)],
+        ];
+        let clusters = fuse(&groups, 0.4);
+        assert_eq!(clusters.len(), 1);
+        let fused = &clusters[0].fused;
+        assert_eq!(
+            (fused.top, fused.left, fused.bottom, fused.right),
+            (0.5, 0.5, 10.5, 10.5)
+        );
+        assert!((fused.score - 0.6).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,148 @@
+//! Reads the outputs of the graph of ONNX models, which menoh does not
+//! expose, to find the detection head when `Config.output` is omitted.
+//!
+//! Only the few fields on the way to the outputs are decoded; the rest of the
+//! protobuf message, the weights included, is skipped.
+
+use std::fs;
+use std::io;
+use std::path;
+
+/// `ModelProto.graph`.
+const GRAPH: u64 = 7;
+/// `GraphProto.output`.
+const OUTPUT: u64 = 12;
+/// `ValueInfoProto.name`.
+const NAME: u64 = 1;
+/// `ValueInfoProto.type`, `TypeProto.tensor_type`, `TypeProto.Tensor.shape`
+/// and `TensorShapeProto.dim`.
+const DIMS: [u64; 4] = [2, 1, 2, 1];
+/// `TensorShapeProto.Dimension.dim_value`.
+const DIM_VALUE: u64 = 1;
+
+/// Returns the names and shapes of the outputs of the graph of the model,
+/// with dynamic dimensions as `-1`. The shape is empty when not recorded.
+pub fn outputs<P>(path: P) -> io::Result<Vec<(String, Vec<i64>)>>
+where
+    P: AsRef<path::Path>,
+{
+    let data = fs::read(path)?;
+    let graphs = messages(&data, GRAPH)?;
+    if graphs.is_empty() {
+        return Err(invalid());
+    }
+    let mut outputs = Vec::new();
+    for graph in graphs {
+        for output in messages(graph, OUTPUT)? {
+            let name = messages(output, NAME)?
+                .last()
+                .map(|name| String::from_utf8_lossy(name).into_owned())
+                .unwrap_or_default();
+            let mut dims = vec![output];
+            for &field in DIMS.iter() {
+                let mut inner = Vec::new();
+                for d in dims {
+                    inner.extend(messages(d, field)?);
+                }
+                dims = inner;
+            }
+            let shape = dims
+                .into_iter()
+                .map(|dim| {
+                    let value = Fields(dim).find_map(|f| match f {
+                        Ok((DIM_VALUE, Value::Varint(v))) => Some(v as i64),
+                        _ => None,
+                    });
+                    value.unwrap_or(-1)
+                })
+                .collect();
+            outputs.push((name, shape));
+        }
+    }
+    Ok(outputs)
+}
+
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// The fields of a protobuf message, in order.
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = io::Result<(u64, Value<'a>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.0.is_empty() {
+            return None;
+        }
+        let field = self.field();
+        if field.is_err() {
+            self.0 = &[];
+        }
+        Some(field)
+    }
+}
+
+impl<'a> Fields<'a> {
+    fn field(&mut self) -> io::Result<(u64, Value<'a>)> {
+        let key = self.varint()?;
+        let value = match key & 7 {
+            0 => Value::Varint(self.varint()?),
+            1 => {
+                self.take(8)?;
+                Value::Fixed
+            }
+            2 => {
+                let len = self.varint()? as usize;
+                Value::Bytes(self.take(len)?)
+            }
+            5 => {
+                self.take(4)?;
+                Value::Fixed
+            }
+            _ => return Err(invalid()),
+        };
+        Ok((key >> 3, value))
+    }
+
+    fn varint(&mut self) -> io::Result<u64> {
+        let mut v = 0;
+        for (i, &b) in self.0.iter().enumerate().take(10) {
+            v |= u64::from(b & 0x7f) << (7 * i);
+            if b & 0x80 == 0 {
+                self.0 = &self.0[i + 1..];
+                return Ok(v);
+            }
+        }
+        Err(invalid())
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if len > self.0.len() {
+            return Err(invalid());
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+}
+
+/// The embedded messages, or strings, of `field` in `message`.
+fn messages(message: &[u8], field: u64) -> io::Result<Vec<&[u8]>> {
+    let mut found = Vec::new();
+    for f in Fields(message) {
+        if let (n, Value::Bytes(b)) = f? {
+            if n == field {
+                found.push(b);
+            }
+        }
+    }
+    Ok(found)
+}
+
+fn invalid() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "not an ONNX model")
+}
@@ -0,0 +1,161 @@
+//! ONVIF analytics metadata (`tt:MetadataStream`) output.
+//!
+//! Each call produces one document holding a `tt:VideoAnalytics` frame and
+//! one `tns1:RuleEngine/ObjectDetection/Object` notification per detection.
+//! Coordinates use the ONVIF normalized frame: x and y in [-1, 1], y up.
+//!
+//! `Stream` appends the documents of successive frames to a file, as the
+//! metadata track of a camera carries them, for VMS bridges following it.
+
+use std::fs;
+use std::io;
+use std::path;
+use std::time;
+
+use std::io::Write;
+
+use bb;
+use rfc3339;
+
+use rect::Rect;
+
+pub fn write_metadata<'a, W, B>(
+    w: &mut W,
+    utc_time: time::SystemTime,
+    bbox: B,
+    label_names: &[String],
+    height: u32,
+    width: u32,
+) -> io::Result<()>
+where
+    W: io::Write,
+    B: Iterator<Item = &'a bb::Bb>,
+{
+    let utc_time = rfc3339::format(utc_time);
+    let bbox: Vec<_> = bbox.collect();
+    let x = |v: f32| 2. * v / width as f32 - 1.;
+    let y = |v: f32| 1. - 2. * v / height as f32;
+
+    writeln!(w, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        w,
+        r#"<tt:MetadataStream xmlns:tt="http://www.onvif.org/ver10/schema" xmlns:wsnt="http://docs.oasis-open.org/wsn/b-2" xmlns:tns1="http://www.onvif.org/ver10/topics">"#
+    )?;
+
+    writeln!(w, "<tt:VideoAnalytics>")?;
+    writeln!(w, r#"<tt:Frame UtcTime="{}">"#, utc_time)?;
+    for (id, bb) in bbox.iter().enumerate() {
+        writeln!(w, r#"<tt:Object ObjectId="{}">"#, id)?;
+        writeln!(w, "<tt:Appearance>")?;
+        writeln!(w, "<tt:Shape>")?;
+        writeln!(
+            w,
+            r#"<tt:BoundingBox left="{}" top="{}" right="{}" bottom="{}"/>"#,
+            x(bb.left()),
+            y(bb.top()),
+            x(bb.right()),
+            y(bb.bottom())
+        )?;
+        writeln!(
+            w,
+            r#"<tt:CenterOfGravity x="{}" y="{}"/>"#,
+            x((bb.left() + bb.right()) / 2.),
+            y((bb.top() + bb.bottom()) / 2.)
+        )?;
+        writeln!(w, "</tt:Shape>")?;
+        writeln!(w, "<tt:Class>")?;
+        writeln!(
+            w,
+            r#"<tt:Type Likelihood="{}">{}</tt:Type>"#,
+            bb.score,
+            escape(&label_names[bb.label])
+        )?;
+        writeln!(w, "</tt:Class>")?;
+        writeln!(w, "</tt:Appearance>")?;
+        writeln!(w, "</tt:Object>")?;
+    }
+    writeln!(w, "</tt:Frame>")?;
+    writeln!(w, "</tt:VideoAnalytics>")?;
+
+    writeln!(w, "<tt:Event>")?;
+    for (id, bb) in bbox.iter().enumerate() {
+        writeln!(w, "<wsnt:NotificationMessage>")?;
+        writeln!(
+            w,
+            r#"<wsnt:Topic Dialect="http://www.onvif.org/ver10/tev/topicExpression/ConcreteSet">tns1:RuleEngine/ObjectDetection/Object</wsnt:Topic>"#
+        )?;
+        writeln!(w, "<wsnt:Message>")?;
+        writeln!(
+            w,
+            r#"<tt:Message UtcTime="{}" PropertyOperation="Changed">"#,
+            utc_time
+        )?;
+        writeln!(
+            w,
+            r#"<tt:Source><tt:SimpleItem Name="Rule" Value="menoh-yolo"/></tt:Source>"#
+        )?;
+        writeln!(w, "<tt:Data>")?;
+        writeln!(w, r#"<tt:SimpleItem Name="ObjectId" Value="{}"/>"#, id)?;
+        writeln!(
+            w,
+            r#"<tt:SimpleItem Name="ClassTypes" Value="{}"/>"#,
+            escape(&label_names[bb.label])
+        )?;
+        writeln!(
+            w,
+            r#"<tt:SimpleItem Name="Likelihood" Value="{}"/>"#,
+            bb.score
+        )?;
+        writeln!(w, "</tt:Data>")?;
+        writeln!(w, "</tt:Message>")?;
+        writeln!(w, "</wsnt:Message>")?;
+        writeln!(w, "</wsnt:NotificationMessage>")?;
+    }
+    writeln!(w, "</tt:Event>")?;
+
+    writeln!(w, "</tt:MetadataStream>")
+}
+
+pub struct Stream {
+    file: fs::File,
+}
+
+impl Stream {
+    /// Appends to `path`, created if missing.
+    pub fn open<P>(path: P) -> io::Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self { file })
+    }
+
+    /// Appends the document of a frame, in a single write so that readers
+    /// never see part of it.
+    pub fn write<'a, B>(
+        &mut self,
+        utc_time: time::SystemTime,
+        bbox: B,
+        label_names: &[String],
+        height: u32,
+        width: u32,
+    ) -> io::Result<()>
+    where
+        B: Iterator<Item = &'a bb::Bb>,
+    {
+        let mut document = Vec::new();
+        write_metadata(&mut document, utc_time, bbox, label_names, height, width)?;
+        self.file.write_all(&document)
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
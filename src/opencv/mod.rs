@@ -3,6 +3,7 @@ use image;
 use std::ffi;
 use std::mem;
 use std::ptr;
+use std::time;
 
 mod sys;
 
@@ -100,6 +101,15 @@ pub fn wait_key(delay: Option<usize>) -> Option<char> {
     }
 }
 
+// properties of videoio_c.h
+const CAP_PROP_POS_MSEC: i32 = 0;
+const CAP_PROP_POS_FRAMES: i32 = 1;
+const CAP_PROP_FPS: i32 = 5;
+
+/// How long before the frame sought `Capture::seek` sets the position, more
+/// than the keyframe interval of most videos.
+const SEEK_MARGIN: time::Duration = time::Duration::from_secs(10);
+
 pub struct Capture {
     capture: *mut sys::CvCapture,
 }
@@ -114,6 +124,17 @@ impl Capture {
         }
     }
 
+    /// Opens a video file or a stream URL such as `rtsp://...`.
+    pub fn open_file(filename: &str) -> Option<Self> {
+        let filename = ffi::CString::new(filename).ok()?;
+        let capture = unsafe { sys::cvCreateFileCapture(filename.as_ptr()) };
+        if capture.is_null() {
+            None
+        } else {
+            Some(Self { capture })
+        }
+    }
+
     pub fn query_frame(&mut self) -> Option<IplImage> {
         unsafe {
             let frame = sys::cvQueryFrame(self.capture).as_ref()?;
@@ -126,6 +147,45 @@ impl Capture {
             Some(img)
         }
     }
+
+    /// Frames per second of a video file, if known.
+    pub fn fps(&self) -> Option<f64> {
+        let fps = unsafe { sys::cvGetCaptureProperty(self.capture, CAP_PROP_FPS as _) };
+        if fps > 0. {
+            Some(fps)
+        } else {
+            None
+        }
+    }
+
+    /// Moves before the frame of index `frame` of a video file, and returns
+    /// the index of the next frame queried. As backends may land on the
+    /// keyframe after the position set and echo it back, seeking sets a
+    /// position `SEEK_MARGIN` earlier and reads where it landed from the time
+    /// of the frame decoded there.
+    pub fn seek(&mut self, frame: u64) -> u64 {
+        let fps = match self.fps() {
+            Some(fps) => fps,
+            None => return 0,
+        };
+        let margin = (SEEK_MARGIN.as_secs_f64() * fps) as u64;
+        if frame <= margin {
+            return 0;
+        }
+        unsafe {
+            sys::cvSetCaptureProperty(
+                self.capture,
+                CAP_PROP_POS_FRAMES as _,
+                (frame - margin) as f64,
+            );
+            // past the end, which the next query tells
+            if sys::cvQueryFrame(self.capture).is_null() {
+                return frame;
+            }
+            let msec = sys::cvGetCaptureProperty(self.capture, CAP_PROP_POS_MSEC as _);
+            (msec.max(0.) * fps / 1000.).round() as u64 + 1
+        }
+    }
 }
 
 impl Drop for Capture {
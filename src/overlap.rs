@@ -0,0 +1,225 @@
+//! Overlapped detection of a stream of frames: preprocessing, the network
+//! and decoding run on their own threads, so that the resize of a frame runs
+//! while the network runs on the previous one.
+//!
+//! The stages are connected by channels holding `depth` frames, so that a
+//! fast source waits for the network rather than queueing frames without
+//! bound. The detections come out in the order of the frames.
+//!
+//! Each stage runs with the placement of its thread in the config (see
+//! `affinity`).
+
+use image;
+use ndarray;
+use std::path;
+use std::sync;
+use std::thread;
+use std::time;
+
+use affinity;
+use bb;
+use detector;
+use yolo_v2;
+
+use image::GenericImage;
+
+/// A frame between preprocessing and the network.
+struct Prepared {
+    id: u64,
+    img: image::DynamicImage,
+    input: ndarray::ArrayD<f32>,
+    letterbox: yolo_v2::Letterbox,
+    deadline: Option<time::Instant>,
+}
+
+/// A frame between the network and decoding.
+struct Inferred {
+    id: u64,
+    img: image::DynamicImage,
+    outputs: Result<Vec<ndarray::ArrayD<f32>>, detector::Error>,
+    letterbox: yolo_v2::Letterbox,
+    deadline: Option<time::Instant>,
+}
+
+pub struct Pipeline {
+    frames: Option<sync::mpsc::SyncSender<(u64, image::DynamicImage)>>,
+    detections: sync::mpsc::Receiver<(u64, Result<Vec<bb::Bb>, detector::Error>)>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl Pipeline {
+    /// Builds the model on the thread of the network, as `from_onnx`, and
+    /// returns once it is built. Only the `yolo` architecture is supported.
+    pub fn new<P>(
+        path: P,
+        config: sync::Arc<yolo_v2::Config>,
+        backend: &str,
+        backend_config: &str,
+        depth: usize,
+    ) -> Result<Self, detector::Error>
+    where
+        P: AsRef<path::Path>,
+    {
+        if let detector::Architecture::Boxes(_) = config.architecture {
+            return Err(detector::Error::Config(
+                "overlapped detection requires the yolo architecture".to_owned(),
+            ));
+        }
+        let depth = depth.max(1);
+        let (frames, frames_rx) = sync::mpsc::sync_channel::<(u64, image::DynamicImage)>(depth);
+        let (prepared, prepared_rx) = sync::mpsc::sync_channel::<Prepared>(depth);
+        let (inferred, inferred_rx) = sync::mpsc::sync_channel::<Inferred>(depth);
+        // unbounded, so that the stages never wait for the caller
+        let (detections, detections_rx) = sync::mpsc::channel();
+        let mut threads = Vec::new();
+        // once each thread is placed, and the model built
+        let (ready_tx, ready_rx) = sync::mpsc::channel();
+
+        let preprocess_config = config.clone();
+        let ready = ready_tx.clone();
+        threads.push(thread::spawn(move || {
+            let config = preprocess_config;
+            if !placed(&config.threads.preprocess, &ready) {
+                return;
+            }
+            for (id, img) in frames_rx {
+                let deadline = config.params.deadline.map(|secs| {
+                    time::Instant::now()
+                        + time::Duration::from_millis((secs.max(0.) * 1000.) as u64)
+                });
+                let (input, letterbox) = yolo_v2::preprocess(&img, &config);
+                let frame = Prepared {
+                    id,
+                    img,
+                    input,
+                    letterbox,
+                    deadline,
+                };
+                if prepared.send(frame).is_err() {
+                    return;
+                }
+            }
+        }));
+
+        let path = path.as_ref().to_owned();
+        let infer_config = config.clone();
+        let (backend, backend_config) = (backend.to_owned(), backend_config.to_owned());
+        let ready = ready_tx.clone();
+        threads.push(thread::spawn(move || {
+            let config = infer_config;
+            if !placed(&config.threads.inference, &ready) {
+                return;
+            }
+            let mut model =
+                match yolo_v2::YOLOv2::from_onnx(&path, &config, &backend, &backend_config) {
+                    Ok(model) => {
+                        let _ = ready.send(Ok(()));
+                        model
+                    }
+                    Err(err) => {
+                        let _ = ready.send(Err(err));
+                        return;
+                    }
+                };
+            for frame in prepared_rx {
+                let image =
+                    config.input_image(frame.img.height(), frame.img.width(), frame.letterbox);
+                let outputs = model.infer(&frame.input, &image);
+                let frame = Inferred {
+                    id: frame.id,
+                    img: frame.img,
+                    outputs,
+                    letterbox: frame.letterbox,
+                    deadline: frame.deadline,
+                };
+                if inferred.send(frame).is_err() {
+                    return;
+                }
+            }
+        }));
+
+        threads.push(thread::spawn(move || {
+            if !placed(&config.threads.decode, &ready_tx) {
+                return;
+            }
+            for frame in inferred_rx {
+                let (img, letterbox, deadline) = (frame.img, frame.letterbox, frame.deadline);
+                let bbox = frame.outputs.and_then(|outputs| {
+                    let views: Vec<_> = outputs.iter().map(|o| o.view()).collect();
+                    yolo_v2::decode_outputs(
+                        &views,
+                        0,
+                        &config,
+                        &config.params,
+                        &img,
+                        letterbox,
+                        deadline,
+                    )
+                    .map(|decoded| decoded.bbox)
+                });
+                if detections.send((frame.id, bbox)).is_err() {
+                    return;
+                }
+            }
+        }));
+
+        let pipeline = Self {
+            frames: Some(frames),
+            detections: detections_rx,
+            threads,
+        };
+        // the three placements, and the model
+        for _ in 0..4 {
+            ready_rx.recv().unwrap()?;
+        }
+        Ok(pipeline)
+    }
+
+    /// Queues a frame, waiting while `depth` frames wait for preprocessing.
+    /// The frame is given back if the pipeline has stopped.
+    pub fn send(&self, id: u64, img: image::DynamicImage) -> Result<(), image::DynamicImage> {
+        match self.frames {
+            Some(ref frames) => frames.send((id, img)).map_err(|e| (e.0).1),
+            None => Err(img),
+        }
+    }
+
+    /// The detections of the next frame, waiting for them. `None` once every
+    /// frame is done after `close`.
+    pub fn recv(&self) -> Option<(u64, Result<Vec<bb::Bb>, detector::Error>)> {
+        self.detections.recv().ok()
+    }
+
+    /// The detections of the next frame if they are done.
+    pub fn try_recv(&self) -> Option<(u64, Result<Vec<bb::Bb>, detector::Error>)> {
+        self.detections.try_recv().ok()
+    }
+
+    /// Stops accepting frames; the queued ones are still detected.
+    pub fn close(&mut self) {
+        self.frames = None;
+    }
+}
+
+/// Applies `placement` to the calling thread, telling `ready`. Whether it
+/// succeeded.
+fn placed(
+    placement: &affinity::Placement,
+    ready: &sync::mpsc::Sender<Result<(), detector::Error>>,
+) -> bool {
+    let placed = placement
+        .apply()
+        .map_err(|e| detector::Error::Config(format!("threads: {}", e)));
+    let ok = placed.is_ok();
+    let _ = ready.send(placed);
+    ok
+}
+
+impl Drop for Pipeline {
+    fn drop(&mut self) {
+        self.close();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
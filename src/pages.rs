@@ -0,0 +1,378 @@
+//! Pages of multi-page TIFFs and frames of animated PNGs, decoded one at a
+//! time so that large stacks need not fit in memory as images.
+
+use image;
+use std::fs;
+use std::io;
+use std::ops;
+use std::path;
+use std::time;
+use std::vec;
+
+use image::tiff::TIFFDecoder;
+use image::{GenericImage, ImageDecoder};
+use std::io::Read;
+
+pub struct Page {
+    pub img: image::DynamicImage,
+    /// From 0.
+    pub index: u32,
+    /// Time since the first frame of an animation, zero for TIFF pages.
+    pub offset: time::Duration,
+}
+
+pub enum Pages {
+    Tiff(Tiff),
+    Apng(Apng),
+}
+
+/// Returns the pages of the TIFF or the animated PNG at `path`, or `None` for
+/// single images and other formats.
+pub fn open(path: &path::Path) -> io::Result<Option<Pages>> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+    match extension.as_deref() {
+        Some("tif") | Some("tiff") => {
+            let file = io::BufReader::new(fs::File::open(path)?);
+            let decoder = TIFFDecoder::new(file).map_err(invalid_data)?;
+            if !decoder.more_images() {
+                return Ok(None);
+            }
+            Ok(Some(Pages::Tiff(Tiff {
+                decoder: Some(decoder),
+                error: None,
+                index: 0,
+            })))
+        }
+        Some("png") | Some("apng") => {
+            let mut data = Vec::new();
+            fs::File::open(path)?.read_to_end(&mut data)?;
+            Ok(Apng::new(data)?.map(Pages::Apng))
+        }
+        _ => Ok(None),
+    }
+}
+
+impl Iterator for Pages {
+    type Item = io::Result<Page>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match *self {
+            Pages::Tiff(ref mut tiff) => tiff.next(),
+            Pages::Apng(ref mut apng) => apng.next(),
+        }
+    }
+}
+
+pub struct Tiff {
+    decoder: Option<TIFFDecoder<io::BufReader<fs::File>>>,
+    /// Failure to read the next page, reported after the current one.
+    error: Option<io::Error>,
+    index: u32,
+}
+
+impl Tiff {
+    fn next(&mut self) -> Option<io::Result<Page>> {
+        if let Some(e) = self.error.take() {
+            return Some(Err(e));
+        }
+        let mut decoder = self.decoder.take()?;
+        let img = tiff_page(&mut decoder);
+        if decoder.more_images() {
+            match decoder.next_image() {
+                Ok(decoder) => self.decoder = Some(decoder),
+                Err(e) => self.error = Some(invalid_data(e)),
+            }
+        }
+        let index = self.index;
+        self.index += 1;
+        Some(img.map(|img| Page {
+            img,
+            index,
+            offset: time::Duration::from_secs(0),
+        }))
+    }
+}
+
+fn tiff_page<R>(decoder: &mut TIFFDecoder<R>) -> io::Result<image::DynamicImage>
+where
+    R: io::Read + io::Seek,
+{
+    use image::ColorType::{Gray, RGB, RGBA};
+    use image::DecodingResult::{U16, U8};
+
+    let (width, height) = decoder.dimensions().map_err(invalid_data)?;
+    let color = decoder.colortype().map_err(invalid_data)?;
+    let data = match decoder.read_image().map_err(invalid_data)? {
+        U8(data) => data,
+        // keeps the high byte
+        U16(data) => data.iter().map(|v| (v >> 8) as u8).collect(),
+    };
+    let img =
+        match color {
+            RGB(8) | RGB(16) => image::ImageBuffer::from_raw(width, height, data)
+                .map(image::DynamicImage::ImageRgb8),
+            RGBA(8) | RGBA(16) => image::ImageBuffer::from_raw(width, height, data)
+                .map(image::DynamicImage::ImageRgba8),
+            Gray(8) | Gray(16) => image::ImageBuffer::from_raw(width, height, data)
+                .map(image::DynamicImage::ImageLuma8),
+            _ => None,
+        };
+    img.ok_or_else(|| invalid_data(format!("unsupported TIFF page: {:?}", color)))
+}
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Chunks that apply to every frame, copied into each of them.
+const SHARED_CHUNKS: [&[u8]; 7] = [
+    b"PLTE", b"tRNS", b"gAMA", b"cHRM", b"sRGB", b"iCCP", b"sBIT",
+];
+
+pub struct Apng {
+    data: Vec<u8>,
+    ihdr: Vec<u8>,
+    shared: Vec<u8>,
+    frames: vec::IntoIter<Control>,
+    canvas: image::RgbaImage,
+    /// The last frame, with the area it covered if it is to be restored.
+    last: Option<(Control, Option<image::RgbaImage>)>,
+    index: u32,
+    offset: time::Duration,
+}
+
+/// `fcTL`, with the ranges of the data of the frame.
+#[derive(Clone)]
+struct Control {
+    width: u32,
+    height: u32,
+    x: u32,
+    y: u32,
+    delay: time::Duration,
+    dispose: u8,
+    blend: u8,
+    data: Vec<ops::Range<usize>>,
+}
+
+impl Apng {
+    /// Returns `None` unless `data` is an animated PNG of several frames.
+    fn new(data: Vec<u8>) -> io::Result<Option<Self>> {
+        if !data.starts_with(PNG_SIGNATURE) {
+            return Err(invalid_data("not a PNG"));
+        }
+        let mut ihdr = None;
+        let mut animated = false;
+        let mut shared = Vec::new();
+        let mut frames = Vec::new();
+        let mut current: Option<Control> = None;
+        let mut at = PNG_SIGNATURE.len();
+        while at < data.len() {
+            let (kind, body) = chunk(&data, at)?;
+            match kind {
+                b"IHDR" if body.len() == 13 => ihdr = Some(data[body.clone()].to_vec()),
+                b"acTL" => animated = true,
+                b"fcTL" => {
+                    frames.extend(current.take());
+                    current = Some(control(&data[body.clone()])?);
+                }
+                // the default image is only a frame when after a `fcTL`
+                b"IDAT" => {
+                    if let Some(ref mut c) = current {
+                        c.data.push(body.clone());
+                    }
+                }
+                b"fdAT" if body.len() >= 4 => {
+                    if let Some(ref mut c) = current {
+                        c.data.push(body.start + 4..body.end);
+                    }
+                }
+                b"IEND" => break,
+                kind if SHARED_CHUNKS.contains(&kind) => {
+                    shared.extend_from_slice(&data[at..body.end + 4])
+                }
+                _ => (),
+            }
+            at = body.end + 4;
+        }
+        frames.extend(current);
+        let ihdr = ihdr.ok_or_else(|| invalid_data("no IHDR"))?;
+        if !animated || frames.len() < 2 {
+            return Ok(None);
+        }
+        let (width, height) = (be32(&ihdr[0..]), be32(&ihdr[4..]));
+        for c in frames.iter() {
+            if c.x as u64 + c.width as u64 > width as u64
+                || c.y as u64 + c.height as u64 > height as u64
+            {
+                return Err(invalid_data("APNG frame out of the canvas"));
+            }
+        }
+        Ok(Some(Self {
+            data,
+            ihdr,
+            shared,
+            frames: frames.into_iter(),
+            canvas: image::RgbaImage::new(width, height),
+            last: None,
+            index: 0,
+            offset: time::Duration::from_secs(0),
+        }))
+    }
+
+    fn next(&mut self) -> Option<io::Result<Page>> {
+        let mut c = self.frames.next()?;
+        match self.last.take() {
+            Some((ref last, Some(ref area))) => {
+                self.canvas.copy_from(area, last.x, last.y);
+            }
+            Some((ref last, None)) if last.dispose == 1 => {
+                for y in last.y..last.y + last.height {
+                    for x in last.x..last.x + last.width {
+                        self.canvas.put_pixel(x, y, image::Rgba([0; 4]));
+                    }
+                }
+            }
+            _ => (),
+        }
+        // restoring before the first frame clears
+        if self.index == 0 && c.dispose == 2 {
+            c.dispose = 1;
+        }
+        let frame = match self.decode(&c) {
+            Ok(frame) => frame,
+            Err(e) => return Some(Err(e)),
+        };
+        let area = if c.dispose == 2 {
+            Some(image::imageops::crop(&mut self.canvas, c.x, c.y, c.width, c.height).to_image())
+        } else {
+            None
+        };
+        for (x, y, p) in frame.enumerate_pixels() {
+            let dst = self.canvas.get_pixel_mut(c.x + x, c.y + y);
+            *dst = if c.blend == 0 { *p } else { over(*p, *dst) };
+        }
+
+        let page = Page {
+            img: image::DynamicImage::ImageRgb8(
+                image::DynamicImage::ImageRgba8(self.canvas.clone()).to_rgb(),
+            ),
+            index: self.index,
+            offset: self.offset,
+        };
+        self.index += 1;
+        self.offset += c.delay;
+        self.last = Some((c, area));
+        Some(Ok(page))
+    }
+
+    /// Decodes the frame alone, as a PNG of its own.
+    fn decode(&self, c: &Control) -> io::Result<image::RgbaImage> {
+        let mut ihdr = self.ihdr.clone();
+        ihdr[0..4].copy_from_slice(&to_be32(c.width));
+        ihdr[4..8].copy_from_slice(&to_be32(c.height));
+        let mut png = PNG_SIGNATURE.to_vec();
+        write_chunk(&mut png, b"IHDR", &ihdr);
+        png.extend_from_slice(&self.shared);
+        for range in c.data.iter() {
+            write_chunk(&mut png, b"IDAT", &self.data[range.clone()]);
+        }
+        write_chunk(&mut png, b"IEND", &[]);
+        let img = image::load_from_memory_with_format(&png, image::ImageFormat::PNG)
+            .map_err(invalid_data)?;
+        Ok(img.to_rgba())
+    }
+}
+
+/// Returns the type and the range of the data of the chunk at `at`.
+fn chunk(data: &[u8], at: usize) -> io::Result<(&[u8], ops::Range<usize>)> {
+    if at + 8 > data.len() {
+        return Err(invalid_data("truncated PNG"));
+    }
+    let start = at + 8;
+    let end = start + be32(&data[at..]) as usize;
+    if end + 4 > data.len() {
+        return Err(invalid_data("truncated PNG"));
+    }
+    Ok((&data[at + 4..start], start..end))
+}
+
+fn control(body: &[u8]) -> io::Result<Control> {
+    if body.len() != 26 {
+        return Err(invalid_data("malformed fcTL"));
+    }
+    let delay_num = u64::from(be16(&body[20..]));
+    let delay_den = match be16(&body[22..]) {
+        0 => 100,
+        den => u64::from(den),
+    };
+    Ok(Control {
+        width: be32(&body[4..]),
+        height: be32(&body[8..]),
+        x: be32(&body[12..]),
+        y: be32(&body[16..]),
+        delay: time::Duration::from_millis(delay_num * 1000 / delay_den),
+        dispose: body[24],
+        blend: body[25],
+        data: Vec::new(),
+    })
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8], body: &[u8]) {
+    png.extend_from_slice(&to_be32(body.len() as u32));
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(body);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&to_be32(crc));
+}
+
+/// `src` over `dst`.
+fn over(src: image::Rgba<u8>, dst: image::Rgba<u8>) -> image::Rgba<u8> {
+    let (sa, da) = (f32::from(src[3]) / 255., f32::from(dst[3]) / 255.);
+    let a = sa + da * (1. - sa);
+    if a == 0. {
+        return image::Rgba([0; 4]);
+    }
+    let mut out = [0; 4];
+    for i in 0..3 {
+        let c = (f32::from(src[i]) * sa + f32::from(dst[i]) * da * (1. - sa)) / a;
+        out[i] = c.round() as u8;
+    }
+    out[3] = (a * 255.).round() as u8;
+    image::Rgba(out)
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= u32::from(b);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                crc >> 1 ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn be16(b: &[u8]) -> u16 {
+    u16::from(b[0]) << 8 | u16::from(b[1])
+}
+
+fn be32(b: &[u8]) -> u32 {
+    u32::from(b[0]) << 24 | u32::from(b[1]) << 16 | u32::from(b[2]) << 8 | u32::from(b[3])
+}
+
+fn to_be32(v: u32) -> [u8; 4] {
+    [(v >> 24) as u8, (v >> 16) as u8, (v >> 8) as u8, v as u8]
+}
+
+fn invalid_data<E>(e: E) -> io::Error
+where
+    E: ToString,
+{
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
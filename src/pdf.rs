@@ -0,0 +1,63 @@
+//! Rasterization of PDF pages with `pdfinfo` and `pdftoppm` of Poppler, since
+//! no PDF renderer is available to this crate.
+
+use image;
+use std::io;
+use std::path;
+use std::process;
+
+/// Returns the number of pages of the PDF at `path`.
+pub fn page_count(path: &path::Path) -> io::Result<u32> {
+    let info = run(process::Command::new("pdfinfo").arg(path))?;
+    String::from_utf8_lossy(&info)
+        .lines()
+        .filter(|line| line.starts_with("Pages:"))
+        .filter_map(|line| line["Pages:".len()..].trim().parse().ok())
+        .next()
+        .ok_or_else(|| invalid_data(format!("{}: no page count", path.display())))
+}
+
+/// Renders the page `index`, from 0, at `dpi`.
+pub fn render(path: &path::Path, index: u32, dpi: u32) -> io::Result<image::DynamicImage> {
+    let page = (index + 1).to_string();
+    let ppm = run(process::Command::new("pdftoppm")
+        .args([
+            "-r",
+            &dpi.to_string(),
+            "-f",
+            &page,
+            "-l",
+            &page,
+            "-singlefile",
+        ])
+        .arg(path))?;
+    image::load_from_memory_with_format(&ppm, image::ImageFormat::PNM)
+        .map_err(|e| invalid_data(format!("{} page {}: {}", path.display(), index, e)))
+}
+
+fn run(command: &mut process::Command) -> io::Result<Vec<u8>> {
+    let output = command
+        .stdin(process::Stdio::null())
+        .stderr(process::Stdio::piped())
+        .output()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => io::Error::new(
+                e.kind(),
+                format!("{:?} not found, install Poppler", command),
+            ),
+            _ => e,
+        })?;
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(invalid_data(format!(
+            "{:?} failed: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
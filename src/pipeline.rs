@@ -0,0 +1,1289 @@
+//! End-to-end pipelines described by a single file, run with
+//! `menoh-yolo run PIPELINE`.
+//!
+//! A pipeline reads frames from its sources and runs every detector on each
+//! of them. The hooks, the recording rules and the sinks of the pipeline are
+//! added to those of the config of each detector. The file is JSON, or YAML
+//! when named `.yaml` or `.yml` (see `yaml` for the syntax supported).
+//!
+//! ```json
+//! {
+//!   "sources": ["http://192.168.0.10/video.mjpg", "frames/"],
+//!   "detectors": [{"model": "yolo_v2_voc0712.onnx", "config": "yolo_v2_voc0712.json"}],
+//!   "tracker": {"iou": 0.3},
+//!   "recording": {"dir": "clips", "rules": [{"classes": ["person"], "post": 10}]},
+//!   "sinks": [{"type": "jsonl", "path": "detections.jsonl"}]
+//! }
+//! ```
+
+use serde_json;
+use std::collections;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path;
+use std::time;
+
+use affinity;
+use backend;
+use background;
+use bb;
+use detector;
+use doctor;
+use downscale;
+use dump;
+use ensemble;
+use hook;
+use ids;
+use memory;
+use profile;
+use quality;
+use recorder;
+use results;
+use rfc3339;
+use scene;
+use scheduler;
+use schema;
+use severity;
+use sink;
+use source;
+use tracker;
+use yaml;
+use yolo_v2;
+
+use image::GenericImage;
+use std::io::Write;
+
+#[derive(Deserialize)]
+pub struct Config {
+    /// Specs of the sources (see `source::open`).
+    pub sources: Vec<String>,
+    pub detectors: Vec<Detector>,
+    /// Assigns track IDs to the detections above the track threshold.
+    #[serde(default)]
+    pub tracker: Option<Tracker>,
+    #[serde(default)]
+    pub recording: Option<Recording>,
+    /// Marks or drops the detections of objects that stay in place.
+    #[serde(default)]
+    pub background: Option<background::Config>,
+    #[serde(default)]
+    pub hooks: Vec<hook::Hook>,
+    /// Added to the severity rules of every detector.
+    #[serde(default)]
+    pub severities: Vec<severity::Rule>,
+    #[serde(default)]
+    pub sinks: Vec<sink::Config>,
+    /// Caps the share of time spent on detection, for background jobs.
+    #[serde(default)]
+    pub duty_cycle: Option<scheduler::Quota>,
+    /// Cores and priority of the thread running the detectors, in place of
+    /// the `threads` of their configs.
+    #[serde(default)]
+    pub threads: affinity::Placement,
+    /// Key/values attached to every result, alongside the EXIF of the images.
+    #[serde(default)]
+    pub metadata: collections::BTreeMap<String, String>,
+    /// Merges the detections of the detectors, which must have the same
+    /// labels, into those of a single detector named `ensemble`, with the
+    /// hooks, recording rules and sinks of the first.
+    #[serde(default)]
+    pub ensemble: Option<Ensemble>,
+    #[serde(default)]
+    pub dump: Option<Dump>,
+    /// Segments the frames of each source by scene, ending the tracks at the
+    /// cuts.
+    #[serde(default)]
+    pub scenes: Option<scene::Config>,
+    /// The file of the pipeline, set by `load`.
+    #[serde(skip)]
+    pub path: Option<path::PathBuf>,
+}
+
+#[derive(Deserialize)]
+pub struct Detector {
+    /// Reported as `model` in the detections. Defaults to the file stem of
+    /// `model`.
+    #[serde(default)]
+    pub name: Option<String>,
+    pub model: path::PathBuf,
+    pub config: ModelConfig,
+    #[serde(default = "default_backend")]
+    pub backend: String,
+    #[serde(default, deserialize_with = "backend::deserialize")]
+    pub backend_config: backend::MkldnnConfig,
+    /// A second profile, used at night.
+    #[serde(default)]
+    pub night: Option<Night>,
+}
+
+/// The profile of a detector at night. The params alone are switched on the
+/// same model; a `model` or a `config` builds a second model.
+#[derive(Deserialize)]
+pub struct Night {
+    pub switch: profile::Switch,
+    /// Replaces the model, with the config of the detector unless `config`
+    /// is given.
+    #[serde(default)]
+    pub model: Option<path::PathBuf>,
+    /// Replaces the config, with the model of the detector unless `model` is
+    /// given. Its labels must be those of the detector, whose hooks,
+    /// recording rules and sinks are kept.
+    #[serde(default)]
+    pub config: Option<ModelConfig>,
+    /// Replaces the params of the config, as thresholds or the quality gate.
+    #[serde(default)]
+    pub params: Option<yolo_v2::Params>,
+}
+
+fn default_backend() -> String {
+    "mkldnn".to_owned()
+}
+
+impl Detector {
+    pub fn name(&self) -> String {
+        match self.name {
+            Some(ref name) => name.clone(),
+            None => self
+                .model
+                .file_stem()
+                .map_or_else(String::new, |s| s.to_string_lossy().into_owned()),
+        }
+    }
+}
+
+/// Either the path of a model config or the config itself.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum ModelConfig {
+    Path(path::PathBuf),
+    Inline(Box<yolo_v2::Config>),
+}
+
+#[derive(Deserialize)]
+pub struct Tracker {
+    #[serde(default = "default_iou")]
+    pub iou: f32,
+    #[serde(default = "default_max_misses")]
+    pub max_misses: usize,
+    #[serde(default)]
+    pub ids: ids::TrackIds,
+    #[serde(default)]
+    pub motion: tracker::Motion,
+    /// The weight of each detection in the smoothed boxes of its track, in
+    /// (0, 1]; the boxes are not smoothed if missing.
+    #[serde(default)]
+    pub smoothing: Option<f32>,
+}
+
+fn default_iou() -> f32 {
+    0.3
+}
+
+fn default_max_misses() -> usize {
+    15
+}
+
+#[derive(Deserialize)]
+pub struct Ensemble {
+    /// IoU from which the boxes of a label found by different detectors are
+    /// merged.
+    #[serde(default = "default_ensemble_iou")]
+    pub iou: f32,
+}
+
+fn default_ensemble_iou() -> f32 {
+    0.55
+}
+
+/// Dumps the state of the running pipeline to a new directory of `dir` on
+/// request (see `dump`): a copy of the pipeline file, the params, memory and
+/// running hooks of the detectors, the timings of the last `frames` frames,
+/// and the last results of each detector on each source.
+#[derive(Deserialize)]
+pub struct Dump {
+    pub dir: path::PathBuf,
+    #[serde(default = "default_dump_frames")]
+    pub frames: usize,
+}
+
+fn default_dump_frames() -> usize {
+    64
+}
+
+#[derive(Deserialize)]
+pub struct Recording {
+    pub dir: path::PathBuf,
+    /// Maximum width of the GIFs of the clips. No GIFs are saved when
+    /// omitted.
+    #[serde(default)]
+    pub gif: Option<u32>,
+    #[serde(default)]
+    pub rules: Vec<recorder::Rule>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Detector(detector::Error),
+    Config(serde_json::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err) => err.fmt(f),
+            Error::Detector(err) => err.fmt(f),
+            Error::Config(err) => err.fmt(f),
+            Error::Invalid(message) => message.fmt(f),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        Error::Io(value)
+    }
+}
+
+impl From<detector::Error> for Error {
+    fn from(value: detector::Error) -> Self {
+        Error::Detector(value)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Self {
+        Error::Config(value)
+    }
+}
+
+pub fn load<P>(path: P) -> Result<Config, Error>
+where
+    P: AsRef<path::Path>,
+{
+    let mut config: Config = if is_yaml(path.as_ref()) {
+        let value = yaml::parse(&fs::read_to_string(path.as_ref())?)
+            .map_err(|err| Error::Invalid(err.to_string()))?;
+        serde_json::from_value(value)?
+    } else {
+        serde_json::from_reader(io::BufReader::new(fs::File::open(path.as_ref())?))?
+    };
+    config.path = Some(path.as_ref().to_owned());
+    for detector in config.detectors.iter_mut() {
+        if let ModelConfig::Inline(ref mut model_config) = detector.config {
+            model_config.find_onnx_output(&detector.model)?;
+        }
+        if let Some(ref mut night) = detector.night {
+            if let Some(ModelConfig::Inline(ref mut model_config)) = night.config {
+                model_config.find_onnx_output(night.model.as_ref().unwrap_or(&detector.model))?;
+            }
+        }
+    }
+    Ok(config)
+}
+
+fn is_yaml(path: &path::Path) -> bool {
+    path.extension().is_some_and(|e| e == "yaml" || e == "yml")
+}
+
+/// Validates the pipeline without processing any frame: the configs and the
+/// models of the detectors, the labels referred to by the rules, and whether
+/// the sources and the sinks can be opened.
+pub fn check(config: &Config) -> Vec<doctor::Check> {
+    let mut checks = Vec::new();
+    let mut push = |name, result| checks.push(doctor::Check { name, result });
+
+    if config.sources.is_empty() || config.detectors.is_empty() {
+        push(
+            "pipeline",
+            Err("a pipeline needs sources and detectors".to_owned()),
+        );
+    }
+
+    let pipeline_labels = referenced_labels(&config.hooks, &[], &config.severities, &config.sinks);
+    let pipeline_rules = config.recording.as_ref().map_or(&[][..], |r| &r.rules[..]);
+    let mut all_label_names = Vec::new();
+    let mut label_sets = Vec::new();
+    for detector in config.detectors.iter() {
+        let name = detector.name();
+        let loaded;
+        let model_config = match detector.config {
+            ModelConfig::Path(ref path) => match load_model_config(path, &detector.model) {
+                Ok(model_config) => {
+                    loaded = model_config;
+                    &loaded
+                }
+                Err(e) => {
+                    push(
+                        "config",
+                        Err(format!("{}: {}: {}", name, path.display(), e)),
+                    );
+                    continue;
+                }
+            },
+            ModelConfig::Inline(ref model_config) => &**model_config,
+        };
+        let with_name = |r: Result<String, String>| {
+            r.map(|m| format!("{}: {}", name, m))
+                .map_err(|m| format!("{}: {}", name, m))
+        };
+        if !push_ok(
+            &mut push,
+            "config",
+            with_name(doctor::check_config(model_config)),
+        ) {
+            continue;
+        }
+        all_label_names.extend(model_config.label_names.iter().cloned());
+        label_sets.push(model_config.label_names.clone());
+        if let Some(ref night) = detector.night {
+            let loaded;
+            let night_config = match night.config {
+                Some(ModelConfig::Path(ref path)) => {
+                    let model = night.model.as_ref().unwrap_or(&detector.model);
+                    match load_model_config(path, model) {
+                        Ok(night_config) => {
+                            loaded = night_config;
+                            Ok(Some(&loaded))
+                        }
+                        Err(e) => Err(e.to_string()),
+                    }
+                }
+                Some(ModelConfig::Inline(ref night_config)) => Ok(Some(&**night_config)),
+                None => Ok(None),
+            };
+            let checked = night_config
+                .and_then(|night_config| check_night(night, night_config, model_config));
+            push("night", with_name(checked.map(|_| "valid".to_owned())));
+        }
+
+        let mut labels = referenced_labels(
+            &model_config.hooks,
+            &model_config.recording,
+            &model_config.severities,
+            &model_config.sinks,
+        );
+        if let Some(ref classes) = model_config.params.classes {
+            labels.extend(classes.iter());
+        }
+        labels.extend(model_config.params.constraints.keys());
+        labels.extend(model_config.params.score_corrections.keys());
+        push(
+            "labels",
+            with_name(check_labels(labels, &model_config.label_names)),
+        );
+        if config.recording.is_some()
+            && model_config.recording.is_empty()
+            && pipeline_rules.is_empty()
+        {
+            push(
+                "recording",
+                with_name(Err("recording requires rules".to_owned())),
+            );
+        }
+
+        for sink in model_config.sinks.iter() {
+            push("sink", with_name(sink.probe().map_err(|e| e.to_string())));
+        }
+
+        let model_file = detector
+            .model
+            .metadata()
+            .map(|m| format!("{} ({} bytes)", detector.model.display(), m.len()))
+            .map_err(|e| format!("{}: {}", detector.model.display(), e));
+        if !push_ok(&mut push, "model file", with_name(model_file)) {
+            continue;
+        }
+        if let detector::Architecture::Boxes(_) = model_config.architecture {
+            let built = detector::from_onnx(
+                &detector.model,
+                model_config,
+                &detector.backend,
+                &detector.backend_config.to_json(),
+            );
+            let built = built.map(|_| detector.backend.clone());
+            push("model build", with_name(built.map_err(|e| e.to_string())));
+            continue;
+        }
+        match yolo_v2::YOLOv2::from_onnx(
+            &detector.model,
+            model_config,
+            &detector.backend,
+            &detector.backend_config.to_json(),
+        ) {
+            Ok(model) => {
+                push("model build", with_name(Ok(detector.backend.clone())));
+                push(
+                    "output shape",
+                    with_name(doctor::check_output_shape(&model)),
+                );
+            }
+            Err(e) => push("model build", with_name(Err(e.to_string()))),
+        }
+    }
+
+    if config.ensemble.is_some() {
+        let result = if label_sets.windows(2).any(|w| w[0] != w[1]) {
+            Err("the detectors of an ensemble must have the same labels".to_owned())
+        } else if config.detectors.iter().any(|d| d.night.is_some()) {
+            Err("the detectors of an ensemble cannot have night profiles".to_owned())
+        } else {
+            Ok(format!("{} detectors", config.detectors.len()))
+        };
+        push("ensemble", result);
+    }
+
+    if !all_label_names.is_empty() {
+        let mut labels = pipeline_labels;
+        labels.extend(pipeline_rules.iter().flat_map(|r| r.classes.iter()));
+        push("pipeline labels", check_labels(labels, &all_label_names));
+    }
+
+    if let Some(alpha) = config.tracker.as_ref().and_then(|t| t.smoothing) {
+        push("tracker", check_smoothing(alpha));
+    }
+    if let Some(ref scenes) = config.scenes {
+        push("scenes", check_scenes(scenes));
+    }
+
+    for spec in config.sources.iter() {
+        push(
+            "source",
+            source::open(spec)
+                .map(|_| spec.clone())
+                .map_err(|e| format!("{}: {}", spec, e)),
+        );
+    }
+    for sink in config.sinks.iter() {
+        push("sink", sink.probe().map_err(|e| e.to_string()));
+    }
+    checks
+}
+
+/// Checks the switch and the config of the night of a detector configured
+/// with `config`.
+fn check_night(
+    night: &Night,
+    night_config: Option<&yolo_v2::Config>,
+    config: &yolo_v2::Config,
+) -> Result<(), String> {
+    profile::Switcher::new(&night.switch)?;
+    if let Some(night_config) = night_config {
+        doctor::check_config(night_config)?;
+        if night_config.label_names != config.label_names {
+            return Err("the labels of the night differ from those of the day".to_owned());
+        }
+    }
+    Ok(())
+}
+
+fn push_ok<F>(push: &mut F, name: &'static str, result: Result<String, String>) -> bool
+where
+    F: FnMut(&'static str, Result<String, String>),
+{
+    let ok = result.is_ok();
+    push(name, result);
+    ok
+}
+
+/// Labels referred to by class filters.
+fn referenced_labels<'a>(
+    hooks: &'a [hook::Hook],
+    rules: &'a [recorder::Rule],
+    severities: &'a [severity::Rule],
+    sinks: &'a [sink::Config],
+) -> Vec<&'a String> {
+    let mut labels: Vec<_> = hooks.iter().flat_map(|h| h.classes.iter()).collect();
+    labels.extend(rules.iter().flat_map(|r| r.classes.iter()));
+    labels.extend(severities.iter().flat_map(|r| r.classes.iter()));
+    let ranks = severities
+        .iter()
+        .filter_map(|r| r.rank.as_ref())
+        .chain(sinks.iter().filter_map(|s| s.rank.as_ref()));
+    labels.extend(ranks.flat_map(|r| r.classes.keys()));
+    for sink in sinks.iter() {
+        if let Some(ref classes) = sink.classes {
+            labels.extend(classes.iter());
+        }
+    }
+    labels
+}
+
+fn check_scenes(scenes: &scene::Config) -> Result<String, String> {
+    if scenes.threshold > 0. && scenes.threshold <= 1. {
+        Ok(format!("scene threshold {}", scenes.threshold))
+    } else {
+        Err(format!(
+            "scene threshold {} is not in (0, 1]",
+            scenes.threshold
+        ))
+    }
+}
+
+fn check_smoothing(alpha: f32) -> Result<String, String> {
+    if alpha > 0. && alpha <= 1. {
+        Ok(format!("smoothing {}", alpha))
+    } else {
+        Err(format!("smoothing {} is not in (0, 1]", alpha))
+    }
+}
+
+fn check_labels(labels: Vec<&String>, label_names: &[String]) -> Result<String, String> {
+    let mut unknown: Vec<_> = labels
+        .into_iter()
+        .filter(|l| !label_names.contains(l))
+        .map(|l| l.as_str())
+        .collect();
+    unknown.sort();
+    unknown.dedup();
+    if unknown.is_empty() {
+        Ok("all known".to_owned())
+    } else {
+        Err(format!("unknown labels: {}", unknown.join(", ")))
+    }
+}
+
+/// Per-detector state.
+struct Stage<'a> {
+    name: String,
+    config: &'a yolo_v2::Config,
+    model: Box<dyn detector::Detector + 'a>,
+    night: Option<NightStage<'a>>,
+    hooks: hook::Hooks,
+    sinks: sink::Sinks,
+}
+
+struct NightStage<'a> {
+    switch: &'a profile::Switch,
+    /// The model of the night, when it is not the model of the day.
+    model: Option<Box<dyn detector::Detector + 'a>>,
+    /// The params of the day and of the night, switched on the model of the
+    /// day when there is no model of the night.
+    params: Option<(yolo_v2::Params, yolo_v2::Params)>,
+    /// Whether the model of the day has the params of the night.
+    applied: bool,
+}
+
+/// The model of `stage` for a frame of the day or of the night, with the
+/// params of the profile, apart from the other fields of the stage.
+fn profile_model<'m, 'a>(
+    model: &'m mut Box<dyn detector::Detector + 'a>,
+    profile: &'m mut Option<NightStage<'a>>,
+    night: bool,
+) -> &'m mut (dyn detector::Detector + 'a) {
+    let profile = match *profile {
+        Some(ref mut profile) => profile,
+        None => return &mut **model,
+    };
+    if let Some(ref mut night_model) = profile.model {
+        return if night {
+            &mut **night_model
+        } else {
+            &mut **model
+        };
+    }
+    if let Some((ref day, ref params)) = profile.params {
+        if profile.applied != night {
+            model.set_params(if night { params } else { day }.clone());
+            profile.applied = night;
+        }
+    }
+    &mut **model
+}
+
+/// State of a detector on a source.
+struct Lane {
+    /// Whether the source is at night, when the detector has a night
+    /// profile.
+    night: Option<profile::Switcher>,
+    tracker: Option<tracker::Tracker>,
+    /// Whether the tracked boxes are replaced with their smoothed boxes.
+    smoothing: bool,
+    /// UUIDs of the current tracks, when asked for.
+    track_uuids: Option<collections::HashMap<u64, String>>,
+    recorder: Option<recorder::Recorder>,
+    background: Option<(background::Background, bool)>,
+    /// The results of the last frame.
+    last: Option<schema::Frame>,
+}
+
+/// How long a frame took to be read and detected, kept for the dumps.
+#[derive(Serialize)]
+struct Timing {
+    source: String,
+    frame: String,
+    time: String,
+    read_ms: f64,
+    /// Per detector, with its hooks, recording and sinks.
+    detect_ms: collections::BTreeMap<String, f64>,
+}
+
+#[derive(Serialize)]
+struct Snapshot<'a> {
+    time: String,
+    uptime_secs: f64,
+    frames: u64,
+    /// The sources not ended yet.
+    sources: Vec<&'a str>,
+    detectors: Vec<StageSnapshot<'a>>,
+    timings: &'a collections::VecDeque<Timing>,
+    last: Vec<&'a schema::Frame>,
+}
+
+#[derive(Serialize)]
+struct StageSnapshot<'a> {
+    name: &'a str,
+    params: &'a yolo_v2::Params,
+    memory: Option<memory::Usage>,
+    hooks: Vec<HookSnapshot<'a>>,
+}
+
+#[derive(Serialize)]
+struct HookSnapshot<'a> {
+    command: &'a str,
+    /// Its commands still running.
+    running: usize,
+}
+
+/// Runs the pipeline until all the sources end. The warnings of the
+/// detectors, the failures of the sinks and the dumps are passed to `warn`,
+/// the pipeline never printing them.
+pub fn run<F>(config: Config, mut warn: F) -> Result<(), Error>
+where
+    F: FnMut(detector::Warning),
+{
+    run_with(config, None, &mut warn)
+}
+
+/// Runs the pipeline on a thread, and returns the frames sent to its sinks.
+/// The warnings are passed to `warn` as by `run`, on that thread.
+pub fn stream<F>(config: Config, mut warn: F) -> results::DetectionStream
+where
+    F: FnMut(detector::Warning) + Send + 'static,
+{
+    results::DetectionStream::spawn(move |tap| run_with(config, Some(&tap), &mut warn))
+}
+
+fn run_with(
+    config: Config,
+    tap: Option<&results::Tap>,
+    warn: &mut dyn FnMut(detector::Warning),
+) -> Result<(), Error> {
+    if config.sources.is_empty() || config.detectors.is_empty() {
+        return Err(Error::Invalid(
+            "a pipeline needs sources and detectors".to_owned(),
+        ));
+    }
+    if let Some(alpha) = config.tracker.as_ref().and_then(|t| t.smoothing) {
+        check_smoothing(alpha).map_err(|e| Error::Invalid(format!("tracker: {}", e)))?;
+    }
+    if let Some(ref scenes) = config.scenes {
+        check_scenes(scenes).map_err(Error::Invalid)?;
+    }
+
+    let mut model_configs = Vec::new();
+    let mut names = Vec::new();
+    let mut config_files = Vec::new();
+    for mut detector in config.detectors {
+        names.push(detector.name());
+        config_files.push(match detector.config {
+            ModelConfig::Path(ref path) => Some(path.clone()),
+            ModelConfig::Inline(_) => None,
+        });
+        let night = match detector.night.take() {
+            Some(mut profile) => {
+                let night_config = match profile.config.take() {
+                    Some(ModelConfig::Path(ref path)) => {
+                        let model = profile.model.as_ref().unwrap_or(&detector.model);
+                        Some(Box::new(load_model_config(path, model)?))
+                    }
+                    Some(ModelConfig::Inline(night_config)) => Some(night_config),
+                    None => None,
+                };
+                Some((profile, night_config))
+            }
+            None => None,
+        };
+        let mut model_config = match detector.config {
+            ModelConfig::Path(ref path) => load_model_config(path, &detector.model)?,
+            ModelConfig::Inline(model_config) => *model_config,
+        };
+        model_config.hooks.extend(config.hooks.iter().cloned());
+        model_config
+            .severities
+            .extend(config.severities.iter().cloned());
+        model_config.sinks.extend(config.sinks.iter().cloned());
+        if let Some(ref recording) = config.recording {
+            model_config
+                .recording
+                .extend(recording.rules.iter().cloned());
+            if model_config.recording.is_empty() {
+                return Err(Error::Invalid(format!(
+                    "{}: recording requires rules",
+                    names.last().unwrap()
+                )));
+            }
+        }
+        if let Some((ref profile, ref night_config)) = night {
+            check_night(profile, night_config.as_deref(), &model_config)
+                .map_err(|e| Error::Invalid(format!("{}: night: {}", names.last().unwrap(), e)))?;
+        }
+        model_configs.push((
+            detector.model,
+            detector.backend,
+            detector.backend_config.to_json(),
+            model_config,
+            night,
+        ));
+    }
+    if config.ensemble.is_some() {
+        let label_names = &model_configs[0].3.label_names;
+        for (_, _, _, model_config, night) in model_configs.iter() {
+            if model_config.label_names != *label_names {
+                return Err(Error::Invalid(
+                    "the detectors of an ensemble must have the same labels".to_owned(),
+                ));
+            }
+            if night.is_some() {
+                return Err(Error::Invalid(
+                    "the detectors of an ensemble cannot have night profiles".to_owned(),
+                ));
+            }
+        }
+    }
+
+    let mut models = Vec::new();
+    for ((name, config_file), (model, backend, backend_config, model_config, night)) in names
+        .into_iter()
+        .zip(config_files)
+        .zip(model_configs.iter())
+    {
+        let mut run = schema::Run::new(
+            &model.to_string_lossy(),
+            model_config,
+            backend,
+            backend_config,
+        );
+        if let Some(path) = config_file {
+            run = run.with_config_file(path);
+        }
+        let night = match *night {
+            Some((ref profile, ref night_config)) => {
+                let night_model = if profile.model.is_some() || night_config.is_some() {
+                    let mut night_model = detector::from_onnx(
+                        profile.model.as_ref().unwrap_or(model),
+                        night_config.as_deref().unwrap_or(model_config),
+                        backend,
+                        backend_config,
+                    )?;
+                    if let Some(ref params) = profile.params {
+                        night_model.set_params(params.clone());
+                    }
+                    Some(night_model)
+                } else {
+                    None
+                };
+                let params = match profile.params {
+                    Some(ref params) if night_model.is_none() => {
+                        Some((model_config.params.clone(), params.clone()))
+                    }
+                    _ => None,
+                };
+                Some(NightStage {
+                    switch: &profile.switch,
+                    model: night_model,
+                    params,
+                    applied: false,
+                })
+            }
+            None => None,
+        };
+        let model = detector::from_onnx(model, model_config, backend, backend_config)?;
+        models.push((name, model_config, model, night, run));
+    }
+    if let Some(ref ensemble) = config.ensemble {
+        let model_config = models[0].1;
+        let run = schema::Run {
+            members: models.iter().map(|m| m.4.clone()).collect(),
+            ..models[0].4.clone()
+        };
+        let members = models
+            .into_iter()
+            .map(|(_, _, model, _, _)| model)
+            .collect();
+        let model: Box<dyn detector::Detector> =
+            Box::new(ensemble::Ensemble::new(members, ensemble.iou));
+        models = vec![("ensemble".to_owned(), model_config, model, None, run)];
+    }
+
+    let mut stages = Vec::new();
+    for (name, model_config, model, night, run) in models {
+        let params = &model_config.params;
+        let display = params.thresholds.display;
+        let mut sinks = sink::Sinks::open(
+            &model_config.sinks,
+            &model_config.label_names,
+            display,
+            &params.class_thresholds,
+        )?;
+        if let Some(tap) = tap {
+            sinks.attach(tap.sink(), None, display);
+        }
+        sinks.header(&run)?;
+        stages.push(Stage {
+            name,
+            config: model_config,
+            model,
+            night,
+            hooks: hook::Hooks::new(&model_config.hooks, &model_config.label_names)
+                .with_summary(&model_config.summary),
+            sinks,
+        });
+    }
+
+    let n_lanes = config.sources.len() * stages.len();
+    let mut lanes = Vec::new();
+    for s in 0..config.sources.len() {
+        let mut row = Vec::new();
+        for stage in stages.iter() {
+            let model_config = stage.config;
+            let recorder = match config.recording {
+                Some(ref recording) => {
+                    // Lanes record to their own directories, so that clips of
+                    // different sources are not mixed.
+                    let dir = if n_lanes > 1 {
+                        recording.dir.join(format!("{}-{}", stage.name, s))
+                    } else {
+                        recording.dir.clone()
+                    };
+                    let mut recorder = recorder::Recorder::new(
+                        dir,
+                        &model_config.recording,
+                        &model_config.label_names,
+                    )?;
+                    recorder.set_gif(recording.gif);
+                    Some(recorder)
+                }
+                None => None,
+            };
+            let night = stage
+                .night
+                .as_ref()
+                .map(|night| profile::Switcher::new(night.switch).expect("checked by check_night"));
+            row.push(Lane {
+                night,
+                tracker: config.tracker.as_ref().map(|t| {
+                    let tracker = tracker::Tracker::new(t.iou, t.max_misses).with_motion(t.motion);
+                    match t.smoothing {
+                        Some(alpha) => tracker.with_smoothing(alpha),
+                        None => tracker,
+                    }
+                }),
+                smoothing: config
+                    .tracker
+                    .as_ref()
+                    .is_some_and(|t| t.smoothing.is_some()),
+                track_uuids: match config.tracker {
+                    Some(ref t) if t.ids == ids::TrackIds::Uuid => {
+                        Some(collections::HashMap::new())
+                    }
+                    _ => None,
+                },
+                recorder,
+                background: config
+                    .background
+                    .as_ref()
+                    .map(|b| (background::Background::new(b), b.suppress)),
+                last: None,
+            });
+        }
+        lanes.push(row);
+    }
+
+    config
+        .threads
+        .apply()
+        .map_err(|e| Error::Invalid(format!("threads: {}", e)))?;
+    let mut sources = Vec::new();
+    for (s, spec) in config.sources.iter().enumerate() {
+        sources.push((s, source::open(spec)?));
+    }
+    #[cfg(unix)]
+    {
+        if config.dump.is_some() {
+            dump::listen()?;
+        }
+    }
+    let specs = &config.sources;
+    let scene_config = config.scenes.as_ref();
+    let mut scenes: Vec<_> = specs
+        .iter()
+        .map(|spec| scene_config.map(|c| scene::Scenes::new(c, spec)))
+        .collect();
+    let mut scene_file = match scene_config.and_then(|c| c.path.as_ref()) {
+        Some(path) => Some(io::BufWriter::new(
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?,
+        )),
+        None => None,
+    };
+    let start = time::Instant::now();
+    let mut n_frames = 0;
+    let mut timings = collections::VecDeque::new();
+    let mut duty_cycle = scheduler::DutyCycle::new(config.duty_cycle);
+    // Sources are read in turn, so that a slow one does not starve the
+    // others of the detectors.
+    while !sources.is_empty() && !tap.is_some_and(|t| t.is_stopped()) {
+        let mut i = 0;
+        while i < sources.len() {
+            if let Some(ref dump) = config.dump {
+                if dump::requested() {
+                    let snapshot = Snapshot {
+                        time: rfc3339::format(time::SystemTime::now()),
+                        uptime_secs: secs(start.elapsed()),
+                        frames: n_frames,
+                        sources: sources.iter().map(|&(s, _)| &specs[s][..]).collect(),
+                        detectors: stages
+                            .iter_mut()
+                            .map(|stage| StageSnapshot {
+                                name: &stage.name,
+                                params: stage.model.params(),
+                                memory: stage.model.memory_usage().ok(),
+                                hooks: stage
+                                    .hooks
+                                    .depths()
+                                    .into_iter()
+                                    .map(|(command, running)| HookSnapshot { command, running })
+                                    .collect(),
+                            })
+                            .collect(),
+                        timings: &timings,
+                        last: lanes
+                            .iter()
+                            .flatten()
+                            .filter_map(|l| l.last.as_ref())
+                            .collect(),
+                    };
+                    match write_dump(dump, config.path.as_ref(), &snapshot) {
+                        Ok(dir) => warn(detector::Warning::Dumped {
+                            dir: dir.display().to_string(),
+                        }),
+                        Err(e) => warn(detector::Warning::DumpFailed {
+                            error: e.to_string(),
+                        }),
+                    }
+                }
+            }
+
+            let read = time::Instant::now();
+            let s = sources[i].0;
+            let mut frame = match sources[i].1.next_frame() {
+                Some(frame) => frame?,
+                None => {
+                    if let Some(scene) = scenes[s].as_mut().and_then(|s| s.finish()) {
+                        write_scene(scene_file.as_mut(), &scene)?;
+                    }
+                    sources.remove(i);
+                    continue;
+                }
+            };
+            frame.metadata.fields.extend(config.metadata.clone());
+            let scene = match scenes[s] {
+                Some(ref mut scenes) => {
+                    let time = rfc3339::format(frame.time);
+                    if let Some(ended) = scenes.update(&frame.img, &time) {
+                        write_scene(scene_file.as_mut(), &ended)?;
+                        for lane in lanes[s].iter_mut() {
+                            lane.end_tracks();
+                        }
+                    }
+                    Some(scenes.index())
+                }
+                None => None,
+            };
+            let mut timing = Timing {
+                source: config.sources[sources[i].0].clone(),
+                frame: frame.name.clone(),
+                time: rfc3339::format(frame.time),
+                read_ms: 1000. * secs(read.elapsed()),
+                detect_ms: collections::BTreeMap::new(),
+            };
+            for (stage, lane) in stages.iter_mut().zip(lanes[s].iter_mut()) {
+                let detect = time::Instant::now();
+                duty_cycle.run(|| process(stage, lane, &frame, scene, &mut *warn))?;
+                let ms = 1000. * secs(detect.elapsed());
+                timing.detect_ms.insert(stage.name.clone(), ms);
+            }
+            n_frames += 1;
+            if let Some(ref dump) = config.dump {
+                if timings.len() >= dump.frames {
+                    timings.pop_front();
+                }
+                if dump.frames > 0 {
+                    timings.push_back(timing);
+                }
+            }
+            i += 1;
+        }
+    }
+
+    for scene in scenes.iter_mut().filter_map(|s| s.as_mut()?.finish()) {
+        write_scene(scene_file.as_mut(), &scene)?;
+    }
+    for row in lanes.iter_mut() {
+        for lane in row.iter_mut() {
+            if let Some(ref mut recorder) = lane.recorder {
+                recorder.finish()?;
+            }
+        }
+    }
+    for stage in stages.iter_mut() {
+        stage.sinks.finish()?;
+        stage.hooks.wait()?;
+    }
+    Ok(())
+}
+
+/// Appends `scene` to `file` as a line of JSON.
+fn write_scene(file: Option<&mut io::BufWriter<fs::File>>, scene: &scene::Scene) -> io::Result<()> {
+    if let Some(file) = file {
+        serde_json::to_writer(&mut *file, scene)?;
+        file.write_all(b"\n")?;
+        file.flush()?;
+    }
+    Ok(())
+}
+
+fn secs(d: time::Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 * 1e-9
+}
+
+/// Writes `snapshot` to a new directory of `dump`, with a copy of the
+/// pipeline file at `path`.
+fn write_dump(
+    dump: &Dump,
+    path: Option<&path::PathBuf>,
+    snapshot: &Snapshot,
+) -> io::Result<path::PathBuf> {
+    fs::create_dir_all(&dump.dir)?;
+    // numbered after the first of a second
+    let name = format!("dump-{}", snapshot.time.replace(':', "-"));
+    let mut dir = dump.dir.join(&name);
+    let mut n = 1;
+    loop {
+        match fs::create_dir(&dir) {
+            Ok(()) => break,
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                dir = dump.dir.join(format!("{}-{}", name, n));
+                n += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    if let Some(path) = path {
+        fs::copy(path, dir.join("pipeline.json"))?;
+    }
+    let file = io::BufWriter::new(fs::File::create(dir.join("state.json"))?);
+    serde_json::to_writer_pretty(file, snapshot)?;
+    Ok(dir)
+}
+
+/// Loads the config of a detector, finding the output in `model` when
+/// omitted.
+fn load_model_config(path: &path::Path, model: &path::Path) -> Result<yolo_v2::Config, Error> {
+    let mut config: yolo_v2::Config =
+        serde_json::from_reader(io::BufReader::new(fs::File::open(path)?))?;
+    config.find_onnx_output(model)?;
+    Ok(config)
+}
+
+/// The boxes of a frame, with how they were detected.
+struct Detected {
+    bbox: Vec<bb::Bb>,
+    partial: bool,
+    low_quality: Option<quality::Issue>,
+    agreement: Option<Vec<f32>>,
+    warnings: Vec<detector::Warning>,
+}
+
+/// The boxes of replayed results (see `source::Replay`).
+fn replayed(recorded: &schema::Frame, label_names: &[String]) -> Result<Detected, Error> {
+    let bbox = recorded
+        .detections
+        .iter()
+        .map(|d| {
+            d.bb(label_names).ok_or_else(|| {
+                let source = recorded.source.as_deref().unwrap_or("");
+                Error::Invalid(format!("{}: unknown label {}", source, d.label))
+            })
+        })
+        .collect::<Result<_, _>>()?;
+    let agreement = if recorded.detections.iter().any(|d| d.agreement.is_some()) {
+        let found = recorded
+            .detections
+            .iter()
+            .map(|d| d.agreement.unwrap_or(1.));
+        Some(found.collect())
+    } else {
+        None
+    };
+    Ok(Detected {
+        bbox,
+        partial: recorded.partial,
+        low_quality: recorded.low_quality,
+        agreement,
+        warnings: Vec::new(),
+    })
+}
+
+impl Lane {
+    /// Ends the tracks, as at a cut between scenes.
+    fn end_tracks(&mut self) {
+        if let Some(ref mut tracker) = self.tracker {
+            tracker.finish();
+        }
+        if let Some(ref mut uuids) = self.track_uuids {
+            uuids.clear();
+        }
+    }
+}
+
+fn process(
+    stage: &mut Stage,
+    lane: &mut Lane,
+    frame: &source::Frame,
+    scene: Option<u64>,
+    warn: &mut dyn FnMut(detector::Warning),
+) -> Result<(), Error> {
+    let config = stage.config;
+    let (width, height) = frame.img.dimensions();
+    let night = match frame.recorded {
+        // the results of another detector
+        Some(ref recorded) if recorded.model.as_ref().is_some_and(|m| *m != stage.name) => {
+            return Ok(());
+        }
+        Some(ref recorded) => recorded.night,
+        None => match lane.night {
+            Some(ref mut switcher) => switcher.update(&frame.img, frame.time),
+            None => false,
+        },
+    };
+    let model = profile_model(&mut stage.model, &mut stage.night, night);
+    let detected = match frame.recorded {
+        Some(ref recorded) => replayed(recorded, &config.label_names)?,
+        None => {
+            let mut bbox = match config.max_edge {
+                Some(max_edge) => {
+                    let (img, factor) = downscale::cap(frame.img.clone(), max_edge);
+                    let dimensions = frame.img.dimensions();
+                    let mut bbox =
+                        downscale::predict(model, &img, factor, dimensions, |m| m.predict(&img))?;
+                    downscale::restore(bbox.iter_mut(), factor);
+                    bbox
+                }
+                None => model.predict(&frame.img)?,
+            };
+            model.params().rounding.apply(bbox.iter_mut());
+            Detected {
+                bbox,
+                partial: model.partial(),
+                low_quality: model.low_quality(),
+                agreement: model.agreement().map(|agreement| agreement.to_vec()),
+                warnings: model.warnings().to_vec(),
+            }
+        }
+    };
+    let mut bbox = detected.bbox;
+    // paired with the scores, to follow the boxes kept by `adapt`
+    let agreement: Option<Vec<_>> = detected
+        .agreement
+        .map(|agreement| bbox.iter().map(|bb| bb.score).zip(agreement).collect());
+    let mut warnings = detected.warnings;
+    let adapted = model.params().adapt(&mut bbox, &mut warnings);
+    for warning in warnings {
+        warn(warning);
+    }
+    let params = model.params();
+    let threshold = params.label_thresholds(params.thresholds.track, &config.label_names);
+
+    let mut out = schema::Frame::new(&bbox, &config.label_names, height, width)
+        .with_source(&frame.name)
+        .with_model(&stage.name)
+        .with_time(frame.time)
+        .with_metadata(&frame.metadata)
+        .with_partial(detected.partial)
+        .with_low_quality(detected.low_quality)
+        .with_night(night)
+        .with_severities(&config.severities)
+        .with_threshold(adapted)
+        .with_scene(scene)
+        .with_ids();
+    if let Some(agreement) = agreement {
+        let kept = agreement
+            .into_iter()
+            .filter(|&(score, _)| adapted.is_none_or(|t| score >= t))
+            .map(|(_, found)| found);
+        for (detection, found) in out.detections.iter_mut().zip(kept) {
+            detection.agreement = Some(found);
+            detection.disputed = found < 1.;
+        }
+    }
+    if let Some(ref mut tracker) = lane.tracker {
+        let tracked: Vec<_> = bb::above_each(&bbox, &threshold).cloned().collect();
+        let (ids, ended) = tracker.update(&tracked);
+        let boxes = bbox
+            .iter_mut()
+            .zip(out.detections.iter_mut())
+            .filter(|(bb, _)| bb.score >= threshold[bb.label]);
+        for ((bb, detection), id) in boxes.zip(ids) {
+            match lane.track_uuids {
+                Some(ref mut uuids) => {
+                    let uuid = uuids.entry(id).or_insert_with(ids::uuid);
+                    detection.track_uuid = Some(uuid.clone());
+                }
+                None => detection.track = Some(id),
+            }
+            if lane.smoothing {
+                *bb = tracker.track(id).expect("just updated").average.clone();
+                detection.top = bb.top;
+                detection.left = bb.left;
+                detection.bottom = bb.bottom;
+                detection.right = bb.right;
+            }
+        }
+        if let Some(ref mut uuids) = lane.track_uuids {
+            for track in ended {
+                uuids.remove(&track.id);
+            }
+        }
+    }
+    if let Some((ref mut background, suppress)) = lane.background {
+        let stationary = background.update(&bbox, frame.time);
+        for (detection, &stationary) in out.detections.iter_mut().zip(stationary.iter()) {
+            detection.stationary = stationary;
+        }
+        if suppress {
+            out.detections.retain(|d| !d.stationary);
+            let mut stationary = stationary.into_iter();
+            bbox.retain(|_| !stationary.next().unwrap());
+        }
+    }
+    stage.hooks.fire(&bbox, height, width)?;
+    if let Some(ref mut recorder) = lane.recorder {
+        recorder.record(&frame.img, &bbox)?;
+    }
+    if let Err(e) = stage.sinks.send(&out, &frame.img) {
+        warn(detector::Warning::SinkFailed {
+            error: e.to_string(),
+        });
+    }
+    lane.last = Some(out);
+    Ok(())
+}
@@ -0,0 +1,115 @@
+//! Switching of a detector between its day and night profiles, by the time
+//! of day or by the brightness of the frames, as for outdoor cameras turning
+//! to infrared at night (see `pipeline::Night`).
+
+use std::time;
+
+use quality;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Switch {
+    /// Night from `start` to `end`, as `HH:MM`, in UTC shifted by
+    /// `utc_offset` hours, since the local time zone is not read.
+    Schedule {
+        start: String,
+        end: String,
+        #[serde(default)]
+        utc_offset: f32,
+    },
+    /// Night once the mean luma of the frames (see `quality::stats`) falls
+    /// below `below`, and day once it rises above `above`, so that a scene
+    /// at the threshold does not switch at every frame.
+    Brightness { below: f32, above: f32 },
+}
+
+/// The profile of a stream.
+pub struct Switcher {
+    rule: Rule,
+    night: bool,
+}
+
+enum Rule {
+    /// Minutes from midnight, and the offset in seconds.
+    Schedule {
+        start: u32,
+        end: u32,
+        offset: i64,
+    },
+    Brightness {
+        below: f32,
+        above: f32,
+    },
+}
+
+impl Switcher {
+    pub fn new(switch: &Switch) -> Result<Self, String> {
+        let rule = match *switch {
+            Switch::Schedule {
+                ref start,
+                ref end,
+                utc_offset,
+            } => Rule::Schedule {
+                start: parse_time(start)?,
+                end: parse_time(end)?,
+                offset: (utc_offset * 3600.) as i64,
+            },
+            Switch::Brightness { below, above } if below > above => {
+                return Err(format!(
+                    "brightness below {} is above the brightness above {}",
+                    below, above
+                ));
+            }
+            Switch::Brightness { below, above } => Rule::Brightness { below, above },
+        };
+        Ok(Self { rule, night: false })
+    }
+
+    /// Whether `img`, taken at `time`, is of the night.
+    pub fn update<I>(&mut self, img: &I, time: time::SystemTime) -> bool
+    where
+        I: quality::Luma + ?Sized,
+    {
+        self.night = match self.rule {
+            Rule::Schedule { start, end, offset } => {
+                let secs = time
+                    .duration_since(time::UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs() as i64);
+                let minute = ((secs + offset).rem_euclid(86400) / 60) as u32;
+                if start <= end {
+                    start <= minute && minute < end
+                } else {
+                    // across midnight
+                    minute >= start || minute < end
+                }
+            }
+            Rule::Brightness { below, above } => {
+                let brightness = quality::stats(img).brightness;
+                if self.night {
+                    brightness <= above
+                } else {
+                    brightness < below
+                }
+            }
+        };
+        self.night
+    }
+}
+
+/// Minutes from midnight of `HH:MM`.
+fn parse_time(s: &str) -> Result<u32, String> {
+    let invalid = || format!("invalid time {:?}, expected HH:MM", s);
+    let mut parts = s.splitn(2, ':');
+    let hours: u32 = parts
+        .next()
+        .and_then(|h| h.parse().ok())
+        .ok_or_else(invalid)?;
+    let minutes: u32 = parts
+        .next()
+        .and_then(|m| m.parse().ok())
+        .ok_or_else(invalid)?;
+    if hours > 23 || minutes > 59 {
+        return Err(invalid());
+    }
+    Ok(hours * 60 + minutes)
+}
@@ -0,0 +1,481 @@
+//! Protobuf encoding of the results, for consumers wanting a compact and
+//! typed schema rather than JSON (see `sink::Encoding`). The messages follow
+//! the compatibility rules of `schema`, in proto3:
+//!
+//! ```proto
+//! syntax = "proto3";
+//! package menoh_yolo;
+//!
+//! message Frame {
+//!   uint32 schema_version = 1;
+//!   optional string source = 2;
+//!   optional string model = 3;
+//!   optional string time = 4;
+//!   uint32 height = 5;
+//!   uint32 width = 6;
+//!   repeated Detection detections = 7;
+//!   Metadata metadata = 8;
+//!   bool partial = 9;
+//!   optional string low_quality = 10;
+//!   bool night = 11;
+//!   optional float threshold = 12;
+//!   optional uint64 scene = 13;
+//! }
+//!
+//! message Detection {
+//!   optional string id = 1;
+//!   string label = 2;
+//!   float score = 3;
+//!   float top = 4;
+//!   float left = 5;
+//!   float bottom = 6;
+//!   float right = 7;
+//!   bool truncated = 8;
+//!   float sharpness = 9;
+//!   optional uint64 track = 10;
+//!   optional string track_uuid = 11;
+//!   Provenance provenance = 12;
+//!   bool stationary = 13;
+//!   optional float agreement = 14;
+//!   bool disputed = 15;
+//!   optional string severity = 16;
+//!   optional float rank = 17;
+//!   optional string thumbnail = 18;
+//! }
+//!
+//! message Provenance {
+//!   uint32 scale = 1;
+//!   uint32 row = 2;
+//!   uint32 column = 3;
+//!   uint32 anchor = 4;
+//!   repeated float loc = 5;
+//!   float obj = 6;
+//! }
+//!
+//! message Metadata {
+//!   optional string taken = 1;
+//!   optional string camera = 2;
+//!   optional double focal_length_35mm = 3;
+//!   Gps gps = 4;
+//!   optional uint32 page = 5;
+//!   map<string, string> fields = 6;
+//! }
+//!
+//! message Gps {
+//!   double latitude = 1;
+//!   double longitude = 2;
+//!   optional double altitude = 3;
+//!   optional double direction = 4;
+//! }
+//!
+//! // The run, whose preprocessing and params are free-form, stays JSON.
+//! message Header {
+//!   uint32 schema_version = 1;
+//!   string run = 2;
+//! }
+//! ```
+//!
+//! Enums are sent as their JSON names, so that new values do not need a
+//! new schema.
+//!
+//! `Message` and `fields` are also the wire format of the gRPC client of
+//! `remote`.
+
+use serde_json;
+use std::io;
+
+use std::convert::TryInto;
+
+use schema;
+
+/// A message being encoded. Fields at their default value are omitted, as
+/// proto3 does, except the `optional` ones which are written when set.
+#[derive(Default)]
+pub struct Message(pub Vec<u8>);
+
+impl Message {
+    fn key(&mut self, field: u32, wire_type: u32) {
+        varint(&mut self.0, u64::from(field << 3 | wire_type));
+    }
+
+    pub fn uint(&mut self, field: u32, v: u64) {
+        if v != 0 {
+            self.optional_uint(field, Some(v));
+        }
+    }
+
+    pub fn optional_uint(&mut self, field: u32, v: Option<u64>) {
+        if let Some(v) = v {
+            self.key(field, 0);
+            varint(&mut self.0, v);
+        }
+    }
+
+    pub fn bool(&mut self, field: u32, v: bool) {
+        self.uint(field, v as u64);
+    }
+
+    pub fn float(&mut self, field: u32, v: f32) {
+        if v != 0. {
+            self.optional_float(field, Some(v));
+        }
+    }
+
+    pub fn optional_float(&mut self, field: u32, v: Option<f32>) {
+        if let Some(v) = v {
+            self.key(field, 5);
+            self.0.extend_from_slice(&v.to_bits().to_le_bytes());
+        }
+    }
+
+    pub fn double(&mut self, field: u32, v: f64) {
+        if v != 0. {
+            self.optional_double(field, Some(v));
+        }
+    }
+
+    pub fn optional_double(&mut self, field: u32, v: Option<f64>) {
+        if let Some(v) = v {
+            self.key(field, 1);
+            self.0.extend_from_slice(&v.to_bits().to_le_bytes());
+        }
+    }
+
+    pub fn bytes(&mut self, field: u32, v: &[u8]) {
+        self.key(field, 2);
+        varint(&mut self.0, v.len() as u64);
+        self.0.extend_from_slice(v);
+    }
+
+    pub fn string(&mut self, field: u32, v: &str) {
+        if !v.is_empty() {
+            self.bytes(field, v.as_bytes());
+        }
+    }
+
+    pub fn optional_string(&mut self, field: u32, v: Option<&str>) {
+        if let Some(v) = v {
+            self.bytes(field, v.as_bytes());
+        }
+    }
+
+    pub fn message(&mut self, field: u32, m: &Message) {
+        self.bytes(field, &m.0);
+    }
+
+    /// A submessage, unless it is empty.
+    pub fn optional_message(&mut self, field: u32, m: &Message) {
+        if !m.0.is_empty() {
+            self.message(field, m);
+        }
+    }
+
+    /// A repeated varint field, packed.
+    pub fn packed_uints(&mut self, field: u32, v: &[u64]) {
+        if !v.is_empty() {
+            let mut packed = Vec::new();
+            for &v in v {
+                varint(&mut packed, v);
+            }
+            self.bytes(field, &packed);
+        }
+    }
+}
+
+/// A field of a message being decoded, by wire type.
+pub enum Value<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+impl<'a> Value<'a> {
+    pub fn bytes(&self) -> io::Result<&'a [u8]> {
+        match *self {
+            Value::Bytes(b) => Ok(b),
+            _ => Err(invalid_data("expected a length-delimited field")),
+        }
+    }
+
+    pub fn string(&self) -> io::Result<&'a str> {
+        ::std::str::from_utf8(self.bytes()?).map_err(|_| invalid_data("string not in UTF-8"))
+    }
+
+    /// The values of a repeated varint field, packed or not.
+    pub fn uints(&self) -> io::Result<Vec<u64>> {
+        match *self {
+            Value::Varint(v) => Ok(vec![v]),
+            Value::Bytes(mut b) => {
+                let mut v = Vec::new();
+                while !b.is_empty() {
+                    v.push(read_varint(&mut b)?);
+                }
+                Ok(v)
+            }
+            _ => Err(invalid_data("expected a varint field")),
+        }
+    }
+
+    /// The values of a repeated `float` field, packed or not.
+    pub fn floats(&self) -> io::Result<Vec<f32>> {
+        match *self {
+            Value::Fixed32(v) => Ok(vec![f32::from_bits(v)]),
+            Value::Bytes(b) if b.len() % 4 == 0 => Ok(b
+                .chunks(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect()),
+            _ => Err(invalid_data("expected a float field")),
+        }
+    }
+}
+
+/// The fields of an encoded message, in order.
+pub fn fields(message: &[u8]) -> Fields<'_> {
+    Fields(message)
+}
+
+pub struct Fields<'a>(&'a [u8]);
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = io::Result<(u32, Value<'a>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.0.is_empty() {
+            return None;
+        }
+        let field = self.field();
+        if field.is_err() {
+            self.0 = &[];
+        }
+        Some(field)
+    }
+}
+
+impl<'a> Fields<'a> {
+    fn field(&mut self) -> io::Result<(u32, Value<'a>)> {
+        let key = read_varint(&mut self.0)?;
+        let value = match key & 7 {
+            0 => Value::Varint(read_varint(&mut self.0)?),
+            1 => Value::Fixed64(u64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+            2 => {
+                let len = read_varint(&mut self.0)?;
+                Value::Bytes(self.take(len.min(usize::MAX as u64) as usize)?)
+            }
+            5 => Value::Fixed32(u32::from_le_bytes(self.take(4)?.try_into().unwrap())),
+            _ => return Err(invalid_data("unsupported wire type")),
+        };
+        Ok(((key >> 3) as u32, value))
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if len > self.0.len() {
+            return Err(invalid_data("truncated message"));
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+}
+
+fn read_varint(buf: &mut &[u8]) -> io::Result<u64> {
+    let mut v = 0;
+    for shift in (0..64).step_by(7) {
+        let (&b, rest) = buf
+            .split_first()
+            .ok_or_else(|| invalid_data("truncated message"))?;
+        *buf = rest;
+        v |= u64::from(b & 0x7f) << shift;
+        if b & 0x80 == 0 {
+            return Ok(v);
+        }
+    }
+    Err(invalid_data("varint too long"))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+/// The JSON name of an enum.
+fn name<T>(v: &T) -> Option<String>
+where
+    T: ::serde::Serialize,
+{
+    match serde_json::to_value(v) {
+        Ok(serde_json::Value::String(s)) => Some(s),
+        _ => None,
+    }
+}
+
+fn detection(d: &schema::Detection) -> Message {
+    let mut m = Message::default();
+    m.optional_string(1, d.id.as_deref());
+    m.string(2, &d.label);
+    m.float(3, d.score);
+    m.float(4, d.top);
+    m.float(5, d.left);
+    m.float(6, d.bottom);
+    m.float(7, d.right);
+    m.bool(8, d.truncated);
+    m.float(9, d.sharpness);
+    m.optional_uint(10, d.track);
+    m.optional_string(11, d.track_uuid.as_deref());
+    if let Some(ref p) = d.provenance {
+        let mut provenance = Message::default();
+        provenance.uint(1, p.scale as u64);
+        provenance.uint(2, p.cell.0 as u64);
+        provenance.uint(3, p.cell.1 as u64);
+        provenance.uint(4, p.anchor as u64);
+        let loc: Vec<u8> = p
+            .loc
+            .iter()
+            .flat_map(|v| v.to_bits().to_le_bytes())
+            .collect();
+        provenance.bytes(5, &loc);
+        provenance.float(6, p.obj);
+        m.message(12, &provenance);
+    }
+    m.bool(13, d.stationary);
+    m.optional_float(14, d.agreement);
+    m.bool(15, d.disputed);
+    m.optional_string(16, d.severity.as_ref().and_then(name).as_deref());
+    m.optional_float(17, d.rank);
+    m.optional_string(18, d.thumbnail.as_deref());
+    m
+}
+
+fn metadata(metadata: &schema::Metadata) -> Message {
+    let mut m = Message::default();
+    m.optional_string(1, metadata.taken.as_deref());
+    m.optional_string(2, metadata.camera.as_deref());
+    m.optional_double(3, metadata.focal_length_35mm);
+    if let Some(ref g) = metadata.gps {
+        let mut gps = Message::default();
+        gps.double(1, g.latitude);
+        gps.double(2, g.longitude);
+        gps.optional_double(3, g.altitude);
+        gps.optional_double(4, g.direction);
+        m.message(4, &gps);
+    }
+    m.optional_uint(5, metadata.page.map(u64::from));
+    for (key, value) in &metadata.fields {
+        let mut entry = Message::default();
+        entry.string(1, key);
+        entry.string(2, value);
+        m.message(6, &entry);
+    }
+    m
+}
+
+/// The `Frame` message of `frame`.
+pub fn frame(frame: &schema::Frame) -> Vec<u8> {
+    let mut m = Message::default();
+    m.uint(1, u64::from(frame.schema_version));
+    m.optional_string(2, frame.source.as_deref());
+    m.optional_string(3, frame.model.as_deref());
+    m.optional_string(4, frame.time.as_deref());
+    m.uint(5, u64::from(frame.height));
+    m.uint(6, u64::from(frame.width));
+    for d in &frame.detections {
+        m.message(7, &detection(d));
+    }
+    m.optional_message(8, &metadata(&frame.metadata));
+    m.bool(9, frame.partial);
+    m.optional_string(10, frame.low_quality.map(|i| i.name()));
+    m.bool(11, frame.night);
+    m.optional_float(12, frame.threshold);
+    m.optional_uint(13, frame.scene);
+    m.0
+}
+
+/// The `Header` message of `run`.
+pub fn header(run: &schema::Run) -> serde_json::Result<Vec<u8>> {
+    let mut m = Message::default();
+    m.uint(1, u64::from(schema::SCHEMA_VERSION));
+    m.string(2, &serde_json::to_string(run)?);
+    Ok(m.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bb;
+    use severity;
+
+    #[test]
+    fn encodes_frames() {
+        let bb = bb::Bb {
+            top: 1.,
+            left: 2.,
+            bottom: 3.,
+            right: 0.,
+            label: 0,
+            score: 0.5,
+            truncated: true,
+            sharpness: 0.,
+            provenance: None,
+        };
+        let mut frame = schema::Frame::new(&[bb], &["cat".to_owned()], 300, 2).with_scene(Some(0));
+        frame.detections[0].severity = Some(severity::Level::Warn);
+        frame.detections[0].track = Some(0);
+        let expected: &[u8] = &[
+            // schema_version 1, height 300, width 2
+            0x08, 0x01, 0x28, 0xac, 0x02, 0x30, 0x02,
+            // detections: the label, the score, the box, truncated, the track
+            // and the severity; the right border and the sharpness are 0
+            0x3a, 0x24, 0x12, 0x03, b'c', b'a', b't', 0x1d, 0x00, 0x00, 0x00, 0x3f, 0x25, 0x00,
+            0x00, 0x80, 0x3f, 0x2d, 0x00, 0x00, 0x00, 0x40, 0x35, 0x00, 0x00, 0x40, 0x40, 0x40,
+            0x01, 0x50, 0x00, 0x82, 0x01, 0x04, b'w', b'a', b'r', b'n', // scene 0, set
+            0x68, 0x00,
+        ];
+        assert_eq!(self::frame(&frame), expected);
+    }
+
+    #[test]
+    fn encodes_metadata() {
+        let mut metadata = schema::Metadata {
+            page: Some(1),
+            gps: Some(schema::Gps {
+                latitude: 0.,
+                longitude: 1.,
+                altitude: None,
+                direction: Some(0.),
+            }),
+            ..Default::default()
+        };
+        metadata.fields.insert("a".to_owned(), "b".to_owned());
+        let expected: &[u8] = &[
+            // gps: longitude 1.0, direction 0.0
+            0x22, 0x12, 0x11, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf0, 0x3f, 0x21, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // page 1, fields {a: b}
+            0x28, 0x01, 0x32, 0x06, 0x0a, 0x01, b'a', 0x12, 0x01, b'b',
+        ];
+        assert_eq!(self::metadata(&metadata).0, expected);
+    }
+
+    #[test]
+    fn decodes_fields() {
+        let mut m = Message::default();
+        m.string(1, "output");
+        m.packed_uints(3, &[(-1i64) as u64, 125]);
+        m.optional_float(6, Some(0.5));
+        m.uint(3, 13);
+        let fields = fields(&m.0).collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(fields.len(), 4);
+        assert_eq!(fields[0].1.string().unwrap(), "output");
+        assert_eq!(fields[1].1.uints().unwrap(), [u64::MAX, 125]);
+        assert_eq!(fields[2].1.floats().unwrap(), [0.5]);
+        assert_eq!((fields[3].0, fields[3].1.uints().unwrap()), (3, vec![13]));
+        assert!(self::fields(&[0x0a, 0x05, 0x01]).next().unwrap().is_err());
+    }
+}
@@ -0,0 +1,179 @@
+//! Quality measures of detection crops, and of whole images to skip the
+//! network on the unusable ones (see `Gate`).
+
+use image;
+
+use image::{GenericImage, Pixel};
+use rect::Rect;
+
+/// Images that crops are measured on, decoded or raw (see `raw`).
+pub trait Luma {
+    /// `(width, height)`
+    fn dimensions(&self) -> (u32, u32);
+
+    fn luma(&self, x: u32, y: u32) -> u8;
+}
+
+impl Luma for image::DynamicImage {
+    fn dimensions(&self) -> (u32, u32) {
+        GenericImage::dimensions(self)
+    }
+
+    fn luma(&self, x: u32, y: u32) -> u8 {
+        self.get_pixel(x, y).to_luma().data[0]
+    }
+}
+
+/// Variance of the Laplacian of the grayscale crop. Higher is sharper. Crops
+/// smaller than 3x3 pixels get 0.
+pub fn sharpness<I, R>(img: &I, r: &R) -> f32
+where
+    I: Luma + ?Sized,
+    R: Rect<f32>,
+{
+    let (width, height) = img.dimensions();
+    let clip = |v: f32, max: u32| v.max(0.).min(max as f32) as u32;
+    let (top, bottom) = (clip(r.top(), height), clip(r.bottom(), height));
+    let (left, right) = (clip(r.left(), width), clip(r.right(), width));
+    if bottom < top + 3 || right < left + 3 {
+        return 0.;
+    }
+
+    let (h, w) = ((bottom - top) as usize, (right - left) as usize);
+    let mut luma = Vec::with_capacity(h * w);
+    for y in top..bottom {
+        for x in left..right {
+            luma.push(img.luma(x, y) as f32);
+        }
+    }
+    laplacian_variance(&luma, w, h)
+}
+
+/// Variance of the Laplacian of the `w` x `h` grayscale `luma`, 0 if smaller
+/// than 3x3.
+fn laplacian_variance(luma: &[f32], w: usize, h: usize) -> f32 {
+    if h < 3 || w < 3 {
+        return 0.;
+    }
+    let mut sum = 0.;
+    let mut sum_sq = 0.;
+    for y in 1..h - 1 {
+        for x in 1..w - 1 {
+            let l = luma[(y - 1) * w + x]
+                + luma[(y + 1) * w + x]
+                + luma[y * w + x - 1]
+                + luma[y * w + x + 1]
+                - 4. * luma[y * w + x];
+            sum += l;
+            sum_sq += l * l;
+        }
+    }
+    let n = ((h - 2) * (w - 2)) as f32;
+    let mean = sum / n;
+    sum_sq / n - mean * mean
+}
+
+/// Pixels sampled along the longest side of images by `stats`.
+const SAMPLES: u32 = 256;
+
+/// Luma from which pixels are overexposed.
+const OVEREXPOSED: u8 = 250;
+
+/// Measures of a whole image, on a grid of at most `SAMPLES` pixels along
+/// each side, so that they cost little next to the network.
+#[derive(Clone, Copy, Debug)]
+pub struct Stats {
+    /// Mean luma, from 0 to 255.
+    pub brightness: f32,
+    /// Fraction of the pixels with a luma of 250 or more.
+    pub overexposed: f32,
+    /// Variance of the Laplacian of the grid, that is of the image scaled
+    /// down to 256 pixels.
+    pub sharpness: f32,
+}
+
+pub fn stats<I>(img: &I) -> Stats
+where
+    I: Luma + ?Sized,
+{
+    let (width, height) = img.dimensions();
+    let step = width.max(height).div_ceil(SAMPLES).max(1);
+    let (w, h) = (
+        width.div_ceil(step) as usize,
+        height.div_ceil(step) as usize,
+    );
+    let mut luma = Vec::with_capacity(w * h);
+    for y in (0..height).step_by(step as usize) {
+        for x in (0..width).step_by(step as usize) {
+            luma.push(img.luma(x, y));
+        }
+    }
+    let n = luma.len().max(1) as f32;
+    let overexposed = luma.iter().filter(|&&l| l >= OVEREXPOSED).count() as f32 / n;
+    let luma: Vec<_> = luma.into_iter().map(f32::from).collect();
+    Stats {
+        brightness: luma.iter().sum::<f32>() / n,
+        overexposed,
+        sharpness: laplacian_variance(&luma, w, h),
+    }
+}
+
+/// Thresholds of `Stats` below which images are not worth detecting. All
+/// are disabled by default.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Gate {
+    #[serde(default)]
+    pub min_brightness: Option<f32>,
+    #[serde(default)]
+    pub max_overexposed: Option<f32>,
+    #[serde(default)]
+    pub min_sharpness: Option<f32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Issue {
+    Dark,
+    Overexposed,
+    Blurry,
+}
+
+impl Issue {
+    pub fn name(self) -> &'static str {
+        match self {
+            Issue::Dark => "dark",
+            Issue::Overexposed => "overexposed",
+            Issue::Blurry => "blurry",
+        }
+    }
+}
+
+impl Gate {
+    pub fn is_enabled(&self) -> bool {
+        self.min_brightness.is_some()
+            || self.max_overexposed.is_some()
+            || self.min_sharpness.is_some()
+    }
+
+    /// The issue of `img`, if any. Dark images are reported as such rather
+    /// than as blurry.
+    pub fn check<I>(&self, img: &I) -> Option<Issue>
+    where
+        I: Luma + ?Sized,
+    {
+        if !self.is_enabled() {
+            return None;
+        }
+        let stats = stats(img);
+        let below = |t: Option<f32>, v: f32| t.is_some_and(|t| v < t);
+        if below(self.min_brightness, stats.brightness) {
+            Some(Issue::Dark)
+        } else if self.max_overexposed.is_some_and(|t| stats.overexposed > t) {
+            Some(Issue::Overexposed)
+        } else if below(self.min_sharpness, stats.sharpness) {
+            Some(Issue::Blurry)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//! Relevance of the detections, combining their score, their size, their
+//! label and where they are, so that sinks send the most relevant ones first
+//! (see `sink::Config::rank`) and severity rules can match on it (see
+//! `severity::Rule::rank`).
+//!
+//! The relevance is `(score * s + area * a) * class weight * zone weight`,
+//! `area` being the fraction of the image covered by the box.
+
+use std::cmp;
+use std::collections;
+
+use bb;
+use schema;
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Ranking {
+    #[serde(default = "default_score")]
+    pub score: f32,
+    #[serde(default)]
+    pub area: f32,
+    /// Weights of the labels, 1 for those omitted.
+    #[serde(default)]
+    pub classes: collections::HashMap<String, f32>,
+    /// The weight of a detection is the highest of the zones containing the
+    /// center of its box, 1 if none does.
+    #[serde(default)]
+    pub zones: Vec<Zone>,
+}
+
+/// Rectangle in fractions of the height and the width of the image.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Zone {
+    pub top: f32,
+    pub left: f32,
+    pub bottom: f32,
+    pub right: f32,
+    /// Ignored outside rankings.
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+    /// Replaces `{zone}` in the commands of hooks.
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+fn default_score() -> f32 {
+    1.
+}
+
+fn default_weight() -> f32 {
+    1.
+}
+
+impl Default for Ranking {
+    fn default() -> Self {
+        Self {
+            score: default_score(),
+            area: 0.,
+            classes: collections::HashMap::new(),
+            zones: Vec::new(),
+        }
+    }
+}
+
+impl Zone {
+    /// Whether the zone contains the center of the box of `d`, in an image of
+    /// `height` by `width`.
+    pub fn contains(&self, d: &schema::Detection, height: u32, width: u32) -> bool {
+        self.contains_center(
+            (d.top + d.bottom) / 2.,
+            (d.left + d.right) / 2.,
+            height,
+            width,
+        )
+    }
+
+    /// As `contains`, for a box.
+    pub fn contains_bb(&self, bb: &bb::Bb, height: u32, width: u32) -> bool {
+        self.contains_center(
+            (bb.top + bb.bottom) / 2.,
+            (bb.left + bb.right) / 2.,
+            height,
+            width,
+        )
+    }
+
+    fn contains_center(&self, y: f32, x: f32, height: u32, width: u32) -> bool {
+        let (y, x) = (y / height.max(1) as f32, x / width.max(1) as f32);
+        self.top <= y && y < self.bottom && self.left <= x && x < self.right
+    }
+}
+
+impl Ranking {
+    /// Relevance of `d` in an image of `height` by `width`.
+    pub fn value(&self, d: &schema::Detection, height: u32, width: u32) -> f32 {
+        let pixels = height.max(1) as f32 * width.max(1) as f32;
+        let area = (d.bottom - d.top).max(0.) * (d.right - d.left).max(0.) / pixels;
+        let class = self.classes.get(&d.label).cloned().unwrap_or(1.);
+        let zone = self
+            .zones
+            .iter()
+            .filter(|z| z.contains(d, height, width))
+            .map(|z| z.weight)
+            .reduce(f32::max)
+            .unwrap_or(1.);
+        (d.score * self.score + area * self.area) * class * zone
+    }
+
+    /// Sets the `rank` of the detections of `frame` and orders them from the
+    /// most relevant, keeping the order of ties.
+    pub fn sort(&self, frame: &mut schema::Frame) {
+        let (height, width) = (frame.height, frame.width);
+        for d in frame.detections.iter_mut() {
+            d.rank = Some(self.value(d, height, width));
+        }
+        frame
+            .detections
+            .sort_by(|a, b| b.rank.partial_cmp(&a.rank).unwrap_or(cmp::Ordering::Equal));
+    }
+}
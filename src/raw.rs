@@ -0,0 +1,127 @@
+//! Raw frames of capture devices, as V4L2 or GStreamer buffers, which the
+//! detectors letterbox without decoding them to an `image::DynamicImage`.
+
+use detector;
+use quality;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PixelFormat {
+    Rgb,
+    Bgr,
+    Rgba,
+    Bgra,
+    /// YUV 4:2:2 as `Y0 U Y1 V`, in the BT.601 limited range of V4L2.
+    Yuyv,
+    /// YUV 4:2:2 as `U Y0 V Y1`.
+    Uyvy,
+}
+
+impl PixelFormat {
+    /// Bytes per pixel, on average.
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Rgb | PixelFormat::Bgr => 3,
+            PixelFormat::Rgba | PixelFormat::Bgra => 4,
+            PixelFormat::Yuyv | PixelFormat::Uyvy => 2,
+        }
+    }
+}
+
+/// A frame of `width * height` pixels of `format`, with packed rows.
+#[derive(Clone, Copy)]
+pub struct Frame<'a> {
+    data: &'a [u8],
+    width: u32,
+    height: u32,
+    format: PixelFormat,
+}
+
+impl<'a> Frame<'a> {
+    pub fn new(
+        data: &'a [u8],
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+    ) -> Result<Self, detector::Error> {
+        if width == 0 || height == 0 {
+            return Err(detector::Error::Preprocessing(format!(
+                "a {}x{} frame has no pixels",
+                width, height
+            )));
+        }
+        let len = width as usize * height as usize * format.bytes_per_pixel();
+        if data.len() < len {
+            return Err(detector::Error::Preprocessing(format!(
+                "a {}x{} {:?} frame takes {} bytes, not {}",
+                width,
+                height,
+                format,
+                len,
+                data.len()
+            )));
+        }
+        let yuv = format == PixelFormat::Yuyv || format == PixelFormat::Uyvy;
+        if yuv && !width.is_multiple_of(2) {
+            return Err(detector::Error::Preprocessing(format!(
+                "{:?} frames have an even width, not {}",
+                format, width
+            )));
+        }
+        Ok(Self {
+            data,
+            width,
+            height,
+            format,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn rgb(&self, x: u32, y: u32) -> [u8; 3] {
+        let i = (y as usize * self.width as usize + x as usize) * self.format.bytes_per_pixel();
+        let d = self.data;
+        match self.format {
+            PixelFormat::Rgb | PixelFormat::Rgba => [d[i], d[i + 1], d[i + 2]],
+            PixelFormat::Bgr | PixelFormat::Bgra => [d[i + 2], d[i + 1], d[i]],
+            PixelFormat::Yuyv | PixelFormat::Uyvy => {
+                // the macropixel of the two pixels sharing U and V
+                let m = i & !3;
+                let (y0, u, y1, v) = if self.format == PixelFormat::Yuyv {
+                    (d[m], d[m + 1], d[m + 2], d[m + 3])
+                } else {
+                    (d[m + 1], d[m], d[m + 3], d[m + 2])
+                };
+                yuv_to_rgb(if x.is_multiple_of(2) { y0 } else { y1 }, u, v)
+            }
+        }
+    }
+}
+
+impl<'a> quality::Luma for Frame<'a> {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn luma(&self, x: u32, y: u32) -> u8 {
+        let [r, g, b] = self.rgb(x, y);
+        // as `image::Pixel::to_luma`
+        ((2126 * u32::from(r) + 7152 * u32::from(g) + 722 * u32::from(b)) / 10000) as u8
+    }
+}
+
+fn yuv_to_rgb(y: u8, u: u8, v: u8) -> [u8; 3] {
+    let y = 1.164 * (f32::from(y) - 16.);
+    let (u, v) = (f32::from(u) - 128., f32::from(v) - 128.);
+    let clamp = |c: f32| c.round().clamp(0., 255.) as u8;
+    [
+        clamp(y + 1.596 * v),
+        clamp(y - 0.392 * u - 0.813 * v),
+        clamp(y + 2.017 * u),
+    ]
+}
@@ -0,0 +1,250 @@
+//! Event-triggered recording: saves clips of the frames around detections of
+//! selected classes.
+//!
+//! Each event is saved to its own directory. Frames are kept in a ring buffer
+//! while idle, so that a clip starts `pre` seconds before the trigger, and
+//! recording goes on until `post` seconds after the last trigger. Optionally,
+//! each event is also exported as an animated GIF next to its directory.
+
+use image;
+use std::cmp;
+use std::collections;
+use std::fs;
+use std::io;
+use std::path;
+use std::time;
+
+use animation;
+use bb;
+
+#[derive(Clone, Deserialize)]
+pub struct Rule {
+    pub classes: Vec<String>,
+    #[serde(default = "default_threshold")]
+    pub threshold: f32,
+    /// Seconds of footage to keep before the trigger.
+    #[serde(default)]
+    pub pre: f32,
+    /// Seconds of footage to keep after the trigger.
+    #[serde(default)]
+    pub post: f32,
+}
+
+fn default_threshold() -> f32 {
+    0.5
+}
+
+struct Trigger {
+    threshold: f32,
+    /// Whether each label triggers the rule.
+    labels: Vec<bool>,
+    pre: time::Duration,
+    post: time::Duration,
+}
+
+struct Frame {
+    instant: time::Instant,
+    time: time::SystemTime,
+    img: image::DynamicImage,
+}
+
+/// Frames of the last `span`.
+pub struct RingBuffer {
+    span: time::Duration,
+    frames: collections::VecDeque<Frame>,
+}
+
+impl RingBuffer {
+    pub fn new(span: time::Duration) -> Self {
+        Self {
+            span,
+            frames: collections::VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, frame: Frame) {
+        while let Some(oldest) = self.frames.pop_front() {
+            if frame.instant.duration_since(oldest.instant) < self.span {
+                self.frames.push_front(oldest);
+                break;
+            }
+        }
+        if self.span > time::Duration::from_secs(0) {
+            self.frames.push_back(frame);
+        }
+    }
+
+    /// Removes all the frames, returning those of the last `span` before `now`.
+    fn drain(&mut self, now: time::Instant, span: time::Duration) -> Vec<Frame> {
+        self.frames
+            .drain(..)
+            .filter(|f| now.duration_since(f.instant) <= span)
+            .collect()
+    }
+}
+
+struct Event {
+    name: String,
+    until: time::Instant,
+    /// Frames of the GIF, shrunk to `gif_width`.
+    gif: Vec<(time::Instant, image::DynamicImage)>,
+}
+
+pub struct Recorder {
+    dir: path::PathBuf,
+    triggers: Vec<Trigger>,
+    ring: RingBuffer,
+    event: Option<Event>,
+    gif_width: Option<u32>,
+}
+
+impl Recorder {
+    pub fn new<P>(dir: P, rules: &[Rule], label_names: &[String]) -> io::Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        fs::create_dir_all(dir.as_ref())?;
+        let triggers: Vec<_> = rules
+            .iter()
+            .map(|r| Trigger {
+                threshold: r.threshold,
+                labels: label_names.iter().map(|l| r.classes.contains(l)).collect(),
+                pre: duration(r.pre),
+                post: duration(r.post),
+            })
+            .collect();
+        let span = triggers
+            .iter()
+            .map(|t| t.pre)
+            .max()
+            .unwrap_or(time::Duration::from_secs(0));
+        Ok(Self {
+            dir: dir.as_ref().to_owned(),
+            triggers,
+            ring: RingBuffer::new(span),
+            event: None,
+            gif_width: None,
+        })
+    }
+
+    /// Enables the GIF export of the events, with frames scaled down to at
+    /// most `max_width` pixels wide.
+    pub fn set_gif(&mut self, max_width: Option<u32>) {
+        self.gif_width = max_width;
+    }
+
+    /// Returns the longest `pre` and `post` of the rules triggered by `bbox`.
+    fn triggered(&self, bbox: &[bb::Bb]) -> Option<(time::Duration, time::Duration)> {
+        self.triggers
+            .iter()
+            .filter(|t| bb::above(bbox, t.threshold).any(|bb| t.labels[bb.label]))
+            .fold(None, |acc, t| match acc {
+                Some((pre, post)) => Some((cmp::max(pre, t.pre), cmp::max(post, t.post))),
+                None => Some((t.pre, t.post)),
+            })
+    }
+
+    /// Feeds a frame. Returns the path of the frame if it is saved as part of
+    /// an event.
+    pub fn record(
+        &mut self,
+        img: &image::DynamicImage,
+        bbox: &[bb::Bb],
+    ) -> io::Result<Option<path::PathBuf>> {
+        let frame = Frame {
+            instant: time::Instant::now(),
+            time: time::SystemTime::now(),
+            img: img.clone(),
+        };
+
+        if let Some((pre, post)) = self.triggered(bbox) {
+            let until = frame.instant + post;
+            match self.event {
+                Some(ref mut event) => event.until = cmp::max(event.until, until),
+                None => {
+                    let mut event = Event {
+                        name: stem(frame.time),
+                        until,
+                        gif: Vec::new(),
+                    };
+                    fs::create_dir_all(self.dir.join(&event.name))?;
+                    for f in self.ring.drain(frame.instant, pre) {
+                        self.save(&mut event, &f)?;
+                    }
+                    self.event = Some(event);
+                }
+            }
+        }
+
+        match self.event.take() {
+            Some(mut event) if frame.instant <= event.until => {
+                let path = self.save(&mut event, &frame)?;
+                self.event = Some(event);
+                Ok(Some(path))
+            }
+            event => {
+                if let Some(event) = event {
+                    self.close(event)?;
+                }
+                self.ring.push(frame);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Ends the current event, if any.
+    pub fn finish(&mut self) -> io::Result<()> {
+        match self.event.take() {
+            Some(event) => self.close(event),
+            None => Ok(()),
+        }
+    }
+
+    fn save(&self, event: &mut Event, frame: &Frame) -> io::Result<path::PathBuf> {
+        let path = self
+            .dir
+            .join(&event.name)
+            .join(format!("{}.jpg", stem(frame.time)));
+        frame.img.save(&path)?;
+        if let Some(max_width) = self.gif_width {
+            event
+                .gif
+                .push((frame.instant, animation::shrink(&frame.img, max_width)));
+        }
+        Ok(path)
+    }
+
+    fn close(&self, event: Event) -> io::Result<()> {
+        if event.gif.is_empty() {
+            return Ok(());
+        }
+        let instants: Vec<_> = event.gif.iter().map(|f| f.0).collect();
+        let mut delay = time::Duration::from_millis(100);
+        let frames: Vec<_> = event
+            .gif
+            .into_iter()
+            .enumerate()
+            .map(|(i, (instant, img))| {
+                if let Some(next) = instants.get(i + 1) {
+                    delay = next.duration_since(instant);
+                }
+                animation::Frame { img, delay }
+            })
+            .collect();
+        let path = self.dir.join(format!("{}.gif", event.name));
+        animation::write_gif(io::BufWriter::new(fs::File::create(path)?), &frames)
+    }
+}
+
+/// Milliseconds since the epoch, so that the events and the frames sort
+/// chronologically.
+fn stem(t: time::SystemTime) -> String {
+    let d = t
+        .duration_since(time::UNIX_EPOCH)
+        .unwrap_or(time::Duration::from_secs(0));
+    format!("{}{:03}", d.as_secs(), d.subsec_millis())
+}
+
+fn duration(secs: f32) -> time::Duration {
+    time::Duration::from_millis((secs.max(0.) * 1000.) as u64)
+}
@@ -0,0 +1,152 @@
+//! Minimal Redis client speaking RESP2.
+
+use std::io;
+use std::net;
+use std::time;
+
+use std::io::{BufRead, Read, Write};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Nil,
+    Int(i64),
+    /// Simple and bulk strings.
+    Data(Vec<u8>),
+    Array(Vec<Value>),
+    Error(String),
+}
+
+const TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
+struct Connection {
+    reader: io::BufReader<net::TcpStream>,
+    stream: net::TcpStream,
+}
+
+/// A client reconnecting on the command following a failed one.
+pub struct Client {
+    addrs: Vec<net::SocketAddr>,
+    password: Option<String>,
+    connection: Option<Connection>,
+}
+
+impl Client {
+    pub fn connect<A>(addr: A, password: Option<&str>) -> io::Result<Self>
+    where
+        A: net::ToSocketAddrs,
+    {
+        let mut client = Self {
+            addrs: addr.to_socket_addrs()?.collect(),
+            password: password.map(|p| p.to_owned()),
+            connection: None,
+        };
+        client.reconnect()?;
+        Ok(client)
+    }
+
+    fn reconnect(&mut self) -> io::Result<&mut Connection> {
+        let stream = net::TcpStream::connect(&self.addrs[..])?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut connection = Connection {
+            reader: io::BufReader::new(stream.try_clone()?),
+            stream,
+        };
+        if let Some(ref password) = self.password {
+            connection.pipeline(&[&[b"AUTH", password.as_bytes()]])?;
+        }
+        Ok(self.connection.get_or_insert(connection))
+    }
+
+    pub fn command(&mut self, args: &[&[u8]]) -> io::Result<Value> {
+        self.pipeline(&[args]).map(|mut values| values.remove(0))
+    }
+
+    /// Sends the commands at once and returns their replies. Fails on the
+    /// first error reply, after reading all of them.
+    pub fn pipeline(&mut self, commands: &[&[&[u8]]]) -> io::Result<Vec<Value>> {
+        let connection = match self.connection {
+            Some(ref mut connection) => connection,
+            None => self.reconnect()?,
+        };
+        let result = connection.pipeline(commands);
+        if let Err(ref e) = result {
+            // error replies leave the connection in sync
+            if e.kind() != io::ErrorKind::InvalidInput {
+                self.connection = None;
+            }
+        }
+        result
+    }
+}
+
+impl Connection {
+    fn pipeline(&mut self, commands: &[&[&[u8]]]) -> io::Result<Vec<Value>> {
+        let mut buf = Vec::new();
+        for args in commands.iter() {
+            buf.extend_from_slice(format!("*{}\r\n", args.len()).as_bytes());
+            for arg in args.iter() {
+                buf.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+                buf.extend_from_slice(arg);
+                buf.extend_from_slice(b"\r\n");
+            }
+        }
+        self.stream.write_all(&buf)?;
+
+        let values = commands
+            .iter()
+            .map(|_| self.read_value())
+            .collect::<io::Result<Vec<_>>>()?;
+        for value in values.iter() {
+            if let Value::Error(ref message) = *value {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, message.clone()));
+            }
+        }
+        Ok(values)
+    }
+
+    fn read_value(&mut self) -> io::Result<Value> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed",
+            ));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            return Err(invalid_data("empty reply"));
+        }
+        let (kind, rest) = line.split_at(1);
+        let n = || {
+            rest.parse::<i64>()
+                .map_err(|_| invalid_data("malformed length"))
+        };
+        match kind {
+            "+" => Ok(Value::Data(rest.as_bytes().to_vec())),
+            "-" => Ok(Value::Error(rest.to_owned())),
+            ":" => Ok(Value::Int(n()?)),
+            "$" => match n()? {
+                n if n < 0 => Ok(Value::Nil),
+                n => {
+                    let mut data = vec![0; n as usize + 2];
+                    self.reader.read_exact(&mut data)?;
+                    data.truncate(n as usize);
+                    Ok(Value::Data(data))
+                }
+            },
+            "*" => match n()? {
+                n if n < 0 => Ok(Value::Nil),
+                n => (0..n)
+                    .map(|_| self.read_value())
+                    .collect::<io::Result<_>>()
+                    .map(Value::Array),
+            },
+            _ => Err(invalid_data("unknown reply type")),
+        }
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
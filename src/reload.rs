@@ -0,0 +1,40 @@
+//! Reload requests, raised by SIGHUP.
+//!
+//! The modes that cannot reload ignore SIGHUP, which would otherwise
+//! terminate them.
+
+use std::io;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+pub fn listen() -> io::Result<()> {
+    use libc;
+
+    extern "C" fn handle(_: libc::c_int) {
+        REQUESTED.store(true, Ordering::SeqCst);
+    }
+
+    set(handle as extern "C" fn(libc::c_int) as libc::sighandler_t)
+}
+
+#[cfg(unix)]
+pub fn ignore() -> io::Result<()> {
+    set(::libc::SIG_IGN)
+}
+
+#[cfg(unix)]
+fn set(handler: ::libc::sighandler_t) -> io::Result<()> {
+    if unsafe { ::libc::signal(::libc::SIGHUP, handler) } == ::libc::SIG_ERR {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns whether a reload has been requested since the last call.
+pub fn requested() -> bool {
+    REQUESTED.swap(false, Ordering::SeqCst)
+}
@@ -0,0 +1,469 @@
+//! Client of inference servers speaking the KServe v2 protocol (Triton,
+//! KServe, Seldon MLServer), so that only the network runs remotely while the
+//! preprocessing and the decoding stay local.
+//!
+//! `http://` URLs are those of the HTTP/REST endpoint. With `binary`, tensors
+//! are sent as raw bytes with the binary extension of Triton instead of JSON
+//! numbers. `grpc://` URLs are those of the gRPC endpoint, spoken over
+//! cleartext HTTP/2 (see `grpc`), where tensors are always raw bytes.
+//!
+//! Models must have fixed output shapes but for the batch dimension, since
+//! the decoding is set up from the model metadata before the first request.
+
+use ndarray;
+use serde_json;
+use std::io;
+
+use grpc;
+use http;
+use proto;
+
+/// Largest response, enough for the outputs of detection models.
+const MAX_RESPONSE: usize = 256 << 20;
+
+const MODEL_METADATA: &str = "/inference.GRPCInferenceService/ModelMetadata";
+const MODEL_INFER: &str = "/inference.GRPCInferenceService/ModelInfer";
+
+pub struct Client {
+    /// `http[s]://host:port/v2/models/<name>[/versions/<version>]`, or the
+    /// same with `grpc://`
+    url: http::Url,
+    binary: bool,
+    /// The model name and version (empty for the default) of a `grpc://` URL.
+    grpc: Option<(String, String)>,
+}
+
+#[derive(Deserialize)]
+struct Metadata {
+    inputs: Vec<TensorMetadata>,
+    outputs: Vec<TensorMetadata>,
+}
+
+#[derive(Deserialize)]
+struct TensorMetadata {
+    name: String,
+    shape: Vec<i64>,
+}
+
+#[derive(Serialize)]
+struct InferRequest<'a> {
+    inputs: Vec<Input<'a>>,
+    outputs: Vec<Output<'a>>,
+}
+
+#[derive(Serialize)]
+struct Input<'a> {
+    name: &'a str,
+    shape: &'a [usize],
+    datatype: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<&'a [f32]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parameters: Option<Parameters>,
+}
+
+#[derive(Serialize)]
+struct Output<'a> {
+    name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parameters: Option<Parameters>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+struct Parameters {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    binary_data: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    binary_data_size: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct InferResponse {
+    outputs: Vec<Tensor>,
+}
+
+#[derive(Deserialize)]
+struct Tensor {
+    name: String,
+    shape: Vec<usize>,
+    datatype: String,
+    #[serde(default)]
+    data: Vec<f32>,
+    #[serde(default)]
+    parameters: Parameters,
+}
+
+impl Client {
+    pub fn new(url: &str, binary: bool) -> io::Result<Self> {
+        let (mut url, grpc) = match url.strip_prefix("grpc://") {
+            Some(rest) => {
+                let parsed = http::Url::parse(&format!("http://{}", rest))?;
+                let model = parsed
+                    .path
+                    .trim_end_matches('/')
+                    .strip_prefix("/v2/models/")
+                    .map(|m| match m.find("/versions/") {
+                        Some(i) => (m[..i].to_owned(), m[i + "/versions/".len()..].to_owned()),
+                        None => (m.to_owned(), String::new()),
+                    })
+                    .filter(|m| !m.0.is_empty() && !m.0.contains('/'))
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!(
+                                "expected grpc://host:port/v2/models/<name>[/versions/<version>]: {}",
+                                url
+                            ),
+                        )
+                    })?;
+                (parsed, Some(model))
+            }
+            None => (http::Url::parse(url)?, None),
+        };
+        while url.path.ends_with('/') {
+            url.path.pop();
+        }
+        Ok(Self { url, binary, grpc })
+    }
+
+    /// Returns the shape of the tensor `name` in the model metadata, with
+    /// dynamic dimensions as `-1`.
+    pub fn shape(&self, name: &str) -> io::Result<Vec<i64>> {
+        let metadata = self.metadata()?;
+        metadata
+            .inputs
+            .into_iter()
+            .chain(metadata.outputs)
+            .find(|t| t.name == name)
+            .map(|t| t.shape)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("the model has no tensor {}", name),
+                )
+            })
+    }
+
+    /// Returns the names and shapes of the outputs in the model metadata.
+    pub fn outputs(&self) -> io::Result<Vec<(String, Vec<i64>)>> {
+        let metadata = self.metadata()?;
+        Ok(metadata
+            .outputs
+            .into_iter()
+            .map(|t| (t.name, t.shape))
+            .collect())
+    }
+
+    fn metadata(&self) -> io::Result<Metadata> {
+        let (name, version) = match self.grpc {
+            Some((ref name, ref version)) => (name, version),
+            None => {
+                let response = self.request("GET", "", &[], &[])?;
+                return Ok(serde_json::from_slice(&response.body)?);
+            }
+        };
+        // ModelMetadataRequest
+        let mut request = proto::Message::default();
+        request.string(1, name);
+        request.string(2, version);
+        let response = grpc::call(&self.url, MODEL_METADATA, &request.0, MAX_RESPONSE)?;
+        // ModelMetadataResponse, with the TensorMetadata of the inputs and
+        // the outputs
+        let mut metadata = Metadata {
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+        };
+        for field in proto::fields(&response) {
+            let (number, value) = field?;
+            let tensors = match number {
+                4 => &mut metadata.inputs,
+                5 => &mut metadata.outputs,
+                _ => continue,
+            };
+            let mut tensor = TensorMetadata {
+                name: String::new(),
+                shape: Vec::new(),
+            };
+            for field in proto::fields(value.bytes()?) {
+                match field? {
+                    (1, value) => tensor.name = value.string()?.to_owned(),
+                    (3, value) => tensor
+                        .shape
+                        .extend(value.uints()?.into_iter().map(|d| d as i64)),
+                    _ => {}
+                }
+            }
+            tensors.push(tensor);
+        }
+        Ok(metadata)
+    }
+
+    /// Runs the model on the named `inputs` and returns the tensors
+    /// `output_names`, in this order.
+    pub fn infer(
+        &self,
+        inputs: &[(&str, ndarray::ArrayViewD<f32>)],
+        output_names: &[&str],
+    ) -> io::Result<Vec<ndarray::ArrayD<f32>>> {
+        if let Some((ref name, ref version)) = self.grpc {
+            return self.infer_grpc(name, version, inputs, output_names);
+        }
+        let shapes: Vec<_> = inputs.iter().map(|i| i.1.shape().to_vec()).collect();
+        let data: Vec<Vec<f32>> = inputs
+            .iter()
+            .map(|i| i.1.iter().cloned().collect())
+            .collect();
+        let mut binary = Vec::new();
+        let mut request_inputs = Vec::with_capacity(inputs.len());
+        for ((&(name, _), shape), data) in inputs.iter().zip(shapes.iter()).zip(data.iter()) {
+            let (data, parameters) = if self.binary {
+                let start = binary.len();
+                for v in data.iter() {
+                    let bits = v.to_bits();
+                    binary.extend_from_slice(&[
+                        bits as u8,
+                        (bits >> 8) as u8,
+                        (bits >> 16) as u8,
+                        (bits >> 24) as u8,
+                    ]);
+                }
+                let parameters = Parameters {
+                    binary_data: None,
+                    binary_data_size: Some(binary.len() - start),
+                };
+                (None, Some(parameters))
+            } else {
+                (Some(data.as_slice()), None)
+            };
+            request_inputs.push(Input {
+                name,
+                shape,
+                datatype: "FP32",
+                data,
+                parameters,
+            });
+        }
+        let request = InferRequest {
+            inputs: request_inputs,
+            outputs: output_names
+                .iter()
+                .map(|&name| Output {
+                    name,
+                    parameters: if self.binary {
+                        Some(Parameters {
+                            binary_data: Some(true),
+                            binary_data_size: None,
+                        })
+                    } else {
+                        None
+                    },
+                })
+                .collect(),
+        };
+        let mut body = serde_json::to_vec(&request)?;
+        let header_length = body.len().to_string();
+        let mut headers = vec![("Content-Type", "application/json")];
+        if self.binary {
+            body.extend_from_slice(&binary);
+            headers = vec![
+                ("Content-Type", "application/octet-stream"),
+                ("Inference-Header-Content-Length", &header_length),
+            ];
+        }
+        let response = self.request("POST", "/infer", &headers, &body)?;
+
+        let json_length = response
+            .header("Inference-Header-Content-Length")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(response.body.len())
+            .min(response.body.len());
+        let (json, mut rest) = response.body.split_at(json_length);
+        let outputs: InferResponse = serde_json::from_slice(json)?;
+        let mut tensors = vec![None; output_names.len()];
+        for tensor in outputs.outputs {
+            let size = tensor.parameters.binary_data_size;
+            let (data, next) = match size {
+                Some(size) if size <= rest.len() => rest.split_at(size),
+                Some(_) => return Err(invalid_data("truncated binary output".to_owned())),
+                None => (&[][..], rest),
+            };
+            rest = next;
+            let i = match output_names.iter().position(|&n| n == tensor.name) {
+                Some(i) => i,
+                None => continue,
+            };
+            if tensor.datatype != "FP32" {
+                return Err(invalid_data(format!(
+                    "{} is {}, expected FP32",
+                    tensor.name, tensor.datatype
+                )));
+            }
+            let data = match size {
+                Some(_) => data
+                    .chunks(4)
+                    .map(|b| {
+                        f32::from_bits(
+                            b[0] as u32
+                                | (b[1] as u32) << 8
+                                | (b[2] as u32) << 16
+                                | (b[3] as u32) << 24,
+                        )
+                    })
+                    .collect(),
+                None => tensor.data,
+            };
+            let array = ndarray::ArrayD::from_shape_vec(tensor.shape, data)
+                .map_err(|e| invalid_data(format!("{}: {}", output_names[i], e)))?;
+            tensors[i] = Some(array);
+        }
+        ordered(tensors, output_names)
+    }
+
+    fn infer_grpc(
+        &self,
+        name: &str,
+        version: &str,
+        inputs: &[(&str, ndarray::ArrayViewD<f32>)],
+        output_names: &[&str],
+    ) -> io::Result<Vec<ndarray::ArrayD<f32>>> {
+        // ModelInferRequest, with the InferInputTensor and the
+        // InferRequestedOutputTensor, and the inputs as raw_input_contents
+        let mut request = proto::Message::default();
+        request.string(1, name);
+        request.string(2, version);
+        for &(name, ref array) in inputs {
+            let mut tensor = proto::Message::default();
+            tensor.string(1, name);
+            tensor.string(2, "FP32");
+            let shape: Vec<_> = array.shape().iter().map(|&d| d as u64).collect();
+            tensor.packed_uints(3, &shape);
+            request.message(5, &tensor);
+        }
+        for &name in output_names {
+            let mut tensor = proto::Message::default();
+            tensor.string(1, name);
+            request.message(6, &tensor);
+        }
+        for (_, array) in inputs {
+            let raw: Vec<u8> = array.iter().flat_map(|v| v.to_le_bytes()).collect();
+            request.bytes(7, &raw);
+        }
+        let response = grpc::call(&self.url, MODEL_INFER, &request.0, MAX_RESPONSE)?;
+        infer_response(&response, output_names)
+    }
+
+    fn request(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> io::Result<http::Response> {
+        let mut url = self.url.clone();
+        url.path += path;
+        let response = http::send(method, &url, headers, body, MAX_RESPONSE)?;
+        if response.status == 200 {
+            return Ok(response);
+        }
+        #[derive(Deserialize)]
+        struct Error {
+            error: String,
+        }
+        let message = serde_json::from_slice::<Error>(&response.body)
+            .map(|e| e.error)
+            .unwrap_or_default();
+        let kind = match response.status {
+            404 => io::ErrorKind::NotFound,
+            _ => io::ErrorKind::InvalidData,
+        };
+        Err(io::Error::new(
+            kind,
+            format!(
+                "{} {}: status {} {}",
+                method, url.path, response.status, message
+            ),
+        ))
+    }
+}
+
+/// The tensors `output_names` of a ModelInferResponse, in this order.
+fn infer_response(response: &[u8], output_names: &[&str]) -> io::Result<Vec<ndarray::ArrayD<f32>>> {
+    // the InferOutputTensor, with their name, datatype, shape and contents
+    let mut outputs = Vec::new();
+    let mut raw = Vec::new();
+    for field in proto::fields(response) {
+        match field? {
+            (5, value) => {
+                let (mut name, mut datatype, mut shape, mut data) =
+                    ("", "", Vec::new(), Vec::new());
+                for field in proto::fields(value.bytes()?) {
+                    match field? {
+                        (1, value) => name = value.string()?,
+                        (2, value) => datatype = value.string()?,
+                        (3, value) => shape.extend(value.uints()?.into_iter().map(|d| d as usize)),
+                        // fp32_contents of InferTensorContents
+                        (5, value) => {
+                            for field in proto::fields(value.bytes()?) {
+                                if let (6, value) = field? {
+                                    data.extend(value.floats()?);
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                outputs.push((name, datatype, shape, data));
+            }
+            (6, value) => raw.push(value.bytes()?),
+            _ => {}
+        }
+    }
+    // raw_output_contents, if any, are those of each output in order
+    if !raw.is_empty() && raw.len() != outputs.len() {
+        return Err(invalid_data(
+            "raw_output_contents do not match the outputs".to_owned(),
+        ));
+    }
+    let mut tensors = vec![None; output_names.len()];
+    for (j, (name, datatype, shape, data)) in outputs.into_iter().enumerate() {
+        let i = match output_names.iter().position(|&n| n == name) {
+            Some(i) => i,
+            None => continue,
+        };
+        if datatype != "FP32" {
+            return Err(invalid_data(format!(
+                "{} is {}, expected FP32",
+                name, datatype
+            )));
+        }
+        let data = match raw.get(j) {
+            Some(raw) if raw.len() % 4 == 0 => raw
+                .chunks(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect(),
+            Some(_) => return Err(invalid_data(format!("{}: truncated raw output", name))),
+            None => data,
+        };
+        let array = ndarray::ArrayD::from_shape_vec(shape, data)
+            .map_err(|e| invalid_data(format!("{}: {}", name, e)))?;
+        tensors[i] = Some(array);
+    }
+    ordered(tensors, output_names)
+}
+
+fn ordered(
+    tensors: Vec<Option<ndarray::ArrayD<f32>>>,
+    output_names: &[&str],
+) -> io::Result<Vec<ndarray::ArrayD<f32>>> {
+    tensors
+        .into_iter()
+        .zip(output_names)
+        .map(|(t, name)| {
+            t.ok_or_else(|| invalid_data(format!("no output {} in the response", name)))
+        })
+        .collect()
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,244 @@
+//! In-memory access to the results of a pipeline, for library users.
+//!
+//! `pipeline::stream` runs a pipeline on its own thread and returns a
+//! `DetectionStream` of its frames, as sent to the sinks. The adapters of
+//! `Frames` then filter and group them like other iterators:
+//!
+//! ```no_run
+//! # use menoh_yolo::pipeline;
+//! # use std::time::Duration;
+//! use menoh_yolo::results::Frames;
+//!
+//! let config = pipeline::load("pipeline.json").unwrap();
+//! let frames = pipeline::stream(config, |warning| eprintln!("{}", warning))
+//!     .filter_class(&["person"])
+//!     .throttle(Duration::from_secs(1));
+//! for frame in frames {
+//!     println!("{} persons", frame.unwrap().detections.len());
+//! }
+//! ```
+
+use image;
+use std::collections;
+use std::io;
+use std::sync;
+use std::thread;
+use std::time;
+
+use pipeline;
+use schema;
+use sink;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Frames kept while the consumer is busy, before the pipeline blocks.
+const CAPACITY: usize = 16;
+
+/// The frames of a pipeline running on a thread. The pipeline stops when the
+/// stream is dropped, and its error, if any, is the last item.
+pub struct DetectionStream {
+    frames: sync::mpsc::Receiver<schema::Frame>,
+    stopped: sync::Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<Result<(), pipeline::Error>>>,
+}
+
+/// The end of the channel attached to the sinks of the pipeline.
+pub struct Tap {
+    frames: sync::mpsc::SyncSender<schema::Frame>,
+    stopped: sync::Arc<AtomicBool>,
+}
+
+impl DetectionStream {
+    /// Calls `run` on a new thread with the tap to pass to the pipeline.
+    pub fn spawn<F>(run: F) -> Self
+    where
+        F: FnOnce(Tap) -> Result<(), pipeline::Error> + Send + 'static,
+    {
+        let (tx, rx) = sync::mpsc::sync_channel(CAPACITY);
+        let stopped = sync::Arc::new(AtomicBool::new(false));
+        let tap = Tap {
+            frames: tx,
+            stopped: stopped.clone(),
+        };
+        Self {
+            frames: rx,
+            stopped,
+            thread: Some(thread::spawn(move || run(tap))),
+        }
+    }
+}
+
+impl Iterator for DetectionStream {
+    type Item = Result<schema::Frame, pipeline::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Ok(frame) = self.frames.recv() {
+            return Some(Ok(frame));
+        }
+        match self.thread.take()?.join() {
+            Ok(Ok(())) => None,
+            Ok(Err(e)) => Some(Err(e)),
+            Err(_) => Some(Err(pipeline::Error::Invalid(
+                "the pipeline panicked".to_owned(),
+            ))),
+        }
+    }
+}
+
+impl Drop for DetectionStream {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Tap {
+    /// Whether the stream has been dropped.
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    pub fn sink(&self) -> Box<dyn sink::Sink> {
+        Box::new(Channel(self.frames.clone()))
+    }
+}
+
+struct Channel(sync::mpsc::SyncSender<schema::Frame>);
+
+impl sink::Sink for Channel {
+    fn send(&mut self, frame: &schema::Frame, _: &image::DynamicImage) -> io::Result<()> {
+        // a dropped stream stops the pipeline before the next frame
+        let _ = self.0.send(frame.clone());
+        Ok(())
+    }
+}
+
+/// Adapters of iterators of frames. Errors are passed through.
+pub trait Frames<E>: Iterator<Item = Result<schema::Frame, E>> + Sized {
+    /// Keeps the detections of `classes`, and the frames with any of them.
+    fn filter_class(self, classes: &[&str]) -> FilterClass<Self> {
+        FilterClass {
+            frames: self,
+            classes: classes.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    /// Drops the frames arriving less than `interval` after the last frame
+    /// kept of the same source and model.
+    fn throttle(self, interval: time::Duration) -> Throttle<Self> {
+        Throttle {
+            frames: self,
+            interval,
+            last: collections::HashMap::new(),
+        }
+    }
+
+    /// Groups the frames arriving within consecutive spans of `span`. A group
+    /// is yielded when a frame arrives after its span, or at the end.
+    fn window(self, span: time::Duration) -> Window<Self> {
+        Window {
+            frames: self,
+            span,
+            start: None,
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl<E, I> Frames<E> for I where I: Iterator<Item = Result<schema::Frame, E>> {}
+
+pub struct FilterClass<I> {
+    frames: I,
+    classes: Vec<String>,
+}
+
+impl<E, I> Iterator for FilterClass<I>
+where
+    I: Iterator<Item = Result<schema::Frame, E>>,
+{
+    type Item = Result<schema::Frame, E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let mut frame = match self.frames.next()? {
+                Ok(frame) => frame,
+                Err(e) => return Some(Err(e)),
+            };
+            let classes = &self.classes;
+            frame.detections.retain(|d| classes.contains(&d.label));
+            if !frame.detections.is_empty() {
+                return Some(Ok(frame));
+            }
+        }
+    }
+}
+
+pub struct Throttle<I> {
+    frames: I,
+    interval: time::Duration,
+    last: collections::HashMap<(Option<String>, Option<String>), time::Instant>,
+}
+
+impl<E, I> Iterator for Throttle<I>
+where
+    I: Iterator<Item = Result<schema::Frame, E>>,
+{
+    type Item = Result<schema::Frame, E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let frame = match self.frames.next()? {
+                Ok(frame) => frame,
+                Err(e) => return Some(Err(e)),
+            };
+            let now = time::Instant::now();
+            let key = (frame.source.clone(), frame.model.clone());
+            let due = self
+                .last
+                .get(&key)
+                .is_none_or(|&last| now.duration_since(last) >= self.interval);
+            if due {
+                self.last.insert(key, now);
+                return Some(Ok(frame));
+            }
+        }
+    }
+}
+
+pub struct Window<I> {
+    frames: I,
+    span: time::Duration,
+    start: Option<time::Instant>,
+    pending: Vec<schema::Frame>,
+}
+
+impl<E, I> Iterator for Window<I>
+where
+    I: Iterator<Item = Result<schema::Frame, E>>,
+{
+    type Item = Result<Vec<schema::Frame>, E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let frame = match self.frames.next() {
+                Some(Ok(frame)) => frame,
+                Some(Err(e)) => return Some(Err(e)),
+                None if self.pending.is_empty() => return None,
+                None => return Some(Ok(self.pending.split_off(0))),
+            };
+            let now = time::Instant::now();
+            let start = *self.start.get_or_insert(now);
+            if now.duration_since(start) < self.span {
+                self.pending.push(frame);
+                continue;
+            }
+            // spans without frames are skipped
+            let spans = (now.duration_since(start).as_secs_f64() / self.span.as_secs_f64()) as u32;
+            self.start = Some(start + self.span * spans);
+            let window = self.pending.split_off(0);
+            self.pending.push(frame);
+            if !window.is_empty() {
+                return Some(Ok(window));
+            }
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::ops;
+use std::time;
+
+pub fn format(t: time::SystemTime) -> String {
+    let d = t
+        .duration_since(time::UNIX_EPOCH)
+        .unwrap_or(time::Duration::from_secs(0));
+    let secs = d.as_secs();
+    let (days, secs) = ((secs / 86400) as i64, secs % 86400);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        d.subsec_millis()
+    )
+}
+
+/// Parses the times of `format`, with any fraction of a second and a `Z` or
+/// `+HH:MM` offset. `None` for other strings and for times before 1970.
+pub fn parse(s: &str) -> Option<time::SystemTime> {
+    let b = s.as_bytes();
+    if b.len() < 20 || b[4] != b'-' || b[7] != b'-' || b[13] != b':' || b[16] != b':' {
+        return None;
+    }
+    if b[10] != b'T' && b[10] != b't' && b[10] != b' ' {
+        return None;
+    }
+    let (year, month, day) = (digits(s, 0..4)?, digits(s, 5..7)?, digits(s, 8..10)?);
+    let (hour, minute, second) = (digits(s, 11..13)?, digits(s, 14..16)?, digits(s, 17..19)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    let mut rest = &s[19..];
+    let mut nanos = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let len = fraction.bytes().take_while(|b| b.is_ascii_digit()).count();
+        if len == 0 {
+            return None;
+        }
+        nanos = format!("{:0<9}", &fraction[..len.min(9)]).parse().ok()?;
+        rest = &fraction[len..];
+    }
+    let offset = match rest {
+        "Z" | "z" => 0,
+        _ if rest.len() == 6 && rest.as_bytes()[3] == b':' => {
+            let secs = i64::from(digits(rest, 1..3)? * 3600 + digits(rest, 4..6)? * 60);
+            match rest.as_bytes()[0] {
+                b'+' => secs,
+                b'-' => -secs,
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    let secs = days_from_civil(i64::from(year), month, day) * 86400
+        + i64::from(hour * 3600 + minute * 60 + second)
+        - offset;
+    if secs < 0 {
+        return None;
+    }
+    Some(time::UNIX_EPOCH + time::Duration::new(secs as u64, nanos))
+}
+
+/// The number of `s[range]`, which must only have digits.
+fn digits(s: &str, range: ops::Range<usize>) -> Option<u32> {
+    let digits = s.get(range)?;
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+// http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(y: i64, m: u32, d: u32) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = (if y >= 0 { y } else { y - 399 }) / 400;
+    let yoe = y - era * 400;
+    let mp = i64::from(if m > 2 { m - 3 } else { m + 9 });
+    let doy = (153 * mp + 2) / 5 + i64::from(d) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(z: i64) -> (i64, u32, u32) {
+    let z = z + 719468;
+    let era = (if z >= 0 { z } else { z - 146096 }) / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    (y, m as _, d as _)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2013-05-24T00:00:00Z
+    const SECS: u64 = 1_369_353_600;
+
+    fn at(secs: u64, millis: u64) -> Option<time::SystemTime> {
+        Some(time::UNIX_EPOCH + time::Duration::from_millis(secs * 1000 + millis))
+    }
+
+    #[test]
+    fn parses_offsets() {
+        assert_eq!(parse("2013-05-24T00:00:00Z"), at(SECS, 0));
+        assert_eq!(parse("2013-05-24T02:00:00+02:00"), at(SECS, 0));
+        assert_eq!(parse("2013-05-23T18:30:00-05:30"), at(SECS, 0));
+        assert_eq!(parse("2013-05-24t00:00:00.25z"), at(SECS, 250));
+        assert_eq!(parse("2013-05-24 05:30:00.5+05:30"), at(SECS, 500));
+    }
+
+    #[test]
+    fn rejects_invalid_times() {
+        assert_eq!(parse("1970-01-01T00:00:00+00:01"), None);
+        assert_eq!(parse("2013-05-24T00:00:00"), None);
+        assert_eq!(parse("2013-05-24T00:00:00+0200"), None);
+        assert_eq!(parse("2013-05-24T00:00:00.Z"), None);
+        assert_eq!(parse("2013-13-24T00:00:00Z"), None);
+    }
+
+    #[test]
+    fn parses_what_it_formats() {
+        let t = at(SECS, 123).unwrap();
+        assert_eq!(format(t), "2013-05-24T00:00:00.123Z");
+        assert_eq!(parse(&format(t)), Some(t));
+    }
+}
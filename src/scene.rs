@@ -0,0 +1,145 @@
+//! Cuts between the shots of edited videos, found by the difference of the
+//! color histograms of consecutive frames, so that tracks are not carried
+//! across them and the results can be segmented by scene (see
+//! `pipeline::Config::scenes`).
+
+use image;
+use std::path;
+
+use image::GenericImage;
+
+/// Bins of each channel of the histograms.
+const BINS: usize = 16;
+
+/// Pixels sampled along each axis, at most.
+const SAMPLES: u32 = 128;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Config {
+    /// Distance between the histograms of consecutive frames, from 0 to 1,
+    /// from which a new scene starts.
+    #[serde(default = "default_threshold")]
+    pub threshold: f32,
+    /// Frames a scene lasts at least, so that flashes do not cut it.
+    #[serde(default = "default_min_frames")]
+    pub min_frames: u64,
+    /// JSON lines file the scenes are appended to as they end.
+    #[serde(default)]
+    pub path: Option<path::PathBuf>,
+}
+
+fn default_threshold() -> f32 {
+    0.5
+}
+
+fn default_min_frames() -> u64 {
+    5
+}
+
+/// The frames of a scene of a source.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Scene {
+    pub source: String,
+    /// Index from 0 of the scene in the source.
+    pub index: u64,
+    /// RFC 3339 times of the first and the last frames.
+    pub start: String,
+    pub end: String,
+    /// Index from 0 of the first frame in the source.
+    pub first_frame: u64,
+    pub frames: u64,
+}
+
+/// The scenes of a source.
+pub struct Scenes {
+    threshold: f32,
+    min_frames: u64,
+    source: String,
+    previous: Option<Vec<f32>>,
+    n_frames: u64,
+    current: Option<Scene>,
+}
+
+impl Scenes {
+    pub fn new(config: &Config, source: &str) -> Self {
+        Self {
+            threshold: config.threshold,
+            min_frames: config.min_frames,
+            source: source.to_owned(),
+            previous: None,
+            n_frames: 0,
+            current: None,
+        }
+    }
+
+    /// Adds the next frame, taken at the RFC 3339 `time`. Returns the scene
+    /// ended by it, if it starts a new one.
+    pub fn update(&mut self, img: &image::DynamicImage, time: &str) -> Option<Scene> {
+        let histogram = histogram(img);
+        let cut = match (self.previous.as_ref(), self.current.as_ref()) {
+            (Some(previous), Some(current)) => {
+                current.frames >= self.min_frames
+                    && distance(previous, &histogram) >= self.threshold
+            }
+            _ => false,
+        };
+        self.previous = Some(histogram);
+        let ended = if cut { self.current.take() } else { None };
+        let index = ended.as_ref().map_or(0, |s| s.index + 1);
+        let (n_frames, source) = (self.n_frames, &self.source);
+        let scene = self.current.get_or_insert_with(|| Scene {
+            source: source.clone(),
+            index,
+            start: time.to_owned(),
+            end: time.to_owned(),
+            first_frame: n_frames,
+            frames: 0,
+        });
+        scene.end = time.to_owned();
+        scene.frames += 1;
+        self.n_frames += 1;
+        ended
+    }
+
+    /// Index of the current scene.
+    pub fn index(&self) -> u64 {
+        self.current.as_ref().map_or(0, |s| s.index)
+    }
+
+    /// Ends the current scene, once no more frames follow.
+    pub fn finish(&mut self) -> Option<Scene> {
+        self.previous = None;
+        self.current.take()
+    }
+}
+
+/// Histograms of the red, green and blue channels, each summing to 1.
+fn histogram(img: &image::DynamicImage) -> Vec<f32> {
+    let (width, height) = img.dimensions();
+    let (step_x, step_y) = ((width / SAMPLES).max(1), (height / SAMPLES).max(1));
+    let mut histogram = vec![0.; 3 * BINS];
+    let mut n = 0;
+    for y in (0..height).step_by(step_y as usize) {
+        for x in (0..width).step_by(step_x as usize) {
+            let p = img.get_pixel(x, y);
+            for c in 0..3 {
+                histogram[c * BINS + p[c] as usize * BINS / 256] += 1.;
+            }
+            n += 1;
+        }
+    }
+    for v in histogram.iter_mut() {
+        *v /= n.max(1) as f32;
+    }
+    histogram
+}
+
+/// Half the L1 distance of the histograms, averaged over the channels: 0 for
+/// the same colors, 1 for disjoint ones.
+fn distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter()
+        .zip(b.iter())
+        .map(|(a, b)| (a - b).abs())
+        .sum::<f32>()
+        / 6.
+}
@@ -0,0 +1,553 @@
+//! Priority scheduling of inference requests over a pool of detectors.
+//!
+//! Requests are queued per priority and picked by smooth weighted round-robin,
+//! so latency-sensitive work goes first without starving the rest. A
+//! `DutyCycle` caps the share of time the workers spend on inference.
+
+use image;
+use std::collections;
+use std::fmt;
+use std::path;
+use std::str;
+use std::sync;
+use std::thread;
+use std::time;
+
+use bb;
+use detector;
+use downscale;
+use memory;
+use quality;
+use yolo_v2;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    High,
+    Normal,
+    Low,
+}
+
+impl Priority {
+    fn index(self) -> usize {
+        match self {
+            Priority::High => 0,
+            Priority::Normal => 1,
+            Priority::Low => 2,
+        }
+    }
+}
+
+/// Relative share of picks given to each priority while all of them have
+/// pending requests.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct Weights {
+    pub high: u32,
+    pub normal: u32,
+    pub low: u32,
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Self {
+            high: 8,
+            normal: 4,
+            low: 1,
+        }
+    }
+}
+
+/// A pool of `workers` detectors serving the requests of `serve`, as the
+/// `pool` of the config of the model.
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct Config {
+    #[serde(default = "default_workers")]
+    pub workers: usize,
+    #[serde(default)]
+    pub weights: Weights,
+    #[serde(default)]
+    pub limits: Limits,
+}
+
+fn default_workers() -> usize {
+    1
+}
+
+/// Caps on the pending requests of a pool. Requests beyond them are rejected.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Limits {
+    pub max_pending: Option<usize>,
+    /// Total size of the decoded images waiting in the queue.
+    pub max_pending_bytes: Option<usize>,
+    /// Raises an `Alert` when the queue stays deep, before it gets full.
+    pub watermark: Option<Watermark>,
+}
+
+/// A queue holding more than `pending` requests for `secs` seconds in a row.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub struct Watermark {
+    pub pending: usize,
+    #[serde(default)]
+    pub secs: f32,
+}
+
+/// Crossings of the `Watermark` of a pool (see `Pool::on_watermark`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Alert {
+    /// The queue has held more requests than the watermark for `since`.
+    High {
+        pending: usize,
+        since: time::Duration,
+    },
+    /// The queue is back at or below the watermark.
+    Cleared { pending: usize },
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Alert::High { pending, since } => write!(
+                f,
+                "{} requests pending, above the watermark for {:.1} s",
+                pending,
+                since.as_secs_f64()
+            ),
+            Alert::Cleared { pending } => {
+                write!(f, "{} requests pending, back below the watermark", pending)
+            }
+        }
+    }
+}
+
+/// Follows the depth of a queue against a watermark.
+struct Monitor {
+    watermark: Option<Watermark>,
+    above: Option<time::Instant>,
+    raised: bool,
+    peak: usize,
+}
+
+impl Monitor {
+    /// The alert raised by a queue of `pending` requests, if any.
+    fn observe(&mut self, pending: usize) -> Option<Alert> {
+        self.peak = self.peak.max(pending);
+        let watermark = self.watermark?;
+        if pending > watermark.pending {
+            let since = self.above.get_or_insert_with(time::Instant::now).elapsed();
+            let secs = since.as_secs() as f32 + since.subsec_nanos() as f32 * 1e-9;
+            if self.raised || secs < watermark.secs {
+                return None;
+            }
+            self.raised = true;
+            Some(Alert::High { pending, since })
+        } else {
+            self.above = None;
+            if !self.raised {
+                return None;
+            }
+            self.raised = false;
+            Some(Alert::Cleared { pending })
+        }
+    }
+}
+
+type AlertFn = Box<dyn FnMut(Alert) + Send>;
+
+/// Observes `pending` requests, calling the alert function once the monitor
+/// is released, so that it can query the pool.
+fn observe(monitor: &sync::Mutex<Monitor>, alert: &sync::Mutex<Option<AlertFn>>, pending: usize) {
+    let raised = monitor.lock().unwrap().observe(pending);
+    if let Some(raised) = raised {
+        if let Some(ref mut f) = *alert.lock().unwrap() {
+            f(raised);
+        }
+    }
+}
+
+/// Work for `busy_ms`, then sleep for `idle_ms`, on average.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Quota {
+    pub busy_ms: u64,
+    pub idle_ms: u64,
+}
+
+/// Parses `BUSY/IDLE` in milliseconds, e.g. `200/800`.
+impl str::FromStr for Quota {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut parts = s.splitn(2, '/').map(|p| p.trim().parse());
+        match (parts.next(), parts.next()) {
+            (Some(Ok(busy_ms)), Some(Ok(idle_ms))) if busy_ms > 0 => Ok(Self { busy_ms, idle_ms }),
+            _ => Err(format!(
+                "invalid duty cycle {:?}, expected BUSY/IDLE in ms",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Quota {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.busy_ms, self.idle_ms)
+    }
+}
+
+/// Throttles work to a `Quota`, which can be changed at any time. Clones share
+/// the quota, but each throttles its own work.
+#[derive(Clone, Default)]
+pub struct DutyCycle {
+    quota: sync::Arc<sync::Mutex<Option<Quota>>>,
+    busy: time::Duration,
+}
+
+impl DutyCycle {
+    pub fn new(quota: Option<Quota>) -> Self {
+        Self {
+            quota: sync::Arc::new(sync::Mutex::new(quota)),
+            busy: time::Duration::from_secs(0),
+        }
+    }
+
+    pub fn quota(&self) -> Option<Quota> {
+        *self.quota.lock().unwrap()
+    }
+
+    pub fn set_quota(&self, quota: Option<Quota>) {
+        *self.quota.lock().unwrap() = quota;
+    }
+
+    /// Runs `f`, then sleeps once the work since the last sleep reaches the
+    /// busy time, for as long as the quota gives to that work.
+    pub fn run<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        let start = time::Instant::now();
+        let result = f();
+        let quota = match self.quota() {
+            Some(quota) => quota,
+            None => {
+                self.busy = time::Duration::from_secs(0);
+                return result;
+            }
+        };
+        self.busy += start.elapsed();
+        let busy_ms = self.busy.as_secs() * 1000 + self.busy.subsec_millis() as u64;
+        if busy_ms >= quota.busy_ms {
+            thread::sleep(time::Duration::from_millis(
+                busy_ms * quota.idle_ms / quota.busy_ms,
+            ));
+            self.busy = time::Duration::from_secs(0);
+        }
+        result
+    }
+}
+
+struct State<T> {
+    queues: [collections::VecDeque<T>; 3],
+    current: [i64; 3],
+    closed: bool,
+}
+
+pub struct WeightedQueue<T> {
+    weights: [i64; 3],
+    state: sync::Mutex<State<T>>,
+    cond: sync::Condvar,
+}
+
+impl<T> WeightedQueue<T> {
+    pub fn new(weights: Weights) -> Self {
+        Self {
+            weights: [weights.high as _, weights.normal as _, weights.low as _],
+            state: sync::Mutex::new(State {
+                queues: [
+                    collections::VecDeque::new(),
+                    collections::VecDeque::new(),
+                    collections::VecDeque::new(),
+                ],
+                current: [0; 3],
+                closed: false,
+            }),
+            cond: sync::Condvar::new(),
+        }
+    }
+
+    pub fn push(&self, item: T, priority: Priority) {
+        let mut state = self.state.lock().unwrap();
+        state.queues[priority.index()].push_back(item);
+        self.cond.notify_one();
+    }
+
+    /// Blocks until an item is available. Returns `None` once the queue is
+    /// closed and drained.
+    pub fn pop(&self) -> Option<T> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(item) = self.pick(&mut state) {
+                return Some(item);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.cond.wait(state).unwrap();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.queues.iter().map(|q| q.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.cond.notify_all();
+    }
+
+    fn pick(&self, state: &mut State<T>) -> Option<T> {
+        let mut total = 0;
+        let mut best: Option<usize> = None;
+        for i in 0..3 {
+            if state.queues[i].is_empty() {
+                continue;
+            }
+            state.current[i] += self.weights[i];
+            total += self.weights[i];
+            match best {
+                Some(b) if state.current[b] >= state.current[i] => (),
+                _ => best = Some(i),
+            }
+        }
+        let best = best?;
+        state.current[best] -= total;
+        state.queues[best].pop_front()
+    }
+}
+
+struct Job {
+    img: image::DynamicImage,
+    /// Scaled down by `factor` from an image of `dimensions` (see
+    /// `downscale::predict`).
+    factor: f32,
+    dimensions: (u32, u32),
+    size: usize,
+    /// The parameters when the request was submitted.
+    params: sync::Arc<yolo_v2::Params>,
+    reply: sync::mpsc::Sender<Result<Detections, detector::Error>>,
+}
+
+/// The detections of a request, with what the detector tells of them.
+pub struct Detections {
+    pub bbox: Vec<bb::Bb>,
+    pub partial: bool,
+    pub low_quality: Option<quality::Issue>,
+    pub warnings: Vec<detector::Warning>,
+}
+
+/// A pool of detectors, each running on its own thread with its own model.
+pub struct Pool {
+    queue: sync::Arc<WeightedQueue<Job>>,
+    queued_bytes: sync::Arc<AtomicUsize>,
+    monitor: sync::Arc<sync::Mutex<Monitor>>,
+    alert: sync::Arc<sync::Mutex<Option<AlertFn>>>,
+    params: sync::Mutex<sync::Arc<yolo_v2::Params>>,
+    limits: Limits,
+    models: memory::Usage,
+    duty_cycle: DutyCycle,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl Pool {
+    pub fn new<P>(
+        path: P,
+        config: sync::Arc<yolo_v2::Config>,
+        backend: &str,
+        backend_config: &str,
+        n_workers: usize,
+        weights: Weights,
+        limits: Limits,
+    ) -> Result<Self, detector::Error>
+    where
+        P: AsRef<path::Path>,
+    {
+        let queue: sync::Arc<WeightedQueue<Job>> = sync::Arc::new(WeightedQueue::new(weights));
+        let queued_bytes = sync::Arc::new(AtomicUsize::new(0));
+        let monitor = sync::Arc::new(sync::Mutex::new(Monitor {
+            watermark: limits.watermark,
+            above: None,
+            raised: false,
+            peak: 0,
+        }));
+        let alert = sync::Arc::new(sync::Mutex::new(None));
+        let params = sync::Mutex::new(sync::Arc::new(config.params.clone()));
+        let duty_cycle = DutyCycle::new(None);
+        let (ready_tx, ready_rx) = sync::mpsc::channel();
+        let mut workers = Vec::with_capacity(n_workers);
+        for _ in 0..n_workers {
+            let path = path.as_ref().to_owned();
+            let config = config.clone();
+            let (backend, backend_config) = (backend.to_owned(), backend_config.to_owned());
+            let queue = queue.clone();
+            let queued_bytes = queued_bytes.clone();
+            let monitor = monitor.clone();
+            let alert = alert.clone();
+            let ready = ready_tx.clone();
+            let mut duty_cycle = duty_cycle.clone();
+            workers.push(thread::spawn(move || {
+                if let Err(e) = config.threads.inference.apply() {
+                    let _ = ready.send(Err(detector::Error::Config(format!("threads: {}", e))));
+                    return;
+                }
+                let built = detector::from_onnx(path, &config, &backend, &backend_config);
+                let mut model = match built {
+                    Ok(model) => {
+                        let _ = ready.send(model.memory_usage());
+                        model
+                    }
+                    Err(err) => {
+                        let _ = ready.send(Err(err));
+                        return;
+                    }
+                };
+                let mut params = sync::Arc::new(config.params.clone());
+                while let Some(job) = queue.pop() {
+                    queued_bytes.fetch_sub(job.size, Ordering::SeqCst);
+                    observe(&monitor, &alert, queue.len());
+                    if !sync::Arc::ptr_eq(&params, &job.params) {
+                        model.set_params((*job.params).clone());
+                        params = job.params.clone();
+                    }
+                    let (img, factor, dimensions) = (&job.img, job.factor, job.dimensions);
+                    let detected = duty_cycle.run(|| {
+                        downscale::predict(&mut *model, img, factor, dimensions, |m| m.predict(img))
+                    });
+                    let _ = job.reply.send(detected.map(|bbox| Detections {
+                        bbox,
+                        partial: model.partial(),
+                        low_quality: model.low_quality(),
+                        warnings: model.warnings().to_vec(),
+                    }));
+                }
+            }));
+        }
+
+        let mut pool = Self {
+            queue,
+            queued_bytes,
+            monitor,
+            alert,
+            params,
+            limits,
+            models: memory::Usage::default(),
+            duty_cycle,
+            workers,
+        };
+        for _ in 0..n_workers {
+            pool.models = pool.models + ready_rx.recv().unwrap()?;
+        }
+        Ok(pool)
+    }
+
+    /// Queues an image, scaled down by `factor` from an image of
+    /// `dimensions`, to be detected with the parameters of the pool at this
+    /// time (see `downscale::predict`). The detections are delivered through
+    /// the returned receiver. The image is given back if the queue is full.
+    pub fn submit(
+        &self,
+        img: image::DynamicImage,
+        (factor, dimensions): (f32, (u32, u32)),
+        priority: Priority,
+    ) -> Result<sync::mpsc::Receiver<Result<Detections, detector::Error>>, image::DynamicImage>
+    {
+        let size = memory::image_size(&img);
+        let pending = self.queue.len();
+        observe(&self.monitor, &self.alert, pending);
+        if let Some(max) = self.limits.max_pending {
+            if pending >= max {
+                return Err(img);
+            }
+        }
+        if let Some(max) = self.limits.max_pending_bytes {
+            if self.queued_bytes.load(Ordering::SeqCst) + size > max {
+                return Err(img);
+            }
+        }
+
+        let (reply, rx) = sync::mpsc::channel();
+        let params = self.params.lock().unwrap().clone();
+        self.queued_bytes.fetch_add(size, Ordering::SeqCst);
+        self.queue.push(
+            Job {
+                img,
+                factor,
+                dimensions,
+                size,
+                params,
+                reply,
+            },
+            priority,
+        );
+        observe(&self.monitor, &self.alert, self.queue.len());
+        Ok(rx)
+    }
+
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// The most requests the queue has held at once.
+    pub fn peak_pending(&self) -> usize {
+        self.monitor.lock().unwrap().peak
+    }
+
+    /// Whether the queue is above `Limits::watermark` since the last `High`
+    /// alert.
+    pub fn above_watermark(&self) -> bool {
+        self.monitor.lock().unwrap().raised
+    }
+
+    /// Calls `f` from the thread submitting or picking a request when the
+    /// queue has stayed above `Limits::watermark` for its duration, and again
+    /// once it is back below. Without a watermark, `f` is never called.
+    pub fn on_watermark<F>(&self, f: F)
+    where
+        F: FnMut(Alert) + Send + 'static,
+    {
+        *self.alert.lock().unwrap() = Some(Box::new(f));
+    }
+
+    /// Sets the parameters of the requests submitted from now on.
+    pub fn set_params(&self, params: yolo_v2::Params) {
+        *self.params.lock().unwrap() = sync::Arc::new(params);
+    }
+
+    /// Limits the share of time each worker spends on inference. `None`
+    /// lifts the limit.
+    pub fn set_quota(&self, quota: Option<Quota>) {
+        self.duty_cycle.set_quota(quota);
+    }
+
+    pub fn memory_usage(&self) -> memory::Usage {
+        memory::Usage {
+            queues: self.queued_bytes.load(Ordering::SeqCst),
+            ..self.models
+        }
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        self.queue.close();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,387 @@
+//! Versioned wire schema of detection results, shared by the server and the
+//! exporters.
+//!
+//! Compatibility rules, within a `schema_version`:
+//!
+//! * Fields are only added, never renamed or removed, and their meaning and
+//!   units do not change. Consumers must ignore unknown fields.
+//! * Optional fields may be missing; consumers must not rely on them.
+//!
+//! Any other change increments `SCHEMA_VERSION`.
+
+use serde_json;
+use std::collections;
+use std::path;
+use std::time;
+
+use bb;
+use digest;
+use ids;
+use quality;
+use rfc3339;
+use severity;
+use yolo_v2;
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Detection {
+    /// Hash of the frame, the label and the box (see `ids`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub label: String,
+    pub score: f32,
+    /// Coordinates in pixels of the original image.
+    pub top: f32,
+    pub left: f32,
+    pub bottom: f32,
+    pub right: f32,
+    /// Whether the box touches the image border.
+    pub truncated: bool,
+    /// Variance of the Laplacian of the crop.
+    pub sharpness: f32,
+    /// ID of the track of the object, when tracking is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track: Option<u64>,
+    /// UUID of the track, when asked for instead of a number.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track_uuid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<bb::Provenance>,
+    /// Whether the object has stayed at the same place for long, when the
+    /// background is learned (see `background`).
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub stationary: bool,
+    /// Fraction of the members of an ensemble that found the object.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agreement: Option<f32>,
+    /// Whether only some of the members found the object, for review.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub disputed: bool,
+    /// Set by the severity rules of the config (see `severity`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity: Option<severity::Level>,
+    /// Relevance of the detection, when its sink ranks them (see `rank`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rank: Option<f32>,
+    /// Base64 JPEG of the crop, when its sink embeds them (see `thumbnail`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<String>,
+}
+
+impl Detection {
+    pub fn new(bb: &bb::Bb, label_names: &[String]) -> Self {
+        Self {
+            id: None,
+            label: label_names[bb.label].clone(),
+            score: bb.score,
+            top: bb.top,
+            left: bb.left,
+            bottom: bb.bottom,
+            right: bb.right,
+            truncated: bb.truncated,
+            sharpness: bb.sharpness,
+            track: None,
+            track_uuid: None,
+            provenance: bb.provenance.clone(),
+            stationary: false,
+            agreement: None,
+            disputed: false,
+            severity: None,
+            rank: None,
+            thumbnail: None,
+        }
+    }
+
+    /// The box of the detection, as read back, or `None` if its label is not
+    /// in `label_names`.
+    pub fn bb(&self, label_names: &[String]) -> Option<bb::Bb> {
+        Some(bb::Bb {
+            top: self.top,
+            left: self.left,
+            bottom: self.bottom,
+            right: self.right,
+            label: label_names.iter().position(|l| *l == self.label)?,
+            score: self.score,
+            truncated: self.truncated,
+            sharpness: self.sharpness,
+            provenance: self.provenance.clone(),
+        })
+    }
+}
+
+/// Metadata of the source image, read from its EXIF (see `exif`) or given by
+/// the user, so that results can be joined with the original data.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Metadata {
+    /// Time of capture in the local time of the camera, as
+    /// `YYYY-MM-DDTHH:MM:SS`, followed by the UTC offset if recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub taken: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub camera: Option<String>,
+    /// 35 mm equivalent focal length, in millimeters.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focal_length_35mm: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gps: Option<Gps>,
+    /// Index from 0 of the page of a multi-page TIFF, or of the frame of an
+    /// animated PNG.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page: Option<u32>,
+    /// User-supplied key/values.
+    #[serde(default, skip_serializing_if = "collections::BTreeMap::is_empty")]
+    pub fields: collections::BTreeMap<String, String>,
+}
+
+impl Metadata {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// WGS 84 position, in degrees. The altitude is in meters above sea level.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Gps {
+    pub latitude: f64,
+    pub longitude: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub altitude: Option<f64>,
+    /// Direction the camera points to, in degrees clockwise from north.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub direction: Option<f64>,
+}
+
+/// The detections of an image.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Frame {
+    pub schema_version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Name of the detector, when several run on the same source.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// RFC 3339 time of capture or reception.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<String>,
+    pub height: u32,
+    pub width: u32,
+    pub detections: Vec<Detection>,
+    #[serde(default, skip_serializing_if = "Metadata::is_empty")]
+    pub metadata: Metadata,
+    /// Whether the detection deadline was hit, leaving the least likely
+    /// cells undecoded.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub partial: bool,
+    /// Why the frame was not run through the network, having failed the
+    /// quality gate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub low_quality: Option<quality::Issue>,
+    /// Whether the night profile of the detector was used (see
+    /// `pipeline::Night`).
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub night: bool,
+    /// The display threshold of the frame, when adapted to the number of
+    /// boxes (see `yolo_v2::Params::adapt`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<f32>,
+    /// Index of the scene of the frame in its source, when the scenes are
+    /// segmented (see `scene`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scene: Option<u64>,
+}
+
+fn is_false(b: &bool) -> bool {
+    !b
+}
+
+impl Frame {
+    pub fn new<'a, I>(bbox: I, label_names: &[String], height: u32, width: u32) -> Self
+    where
+        I: IntoIterator<Item = &'a bb::Bb>,
+    {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            source: None,
+            model: None,
+            time: None,
+            height,
+            width,
+            detections: bbox
+                .into_iter()
+                .map(|bb| Detection::new(bb, label_names))
+                .collect(),
+            metadata: Metadata::default(),
+            partial: false,
+            low_quality: None,
+            night: false,
+            threshold: None,
+            scene: None,
+        }
+    }
+
+    pub fn with_time(self, t: time::SystemTime) -> Self {
+        Self {
+            time: Some(rfc3339::format(t)),
+            ..self
+        }
+    }
+
+    pub fn with_source(self, source: &str) -> Self {
+        Self {
+            source: Some(source.to_owned()),
+            ..self
+        }
+    }
+    pub fn with_model(self, model: &str) -> Self {
+        Self {
+            model: Some(model.to_owned()),
+            ..self
+        }
+    }
+
+    pub fn with_metadata(self, metadata: &Metadata) -> Self {
+        Self {
+            metadata: metadata.clone(),
+            ..self
+        }
+    }
+
+    pub fn with_partial(self, partial: bool) -> Self {
+        Self { partial, ..self }
+    }
+
+    pub fn with_night(self, night: bool) -> Self {
+        Self { night, ..self }
+    }
+
+    pub fn with_threshold(self, threshold: Option<f32>) -> Self {
+        Self { threshold, ..self }
+    }
+
+    pub fn with_scene(self, scene: Option<u64>) -> Self {
+        Self { scene, ..self }
+    }
+
+    pub fn with_low_quality(self, low_quality: Option<quality::Issue>) -> Self {
+        Self {
+            low_quality,
+            ..self
+        }
+    }
+
+    /// Sets the severities of the detections by `rules`.
+    pub fn with_severities(mut self, rules: &[severity::Rule]) -> Self {
+        let (height, width) = (self.height, self.width);
+        for d in self.detections.iter_mut() {
+            d.severity = severity::level(rules, d, height, width);
+        }
+        self
+    }
+
+    /// Sets the IDs of the detections, from the source, the model, the time
+    /// and the page of the frame, which should therefore be set first.
+    pub fn with_ids(mut self) -> Self {
+        let frame = format!(
+            "{}\0{}\0{}\0{}",
+            self.source.as_deref().unwrap_or(""),
+            self.model.as_deref().unwrap_or(""),
+            self.time.as_deref().unwrap_or(""),
+            self.metadata.page.unwrap_or(0)
+        );
+        for d in self.detections.iter_mut() {
+            let bb = [d.top, d.left, d.bottom, d.right];
+            d.id = Some(ids::detection_id(&frame, &d.label, bb));
+        }
+        self
+    }
+}
+
+/// How the results of a run were produced, written at their head by the
+/// sinks enabling it (see `sink::Config::header`) so that they can be
+/// audited and reproduced later.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Run {
+    /// Version of menoh-yolo.
+    pub version: String,
+    /// RFC 3339 time of the start of the run.
+    pub started: String,
+    /// Path of the ONNX model, or URL of the remote model.
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_sha256: Option<String>,
+    /// Missing for configs inlined in a pipeline.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config_sha256: Option<String>,
+    pub backend: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub backend_config: String,
+    /// `yolo_v2::Preprocessing` of the config.
+    pub preprocessing: serde_json::Value,
+    /// `yolo_v2::Params` at the start of the run.
+    pub params: serde_json::Value,
+    /// The members of an ensemble, the first of which is also described
+    /// above since it gives the config.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub members: Vec<Run>,
+}
+
+impl Run {
+    /// The model is hashed when it is a file.
+    pub fn new(model: &str, config: &yolo_v2::Config, backend: &str, backend_config: &str) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            started: rfc3339::format(time::SystemTime::now()),
+            model: model.to_owned(),
+            model_sha256: digest::file_sha256(model).ok(),
+            config_sha256: None,
+            backend: backend.to_owned(),
+            backend_config: backend_config.to_owned(),
+            preprocessing: serde_json::to_value(config.preprocessing()).unwrap_or_default(),
+            params: serde_json::to_value(&config.params).unwrap_or_default(),
+            members: Vec::new(),
+        }
+    }
+
+    pub fn with_config_file<P>(self, path: P) -> Self
+    where
+        P: AsRef<path::Path>,
+    {
+        Self {
+            config_sha256: digest::file_sha256(path).ok(),
+            ..self
+        }
+    }
+}
+
+/// Totals of a run of `menoh-yolo stream`, written by `--report` for
+/// orchestration scripts.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Summary {
+    pub processed: u64,
+    /// Frames that could not be read or detected, and were skipped.
+    pub failed: u64,
+    /// Detections in the results of the processed frames.
+    pub detections: u64,
+    pub wall_secs: f64,
+    /// Whether the run stopped on consecutive failures, before the end of
+    /// the source.
+    pub stopped: bool,
+}
+
+/// The first line of JSON lines results with a header, told apart from the
+/// frames by `run`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Header {
+    pub schema_version: u32,
+    pub run: Run,
+}
+
+impl Header {
+    pub fn new(run: &Run) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            run: run.clone(),
+        }
+    }
+}
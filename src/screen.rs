@@ -0,0 +1,138 @@
+//! Screen capture with ffmpeg (`x11grab` on Linux, `gdigrab` on Windows,
+//! `avfoundation` on macOS), since no capture API is available to this crate.
+//! Frames are read as MJPEG from its standard output.
+
+use std::env;
+use std::fmt;
+use std::process;
+use std::str;
+
+pub const DEFAULT_FPS: f32 = 2.;
+
+/// `WxH+X+Y`, in pixels of the screen, as X11 geometries.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Region {
+    pub width: u32,
+    pub height: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl str::FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid region {}, expected WxH+X+Y", s);
+        let mut parts = s.split('+');
+        let size = parts.next().ok_or_else(invalid)?;
+        let mut size = size.splitn(2, 'x');
+        let next = |part: Option<&str>| part.and_then(|p| p.parse().ok()).ok_or_else(invalid);
+        let width = next(size.next())?;
+        let height = next(size.next())?;
+        let (x, y) = match (parts.next(), parts.next(), parts.next()) {
+            (None, _, _) => (0, 0),
+            (x, y, None) => (next(x)?, next(y)?),
+            _ => return Err(invalid()),
+        };
+        Ok(Self {
+            width,
+            height,
+            x,
+            y,
+        })
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}+{}+{}", self.width, self.height, self.x, self.y)
+    }
+}
+
+/// What to capture, from the spec `screen[:TARGET][@FPS]`, where `TARGET` is
+/// a region or `window=W`: an X11 window ID, or a window title on Windows.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Spec {
+    pub region: Option<Region>,
+    pub window: Option<String>,
+    pub fps: f32,
+}
+
+impl str::FromStr for Spec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (head, fps) = match s.rfind('@') {
+            Some(i) => {
+                let fps = s[i + 1..]
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|&fps| fps > 0.)
+                    .ok_or_else(|| format!("invalid frame rate: {}", s))?;
+                (&s[..i], fps)
+            }
+            None => (s, DEFAULT_FPS),
+        };
+        let mut spec = Self {
+            region: None,
+            window: None,
+            fps,
+        };
+        let mut parts = head.splitn(2, ':');
+        let target = match (parts.next(), parts.next()) {
+            (Some("screen"), target) => target.unwrap_or(""),
+            _ => return Err(format!("not a screen spec: {}", s)),
+        };
+        let mut parts = target.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some("window"), Some(window)) => spec.window = Some(window.to_owned()),
+            (Some(""), None) => (),
+            _ => spec.region = Some(target.parse()?),
+        }
+        Ok(spec)
+    }
+}
+
+impl Spec {
+    /// The ffmpeg command writing the captured frames to its standard output.
+    pub fn command(&self) -> process::Command {
+        let mut c = process::Command::new("ffmpeg");
+        c.args(["-loglevel", "error", "-framerate", &self.fps.to_string()]);
+        if cfg!(target_os = "windows") {
+            c.args(["-f", "gdigrab"]);
+            if let Some(r) = self.region {
+                c.args(["-offset_x", &r.x.to_string(), "-offset_y", &r.y.to_string()]);
+                c.args(["-video_size", &format!("{}x{}", r.width, r.height)]);
+            }
+            match self.window {
+                Some(ref title) => c.args(["-i", &format!("title={}", title)]),
+                None => c.args(["-i", "desktop"]),
+            };
+        } else if cfg!(target_os = "macos") {
+            c.args(["-f", "avfoundation", "-i", "Capture screen 0"]);
+            if let Some(r) = self.region {
+                c.args([
+                    "-vf",
+                    &format!("crop={}:{}:{}:{}", r.width, r.height, r.x, r.y),
+                ]);
+            }
+        } else {
+            let display = env::var("DISPLAY").unwrap_or_else(|_| ":0".to_owned());
+            c.args(["-f", "x11grab"]);
+            if let Some(ref id) = self.window {
+                c.args(["-window_id", id]);
+            }
+            match self.region {
+                Some(r) => {
+                    c.args(["-video_size", &format!("{}x{}", r.width, r.height)]);
+                    c.args(["-i", &format!("{}+{},{}", display, r.x, r.y)]);
+                }
+                None => {
+                    c.args(["-i", &display]);
+                }
+            }
+        }
+        c.args(["-an", "-f", "mjpeg", "-q:v", "3", "-"]);
+        c
+    }
+}
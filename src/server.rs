@@ -0,0 +1,568 @@
+//! HTTP server running detections, with endpoints to tune the post-processing
+//! parameters live.
+//!
+//! * `POST /detect`: detects objects in the image in the body. With
+//!   `?stream=NAME`, the annotated image is also shown on the preview of the
+//!   stream.
+//! * `GET /preview/NAME`: streams the annotated images of a stream as MJPEG,
+//!   for watching in a browser.
+//! * `GET /config`: returns the current parameters.
+//! * `PUT /config/threshold`: takes a number setting all the thresholds, or an
+//!   object with any of `track`, `display` and `alert`.
+//! * `PUT /config/classes`: takes a list of labels to report, or `null` to
+//!   report all of them.
+//! * `PUT /config/class_thresholds`: replaces the score thresholds per label.
+//! * `PUT /config/border`: replaces the border policy.
+//! * `PUT /config/constraints`: replaces the constraints per label.
+//! * `GET /metrics`: returns the depth of the queue of the pool, if any.
+//!
+//! Detections are returned as `schema::Frame`.
+//!
+//! Requests are served one at a time, so a change applies as a whole to every
+//! request received after it. With a pool (see `with_pool`), the detections
+//! are queued by priority, from `?priority=high`, `normal` or `low`, and run
+//! side by side on its workers, with the parameters of when they were
+//! received.
+
+use image;
+use rusttype;
+use serde_json;
+use std::collections;
+use std::io;
+use std::net;
+use std::sync;
+use std::thread;
+use std::time;
+
+use bb;
+use color;
+use detector;
+use downscale;
+use drawing;
+use filter;
+use http;
+use scheduler;
+use schema;
+use yolo_v2;
+
+use image::GenericImage;
+use std::io::Write;
+
+const MAX_BODY: usize = 64 << 20;
+const BOUNDARY: &str = "menoh-yolo-frame";
+/// Preview clients not accepting a frame within this time are dropped, so that
+/// they cannot stall detections.
+const PREVIEW_TIMEOUT: time::Duration = time::Duration::from_secs(1);
+/// How often the detections of the pool are checked and `run_with` ticks
+/// while no request comes.
+const POLL: time::Duration = time::Duration::from_millis(10);
+/// Clients not sending or accepting any data within this time are answered
+/// 408 or dropped, so that they cannot stall the server.
+const CLIENT_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
+struct Preview {
+    stream: String,
+    client: net::TcpStream,
+}
+
+/// A detection running on the pool, answered once done.
+struct Pending {
+    client: net::TcpStream,
+    queued: Queued,
+}
+
+struct Queued {
+    stream: Option<String>,
+    /// The image detected, kept for the preview of `stream`.
+    img: Option<image::DynamicImage>,
+    factor: f32,
+    width: u32,
+    height: u32,
+    params: yolo_v2::Params,
+    detections: sync::mpsc::Receiver<Result<scheduler::Detections, detector::Error>>,
+}
+
+pub struct Server<'a> {
+    listener: net::TcpListener,
+    model: Box<dyn detector::Detector + 'a>,
+    label_names: &'a [String],
+    font: rusttype::Font<'static>,
+    previews: Vec<Preview>,
+    /// The last annotated frame of each stream, as JPEG.
+    last_frames: collections::HashMap<String, Vec<u8>>,
+    pool: Option<scheduler::Pool>,
+    pending: Vec<Pending>,
+}
+
+impl<'a> Server<'a> {
+    pub fn bind<A>(
+        addr: A,
+        model: Box<dyn detector::Detector + 'a>,
+        label_names: &'a [String],
+    ) -> io::Result<Self>
+    where
+        A: net::ToSocketAddrs,
+    {
+        Ok(Self {
+            listener: net::TcpListener::bind(addr)?,
+            model,
+            label_names,
+            font: drawing::get_font()?,
+            previews: Vec::new(),
+            last_frames: collections::HashMap::new(),
+            pool: None,
+            pending: Vec::new(),
+        })
+    }
+
+    /// Runs the detections on `pool`, built from the same model, instead of
+    /// the model of the server, which keeps the parameters.
+    pub fn with_pool(self, pool: scheduler::Pool) -> Self {
+        pool.set_params(self.model.params().clone());
+        Self {
+            pool: Some(pool),
+            ..self
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<net::SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn run(&mut self) -> io::Result<()> {
+        self.run_with(|| Ok(()))
+    }
+
+    /// `run`, calling `tick` after each request and every `POLL` while none
+    /// comes, as to ping a watchdog only while requests are served.
+    pub fn run_with<F>(&mut self, mut tick: F) -> io::Result<()>
+    where
+        F: FnMut() -> io::Result<()>,
+    {
+        // polled for the detections of the pool and for the ticks
+        self.listener.set_nonblocking(true)?;
+        loop {
+            tick()?;
+            self.answer();
+            let mut stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(POLL);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            stream.set_nonblocking(false)?;
+            stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+            stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+            let result = match http::read_request(&mut stream, MAX_BODY) {
+                Ok(ref request)
+                    if request.method == "GET" && request.path.starts_with("/preview/") =>
+                {
+                    let name = request.path["/preview/".len()..].split('?').next().unwrap();
+                    let _ = self.subscribe(name.to_owned(), stream);
+                    continue;
+                }
+                Ok(ref request)
+                    if self.pool.is_some()
+                        && request.method == "POST"
+                        && request.path.split('?').next() == Some("/detect") =>
+                {
+                    self.submit(request, stream);
+                    continue;
+                }
+                Ok(request) => self.handle(&request),
+                Err(err) => Err((http::status(&err), err.to_string())),
+            };
+            respond(&mut stream, result);
+        }
+    }
+
+    /// Queues the image of a `POST /detect` on the pool, answering `client`
+    /// once detected, or at once if it cannot be queued.
+    fn submit(&mut self, request: &http::Request, mut client: net::TcpStream) {
+        match self.queue(request) {
+            Ok(queued) => self.pending.push(Pending { client, queued }),
+            Err(err) => respond(&mut client, Err(err)),
+        }
+    }
+
+    fn queue(&mut self, request: &http::Request) -> Result<Queued, (u16, String)> {
+        let priority = match query_param(&request.path, "priority") {
+            None | Some("normal") => scheduler::Priority::Normal,
+            Some("high") => scheduler::Priority::High,
+            Some("low") => scheduler::Priority::Low,
+            Some(other) => return Err((400, format!("unknown priority: {}", other))),
+        };
+        let stream = query_param(&request.path, "stream").map(|s| s.to_owned());
+        let img = color::load_from_memory(&request.body).map_err(|err| (400, err.to_string()))?;
+        let (width, height) = img.dimensions();
+        let (img, factor) = match self.model.config().max_edge {
+            Some(max_edge) => downscale::cap(img, max_edge),
+            None => (img, 1.),
+        };
+        let kept = stream.as_ref().map(|_| img.clone());
+        let detections = self
+            .pool
+            .as_ref()
+            .unwrap()
+            .submit(img, (factor, (width, height)), priority)
+            .map_err(|_| (503, "too many pending detections".to_owned()))?;
+        Ok(Queued {
+            stream,
+            img: kept,
+            factor,
+            width,
+            height,
+            params: self.model.params().clone(),
+            detections,
+        })
+    }
+
+    /// Answers the detections of the pool done.
+    fn answer(&mut self) {
+        let mut i = 0;
+        while i < self.pending.len() {
+            let detected = match self.pending[i].queued.detections.try_recv() {
+                Ok(detected) => detected.map_err(|err| (500, err.to_string())),
+                Err(sync::mpsc::TryRecvError::Empty) => {
+                    i += 1;
+                    continue;
+                }
+                Err(sync::mpsc::TryRecvError::Disconnected) => {
+                    Err((500, "the pool stopped".to_owned()))
+                }
+            };
+            let Pending { mut client, queued } = self.pending.swap_remove(i);
+            let result = detected.and_then(|detected| {
+                let Queued {
+                    stream,
+                    img,
+                    factor,
+                    width,
+                    height,
+                    params,
+                    ..
+                } = queued;
+                let annotated = stream.as_deref().zip(img);
+                self.respond(&params, detected, annotated, factor, (width, height))
+            });
+            respond(&mut client, result);
+        }
+    }
+
+    fn handle(&mut self, request: &http::Request) -> Result<serde_json::Value, (u16, String)> {
+        let path = request.path.split('?').next().unwrap_or("");
+        match (request.method.as_str(), path) {
+            ("POST", "/detect") => self.detect(&request.body, query_param(&request.path, "stream")),
+            ("GET", "/config") => Ok(self.config()),
+            ("GET", "/metrics") => Ok(self.metrics()),
+            ("PUT", "/config/threshold") => self.update(&request.body, set_threshold),
+            ("PUT", "/config/classes") => self.update(&request.body, set_classes),
+            ("PUT", "/config/class_thresholds") => self.update(&request.body, set_class_thresholds),
+            ("PUT", "/config/border") => self.update(&request.body, set_border),
+            ("PUT", "/config/constraints") => self.update(&request.body, set_constraints),
+            (_, "/detect")
+            | (_, "/config")
+            | (_, "/metrics")
+            | (_, "/config/threshold")
+            | (_, "/config/classes")
+            | (_, "/config/class_thresholds")
+            | (_, "/config/border")
+            | (_, "/config/constraints") => Err((
+                405,
+                format!("{} is not allowed on {}", request.method, path),
+            )),
+            _ => Err((404, format!("{} is not found", path))),
+        }
+    }
+
+    fn detect(
+        &mut self,
+        body: &[u8],
+        stream: Option<&str>,
+    ) -> Result<serde_json::Value, (u16, String)> {
+        let img = color::load_from_memory(body).map_err(|err| (400, err.to_string()))?;
+        let (width, height) = img.dimensions();
+        let (img, factor) = match self.model.config().max_edge {
+            Some(max_edge) => downscale::cap(img, max_edge),
+            None => (img, 1.),
+        };
+        let dimensions = (width, height);
+        let bbox = downscale::predict(&mut *self.model, &img, factor, dimensions, |m| {
+            m.predict(&img)
+        })
+        .map_err(|err| (500, err.to_string()))?;
+        let detected = scheduler::Detections {
+            bbox,
+            partial: self.model.partial(),
+            low_quality: self.model.low_quality(),
+            warnings: self.model.warnings().to_vec(),
+        };
+        let params = self.model.params().clone();
+        let annotated = stream.map(|stream| (stream, img));
+        self.respond(&params, detected, annotated, factor, (width, height))
+    }
+
+    /// The frame of `detected` with `params`, on an image scaled down by
+    /// `factor` from `width` by `height`, drawn on the preview of a stream
+    /// when `annotated` gives it with the image.
+    fn respond(
+        &mut self,
+        params: &yolo_v2::Params,
+        detected: scheduler::Detections,
+        annotated: Option<(&str, image::DynamicImage)>,
+        factor: f32,
+        (width, height): (u32, u32),
+    ) -> Result<serde_json::Value, (u16, String)> {
+        let mut bbox = detected.bbox;
+        let mut warnings = detected.warnings;
+        // the threshold adjusted is in the response
+        let adapted = params.adapt(&mut bbox, &mut warnings);
+        let thresh = params.label_thresholds(
+            adapted.unwrap_or(params.thresholds.display),
+            self.label_names,
+        );
+        let stream = annotated.as_ref().map(|&(stream, _)| stream.to_owned());
+        for warning in warnings {
+            eprintln!("{}: {}", stream.as_deref().unwrap_or("detect"), warning);
+        }
+        if let Some((stream, mut img)) = annotated {
+            drawing::draw_bbox_mut(
+                &mut img,
+                bb::above_each(&bbox, &thresh),
+                self.label_names,
+                &self.font,
+            );
+            self.publish(stream, &img)
+                .map_err(|err| (500, err.to_string()))?;
+        }
+        downscale::restore(bbox.iter_mut(), factor);
+        params.rounding.apply(bbox.iter_mut());
+        let frame = schema::Frame::new(
+            bb::above_each(&bbox, &thresh),
+            self.label_names,
+            height,
+            width,
+        )
+        .with_time(time::SystemTime::now())
+        .with_partial(detected.partial)
+        .with_low_quality(detected.low_quality)
+        .with_threshold(adapted)
+        .with_severities(&self.model.config().severities);
+        let frame = match stream {
+            Some(ref stream) => frame.with_source(stream),
+            None => frame,
+        }
+        .with_ids();
+        Ok(serde_json::to_value(frame).unwrap())
+    }
+
+    fn subscribe(&mut self, stream: String, mut client: net::TcpStream) -> io::Result<()> {
+        client.set_write_timeout(Some(PREVIEW_TIMEOUT))?;
+        write!(
+            client,
+            "HTTP/1.1 200 OK\r\nContent-Type: multipart/x-mixed-replace; boundary={}\r\n\
+             Cache-Control: no-cache\r\nConnection: close\r\n\r\n",
+            BOUNDARY
+        )?;
+        if let Some(jpeg) = self.last_frames.get(&stream) {
+            write_part(&mut client, jpeg)?;
+        }
+        self.previews.push(Preview { stream, client });
+        Ok(())
+    }
+
+    fn publish(&mut self, stream: &str, img: &image::DynamicImage) -> io::Result<()> {
+        let img = img.to_rgb();
+        let mut jpeg = Vec::new();
+        image::jpeg::JPEGEncoder::new(&mut jpeg).encode(
+            &img,
+            img.width(),
+            img.height(),
+            image::ColorType::RGB(8),
+        )?;
+        self.previews
+            .retain(|p| p.stream != stream || write_part(&mut &p.client, &jpeg).is_ok());
+        self.last_frames.insert(stream.to_owned(), jpeg);
+        Ok(())
+    }
+
+    fn config(&self) -> serde_json::Value {
+        serde_json::to_value(self.model.params()).unwrap()
+    }
+
+    fn metrics(&self) -> serde_json::Value {
+        let pool = self.pool.as_ref().map(|pool| {
+            json!({
+                "pending": pool.pending(),
+                "peak_pending": pool.peak_pending(),
+                "above_watermark": pool.above_watermark(),
+                "memory": pool.memory_usage(),
+            })
+        });
+        json!({ "pool": pool })
+    }
+
+    /// Applies `f` to a copy of the parameters, and swaps them in only if it
+    /// succeeds.
+    fn update<F>(&mut self, body: &[u8], f: F) -> Result<serde_json::Value, (u16, String)>
+    where
+        F: Fn(&mut yolo_v2::Params, serde_json::Value, &[String]) -> Result<(), String>,
+    {
+        let value = serde_json::from_slice(body).map_err(|err| (400, err.to_string()))?;
+        let mut params = self.model.params().clone();
+        f(&mut params, value, self.label_names).map_err(|message| (400, message))?;
+        if let Some(ref pool) = self.pool {
+            pool.set_params(params.clone());
+        }
+        self.model.set_params(params);
+        Ok(self.config())
+    }
+}
+
+/// Writes the JSON of `result`, or of its error, to `client`.
+fn respond(client: &mut net::TcpStream, result: Result<serde_json::Value, (u16, String)>) {
+    let (status, body) = match result {
+        Ok(body) => (200, body),
+        Err((status, message)) => (status, json!({ "error": message })),
+    };
+    let _ = http::write_response(
+        client,
+        status,
+        "application/json",
+        body.to_string().as_bytes(),
+    );
+}
+
+fn write_part<W>(w: &mut W, jpeg: &[u8]) -> io::Result<()>
+where
+    W: io::Write,
+{
+    write!(
+        w,
+        "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+        BOUNDARY,
+        jpeg.len()
+    )?;
+    w.write_all(jpeg)?;
+    w.write_all(b"\r\n")?;
+    w.flush()
+}
+
+/// Returns the value of `name` in the query of `path`, without decoding.
+fn query_param<'r>(path: &'r str, name: &str) -> Option<&'r str> {
+    let query = &path[path.find('?')? + 1..];
+    query.split('&').find_map(|kv| {
+        let mut kv = kv.splitn(2, '=');
+        if kv.next() == Some(name) {
+            Some(kv.next().unwrap_or(""))
+        } else {
+            None
+        }
+    })
+}
+
+fn set_threshold(
+    params: &mut yolo_v2::Params,
+    value: serde_json::Value,
+    _: &[String],
+) -> Result<(), String> {
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct Update {
+        track: Option<f32>,
+        display: Option<f32>,
+        alert: Option<f32>,
+    }
+
+    let update = match value.as_f64() {
+        Some(t) => Update {
+            track: Some(t as f32),
+            display: Some(t as f32),
+            alert: Some(t as f32),
+        },
+        None => serde_json::from_value(value).map_err(|err| err.to_string())?,
+    };
+    let current = params.thresholds.clone();
+    params.thresholds = yolo_v2::Thresholds {
+        track: in_unit(update.track.unwrap_or(current.track))?,
+        display: in_unit(update.display.unwrap_or(current.display))?,
+        alert: in_unit(update.alert.unwrap_or(current.alert))?,
+    };
+    Ok(())
+}
+
+fn set_classes(
+    params: &mut yolo_v2::Params,
+    value: serde_json::Value,
+    label_names: &[String],
+) -> Result<(), String> {
+    let classes: Option<Vec<String>> =
+        serde_json::from_value(value).map_err(|err| err.to_string())?;
+    if let Some(ref classes) = classes {
+        for label in classes.iter() {
+            known(label, label_names)?;
+        }
+    }
+    params.classes = classes;
+    Ok(())
+}
+
+fn set_class_thresholds(
+    params: &mut yolo_v2::Params,
+    value: serde_json::Value,
+    label_names: &[String],
+) -> Result<(), String> {
+    let thresholds: collections::HashMap<String, f32> =
+        serde_json::from_value(value).map_err(|err| err.to_string())?;
+    for (label, &t) in thresholds.iter() {
+        known(label, label_names)?;
+        in_unit(t)?;
+    }
+    params.class_thresholds = thresholds;
+    Ok(())
+}
+
+fn set_border(
+    params: &mut yolo_v2::Params,
+    value: serde_json::Value,
+    _: &[String],
+) -> Result<(), String> {
+    let border: filter::Border = serde_json::from_value(value).map_err(|err| err.to_string())?;
+    if border.margin < 0. {
+        return Err(format!("margin must be non-negative: {}", border.margin));
+    }
+    params.border = border;
+    Ok(())
+}
+
+fn set_constraints(
+    params: &mut yolo_v2::Params,
+    value: serde_json::Value,
+    label_names: &[String],
+) -> Result<(), String> {
+    let constraints: collections::HashMap<String, filter::Constraint> =
+        serde_json::from_value(value).map_err(|err| err.to_string())?;
+    for label in constraints.keys() {
+        known(label, label_names)?;
+    }
+    params.constraints = constraints;
+    Ok(())
+}
+
+fn in_unit(t: f32) -> Result<f32, String> {
+    if !(0. ..=1.).contains(&t) {
+        Err(format!("threshold must be in [0, 1]: {}", t))
+    } else {
+        Ok(t)
+    }
+}
+
+fn known(label: &str, label_names: &[String]) -> Result<(), String> {
+    if label_names.iter().any(|l| l == label) {
+        Ok(())
+    } else {
+        Err(format!("unknown label: {}", label))
+    }
+}
@@ -0,0 +1,48 @@
+//! Severities of the detections, assigned by rules on their label, score and
+//! position, so that a person in a restricted area and a cat can be routed to
+//! different sinks (see `sink::Config::min_severity`).
+
+use rank;
+use schema;
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Level {
+    Info,
+    Warn,
+    Critical,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Rule {
+    pub classes: Vec<String>,
+    #[serde(default)]
+    pub min_score: f32,
+    pub level: Level,
+    /// Compares `min_score` to the relevance of the detection by this ranking
+    /// instead of its score.
+    #[serde(default)]
+    pub rank: Option<rank::Ranking>,
+    /// Limits the rule to the detections whose box has its center in the
+    /// zone.
+    #[serde(default)]
+    pub zone: Option<rank::Zone>,
+}
+
+/// The highest level of the rules matching `d`, in an image of `height` by
+/// `width`, if any.
+pub fn level(rules: &[Rule], d: &schema::Detection, height: u32, width: u32) -> Option<Level> {
+    rules
+        .iter()
+        .filter(|r| {
+            let score = match r.rank {
+                Some(ref rank) => rank.value(d, height, width),
+                None => d.score,
+            };
+            score >= r.min_score
+                && r.classes.contains(&d.label)
+                && r.zone.as_ref().is_none_or(|z| z.contains(d, height, width))
+        })
+        .map(|r| r.level)
+        .max()
+}
@@ -0,0 +1,1354 @@
+//! Destinations of detection results.
+//!
+//! Any number of sinks can be attached at once, each with its own class and
+//! score filter.
+//!
+//! Files replaced by a sink are written under a temporary name and renamed
+//! once complete (see `AtomicFile`), so that interrupted runs never leave a
+//! truncated file at the path. JSON lines files are appended to in whole
+//! lines, and a line cut by an interrupted run is trimmed when reopened.
+//! With the `sqlite` feature, the rows of SQLite databases are written
+//! through the system `libsqlite3` (see `sqlite`).
+//!
+//! The MQTT, webhook and command sinks send the JSON of the frame, or their
+//! `template` rendered with it (see `template`).
+
+use image;
+use serde_json;
+use std::cmp;
+use std::collections;
+use std::ffi;
+use std::fs;
+use std::io;
+use std::net;
+use std::path;
+use std::process;
+use std::time;
+
+use bb;
+use coco;
+use geo;
+use home_assistant;
+use hook;
+use http;
+#[cfg(feature = "kafka")]
+use kafka;
+use mqtt;
+#[cfg(feature = "kafka")]
+use proto;
+use rank;
+use redis;
+#[cfg(feature = "s3")]
+use s3;
+use schema;
+use severity;
+#[cfg(feature = "sqlite")]
+use sqlite;
+use template;
+use thumbnail;
+
+use std::io::{Read, Seek, Write};
+
+pub trait Sink {
+    fn send(&mut self, frame: &schema::Frame, img: &image::DynamicImage) -> io::Result<()>;
+
+    /// Records how the results are produced, before the first frame. Sinks
+    /// without a place for it ignore it.
+    fn header(&mut self, _run: &schema::Run) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Completes the output once no more frames follow.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct Config {
+    #[serde(flatten)]
+    pub kind: Kind,
+    /// Labels to send. All labels are sent when omitted.
+    #[serde(default)]
+    pub classes: Option<Vec<String>>,
+    /// Defaults to the display threshold.
+    #[serde(default)]
+    pub min_score: Option<f32>,
+    /// Sends only the detections of at least this severity (see `severity`),
+    /// dropping those without.
+    #[serde(default)]
+    pub min_severity: Option<severity::Level>,
+    /// Syncs file sinks to the disk, after each line of JSON lines files
+    /// and before replacing the other files.
+    #[serde(default)]
+    pub fsync: bool,
+    /// What to do with the frames left without detections by the filter.
+    #[serde(default)]
+    pub empty: Empty,
+    /// With `Empty::Skip`, seconds without sending anything after which an
+    /// empty frame is sent anyway, so that consumers can tell an empty scene
+    /// from a stalled pipeline.
+    #[serde(default)]
+    pub keepalive: Option<f32>,
+    /// Orders the detections from the most relevant (see `rank`), the highest
+    /// score first when omitted.
+    #[serde(default)]
+    pub rank: Option<rank::Ranking>,
+    /// Sends only this many of the most relevant detections of each frame.
+    #[serde(default)]
+    pub top: Option<usize>,
+    /// Embeds a thumbnail of each detection sent (see `thumbnail`).
+    #[serde(default)]
+    pub thumbnails: Option<thumbnail::Config>,
+    /// Records the run before the first frame (see `Sink::header`), which
+    /// consumers of the frames alone need to skip.
+    #[serde(default)]
+    pub header: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Empty {
+    /// Every frame is sent, with its detections or without.
+    #[default]
+    Send,
+    Skip,
+}
+
+/// How the frames are encoded, by the sinks offering a choice.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    #[default]
+    Json,
+    /// The messages of `proto`.
+    Protobuf,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Kind {
+    /// JSON lines on the standard output.
+    Stdout,
+    /// JSON lines appended to a file.
+    Jsonl {
+        path: path::PathBuf,
+    },
+    /// A GeoJSON feature collection of the detections, replacing the file
+    /// (see `geo`).
+    #[serde(rename = "geojson")]
+    GeoJson {
+        path: path::PathBuf,
+        #[serde(default)]
+        projection: geo::Projection,
+        #[serde(default)]
+        geometry: geo::Geometry,
+    },
+    /// Rows appended to the tables `frames` and `detections` of an SQLite
+    /// database, created if missing (see `SQLITE_SCHEMA`).
+    #[cfg(feature = "sqlite")]
+    Sqlite {
+        path: path::PathBuf,
+    },
+    /// A COCO results array of the detections, replacing the file (see
+    /// `coco`).
+    Coco {
+        path: path::PathBuf,
+        /// Category IDs of the labels, when not numbered from 1 in the order
+        /// of `label_names`.
+        #[serde(default)]
+        category_ids: collections::BTreeMap<String, u32>,
+    },
+    /// JSON messages published to an MQTT topic.
+    Mqtt {
+        addr: String,
+        topic: String,
+        #[serde(default)]
+        template: Option<String>,
+    },
+    /// JSON bodies posted to an `http[s]://` URL, sent as text if a `template`
+    /// does not render JSON.
+    Webhook {
+        url: String,
+        #[serde(default)]
+        template: Option<String>,
+    },
+    /// JSON written to the standard input of a shell command run for each
+    /// frame.
+    Command {
+        command: String,
+        #[serde(default)]
+        template: Option<String>,
+    },
+    HomeAssistant {
+        addr: String,
+        node_id: String,
+    },
+    /// JSON entries appended to a Redis stream, and a key per label present
+    /// in the frame (`<stream>:present:<label>`, holding the number of
+    /// detections), expiring `presence_ttl` seconds after the last frame with
+    /// the label.
+    Redis {
+        addr: String,
+        #[serde(default)]
+        password: Option<String>,
+        stream: String,
+        /// Approximate length the stream is trimmed to.
+        #[serde(default = "default_max_len")]
+        max_len: u64,
+        #[serde(default = "default_presence_ttl")]
+        presence_ttl: u64,
+    },
+    /// Records produced to a Kafka topic, keyed by `key` or by the source
+    /// of the frame.
+    #[cfg(feature = "kafka")]
+    Kafka {
+        addr: String,
+        topic: String,
+        #[serde(default)]
+        key: Option<String>,
+        #[serde(default)]
+        encoding: Encoding,
+    },
+    /// JSON objects written to S3-compatible storage, one per frame (see
+    /// `s3`).
+    #[cfg(feature = "s3")]
+    S3 {
+        bucket: String,
+        #[serde(default)]
+        prefix: String,
+    },
+}
+
+fn default_max_len() -> u64 {
+    10000
+}
+
+fn default_presence_ttl() -> u64 {
+    10
+}
+
+const PROBE_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+impl Config {
+    /// Checks that the sink can be reached, without sending anything.
+    pub fn probe(&self) -> io::Result<String> {
+        match self.kind {
+            Kind::Stdout => Ok("stdout".to_owned()),
+            Kind::Command { ref command, .. } => Ok(command.clone()),
+            Kind::Jsonl { ref path }
+            | Kind::GeoJson { ref path, .. }
+            | Kind::Coco { ref path, .. } => writable(path),
+            #[cfg(feature = "sqlite")]
+            Kind::Sqlite { ref path } => writable(path),
+            Kind::Mqtt { ref addr, .. }
+            | Kind::HomeAssistant { ref addr, .. }
+            | Kind::Redis { ref addr, .. } => {
+                connect(addr.as_str(), addr)?;
+                Ok(addr.clone())
+            }
+            #[cfg(feature = "kafka")]
+            Kind::Kafka { ref addr, .. } => {
+                connect(addr.as_str(), addr)?;
+                Ok(addr.clone())
+            }
+            Kind::Webhook { ref url, .. } => {
+                let parsed = http::Url::parse(url)?;
+                connect((parsed.host.as_str(), parsed.port), url)?;
+                Ok(url.clone())
+            }
+            #[cfg(feature = "s3")]
+            Kind::S3 { ref bucket, .. } => {
+                let name = format!("s3://{}", bucket);
+                s3::Client::from_env()
+                    .and_then(|client| client.head_bucket(bucket))
+                    .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", name, e)))?;
+                Ok(name)
+            }
+        }
+    }
+}
+
+/// Checks that the directory of `path` is writable.
+fn writable(path: &path::Path) -> io::Result<String> {
+    let dir = match path.parent() {
+        Some(dir) if dir != path::Path::new("") => dir,
+        _ => path::Path::new("."),
+    };
+    if dir.metadata()?.permissions().readonly() {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is read-only", dir.display()),
+        ))
+    } else {
+        Ok(path.display().to_string())
+    }
+}
+
+/// Errors mention `name`, since they are reported without the config.
+fn connect<A>(addr: A, name: &str) -> io::Result<()>
+where
+    A: net::ToSocketAddrs,
+{
+    let mut err = io::Error::new(io::ErrorKind::NotFound, "no address");
+    for addr in addr.to_socket_addrs()? {
+        match net::TcpStream::connect_timeout(&addr, PROBE_TIMEOUT) {
+            Ok(_) => return Ok(()),
+            Err(e) => err = e,
+        }
+    }
+    Err(io::Error::new(err.kind(), format!("{}: {}", name, err)))
+}
+
+pub struct Stdout;
+
+impl Sink for Stdout {
+    fn send(&mut self, frame: &schema::Frame, _: &image::DynamicImage) -> io::Result<()> {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        serde_json::to_writer(&mut stdout, frame)?;
+        writeln!(stdout)
+    }
+
+    fn header(&mut self, run: &schema::Run) -> io::Result<()> {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        serde_json::to_writer(&mut stdout, &schema::Header::new(run))?;
+        writeln!(stdout)
+    }
+}
+
+/// A file written under a temporary name next to its path, and renamed to
+/// it by `commit`, or when dropped if every write succeeded.
+pub struct AtomicFile {
+    file: fs::File,
+    tmp: path::PathBuf,
+    path: path::PathBuf,
+    fsync: bool,
+    /// Set when a write failed, leaving the file incomplete.
+    broken: bool,
+    committed: bool,
+}
+
+impl AtomicFile {
+    pub fn create<P>(path: P, fsync: bool) -> io::Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        let path = path.as_ref().to_owned();
+        let mut name = ffi::OsString::from(".");
+        name.push(path.file_name().unwrap_or_default());
+        name.push(format!(".{}.tmp", process::id()));
+        let tmp = path.with_file_name(name);
+        Ok(Self {
+            file: fs::File::create(&tmp)?,
+            tmp,
+            path,
+            fsync,
+            broken: false,
+            committed: false,
+        })
+    }
+
+    /// Runs `write` on the file.
+    pub fn write<F>(&mut self, write: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut fs::File) -> io::Result<()>,
+    {
+        let result = write(&mut self.file);
+        if result.is_err() {
+            self.broken = true;
+        }
+        result
+    }
+
+    /// Renames the file to its path, after syncing it if `fsync`. An
+    /// incomplete file is left under its temporary name.
+    pub fn commit(&mut self) -> io::Result<()> {
+        if self.committed {
+            return Ok(());
+        }
+        if self.broken {
+            return Err(io::Error::other(format!(
+                "{} is incomplete, left as {}",
+                self.path.display(),
+                self.tmp.display()
+            )));
+        }
+        if self.fsync {
+            self.file.sync_all()?;
+        }
+        fs::rename(&self.tmp, &self.path)?;
+        self.committed = true;
+        // the rename itself is durable once the directory is synced
+        #[cfg(unix)]
+        {
+            if self.fsync {
+                let dir = match self.path.parent() {
+                    Some(dir) if dir != path::Path::new("") => dir,
+                    _ => path::Path::new("."),
+                };
+                fs::File::open(dir)?.sync_all()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        let _ = self.commit();
+    }
+}
+
+pub struct JsonLines {
+    file: fs::File,
+    fsync: bool,
+}
+
+impl JsonLines {
+    pub fn open<P>(path: P, fsync: bool) -> io::Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+        trim_partial_line(&mut file)?;
+        Ok(Self { file, fsync })
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        self.file.write_all(line)?;
+        if self.fsync {
+            self.file.sync_data()?;
+        }
+        Ok(())
+    }
+}
+
+/// Truncates `file` after its last newline, dropping the end of a line that
+/// an interrupted writer did not complete.
+fn trim_partial_line(file: &mut fs::File) -> io::Result<()> {
+    const CHUNK: u64 = 4096;
+    let len = file.metadata()?.len();
+    let mut end = len;
+    let mut buf = vec![0; CHUNK as usize];
+    while end > 0 {
+        let start = end.saturating_sub(CHUNK);
+        let chunk = &mut buf[..(end - start) as usize];
+        file.seek(io::SeekFrom::Start(start))?;
+        file.read_exact(chunk)?;
+        if let Some(i) = chunk.iter().rposition(|&b| b == b'\n') {
+            end = start + i as u64 + 1;
+            break;
+        }
+        end = start;
+    }
+    if end < len {
+        file.set_len(end)?;
+    }
+    Ok(())
+}
+
+impl Sink for JsonLines {
+    fn send(&mut self, frame: &schema::Frame, _: &image::DynamicImage) -> io::Result<()> {
+        // A single write per line, so that concurrent writers do not interleave.
+        let mut line = serde_json::to_vec(frame)?;
+        line.push(b'\n');
+        self.write_line(&line)
+    }
+
+    /// Appended, so that a file shared by several runs has a header before
+    /// the frames of each.
+    fn header(&mut self, run: &schema::Run) -> io::Result<()> {
+        let mut line = serde_json::to_vec(&schema::Header::new(run))?;
+        line.push(b'\n');
+        self.write_line(&line)
+    }
+}
+
+/// Appends the features of each frame before the end of the collection, so
+/// that the file is valid in between.
+pub struct GeoJson {
+    file: AtomicFile,
+    projection: geo::Projection,
+    geometry: geo::Geometry,
+    empty: bool,
+}
+
+const GEOJSON_HEAD: &[u8] = b"{\"type\":\"FeatureCollection\",\"features\":[";
+const GEOJSON_TAIL: &[u8] = b"\n]}\n";
+
+impl GeoJson {
+    pub fn create<P>(
+        path: P,
+        projection: geo::Projection,
+        geometry: geo::Geometry,
+        fsync: bool,
+    ) -> io::Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        let mut file = AtomicFile::create(path, fsync)?;
+        file.write(|f| {
+            f.write_all(GEOJSON_HEAD)?;
+            f.write_all(GEOJSON_TAIL)
+        })?;
+        Ok(Self {
+            file,
+            projection,
+            geometry,
+            empty: true,
+        })
+    }
+}
+
+impl Sink for GeoJson {
+    fn send(&mut self, frame: &schema::Frame, _: &image::DynamicImage) -> io::Result<()> {
+        if frame.detections.is_empty() {
+            return Ok(());
+        }
+        let features = geo::features(frame, &self.projection, self.geometry).map_err(|e| {
+            let source = frame.source.as_ref().map_or("", |s| s.as_str());
+            io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", source, e))
+        })?;
+        let mut buf = Vec::new();
+        for feature in features.iter() {
+            buf.extend_from_slice(if self.empty { b"\n" } else { b",\n" });
+            serde_json::to_writer(&mut buf, feature)?;
+            self.empty = false;
+        }
+        buf.extend_from_slice(GEOJSON_TAIL);
+        self.file.write(|f| {
+            f.seek(io::SeekFrom::End(-(GEOJSON_TAIL.len() as i64)))?;
+            f.write_all(&buf)
+        })
+    }
+
+    /// Adds a `run` member to the collection, unless features were written.
+    fn header(&mut self, run: &schema::Run) -> io::Result<()> {
+        if !self.empty {
+            return Ok(());
+        }
+        let mut head = b"{\"type\":\"FeatureCollection\",\"run\":".to_vec();
+        serde_json::to_writer(&mut head, run)?;
+        head.extend_from_slice(b",\"features\":[");
+        self.file.write(|f| {
+            f.set_len(0)?;
+            f.seek(io::SeekFrom::Start(0))?;
+            f.write_all(&head)?;
+            f.write_all(GEOJSON_TAIL)
+        })
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.file.commit()
+    }
+}
+
+pub struct Coco {
+    file: AtomicFile,
+    categories: coco::Categories,
+    frames: u64,
+    empty: bool,
+}
+
+const COCO_TAIL: &[u8] = b"\n]\n";
+
+impl Coco {
+    pub fn create<P>(path: P, categories: coco::Categories, fsync: bool) -> io::Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        let mut file = AtomicFile::create(path, fsync)?;
+        file.write(|f| {
+            f.write_all(b"[")?;
+            f.write_all(COCO_TAIL)
+        })?;
+        Ok(Self {
+            file,
+            categories,
+            frames: 0,
+            empty: true,
+        })
+    }
+}
+
+impl Sink for Coco {
+    fn send(&mut self, frame: &schema::Frame, _: &image::DynamicImage) -> io::Result<()> {
+        // frames without number in their name are numbered from 1
+        self.frames += 1;
+        let image_id = coco::image_id(frame.source.as_deref(), self.frames);
+        let results = coco::results(frame, image_id, &self.categories);
+        if results.is_empty() {
+            return Ok(());
+        }
+        let mut buf = Vec::new();
+        for result in results.iter() {
+            buf.extend_from_slice(if self.empty { b"\n" } else { b",\n" });
+            serde_json::to_writer(&mut buf, result)?;
+            self.empty = false;
+        }
+        buf.extend_from_slice(COCO_TAIL);
+        self.file.write(|f| {
+            f.seek(io::SeekFrom::End(-(COCO_TAIL.len() as i64)))?;
+            f.write_all(&buf)
+        })
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.file.commit()
+    }
+}
+
+/// Parses the `template` of a sink config.
+fn parse_template(template: &Option<String>) -> io::Result<Option<template::Template>> {
+    match *template {
+        Some(ref source) => template::Template::parse(source)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("template: {}", e))),
+        None => Ok(None),
+    }
+}
+
+/// The JSON of `frame`, or `template` rendered with it.
+fn payload(frame: &schema::Frame, template: &Option<template::Template>) -> io::Result<Vec<u8>> {
+    match *template {
+        Some(ref template) => Ok(template.render(&serde_json::to_value(frame)?).into_bytes()),
+        None => Ok(serde_json::to_vec(frame)?),
+    }
+}
+
+pub struct Mqtt {
+    client: mqtt::Client,
+    topic: String,
+    template: Option<template::Template>,
+}
+
+impl Mqtt {
+    pub fn connect<A>(addr: A, topic: &str) -> io::Result<Self>
+    where
+        A: net::ToSocketAddrs,
+    {
+        Ok(Self {
+            client: mqtt::Client::connect(addr, &format!("menoh-yolo-{}", process::id()), None)?,
+            topic: topic.to_owned(),
+            template: None,
+        })
+    }
+}
+
+impl Sink for Mqtt {
+    fn send(&mut self, frame: &schema::Frame, _: &image::DynamicImage) -> io::Result<()> {
+        self.client
+            .publish(&self.topic, &payload(frame, &self.template)?, false)
+    }
+
+    /// Retained on `<topic>/run`, for the subscribers joining later.
+    fn header(&mut self, run: &schema::Run) -> io::Result<()> {
+        let topic = format!("{}/run", self.topic);
+        let json = serde_json::to_vec(&schema::Header::new(run))?;
+        self.client.publish(&topic, &json, true)
+    }
+}
+
+pub struct Webhook {
+    url: http::Url,
+    template: Option<template::Template>,
+}
+
+const WEBHOOK_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+impl Webhook {
+    pub fn new(url: &str) -> io::Result<Self> {
+        Ok(Self {
+            url: http::Url::parse(url)?,
+            template: None,
+        })
+    }
+}
+
+impl Sink for Webhook {
+    fn send(&mut self, frame: &schema::Frame, _: &image::DynamicImage) -> io::Result<()> {
+        let body = payload(frame, &self.template)?;
+        let content_type = if serde_json::from_slice::<serde_json::Value>(&body).is_ok() {
+            "application/json"
+        } else {
+            "text/plain; charset=utf-8"
+        };
+        let mut stream = http::connect(&self.url, Some(WEBHOOK_TIMEOUT))?;
+        let mut request = format!(
+            "POST {} HTTP/1.0\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n",
+            self.url.path,
+            self.url.host,
+            content_type,
+            body.len()
+        );
+        if let Some(ref userinfo) = self.url.userinfo {
+            request += &format!(
+                "Authorization: Basic {}\r\n",
+                http::base64(userinfo.as_bytes())
+            );
+        }
+        request += "\r\n";
+        stream.write_all(request.as_bytes())?;
+        stream.write_all(&body)?;
+
+        let (status, _) = http::read_response_head(&mut io::BufReader::new(stream))?;
+        if status / 100 == 2 {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("webhook responded with status {}", status),
+            ))
+        }
+    }
+}
+
+/// Runs a command for each frame, without waiting for it.
+pub struct Command {
+    command: String,
+    template: Option<template::Template>,
+    children: Vec<process::Child>,
+}
+
+impl Command {
+    pub fn new(command: &str) -> Self {
+        Self {
+            command: command.to_owned(),
+            template: None,
+            children: Vec::new(),
+        }
+    }
+}
+
+impl Sink for Command {
+    fn send(&mut self, frame: &schema::Frame, _: &image::DynamicImage) -> io::Result<()> {
+        let input = payload(frame, &self.template)?;
+        // reap the commands that are done
+        let mut running = Vec::with_capacity(self.children.len());
+        for mut child in self.children.drain(..) {
+            if child.try_wait()?.is_none() {
+                running.push(child);
+            }
+        }
+        self.children = running;
+        let mut child = hook::shell(&self.command)
+            .stdin(process::Stdio::piped())
+            .spawn()?;
+        // a command that does not read its input is not an error
+        if let Some(mut stdin) = child.stdin.take() {
+            match stdin.write_all(&input) {
+                Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+                r => r?,
+            }
+        }
+        self.children.push(child);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        for mut child in self.children.drain(..) {
+            child.wait()?;
+        }
+        Ok(())
+    }
+}
+
+pub struct HomeAssistant {
+    ha: home_assistant::HomeAssistant,
+    label_names: Vec<String>,
+}
+
+impl HomeAssistant {
+    pub fn new(ha: home_assistant::HomeAssistant, label_names: &[String]) -> Self {
+        Self {
+            ha,
+            label_names: label_names.to_owned(),
+        }
+    }
+}
+
+impl Sink for HomeAssistant {
+    fn send(&mut self, frame: &schema::Frame, img: &image::DynamicImage) -> io::Result<()> {
+        let bbox: Vec<_> = frame
+            .detections
+            .iter()
+            .filter_map(|d| {
+                let label = self.label_names.iter().position(|l| *l == d.label)?;
+                Some(bb::Bb {
+                    top: d.top,
+                    left: d.left,
+                    bottom: d.bottom,
+                    right: d.right,
+                    label,
+                    score: d.score,
+                    truncated: d.truncated,
+                    sharpness: d.sharpness,
+                    provenance: d.provenance.clone(),
+                })
+            })
+            .collect();
+        self.ha.publish(&bbox, img)
+    }
+}
+
+pub struct Redis {
+    pub client: redis::Client,
+    pub stream: String,
+    pub max_len: u64,
+    pub presence_ttl: u64,
+}
+
+impl Sink for Redis {
+    fn send(&mut self, frame: &schema::Frame, _: &image::DynamicImage) -> io::Result<()> {
+        let mut counts: Vec<(&str, usize)> = Vec::new();
+        for d in frame.detections.iter() {
+            match counts.iter_mut().find(|(l, _)| *l == d.label) {
+                Some(c) => c.1 += 1,
+                None => counts.push((&d.label, 1)),
+            }
+        }
+        let json = serde_json::to_vec(frame)?;
+        let max_len = self.max_len.to_string();
+        let ttl = self.presence_ttl.to_string();
+        let presence: Vec<_> = counts
+            .iter()
+            .map(|&(l, n)| (format!("{}:present:{}", self.stream, l), n.to_string()))
+            .collect();
+
+        let mut commands: Vec<Vec<&[u8]>> = vec![vec![
+            b"XADD",
+            self.stream.as_bytes(),
+            b"MAXLEN",
+            b"~",
+            max_len.as_bytes(),
+            b"*",
+            b"frame",
+            &json,
+        ]];
+        for (key, n) in presence.iter() {
+            commands.push(vec![
+                b"SET",
+                key.as_bytes(),
+                n.as_bytes(),
+                b"EX",
+                ttl.as_bytes(),
+            ]);
+        }
+        let commands: Vec<_> = commands.iter().map(|c| c.as_slice()).collect();
+        self.client.pipeline(&commands).map(|_| ())
+    }
+
+    /// Set on `<stream>:run`.
+    fn header(&mut self, run: &schema::Run) -> io::Result<()> {
+        let key = format!("{}:run", self.stream);
+        let json = serde_json::to_vec(&schema::Header::new(run))?;
+        self.client
+            .command(&[b"SET", key.as_bytes(), &json])
+            .map(|_| ())
+    }
+}
+
+/// Tables of the `sqlite` sinks. `json` holds the whole frame, or the header
+/// of the run.
+#[cfg(feature = "sqlite")]
+pub const SQLITE_SCHEMA: &str = "\
+CREATE TABLE IF NOT EXISTS runs (id INTEGER PRIMARY KEY, json TEXT NOT NULL);
+CREATE TABLE IF NOT EXISTS frames (
+    id INTEGER PRIMARY KEY,
+    source TEXT,
+    model TEXT,
+    time TEXT,
+    height INTEGER NOT NULL,
+    width INTEGER NOT NULL,
+    json TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS detections (
+    frame INTEGER NOT NULL REFERENCES frames (id),
+    label TEXT NOT NULL,
+    score REAL,
+    \"top\" REAL,
+    \"left\" REAL,
+    \"bottom\" REAL,
+    \"right\" REAL,
+    track INTEGER
+);
+CREATE INDEX IF NOT EXISTS detections_frame ON detections (frame);";
+
+/// A frame per transaction, committed before `send` returns.
+#[cfg(feature = "sqlite")]
+pub struct Sqlite {
+    database: Option<sqlite::Database>,
+}
+
+#[cfg(feature = "sqlite")]
+impl Sqlite {
+    pub fn open(path: &path::Path) -> io::Result<Self> {
+        let mut database = sqlite::Database::open(path)?;
+        database.execute(SQLITE_SCHEMA)?;
+        Ok(Self {
+            database: Some(database),
+        })
+    }
+
+    fn execute(&mut self, sql: &str) -> io::Result<()> {
+        match self.database {
+            Some(ref mut database) => database.execute(sql),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the database is closed",
+            )),
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Sink for Sqlite {
+    fn send(&mut self, frame: &schema::Frame, _: &image::DynamicImage) -> io::Result<()> {
+        let text = |s: Option<&String>| s.map_or("NULL".to_owned(), |s| sqlite::quote(s));
+        let mut sql = format!(
+            "BEGIN;\nINSERT INTO frames (source, model, time, height, width, json) \
+             VALUES ({}, {}, {}, {}, {}, {});\n",
+            text(frame.source.as_ref()),
+            text(frame.model.as_ref()),
+            text(frame.time.as_ref()),
+            frame.height,
+            frame.width,
+            sqlite::quote(&serde_json::to_string(frame)?)
+        );
+        for d in &frame.detections {
+            sql += &format!(
+                "INSERT INTO detections \
+                 (frame, label, score, \"top\", \"left\", \"bottom\", \"right\", track) \
+                 VALUES ((SELECT max(id) FROM frames), {}, {}, {}, {}, {}, {}, {});\n",
+                sqlite::quote(&d.label),
+                sqlite::real(d.score),
+                sqlite::real(d.top),
+                sqlite::real(d.left),
+                sqlite::real(d.bottom),
+                sqlite::real(d.right),
+                d.track.map_or("NULL".to_owned(), |t| t.to_string())
+            );
+        }
+        sql += "COMMIT;";
+        self.execute(&sql)
+    }
+
+    fn header(&mut self, run: &schema::Run) -> io::Result<()> {
+        let json = serde_json::to_string(&schema::Header::new(run))?;
+        self.execute(&format!(
+            "INSERT INTO runs (json) VALUES ({});",
+            sqlite::quote(&json)
+        ))
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.database.take() {
+            Some(database) => database.close(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "kafka")]
+pub struct Kafka {
+    pub producer: kafka::Producer,
+    /// Defaults to the source of each frame.
+    pub key: Option<String>,
+    pub encoding: Encoding,
+}
+
+#[cfg(feature = "kafka")]
+impl Sink for Kafka {
+    fn send(&mut self, frame: &schema::Frame, _: &image::DynamicImage) -> io::Result<()> {
+        let key = self.key.as_ref().or(frame.source.as_ref());
+        let value = match self.encoding {
+            Encoding::Json => serde_json::to_vec(frame)?,
+            Encoding::Protobuf => proto::frame(frame),
+        };
+        self.producer.send(key.map(|k| k.as_bytes()), &value)
+    }
+
+    /// Keyed `run`.
+    fn header(&mut self, run: &schema::Run) -> io::Result<()> {
+        let value = match self.encoding {
+            Encoding::Json => serde_json::to_vec(&schema::Header::new(run))?,
+            Encoding::Protobuf => proto::header(run)?,
+        };
+        self.producer.send(Some(b"run"), &value)
+    }
+}
+
+#[cfg(feature = "s3")]
+pub struct Objects {
+    client: s3::Client,
+    bucket: String,
+    prefix: String,
+    n_frame: u64,
+}
+
+#[cfg(feature = "s3")]
+impl Objects {
+    pub fn new(client: s3::Client, bucket: &str, prefix: &str) -> Self {
+        Self {
+            client,
+            bucket: bucket.to_owned(),
+            prefix: prefix.to_owned(),
+            n_frame: 0,
+        }
+    }
+
+    /// Frames of images are named after the image, so that a batch maps
+    /// `a/b.jpg` to `b.json`. Frames of streams are named after their time.
+    fn key(&self, frame: &schema::Frame) -> String {
+        let image = frame
+            .source
+            .as_ref()
+            .map(|s| path::Path::new(&s[s.rfind('/').map(|i| i + 1).unwrap_or(0)..]))
+            .filter(|p| p.extension().is_some())
+            .and_then(|p| p.file_stem())
+            .map(|s| s.to_string_lossy().into_owned());
+        let name = match (image, frame.time.as_ref()) {
+            (Some(name), _) => name,
+            (None, Some(time)) => format!("{}-{}", time, self.n_frame),
+            (None, None) => self.n_frame.to_string(),
+        };
+        format!("{}{}.json", self.prefix, name)
+    }
+}
+
+#[cfg(feature = "s3")]
+impl Sink for Objects {
+    fn send(&mut self, frame: &schema::Frame, _: &image::DynamicImage) -> io::Result<()> {
+        let key = self.key(frame);
+        self.n_frame += 1;
+        self.client
+            .put(&self.bucket, &key, &serde_json::to_vec(frame)?)
+    }
+}
+
+/// Drops the detections not accepted by the filter before passing frames on,
+/// and the frames left empty unless `Empty::Send`.
+pub struct Filtered {
+    sink: Box<dyn Sink>,
+    classes: Option<Vec<String>>,
+    min_score: f32,
+    /// Replacing `min_score` for their labels.
+    class_min_scores: collections::HashMap<String, f32>,
+    min_severity: Option<severity::Level>,
+    empty: Empty,
+    keepalive: Option<time::Duration>,
+    last_sent: time::Instant,
+    rank: Option<rank::Ranking>,
+    top: Option<usize>,
+    thumbnails: Option<thumbnail::Config>,
+    header: bool,
+}
+
+impl Filtered {
+    pub fn new(sink: Box<dyn Sink>, classes: Option<Vec<String>>, min_score: f32) -> Self {
+        Self {
+            sink,
+            classes,
+            min_score,
+            class_min_scores: collections::HashMap::new(),
+            min_severity: None,
+            empty: Empty::Send,
+            keepalive: None,
+            last_sent: time::Instant::now(),
+            rank: None,
+            top: None,
+            thumbnails: None,
+            header: false,
+        }
+    }
+
+    pub fn with_class_min_scores(
+        self,
+        class_min_scores: collections::HashMap<String, f32>,
+    ) -> Self {
+        Self {
+            class_min_scores,
+            ..self
+        }
+    }
+
+    pub fn with_min_severity(self, min_severity: Option<severity::Level>) -> Self {
+        Self {
+            min_severity,
+            ..self
+        }
+    }
+
+    pub fn with_empty(self, empty: Empty, keepalive: Option<f32>) -> Self {
+        Self {
+            empty,
+            keepalive: keepalive.map(|secs| time::Duration::from_secs_f32(secs.max(0.))),
+            ..self
+        }
+    }
+
+    pub fn with_rank(self, rank: Option<rank::Ranking>, top: Option<usize>) -> Self {
+        Self { rank, top, ..self }
+    }
+
+    pub fn with_thumbnails(self, thumbnails: Option<thumbnail::Config>) -> Self {
+        Self { thumbnails, ..self }
+    }
+
+    pub fn with_header(self, header: bool) -> Self {
+        Self { header, ..self }
+    }
+}
+
+impl Sink for Filtered {
+    fn send(&mut self, frame: &schema::Frame, img: &image::DynamicImage) -> io::Result<()> {
+        let mut frame = frame.clone();
+        let classes = &self.classes;
+        let (min_score, min_severity) = (self.min_score, self.min_severity);
+        let class_min_scores = &self.class_min_scores;
+        frame.detections.retain(|d| {
+            d.score >= class_min_scores.get(&d.label).cloned().unwrap_or(min_score)
+                && match *classes {
+                    Some(ref classes) => classes.contains(&d.label),
+                    None => true,
+                }
+                && min_severity.is_none_or(|min| d.severity.is_some_and(|s| s >= min))
+        });
+        match self.rank {
+            Some(ref rank) => rank.sort(&mut frame),
+            None if self.top.is_some() => frame.detections.sort_by(|a, b| {
+                b.score
+                    .partial_cmp(&a.score)
+                    .unwrap_or(cmp::Ordering::Equal)
+            }),
+            None => {}
+        }
+        if let Some(top) = self.top {
+            frame.detections.truncate(top);
+        }
+        if let Some(ref thumbnails) = self.thumbnails {
+            let thumbnails: Vec<_> = frame
+                .detections
+                .iter()
+                .map(|d| thumbnail::encode(img, &frame, d, thumbnails))
+                .collect();
+            for (d, thumbnail) in frame.detections.iter_mut().zip(thumbnails) {
+                d.thumbnail = thumbnail;
+            }
+        }
+        if frame.detections.is_empty() && self.empty == Empty::Skip {
+            let due = self
+                .keepalive
+                .is_some_and(|keepalive| self.last_sent.elapsed() >= keepalive);
+            if !due {
+                return Ok(());
+            }
+        }
+        self.last_sent = time::Instant::now();
+        self.sink.send(&frame, img)
+    }
+
+    /// Only if enabled.
+    fn header(&mut self, run: &schema::Run) -> io::Result<()> {
+        if self.header {
+            self.sink.header(run)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.sink.finish()
+    }
+}
+
+/// Sends frames to all the attached sinks.
+#[derive(Default)]
+pub struct Sinks {
+    sinks: Vec<Filtered>,
+    /// Score thresholds per label of the model (see
+    /// `yolo_v2::Params::class_thresholds`), replacing the thresholds the
+    /// sinks are attached with.
+    class_thresholds: collections::HashMap<String, f32>,
+}
+
+impl Sinks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens the sinks described by `configs`. Those without a `min_score`
+    /// send the detections above `display_threshold`, or above the threshold
+    /// of their label in `class_thresholds`.
+    pub fn open(
+        configs: &[Config],
+        label_names: &[String],
+        display_threshold: f32,
+        class_thresholds: &collections::HashMap<String, f32>,
+    ) -> io::Result<Self> {
+        let mut sinks = Self {
+            class_thresholds: class_thresholds.clone(),
+            ..Self::new()
+        };
+        for config in configs.iter() {
+            let sink: Box<dyn Sink> = match config.kind {
+                Kind::Stdout => Box::new(Stdout),
+                Kind::Jsonl { ref path } => Box::new(JsonLines::open(path, config.fsync)?),
+                #[cfg(feature = "sqlite")]
+                Kind::Sqlite { ref path } => Box::new(Sqlite::open(path)?),
+                Kind::GeoJson {
+                    ref path,
+                    ref projection,
+                    geometry,
+                } => Box::new(GeoJson::create(
+                    path,
+                    projection.clone(),
+                    geometry,
+                    config.fsync,
+                )?),
+                Kind::Coco {
+                    ref path,
+                    ref category_ids,
+                } => Box::new(Coco::create(
+                    path,
+                    coco::Categories::new(label_names, category_ids),
+                    config.fsync,
+                )?),
+                Kind::Mqtt {
+                    ref addr,
+                    ref topic,
+                    ref template,
+                } => Box::new(Mqtt {
+                    template: parse_template(template)?,
+                    ..Mqtt::connect(addr.as_str(), topic)?
+                }),
+                Kind::Webhook {
+                    ref url,
+                    ref template,
+                } => Box::new(Webhook {
+                    template: parse_template(template)?,
+                    ..Webhook::new(url)?
+                }),
+                Kind::Command {
+                    ref command,
+                    ref template,
+                } => Box::new(Command {
+                    template: parse_template(template)?,
+                    ..Command::new(command)
+                }),
+                Kind::HomeAssistant {
+                    ref addr,
+                    ref node_id,
+                } => Box::new(HomeAssistant::new(
+                    home_assistant::HomeAssistant::connect(addr.as_str(), node_id, label_names)?,
+                    label_names,
+                )),
+                Kind::Redis {
+                    ref addr,
+                    ref password,
+                    ref stream,
+                    max_len,
+                    presence_ttl,
+                } => Box::new(Redis {
+                    client: redis::Client::connect(
+                        addr.as_str(),
+                        password.as_ref().map(|p| p.as_str()),
+                    )?,
+                    stream: stream.clone(),
+                    max_len,
+                    presence_ttl,
+                }),
+                #[cfg(feature = "kafka")]
+                Kind::Kafka {
+                    ref addr,
+                    ref topic,
+                    ref key,
+                    encoding,
+                } => Box::new(Kafka {
+                    producer: kafka::Producer::connect(addr, topic)?,
+                    key: key.clone(),
+                    encoding,
+                }),
+                #[cfg(feature = "s3")]
+                Kind::S3 {
+                    ref bucket,
+                    ref prefix,
+                } => Box::new(Objects::new(s3::Client::from_env()?, bucket, prefix)),
+            };
+            let (min_score, class_min_scores) = match config.min_score {
+                Some(min_score) => (min_score, collections::HashMap::new()),
+                None => (display_threshold, class_thresholds.clone()),
+            };
+            sinks.sinks.push(
+                Filtered::new(sink, config.classes.clone(), min_score)
+                    .with_class_min_scores(class_min_scores)
+                    .with_min_severity(config.min_severity)
+                    .with_empty(config.empty, config.keepalive)
+                    .with_rank(config.rank.clone(), config.top)
+                    .with_thumbnails(config.thumbnails)
+                    .with_header(config.header),
+            );
+        }
+        Ok(sinks)
+    }
+
+    /// Attaches `sink`, sending the detections above `min_score`, or above
+    /// the class thresholds the sinks were opened with.
+    pub fn attach(&mut self, sink: Box<dyn Sink>, classes: Option<Vec<String>>, min_score: f32) {
+        self.sinks.push(
+            Filtered::new(sink, classes, min_score)
+                .with_class_min_scores(self.class_thresholds.clone()),
+        );
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    /// Sends `frame` to every sink, even if some fail. Returns the first
+    /// error.
+    pub fn send(&mut self, frame: &schema::Frame, img: &image::DynamicImage) -> io::Result<()> {
+        let mut result = Ok(());
+        for sink in self.sinks.iter_mut() {
+            if let Err(err) = sink.send(frame, img) {
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
+        result
+    }
+
+    /// Writes the header of the sinks enabling it. Returns the first error.
+    pub fn header(&mut self, run: &schema::Run) -> io::Result<()> {
+        let mut result = Ok(());
+        for sink in self.sinks.iter_mut() {
+            if let Err(err) = sink.header(run) {
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
+        result
+    }
+
+    /// Completes the output of every sink, once no more frames follow.
+    /// Returns the first error.
+    pub fn finish(&mut self) -> io::Result<()> {
+        let mut result = Ok(());
+        for sink in self.sinks.iter_mut() {
+            if let Err(err) = sink.finish() {
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
+        result
+    }
+}
@@ -0,0 +1,118 @@
+//! Resource usage of the process over long runs, to find leaks before 24/7
+//! deployments.
+//!
+//! The resident memory and the open file descriptors are read from `/proc`,
+//! so only Linux is supported. Allocators and backends keep memory after a
+//! warm-up, hence the usage is compared to the one after the warm-up rather
+//! than at the start, and a resource grows when even its lowest value over
+//! the last quarter of the run exceeds that baseline.
+
+use std::fs;
+use std::io;
+use std::time;
+
+/// RSS growth below which the allocator noise is not reported.
+const MIN_RSS_GROWTH: u64 = 8 << 20;
+/// RSS growth reported relative to the baseline.
+const RSS_GROWTH: f64 = 0.05;
+
+/// Resources held by the process.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Usage {
+    /// Resident memory in bytes.
+    pub rss: u64,
+    /// Open file descriptors, sockets included.
+    pub handles: usize,
+}
+
+pub fn usage() -> io::Result<Usage> {
+    let status = fs::read_to_string("/proc/self/status")?;
+    let rss = status
+        .lines()
+        .find(|l| l.starts_with("VmRSS:"))
+        .and_then(|l| l.split_whitespace().nth(1))
+        .and_then(|kb| kb.parse::<u64>().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no VmRSS"))?;
+    Ok(Usage {
+        rss: rss << 10,
+        handles: fs::read_dir("/proc/self/fd")?.count(),
+    })
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Sample {
+    /// Seconds from the start.
+    pub elapsed: f32,
+    /// Inferences so far.
+    pub iterations: u64,
+    #[serde(flatten)]
+    pub usage: Usage,
+}
+
+/// Samples taken along a run.
+pub struct Monitor {
+    start: time::Instant,
+    warmup: time::Duration,
+    samples: Vec<Sample>,
+    /// Index of the first sample after the warm-up.
+    baseline: Option<usize>,
+}
+
+impl Monitor {
+    pub fn new(warmup: time::Duration) -> Self {
+        Self {
+            start: time::Instant::now(),
+            warmup,
+            samples: Vec::new(),
+            baseline: None,
+        }
+    }
+
+    pub fn sample(&mut self, iterations: u64) -> io::Result<Sample> {
+        let elapsed = self.start.elapsed();
+        let sample = Sample {
+            elapsed: elapsed.as_secs_f32(),
+            iterations,
+            usage: usage()?,
+        };
+        if self.baseline.is_none() && elapsed >= self.warmup {
+            self.baseline = Some(self.samples.len());
+        }
+        self.samples.push(sample);
+        Ok(sample)
+    }
+
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    /// Describes the resources that grew after the warm-up. Empty until
+    /// there are samples to compare.
+    pub fn growth(&self) -> Vec<String> {
+        let after = match self.baseline {
+            Some(b) if self.samples.len() - b >= 4 => &self.samples[b..],
+            _ => return Vec::new(),
+        };
+        let baseline = after[0].usage;
+        let tail = &after[after.len() - after.len() / 4..];
+        let rss = tail.iter().map(|s| s.usage.rss).min().unwrap();
+        let handles = tail.iter().map(|s| s.usage.handles).min().unwrap();
+
+        let mut growth = Vec::new();
+        let tolerance = MIN_RSS_GROWTH.max((baseline.rss as f64 * RSS_GROWTH) as u64);
+        if rss > baseline.rss + tolerance {
+            growth.push(format!(
+                "resident memory grew from {} to {} MiB",
+                baseline.rss >> 20,
+                rss >> 20
+            ));
+        }
+        if handles > baseline.handles {
+            growth.push(format!(
+                "open handles grew from {} to {}",
+                baseline.handles, handles
+            ));
+        }
+        growth
+    }
+}
@@ -0,0 +1,897 @@
+//! Sources of frames to run detection on.
+//!
+//! Every source implements `Source`, so that the command line and the pipeline
+//! consume them the same way. `open` picks the source from a spec:
+//!
+//! - `-`: standard input, either a single image or concatenated JPEGs (e.g.
+//!   `ffmpeg -i ... -f mjpeg -`)
+//! - `http://...` or `https://...`: an MJPEG camera (see `mjpeg`)
+//! - a directory: its images in the order of their file names
+//! - `s3://bucket/prefix`: the images under the prefix (requires the `s3`
+//!   feature, see `s3`)
+//! - `screen`, `screen:WxH+X+Y` or `screen:window=W`, with `@FPS` for another
+//!   rate than 2 per second: captures of the screen by ffmpeg (see `screen`)
+//! - `camera:N`: the webcam of index `N` (requires the `opencv` feature)
+//! - `rtsp://...` or a video file: decoded by OpenCV (requires the `opencv`
+//!   feature)
+//! - a PDF, or `pdf:DPI:path` for another resolution than 150 DPI: its pages
+//!   (requires the `pdf` feature and Poppler, see `pdf`)
+//! - `replay:path`: the frames of JSON lines results (see `Replay`)
+//! - anything else: a single image file
+
+use image;
+use serde_json;
+use std::error;
+use std::fs;
+use std::io;
+#[cfg(feature = "pdf")]
+use std::ops;
+use std::path;
+use std::process;
+use std::time;
+use std::vec;
+
+use color;
+use exif;
+use http;
+use mjpeg;
+#[cfg(feature = "opencv")]
+use opencv;
+use pages;
+#[cfg(feature = "pdf")]
+use pdf;
+use rfc3339;
+#[cfg(feature = "s3")]
+use s3;
+use schema;
+use screen;
+
+use std::io::{BufRead, Read};
+
+pub struct Frame {
+    pub img: image::DynamicImage,
+    /// When the frame was captured, or the modification time of image files.
+    pub time: time::SystemTime,
+    /// Identifies the frame, such as the path of an image of a directory.
+    pub name: String,
+    /// EXIF of image files, empty for video frames.
+    pub metadata: schema::Metadata,
+    /// The results of a replayed frame, standing for the detection of its
+    /// blank image.
+    pub recorded: Option<schema::Frame>,
+}
+
+pub trait Source {
+    /// Returns `None` at the end of the source.
+    fn next_frame(&mut self) -> Option<io::Result<Frame>>;
+
+    /// Frames per second of videos.
+    fn frame_rate(&self) -> Option<f64> {
+        None
+    }
+
+    /// Moves to the frame of index `frame`, before reading any, and returns
+    /// the index of the next frame read, which may be before `frame` with
+    /// some codecs. Sources which cannot seek stay at the first frame.
+    fn seek(&mut self, _frame: u64) -> io::Result<u64> {
+        Ok(0)
+    }
+
+    /// Moves past the next frame, without decoding it when the source can.
+    /// Returns false at the end of the source.
+    fn skip_frame(&mut self) -> bool {
+        self.next_frame().is_some()
+    }
+}
+
+/// A point of a source, as a time from its start or as the index of a frame
+/// from 0.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Position {
+    Time(time::Duration),
+    Frame(u64),
+}
+
+impl Position {
+    /// Parses `HH:MM:SS`, `MM:SS` or `SS`, with a fraction of seconds.
+    pub fn parse_time(s: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid time {}, expected HH:MM:SS", s);
+        let fields: Vec<_> = s.split(':').collect();
+        if fields.len() > 3 {
+            return Err(invalid());
+        }
+        let mut secs = 0.;
+        for (i, field) in fields.iter().enumerate() {
+            let value: f64 = field.parse().map_err(|_| invalid())?;
+            let last = i + 1 == fields.len();
+            if !value.is_finite() || value < 0. || (!last && value.fract() != 0.) {
+                return Err(invalid());
+            }
+            secs = secs * 60. + value;
+        }
+        Ok(Position::Time(time::Duration::from_secs_f64(secs)))
+    }
+
+    /// Index of the frame, the first one at or after the time.
+    fn frame(&self, frame_rate: Option<f64>) -> io::Result<u64> {
+        match (*self, frame_rate) {
+            (Position::Frame(frame), _) => Ok(frame),
+            (Position::Time(t), Some(fps)) => {
+                Ok((t.as_secs_f64() * fps - 1e-6).ceil().max(0.) as u64)
+            }
+            (Position::Time(_), None) => Err(invalid_input(
+                "times require a video with a frame rate, use frame indexes",
+            )),
+        }
+    }
+}
+
+/// The frames of `source` from `start`, included, to `end`, excluded.
+pub struct Range {
+    source: Box<dyn Source>,
+    /// Index of the next frame.
+    next: u64,
+    end: Option<u64>,
+}
+
+impl Range {
+    /// Seeks to `start` in `source`, which has not been read, decoding the
+    /// frames up to it when seeking lands before it.
+    pub fn new(
+        mut source: Box<dyn Source>,
+        start: Option<Position>,
+        end: Option<Position>,
+    ) -> io::Result<Self> {
+        let fps = source.frame_rate();
+        let start = match start {
+            Some(start) => start.frame(fps)?,
+            None => 0,
+        };
+        let end = match end {
+            Some(end) => Some(end.frame(fps)?),
+            None => None,
+        };
+        if end.is_some_and(|end| end <= start) {
+            return Err(invalid_input("the end is not after the start"));
+        }
+        let mut next = if start > 0 { source.seek(start)? } else { 0 };
+        if next > start {
+            return Err(invalid_data(format!(
+                "seeking to frame {} landed on frame {}",
+                start, next
+            )));
+        }
+        // the frames skipped are not run, even if they fail to decode
+        while next < start && source.skip_frame() {
+            next += 1;
+        }
+        Ok(Self { source, next, end })
+    }
+}
+
+impl Source for Range {
+    fn next_frame(&mut self) -> Option<io::Result<Frame>> {
+        if self.end.is_some_and(|end| self.next >= end) {
+            return None;
+        }
+        self.next += 1;
+        self.source.next_frame()
+    }
+
+    fn frame_rate(&self) -> Option<f64> {
+        self.source.frame_rate()
+    }
+}
+
+pub fn open(spec: &str) -> io::Result<Box<dyn Source>> {
+    if spec == "-" {
+        return Ok(Box::new(Stdin::new()));
+    }
+    if spec.starts_with("http://") || spec.starts_with("https://") {
+        return Ok(Box::new(Mjpeg::open(spec)?));
+    }
+    if spec.starts_with("s3://") {
+        return objects(spec);
+    }
+    if spec == "screen" || spec.starts_with("screen:") || spec.starts_with("screen@") {
+        let screen = spec.parse().map_err(invalid_input)?;
+        return Ok(Box::new(Screen::open(&screen)?));
+    }
+    let mut parts = spec.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some("camera"), Some(index)) => {
+            let index = index
+                .parse()
+                .map_err(|_| invalid_input(format!("invalid camera index: {}", spec)))?;
+            return camera(index);
+        }
+        (Some("replay"), Some(path)) => return Ok(Box::new(Replay::open(path)?)),
+        (Some("pdf"), Some(rest)) => {
+            let mut parts = rest.splitn(2, ':');
+            if let (Some(dpi), Some(path)) = (parts.next(), parts.next()) {
+                let dpi = dpi
+                    .parse()
+                    .map_err(|_| invalid_input(format!("invalid DPI: {}", spec)))?;
+                return pdf(path, dpi);
+            }
+        }
+        _ => (),
+    }
+    let path = path::Path::new(spec);
+    if has_extension(path, &["pdf"]) {
+        return pdf(spec, PDF_DPI);
+    }
+    if spec.starts_with("rtsp://") || is_video(path) {
+        return video(spec);
+    }
+    if path.is_dir() {
+        Ok(Box::new(Images::dir(path)?))
+    } else if path.is_file() {
+        Ok(Box::new(Images::file(path)))
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no such file or directory",
+        ))
+    }
+}
+
+/// Downloads the image at the `http[s]://` `url`, of at most `max_len` bytes.
+pub fn fetch(url: &str, max_len: usize) -> io::Result<Frame> {
+    let response = http::get(url, max_len)?;
+    let content_type = response.header("Content-Type").unwrap_or("");
+    if !content_type.starts_with("image/") {
+        return Err(invalid_data(format!(
+            "{}: not an image: {}",
+            url, content_type
+        )));
+    }
+    Ok(Frame {
+        img: color::load_from_memory(&response.body).map_err(invalid_data)?,
+        time: time::SystemTime::now(),
+        name: url.to_owned(),
+        metadata: exif::read(&response.body),
+        recorded: None,
+    })
+}
+
+#[cfg(feature = "opencv")]
+fn camera(index: usize) -> io::Result<Box<dyn Source>> {
+    Ok(Box::new(Capture::open_camera(index)?))
+}
+
+#[cfg(not(feature = "opencv"))]
+fn camera(_: usize) -> io::Result<Box<dyn Source>> {
+    Err(invalid_input("cameras require the opencv feature"))
+}
+
+#[cfg(feature = "s3")]
+fn objects(spec: &str) -> io::Result<Box<dyn Source>> {
+    Ok(Box::new(Objects::list(spec)?))
+}
+
+#[cfg(not(feature = "s3"))]
+fn objects(_: &str) -> io::Result<Box<dyn Source>> {
+    Err(invalid_input("object storage requires the s3 feature"))
+}
+
+#[cfg(feature = "pdf")]
+fn pdf(path: &str, dpi: u32) -> io::Result<Box<dyn Source>> {
+    Ok(Box::new(Pdf::open(path, dpi)?))
+}
+
+#[cfg(not(feature = "pdf"))]
+fn pdf(_: &str, _: u32) -> io::Result<Box<dyn Source>> {
+    Err(invalid_input("PDFs require the pdf feature"))
+}
+
+#[cfg(feature = "opencv")]
+fn video(spec: &str) -> io::Result<Box<dyn Source>> {
+    Ok(Box::new(Capture::open_file(spec)?))
+}
+
+#[cfg(not(feature = "opencv"))]
+fn video(_: &str) -> io::Result<Box<dyn Source>> {
+    Err(invalid_input("videos and RTSP require the opencv feature"))
+}
+
+const PDF_DPI: u32 = 150;
+
+const IMAGE_EXTENSIONS: [&str; 13] = [
+    "apng", "bmp", "gif", "ico", "jpeg", "jpg", "pbm", "pgm", "png", "ppm", "tga", "tif", "tiff",
+];
+const VIDEO_EXTENSIONS: [&str; 6] = ["avi", "m4v", "mkv", "mov", "mp4", "webm"];
+
+fn has_extension(path: &path::Path, extensions: &[&str]) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(e) => extensions.iter().any(|x| x.eq_ignore_ascii_case(e)),
+        None => false,
+    }
+}
+
+fn is_video(path: &path::Path) -> bool {
+    has_extension(path, &VIDEO_EXTENSIONS)
+}
+
+/// Image files, loaded one by one. The pages of multi-page TIFFs and the
+/// frames of animated PNGs are frames of their own.
+pub struct Images {
+    paths: vec::IntoIter<path::PathBuf>,
+    pages: Option<(Paged, pages::Pages)>,
+}
+
+/// What the pages of a file share.
+struct Paged {
+    name: String,
+    time: time::SystemTime,
+    metadata: schema::Metadata,
+}
+
+impl Paged {
+    fn read(path: &path::Path) -> io::Result<Self> {
+        Ok(Self {
+            time: fs::metadata(path)?.modified()?,
+            metadata: exif::read_file(path)?,
+            name: path.to_string_lossy().into_owned(),
+        })
+    }
+}
+
+impl Images {
+    pub fn file<P>(path: P) -> Self
+    where
+        P: AsRef<path::Path>,
+    {
+        Self {
+            paths: vec![path.as_ref().to_owned()].into_iter(),
+            pages: None,
+        }
+    }
+
+    /// The images of `dir`, sorted by file name. Other files are skipped.
+    pub fn dir<P>(dir: P) -> io::Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_file() && has_extension(&path, &IMAGE_EXTENSIONS) {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        Ok(Self {
+            paths: paths.into_iter(),
+            pages: None,
+        })
+    }
+
+    fn next_page(&mut self) -> Option<io::Result<Frame>> {
+        let (ref file, ref mut pages) = *self.pages.as_mut()?;
+        let page = pages.next()?;
+        Some(page.map(|page| {
+            let mut metadata = file.metadata.clone();
+            metadata.page = Some(page.index);
+            Frame {
+                img: page.img,
+                time: file.time + page.offset,
+                name: file.name.clone(),
+                metadata,
+                recorded: None,
+            }
+        }))
+    }
+}
+
+impl Source for Images {
+    fn next_frame(&mut self) -> Option<io::Result<Frame>> {
+        if let Some(page) = self.next_page() {
+            return Some(page);
+        }
+        self.pages = None;
+        let path = self.paths.next()?;
+        let mut load = || {
+            let file = Paged::read(&path)?;
+            if let Some(pages) = pages::open(&path)? {
+                self.pages = Some((file, pages));
+                return self
+                    .next_page()
+                    .unwrap_or_else(|| Err(invalid_data(format!("{}: no pages", path.display()))));
+            }
+            let img = color::open(&path).map_err(|e| invalid_data(e.to_string()))?;
+            Ok(Frame {
+                img,
+                time: file.time,
+                name: file.name,
+                metadata: file.metadata,
+                recorded: None,
+            })
+        };
+        Some(load())
+    }
+
+    fn skip_frame(&mut self) -> bool {
+        if self.next_page().is_some() {
+            return true;
+        }
+        self.pages = None;
+        let path = match self.paths.next() {
+            Some(path) => path,
+            None => return false,
+        };
+        // only multi-page files are opened, to count their pages
+        if let Ok(Some(pages)) = pages::open(&path) {
+            if let Ok(file) = Paged::read(&path) {
+                self.pages = Some((file, pages));
+                self.next_page();
+            }
+        }
+        true
+    }
+}
+
+/// The frames of JSON lines results, as written by `sink::JsonLines`, with
+/// their time and their detections, on blank images of their size. Replayed
+/// through a pipeline, the detections go through the tracking, the hooks and
+/// the sinks again without running the network, so that their rules can be
+/// tried on recorded runs. The headers of the results are skipped.
+pub struct Replay {
+    lines: io::Lines<io::BufReader<fs::File>>,
+    path: String,
+    n_line: usize,
+}
+
+impl Replay {
+    pub fn open(path: &str) -> io::Result<Self> {
+        Ok(Self {
+            lines: io::BufReader::new(fs::File::open(path)?).lines(),
+            path: path.to_owned(),
+            n_line: 0,
+        })
+    }
+
+    fn parse(&self, line: &str) -> io::Result<Option<Frame>> {
+        let invalid =
+            |e: serde_json::Error| invalid_data(format!("{}:{}: {}", self.path, self.n_line, e));
+        let value: serde_json::Value = serde_json::from_str(line).map_err(invalid)?;
+        if value.get("run").is_some() {
+            return Ok(None);
+        }
+        let recorded: schema::Frame = serde_json::from_value(value).map_err(invalid)?;
+        Ok(Some(Frame {
+            img: image::DynamicImage::new_rgb8(recorded.width, recorded.height),
+            time: recorded
+                .time
+                .as_ref()
+                .and_then(|t| rfc3339::parse(t))
+                .unwrap_or_else(time::SystemTime::now),
+            name: recorded.source.clone().unwrap_or_else(|| self.path.clone()),
+            metadata: recorded.metadata.clone(),
+            recorded: Some(recorded),
+        }))
+    }
+}
+
+impl Source for Replay {
+    fn next_frame(&mut self) -> Option<io::Result<Frame>> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e)),
+            };
+            self.n_line += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match self.parse(&line) {
+                Ok(Some(frame)) => return Some(Ok(frame)),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Images in object storage, downloaded one by one.
+#[cfg(feature = "s3")]
+pub struct Objects {
+    client: s3::Client,
+    bucket: String,
+    keys: vec::IntoIter<String>,
+}
+
+#[cfg(feature = "s3")]
+impl Objects {
+    /// The images under `s3://bucket/prefix`. Other objects are skipped.
+    pub fn list(url: &str) -> io::Result<Self> {
+        let (bucket, prefix) = s3::parse_url(url)
+            .ok_or_else(|| invalid_input(format!("not an s3:// URL: {}", url)))?;
+        let client = s3::Client::from_env()?;
+        let keys: Vec<_> = client
+            .list(bucket, prefix)?
+            .into_iter()
+            .filter(|k| has_extension(path::Path::new(k), &IMAGE_EXTENSIONS))
+            .collect();
+        Ok(Self {
+            client,
+            bucket: bucket.to_owned(),
+            keys: keys.into_iter(),
+        })
+    }
+}
+
+#[cfg(feature = "s3")]
+impl Source for Objects {
+    fn next_frame(&mut self) -> Option<io::Result<Frame>> {
+        let key = self.keys.next()?;
+        let load = || {
+            let data = self.client.get(&self.bucket, &key)?;
+            let img = color::load_from_memory(&data).map_err(|e| invalid_data(e.to_string()))?;
+            Ok(Frame {
+                img,
+                time: time::SystemTime::now(),
+                name: format!("s3://{}/{}", self.bucket, key),
+                metadata: exif::read(&data),
+                recorded: None,
+            })
+        };
+        Some(load())
+    }
+}
+
+/// Pages of a PDF, rendered one by one.
+#[cfg(feature = "pdf")]
+pub struct Pdf {
+    path: path::PathBuf,
+    dpi: u32,
+    pages: ops::Range<u32>,
+    time: time::SystemTime,
+}
+
+#[cfg(feature = "pdf")]
+impl Pdf {
+    pub fn open<P>(path: P, dpi: u32) -> io::Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        let path = path.as_ref();
+        Ok(Self {
+            path: path.to_owned(),
+            dpi,
+            pages: 0..pdf::page_count(path)?,
+            time: fs::metadata(path)?.modified()?,
+        })
+    }
+}
+
+#[cfg(feature = "pdf")]
+impl Source for Pdf {
+    fn next_frame(&mut self) -> Option<io::Result<Frame>> {
+        let index = self.pages.next()?;
+        Some(pdf::render(&self.path, index, self.dpi).map(|img| Frame {
+            img,
+            time: self.time,
+            name: self.path.to_string_lossy().into_owned(),
+            metadata: schema::Metadata {
+                page: Some(index),
+                ..Default::default()
+            },
+            recorded: None,
+        }))
+    }
+}
+
+pub struct Mjpeg {
+    stream: mjpeg::Stream,
+    url: String,
+}
+
+impl Mjpeg {
+    pub fn open(url: &str) -> io::Result<Self> {
+        Ok(Self {
+            stream: mjpeg::Stream::open(url)?,
+            url: url.to_owned(),
+        })
+    }
+}
+
+impl Source for Mjpeg {
+    fn next_frame(&mut self) -> Option<io::Result<Frame>> {
+        let img = self.stream.next()?;
+        Some(img.map(|img| Frame {
+            img,
+            time: time::SystemTime::now(),
+            name: self.url.clone(),
+            metadata: schema::Metadata::default(),
+            recorded: None,
+        }))
+    }
+}
+
+/// Captures of the screen (see `screen`).
+pub struct Screen {
+    child: process::Child,
+    reader: io::BufReader<process::ChildStdout>,
+}
+
+impl Screen {
+    pub fn open(spec: &screen::Spec) -> io::Result<Self> {
+        let mut command = spec.command();
+        let mut child = command
+            .stdin(process::Stdio::null())
+            .stdout(process::Stdio::piped())
+            .spawn()
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => {
+                    io::Error::new(e.kind(), "screen capture requires ffmpeg")
+                }
+                _ => e,
+            })?;
+        let stdout = child.stdout.take().unwrap();
+        Ok(Self {
+            child,
+            reader: io::BufReader::new(stdout),
+        })
+    }
+
+    fn read(&mut self) -> io::Result<Option<image::DynamicImage>> {
+        let mut data = Vec::new();
+        if (&mut self.reader).take(2).read_to_end(&mut data)? == 0 {
+            let status = self.child.wait()?;
+            if !status.success() {
+                return Err(invalid_data(format!("ffmpeg exited with {}", status)));
+            }
+            return Ok(None);
+        }
+        if data != [0xff, 0xd8] {
+            return Err(invalid_data("ffmpeg wrote something else than JPEGs"));
+        }
+        read_jpeg(&mut self.reader, &mut data)?;
+        image::load_from_memory(&data)
+            .map(Some)
+            .map_err(|e| invalid_data(e.to_string()))
+    }
+}
+
+impl Source for Screen {
+    fn next_frame(&mut self) -> Option<io::Result<Frame>> {
+        let time = time::SystemTime::now();
+        self.read().transpose().map(|img| {
+            img.map(|img| Frame {
+                img,
+                time,
+                name: "screen".to_owned(),
+                metadata: schema::Metadata::default(),
+                recorded: None,
+            })
+        })
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Standard input. Input starting with a JPEG is read as a sequence of
+/// JPEGs, any other input as a single image.
+pub struct Stdin {
+    reader: io::BufReader<io::Stdin>,
+    done: bool,
+}
+
+impl Stdin {
+    pub fn new() -> Self {
+        Self {
+            reader: io::BufReader::new(io::stdin()),
+            done: false,
+        }
+    }
+
+    fn read(&mut self) -> io::Result<Option<image::DynamicImage>> {
+        let mut data = Vec::new();
+        let n = (&mut self.reader).take(2).read_to_end(&mut data)?;
+        if n == 0 {
+            return Ok(None);
+        }
+        if data == [0xff, 0xd8] {
+            read_jpeg(&mut self.reader, &mut data)?;
+        } else {
+            self.reader.read_to_end(&mut data)?;
+            self.done = true;
+        }
+        color::load_from_memory(&data)
+            .map(Some)
+            .map_err(|e| invalid_data(e.to_string()))
+    }
+}
+
+impl Default for Stdin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Source for Stdin {
+    fn next_frame(&mut self) -> Option<io::Result<Frame>> {
+        if self.done {
+            return None;
+        }
+        let time = time::SystemTime::now();
+        match self.read() {
+            Ok(img) => img.map(|img| {
+                Ok(Frame {
+                    img,
+                    time,
+                    name: "stdin".to_owned(),
+                    metadata: schema::Metadata::default(),
+                    recorded: None,
+                })
+            }),
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Reads the rest of a JPEG whose start of image marker is already in `data`.
+/// The segments are followed by their lengths, so that embedded thumbnails do
+/// not end the image early.
+fn read_jpeg<R>(r: &mut R, data: &mut Vec<u8>) -> io::Result<()>
+where
+    R: io::Read,
+{
+    let mut marker = None;
+    loop {
+        let m = match marker.take() {
+            Some(m) => m,
+            None => next_marker(r)?,
+        };
+        data.extend_from_slice(&[0xff, m]);
+        match m {
+            // End of image
+            0xd9 => return Ok(()),
+            // Markers without segments
+            0x01 | 0xd0..=0xd7 => {}
+            _ => {
+                let mut len = [0; 2];
+                r.read_exact(&mut len)?;
+                data.extend_from_slice(&len);
+                let len = (len[0] as usize) << 8 | len[1] as usize;
+                if len < 2 {
+                    return Err(invalid_data("broken JPEG segment"));
+                }
+                let start = data.len();
+                data.resize(start + len - 2, 0);
+                r.read_exact(&mut data[start..])?;
+                // Start of scan
+                if m == 0xda {
+                    marker = Some(read_scan(r, data)?);
+                }
+            }
+        }
+    }
+}
+
+fn next_marker<R>(r: &mut R) -> io::Result<u8>
+where
+    R: io::Read,
+{
+    if read_byte(r)? != 0xff {
+        return Err(invalid_data("missing JPEG marker"));
+    }
+    loop {
+        match read_byte(r)? {
+            0xff => {}
+            m => return Ok(m),
+        }
+    }
+}
+
+/// Copies the entropy-coded data following a start of scan, and returns the
+/// marker that ends it.
+fn read_scan<R>(r: &mut R, data: &mut Vec<u8>) -> io::Result<u8>
+where
+    R: io::Read,
+{
+    loop {
+        let b = read_byte(r)?;
+        if b != 0xff {
+            data.push(b);
+            continue;
+        }
+        let mut m = read_byte(r)?;
+        while m == 0xff {
+            m = read_byte(r)?;
+        }
+        match m {
+            // Stuffed byte and restart markers
+            0x00 | 0xd0..=0xd7 => data.extend_from_slice(&[0xff, m]),
+            m => return Ok(m),
+        }
+    }
+}
+
+fn read_byte<R>(r: &mut R) -> io::Result<u8>
+where
+    R: io::Read,
+{
+    let mut b = [0];
+    r.read_exact(&mut b)?;
+    Ok(b[0])
+}
+
+/// Webcams, video files and RTSP streams.
+#[cfg(feature = "opencv")]
+pub struct Capture {
+    capture: opencv::Capture,
+    name: String,
+}
+
+#[cfg(feature = "opencv")]
+impl Capture {
+    pub fn open_camera(index: usize) -> io::Result<Self> {
+        let name = format!("camera {}", index);
+        match opencv::Capture::open_camera(index) {
+            Some(capture) => Ok(Self { capture, name }),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("failed to open {}", name),
+            )),
+        }
+    }
+
+    pub fn open_file(filename: &str) -> io::Result<Self> {
+        match opencv::Capture::open_file(filename) {
+            Some(capture) => Ok(Self {
+                capture,
+                name: filename.to_owned(),
+            }),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("failed to open {}", filename),
+            )),
+        }
+    }
+}
+
+#[cfg(feature = "opencv")]
+impl Source for Capture {
+    fn next_frame(&mut self) -> Option<io::Result<Frame>> {
+        let img = self.capture.query_frame()?.into_image();
+        Some(Ok(Frame {
+            img,
+            time: time::SystemTime::now(),
+            name: self.name.clone(),
+            metadata: schema::Metadata::default(),
+            recorded: None,
+        }))
+    }
+
+    fn frame_rate(&self) -> Option<f64> {
+        self.capture.fps()
+    }
+
+    fn seek(&mut self, frame: u64) -> io::Result<u64> {
+        Ok(self.capture.seek(frame))
+    }
+}
+
+fn invalid_data<E>(err: E) -> io::Error
+where
+    E: Into<Box<dyn error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+fn invalid_input<E>(err: E) -> io::Error
+where
+    E: Into<Box<dyn error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidInput, err)
+}
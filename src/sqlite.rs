@@ -0,0 +1,139 @@
+//! SQLite databases, through the system `libsqlite3`.
+//!
+//! Statements are run as SQL text, so values are written as literals (see
+//! `quote` and `real`).
+
+use libc;
+use std::ffi;
+use std::io;
+use std::path;
+use std::ptr;
+
+#[allow(non_camel_case_types)]
+enum sqlite3 {}
+
+const SQLITE_OK: libc::c_int = 0;
+const SQLITE_OPEN_READWRITE: libc::c_int = 0x2;
+const SQLITE_OPEN_CREATE: libc::c_int = 0x4;
+
+type Callback = unsafe extern "C" fn(
+    *mut libc::c_void,
+    libc::c_int,
+    *mut *mut libc::c_char,
+    *mut *mut libc::c_char,
+) -> libc::c_int;
+
+#[link(name = "sqlite3")]
+extern "C" {
+    fn sqlite3_open_v2(
+        filename: *const libc::c_char,
+        db: *mut *mut sqlite3,
+        flags: libc::c_int,
+        vfs: *const libc::c_char,
+    ) -> libc::c_int;
+    fn sqlite3_exec(
+        db: *mut sqlite3,
+        sql: *const libc::c_char,
+        callback: Option<Callback>,
+        arg: *mut libc::c_void,
+        errmsg: *mut *mut libc::c_char,
+    ) -> libc::c_int;
+    fn sqlite3_errmsg(db: *mut sqlite3) -> *const libc::c_char;
+    fn sqlite3_free(p: *mut libc::c_void);
+    fn sqlite3_close(db: *mut sqlite3) -> libc::c_int;
+}
+
+pub struct Database {
+    db: *mut sqlite3,
+}
+
+// a connection may be used from any thread, one at a time
+unsafe impl Send for Database {}
+
+impl Database {
+    pub fn open(path: &path::Path) -> io::Result<Self> {
+        let filename = c_string(&path.to_string_lossy())?;
+        let mut db = ptr::null_mut();
+        let code = unsafe {
+            sqlite3_open_v2(
+                filename.as_ptr(),
+                &mut db,
+                SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE,
+                ptr::null(),
+            )
+        };
+        // the handle is allocated even when opening fails, for its message
+        let database = Self { db };
+        if code != SQLITE_OK {
+            return Err(database.error());
+        }
+        Ok(database)
+    }
+
+    /// Runs `sql`, one or more statements each ended by `;`.
+    pub fn execute(&mut self, sql: &str) -> io::Result<()> {
+        let sql = c_string(sql)?;
+        let mut message = ptr::null_mut();
+        let code =
+            unsafe { sqlite3_exec(self.db, sql.as_ptr(), None, ptr::null_mut(), &mut message) };
+        if code == SQLITE_OK {
+            return Ok(());
+        }
+        let error = if message.is_null() {
+            self.error()
+        } else {
+            let error = failed(unsafe { ffi::CStr::from_ptr(message) });
+            unsafe { sqlite3_free(message as *mut libc::c_void) };
+            error
+        };
+        Err(error)
+    }
+
+    /// Closes the database, once no more statements follow.
+    pub fn close(mut self) -> io::Result<()> {
+        let code = unsafe { sqlite3_close(self.db) };
+        if code != SQLITE_OK {
+            return Err(self.error());
+        }
+        self.db = ptr::null_mut();
+        Ok(())
+    }
+
+    fn error(&self) -> io::Error {
+        failed(unsafe { ffi::CStr::from_ptr(sqlite3_errmsg(self.db)) })
+    }
+}
+
+impl Drop for Database {
+    fn drop(&mut self) {
+        if !self.db.is_null() {
+            unsafe { sqlite3_close(self.db) };
+        }
+    }
+}
+
+fn failed(message: &ffi::CStr) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("sqlite failed: {}", message.to_string_lossy()),
+    )
+}
+
+fn c_string(s: &str) -> io::Result<ffi::CString> {
+    ffi::CString::new(s).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+/// `s` as an SQL string literal.
+pub fn quote(s: &str) -> String {
+    // the statements are a C string, which NUL would end
+    format!("'{}'", s.replace('\'', "''").replace('\0', ""))
+}
+
+/// `v` as an SQL value, NULL if not finite.
+pub fn real(v: f32) -> String {
+    if v.is_finite() {
+        format!("{:?}", v)
+    } else {
+        "NULL".to_owned()
+    }
+}
@@ -0,0 +1,102 @@
+//! Sentences describing the detections of an image, such as "2 persons and 1
+//! bicycle detected, highest confidence 0.94", for notification bodies and
+//! screen readers.
+//!
+//! In the template, `{objects}` is replaced with the number of boxes of each
+//! label, the most frequent first, `{count}` with the number of boxes, and
+//! `{best}` and `{score}` with the label and the score of the best-scoring
+//! box.
+
+use std::cmp;
+use std::collections;
+
+use bb;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Template {
+    #[serde(default = "default_text")]
+    pub text: String,
+    /// The sentence when nothing is detected.
+    #[serde(default = "default_empty")]
+    pub empty: String,
+    /// Irregular plurals, as `{"person": "people"}`. The others get an `s`.
+    #[serde(default)]
+    pub plurals: collections::HashMap<String, String>,
+}
+
+fn default_text() -> String {
+    "{objects} detected, highest confidence {score}".to_owned()
+}
+
+fn default_empty() -> String {
+    "nothing detected".to_owned()
+}
+
+impl Default for Template {
+    fn default() -> Self {
+        Self {
+            text: default_text(),
+            empty: default_empty(),
+            plurals: collections::HashMap::new(),
+        }
+    }
+}
+
+impl Template {
+    pub fn describe<'a, I>(&self, bbox: I, label_names: &[String]) -> String
+    where
+        I: IntoIterator<Item = &'a bb::Bb>,
+    {
+        let bbox: Vec<_> = bbox.into_iter().collect();
+        let best = match bbox.iter().max_by(|a, b| {
+            a.score
+                .partial_cmp(&b.score)
+                .unwrap_or(cmp::Ordering::Equal)
+        }) {
+            Some(best) => best,
+            None => return self.empty.clone(),
+        };
+        let mut counts = vec![0; label_names.len()];
+        for bb in bbox.iter() {
+            counts[bb.label] += 1;
+        }
+        let mut labels: Vec<_> = (0..counts.len()).filter(|&l| counts[l] > 0).collect();
+        // stable, so that ties keep the order of the labels
+        labels.sort_by_key(|&l| cmp::Reverse(counts[l]));
+        let objects: Vec<_> = labels
+            .into_iter()
+            .map(|l| format!("{} {}", counts[l], self.noun(&label_names[l], counts[l])))
+            .collect();
+        self.text
+            .replace("{objects}", &enumerate(&objects))
+            .replace("{count}", &bbox.len().to_string())
+            .replace("{best}", &label_names[best.label])
+            .replace("{score}", &format!("{:.2}", best.score))
+    }
+
+    fn noun(&self, label: &str, count: usize) -> String {
+        if count == 1 {
+            return label.to_owned();
+        }
+        if let Some(plural) = self.plurals.get(label) {
+            return plural.clone();
+        }
+        let es = ["s", "x", "z", "ch", "sh"]
+            .iter()
+            .any(|e| label.ends_with(e));
+        if es {
+            format!("{}es", label)
+        } else {
+            format!("{}s", label)
+        }
+    }
+}
+
+/// "a", "a and b", "a, b and c".
+fn enumerate(items: &[String]) -> String {
+    match items.split_last() {
+        None => String::new(),
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
+    }
+}
@@ -0,0 +1,242 @@
+//! Templates of the messages of the sinks, in a small subset of Handlebars,
+//! rendered with the JSON of the frame (see `schema`).
+//!
+//! `{{source}}` or `{{detections.0.label}}` is replaced with a field, strings
+//! as is and the rest as JSON, and `{{json source}}` with its JSON, to build
+//! JSON payloads. `{{#each detections}}...{{/each}}` repeats its body for each
+//! element, whose fields are found first, with `{{this}}` and `{{@index}}`.
+//! `{{#if detections}}...{{else}}...{{/if}}` keeps the first part when the
+//! field is neither missing, `null`, `false`, `0`, `""` nor `[]`. Arrays
+//! also have a `length`.
+
+use std::borrow::Cow;
+use std::slice;
+
+use serde_json::Value;
+
+#[derive(Clone, Debug)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Text(String),
+    Value {
+        path: Path,
+        json: bool,
+    },
+    Each {
+        path: Path,
+        body: Vec<Node>,
+    },
+    If {
+        path: Path,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+}
+
+#[derive(Clone, Debug)]
+enum Path {
+    Index,
+    /// Empty for `this`.
+    Fields(Vec<String>),
+}
+
+enum Token<'a> {
+    Text(&'a str),
+    Tag(&'a str),
+}
+
+/// An element of `#each`, or the frame.
+struct Scope<'a> {
+    value: &'a Value,
+    index: usize,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let tokens = tokens(source)?;
+        let mut tokens = tokens.iter();
+        match block(&mut tokens)? {
+            (nodes, None) => Ok(Self { nodes }),
+            (_, Some(tag)) => Err(format!("unexpected {{{{{}}}}}", tag)),
+        }
+    }
+
+    pub fn render(&self, context: &Value) -> String {
+        let mut out = String::new();
+        let mut scopes = vec![Scope {
+            value: context,
+            index: 0,
+        }];
+        render(&self.nodes, &mut scopes, &mut out);
+        out
+    }
+}
+
+fn tokens<'a>(source: &'a str) -> Result<Vec<Token<'a>>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    while let Some(start) = rest.find("{{") {
+        if start > 0 {
+            tokens.push(Token::Text(&rest[..start]));
+        }
+        let len = rest[start..]
+            .find("}}")
+            .ok_or_else(|| format!("unclosed {{{{ in {:?}", &rest[start..]))?;
+        tokens.push(Token::Tag(rest[start + 2..start + len].trim()));
+        rest = &rest[start + len + 2..];
+    }
+    if !rest.is_empty() {
+        tokens.push(Token::Text(rest));
+    }
+    Ok(tokens)
+}
+
+/// Parses nodes until `{{else}}` or a closing tag, which is returned.
+fn block<'a>(tokens: &mut slice::Iter<Token<'a>>) -> Result<(Vec<Node>, Option<&'a str>), String> {
+    let mut nodes = Vec::new();
+    while let Some(token) = tokens.next() {
+        let tag = match *token {
+            Token::Text(text) => {
+                nodes.push(Node::Text(text.to_owned()));
+                continue;
+            }
+            Token::Tag(tag) => tag,
+        };
+        if tag == "else" || tag.starts_with('/') {
+            return Ok((nodes, Some(tag)));
+        }
+        let words: Vec<_> = tag.trim_start_matches('#').split_whitespace().collect();
+        if tag.starts_with('#') {
+            let (helper, path) = match words[..] {
+                [helper, arg] => (helper, parse_path(arg)),
+                _ => return Err(format!("invalid block {{{{{}}}}}", tag)),
+            };
+            let (body, mut end) = block(tokens)?;
+            let node = match helper {
+                "each" => Node::Each { path, body },
+                "if" => {
+                    let mut otherwise = Vec::new();
+                    if end == Some("else") {
+                        let (nodes, e) = block(tokens)?;
+                        otherwise = nodes;
+                        end = e;
+                    }
+                    Node::If {
+                        path,
+                        then: body,
+                        otherwise,
+                    }
+                }
+                _ => return Err(format!("unknown block {{{{#{}}}}}", helper)),
+            };
+            if end != Some(&format!("/{}", helper)[..]) {
+                return Err(format!(
+                    "{{{{{}}}}} is not closed by {{{{/{}}}}}",
+                    tag, helper
+                ));
+            }
+            nodes.push(node);
+            continue;
+        }
+        nodes.push(match words[..] {
+            ["json", arg] => Node::Value {
+                path: parse_path(arg),
+                json: true,
+            },
+            [arg] => Node::Value {
+                path: parse_path(arg),
+                json: false,
+            },
+            _ => return Err(format!("invalid tag {{{{{}}}}}", tag)),
+        });
+    }
+    Ok((nodes, None))
+}
+
+fn parse_path(path: &str) -> Path {
+    if path == "@index" {
+        return Path::Index;
+    }
+    let mut fields: Vec<_> = path.split('.').map(|f| f.to_owned()).collect();
+    if fields[0] == "this" {
+        fields.remove(0);
+    }
+    Path::Fields(fields)
+}
+
+fn render(nodes: &[Node], scopes: &mut Vec<Scope>, out: &mut String) {
+    for node in nodes {
+        match *node {
+            Node::Text(ref text) => out.push_str(text),
+            Node::Value { ref path, json } => match lookup(scopes, path) {
+                Some(value) => match *value {
+                    Value::String(ref s) if !json => out.push_str(s),
+                    _ => out.push_str(&value.to_string()),
+                },
+                None if json => out.push_str("null"),
+                None => {}
+            },
+            Node::Each { ref path, ref body } => {
+                // the elements outlive the scopes pushed for them
+                let items = match lookup(scopes, path) {
+                    Some(Cow::Borrowed(Value::Array(items))) => items,
+                    _ => continue,
+                };
+                for (index, value) in items.iter().enumerate() {
+                    scopes.push(Scope { value, index });
+                    render(body, scopes, out);
+                    scopes.pop();
+                }
+            }
+            Node::If {
+                ref path,
+                ref then,
+                ref otherwise,
+            } => {
+                let truthy = lookup(scopes, path).is_some_and(|v| truthy(&v));
+                render(if truthy { then } else { otherwise }, scopes, out);
+            }
+        }
+    }
+}
+
+/// Finds the first field of `path` in the innermost scope that has it.
+fn lookup<'a>(scopes: &[Scope<'a>], path: &Path) -> Option<Cow<'a, Value>> {
+    let scope = scopes.last()?;
+    let fields = match *path {
+        Path::Index => return Some(Cow::Owned(Value::from(scope.index))),
+        Path::Fields(ref fields) if fields.is_empty() => return Some(Cow::Borrowed(scope.value)),
+        Path::Fields(ref fields) => fields,
+    };
+    let scope = scopes
+        .iter()
+        .rev()
+        .find(|s| s.value.get(&fields[0]).is_some())?;
+    let mut value = scope.value;
+    for (i, field) in fields.iter().enumerate() {
+        value = match *value {
+            Value::Array(ref items) if field == "length" && i + 1 == fields.len() => {
+                return Some(Cow::Owned(Value::from(items.len())));
+            }
+            Value::Array(ref items) => items.get(field.parse::<usize>().ok()?)?,
+            Value::Object(ref map) => map.get(field)?,
+            _ => return None,
+        };
+    }
+    Some(Cow::Borrowed(value))
+}
+
+fn truthy(value: &Value) -> bool {
+    match *value {
+        Value::Null => false,
+        Value::Bool(b) => b,
+        Value::Number(ref n) => n.as_f64() != Some(0.),
+        Value::String(ref s) => !s.is_empty(),
+        Value::Array(ref items) => !items.is_empty(),
+        Value::Object(_) => true,
+    }
+}
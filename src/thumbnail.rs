@@ -0,0 +1,88 @@
+//! Small JPEGs of the crops of the detections, embedded in base64 in the
+//! results sent by a sink (see `sink::Config::thumbnails`), so that the
+//! consumers of notifications can show a preview without fetching the frame.
+
+use image;
+
+use http;
+use schema;
+
+use image::GenericImage;
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct Config {
+    /// Longer edge of the thumbnails, in pixels.
+    #[serde(default = "default_max_edge")]
+    pub max_edge: u32,
+    /// Largest JPEG, in bytes. Larger thumbnails are shrunk until they fit,
+    /// down to 16 pixels, or omitted.
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: usize,
+    #[serde(default = "default_quality")]
+    pub quality: u8,
+}
+
+fn default_max_edge() -> u32 {
+    96
+}
+
+fn default_max_bytes() -> usize {
+    8192
+}
+
+fn default_quality() -> u8 {
+    75
+}
+
+const MIN_EDGE: u32 = 16;
+
+/// The base64 JPEG of the crop of `d` of `frame` in `img`, if any fits in
+/// `config.max_bytes`. `img` may be scaled from the frame, as by `max_edge`.
+pub fn encode(
+    img: &image::DynamicImage,
+    frame: &schema::Frame,
+    d: &schema::Detection,
+    config: &Config,
+) -> Option<String> {
+    let scale_y = img.height() as f32 / frame.height.max(1) as f32;
+    let scale_x = img.width() as f32 / frame.width.max(1) as f32;
+    let clip = |v: f32, max: u32| v.max(0.).min(max as f32) as u32;
+    let (top, left) = (
+        clip(d.top * scale_y, img.height()),
+        clip(d.left * scale_x, img.width()),
+    );
+    let (bottom, right) = (
+        clip(d.bottom * scale_y, img.height()),
+        clip(d.right * scale_x, img.width()),
+    );
+    if bottom <= top || right <= left {
+        return None;
+    }
+    let crop = image::ImageBuffer::from_fn(right - left, bottom - top, |x, y| {
+        img.get_pixel(left + x, top + y)
+    });
+    let crop = image::DynamicImage::ImageRgba8(crop);
+    let longer = (right - left).max(bottom - top);
+    let mut edge = config.max_edge.min(longer).max(MIN_EDGE);
+    loop {
+        let thumbnail = crop
+            .resize(edge, edge, image::FilterType::Triangle)
+            .to_rgb();
+        let mut jpeg = Vec::new();
+        image::jpeg::JPEGEncoder::new_with_quality(&mut jpeg, config.quality)
+            .encode(
+                &thumbnail,
+                thumbnail.width(),
+                thumbnail.height(),
+                image::ColorType::RGB(8),
+            )
+            .ok()?;
+        if jpeg.len() <= config.max_bytes {
+            return Some(http::base64(&jpeg));
+        }
+        if edge == MIN_EDGE {
+            return None;
+        }
+        edge = (edge / 2).max(MIN_EDGE);
+    }
+}
@@ -0,0 +1,265 @@
+//! Tiled inference: a large image is split into overlapping crops, each run
+//! at the resolution of the network, so that the small objects of 4K images
+//! are not lost in the downscale to `insize`.
+//!
+//! An object cut by the edge of a tile lies whole in the neighboring tile
+//! when it fits in the overlap, so these cut boxes are dropped. The others
+//! are merged by a suppression over the whole image, along with the boxes of
+//! the whole image for the objects larger than a tile.
+//!
+//! `predict_jpeg` runs on JPEGs decoded by bands of rows instead (see
+//! `jpeg`), for images too large to be held decoded: the whole image is
+//! never held, only the rows of a row of tiles and a scaled-down copy.
+
+use image;
+use std::io;
+use std::slice;
+
+use bb;
+use color;
+use detector;
+use downscale;
+use jpeg;
+use nms;
+use quality;
+
+use image::GenericImage;
+
+/// A crop of the image.
+#[derive(Clone, Copy, Debug)]
+pub struct Tile {
+    pub top: u32,
+    pub left: u32,
+    pub height: u32,
+    pub width: u32,
+}
+
+/// Covers an image of `height * width` pixels with tiles of `size` pixels
+/// (or the image size, if smaller) overlapping by at least `overlap` pixels.
+pub fn tiles(height: u32, width: u32, size: u32, overlap: u32) -> Vec<Tile> {
+    let size = size.max(1);
+    let mut tiles = Vec::new();
+    for top in starts(height, size, overlap) {
+        for left in starts(width, size, overlap) {
+            tiles.push(Tile {
+                top,
+                left,
+                height: size.min(height),
+                width: size.min(width),
+            });
+        }
+    }
+    tiles
+}
+
+/// Starts of the tiles along an axis, the last one flush with the end.
+fn starts(len: u32, size: u32, overlap: u32) -> Vec<u32> {
+    if len <= size {
+        return vec![0];
+    }
+    let step = size.saturating_sub(overlap).max(1);
+    let mut starts: Vec<_> = (0..)
+        .map(|i| i * step)
+        .take_while(|&s| s + size < len)
+        .collect();
+    starts.push(len - size);
+    starts
+}
+
+/// `predict` on the tiles of `img`, run through `predict_batch`, and on the
+/// whole image.
+pub fn predict(
+    model: &mut dyn detector::Detector,
+    img: &image::DynamicImage,
+    size: u32,
+    overlap: u32,
+) -> Result<Vec<bb::Bb>, detector::Error> {
+    let (height, width) = (img.height(), img.width());
+    let tiles = tiles(height, width, size, overlap);
+    // the gate applies to the whole image, which is skipped at once
+    if tiles.len() == 1 || model.params().quality.check(img).is_some() {
+        return model.predict(img);
+    }
+    let mut whole = img.clone();
+    let mut crops: Vec<_> = tiles
+        .iter()
+        .map(|t| whole.crop(t.left, t.top, t.width, t.height))
+        .collect();
+    crops.push(whole);
+
+    let params = model.params().clone();
+    let mut bboxes = predict_crops(model, &crops)?;
+
+    let mut merged = bboxes.pop().unwrap_or_default();
+    for (bbox, tile) in bboxes.into_iter().zip(tiles.iter()) {
+        merge_tile(&mut merged, bbox, tile, height, width, overlap);
+    }
+    nms::suppress(&mut merged, params.nms, params.nms_ties, params.min_score());
+    detector::apply_filters(&mut merged, model.config(), &params, img);
+    Ok(merged)
+}
+
+/// Longer edge of the scaled-down copy of `predict_jpeg`, without `max_edge`.
+pub const THUMBNAIL_EDGE: u32 = 1024;
+
+/// The boxes of `predict_jpeg`, on the image scaled down.
+pub struct Streamed {
+    /// In the pixels of `img`, their sharpness measured on it.
+    pub bbox: Vec<bb::Bb>,
+    pub img: image::DynamicImage,
+    /// To pass to `downscale::restore`.
+    pub factor: f32,
+    /// `(width, height)` of the image.
+    pub dimensions: (u32, u32),
+}
+
+/// `predict` on the JPEG read from `reader`, decoded by bands of rows. The
+/// tiles of a row are run once its rows are decoded, and the whole image is
+/// run scaled down to `edge` pixels on its longer edge.
+///
+/// The quality gate is checked on the scaled-down copy, complete only once
+/// every row of tiles went through the network: an image failing it has its
+/// boxes dropped, but costs the inference of its tiles all the same.
+pub fn predict_jpeg<R>(
+    model: &mut dyn detector::Detector,
+    reader: R,
+    size: u32,
+    overlap: u32,
+    edge: u32,
+) -> Result<Streamed, detector::Error>
+where
+    R: io::Read,
+{
+    let mut decoder = jpeg::Decoder::new(reader)?;
+    let (width, height) = decoder.dimensions();
+    let profile = decoder.icc_profile().and_then(color::Profile::parse);
+    let mut next_band = || -> Result<Option<image::DynamicImage>, detector::Error> {
+        Ok(decoder.next_band()?.map(|band| {
+            let mut band = image::DynamicImage::ImageRgb8(band);
+            if let Some(ref profile) = profile {
+                profile.to_srgb_mut(&mut band);
+            }
+            band
+        }))
+    };
+    let mut thumbnail = downscale::Thumbnail::new(width, height, edge);
+    let tiles = tiles(height, width, size, overlap);
+    let columns = tiles.iter().filter(|t| t.top == 0).count();
+    let stride = width as usize * 3;
+
+    // the rows from `top` to `decoded`
+    let mut rows: Vec<u8> = Vec::new();
+    let (mut top, mut decoded) = (0, 0);
+    let mut merged = Vec::new();
+    for row in tiles.chunks(columns) {
+        while decoded < row[0].top + row[0].height {
+            let band = next_band()?
+                .ok_or_else(|| detector::Error::Preprocessing("truncated JPEG".to_owned()))?;
+            let band = band.to_rgb();
+            thumbnail.add(decoded, &band);
+            decoded += band.height();
+            rows.extend_from_slice(&band.into_raw());
+        }
+        rows.drain(..(row[0].top - top) as usize * stride);
+        top = row[0].top;
+        if tiles.len() == 1 {
+            break;
+        }
+        let crops: Vec<_> = row
+            .iter()
+            .map(|t| {
+                let mut crop = Vec::with_capacity(t.height as usize * t.width as usize * 3);
+                for y in 0..t.height as usize {
+                    let start = y * stride + t.left as usize * 3;
+                    crop.extend_from_slice(&rows[start..start + t.width as usize * 3]);
+                }
+                let crop = image::RgbImage::from_raw(t.width, t.height, crop).unwrap();
+                image::DynamicImage::ImageRgb8(crop)
+            })
+            .collect();
+        let bboxes = predict_crops(model, &crops)?;
+        for (bbox, tile) in bboxes.into_iter().zip(row.iter()) {
+            merge_tile(&mut merged, bbox, tile, height, width, overlap);
+        }
+    }
+    drop(rows);
+    while let Some(band) = next_band()? {
+        thumbnail.add(decoded, &band.to_rgb());
+        decoded += band.height();
+    }
+    let small = thumbnail.image();
+    let factor = width.max(height) as f32 / small.width().max(small.height()) as f32;
+    let streamed = |bbox, img| Streamed {
+        bbox,
+        img,
+        factor,
+        dimensions: (width, height),
+    };
+
+    // as `predict`, with the gate on the whole image, too late for the tiles
+    if tiles.len() == 1 || model.params().quality.check(&small).is_some() {
+        return Ok(streamed(model.predict(&small)?, small));
+    }
+    let params = model.params().clone();
+    let mut whole = predict_crops(model, slice::from_ref(&small))?
+        .pop()
+        .unwrap_or_default();
+    downscale::restore(whole.iter_mut(), factor);
+    merged.extend(whole);
+    nms::suppress(&mut merged, params.nms, params.nms_ties, params.min_score());
+    let scaled = downscale::Scaled {
+        img: &small,
+        width,
+        height,
+    };
+    detector::apply_filters(&mut merged, model.config(), &params, &scaled);
+    downscale::restore(merged.iter_mut(), 1. / factor);
+    Ok(streamed(merged, small))
+}
+
+/// `predict_batch` on `crops`, with the filters turned off: they apply to
+/// the merged boxes, in the pixels of the image.
+fn predict_crops(
+    model: &mut dyn detector::Detector,
+    crops: &[image::DynamicImage],
+) -> Result<Vec<Vec<bb::Bb>>, detector::Error> {
+    let params = model.params().clone();
+    let mut decode = params.without_filters();
+    decode.quality = quality::Gate::default();
+    model.set_params(decode);
+    let bboxes = model.predict_batch(crops);
+    model.set_params(params);
+    bboxes
+}
+
+/// Adds the boxes of `tile` not cut by its seams to `merged`, in the pixels
+/// of the image.
+fn merge_tile(
+    merged: &mut Vec<bb::Bb>,
+    bbox: Vec<bb::Bb>,
+    tile: &Tile,
+    height: u32,
+    width: u32,
+    overlap: u32,
+) {
+    for mut bb in bbox {
+        if cut_by_seam(&bb, tile, height, width, overlap) {
+            continue;
+        }
+        bb.top += tile.top as f32;
+        bb.left += tile.left as f32;
+        bb.bottom += tile.top as f32;
+        bb.right += tile.left as f32;
+        merged.push(bb);
+    }
+}
+
+/// Whether `bb`, in the pixels of `tile`, touches an edge of the tile inside
+/// the image while lying in the overlap with the tile beyond that edge.
+fn cut_by_seam(bb: &bb::Bb, tile: &Tile, height: u32, width: u32, overlap: u32) -> bool {
+    let (h, w, overlap) = (tile.height as f32, tile.width as f32, overlap as f32);
+    (tile.top > 0 && bb.top <= 1. && bb.bottom <= overlap)
+        || (tile.left > 0 && bb.left <= 1. && bb.right <= overlap)
+        || (tile.top + tile.height < height && bb.bottom >= h - 1. && bb.top >= h - overlap)
+        || (tile.left + tile.width < width && bb.right >= w - 1. && bb.left >= w - overlap)
+}
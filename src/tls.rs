@@ -0,0 +1,213 @@
+//! TLS client connections, through the system OpenSSL (3.0 or later).
+//!
+//! Certificates are verified against the default CA certificates of OpenSSL,
+//! which `SSL_CERT_FILE` and `SSL_CERT_DIR` override, e.g. for cameras with
+//! self-signed certificates. Servers closing the connection without a TLS
+//! `close_notify` are accepted, as HTTP/1.0 servers often do; their bodies
+//! are delimited by HTTP.
+
+use libc;
+use std::ffi;
+use std::io;
+use std::net;
+use std::ptr;
+
+use std::os::unix::io::AsRawFd;
+
+enum SslMethod {}
+enum SslCtx {}
+enum Ssl {}
+enum VerifyParam {}
+
+const SSL_VERIFY_PEER: libc::c_int = 1;
+const SSL_OP_IGNORE_UNEXPECTED_EOF: u64 = 1 << 7;
+const SSL_CTRL_SET_TLSEXT_HOSTNAME: libc::c_int = 55;
+const TLSEXT_NAMETYPE_HOST_NAME: libc::c_long = 0;
+const SSL_ERROR_WANT_READ: libc::c_int = 2;
+const SSL_ERROR_WANT_WRITE: libc::c_int = 3;
+const SSL_ERROR_SYSCALL: libc::c_int = 5;
+const SSL_ERROR_ZERO_RETURN: libc::c_int = 6;
+const X509_V_OK: libc::c_long = 0;
+
+#[link(name = "ssl")]
+extern "C" {
+    fn TLS_client_method() -> *const SslMethod;
+    fn SSL_CTX_new(method: *const SslMethod) -> *mut SslCtx;
+    fn SSL_CTX_free(ctx: *mut SslCtx);
+    fn SSL_CTX_set_default_verify_paths(ctx: *mut SslCtx) -> libc::c_int;
+    fn SSL_CTX_set_verify(ctx: *mut SslCtx, mode: libc::c_int, callback: *const libc::c_void);
+    fn SSL_CTX_set_options(ctx: *mut SslCtx, options: u64) -> u64;
+    fn SSL_new(ctx: *mut SslCtx) -> *mut Ssl;
+    fn SSL_free(ssl: *mut Ssl);
+    fn SSL_set_fd(ssl: *mut Ssl, fd: libc::c_int) -> libc::c_int;
+    fn SSL_ctrl(
+        ssl: *mut Ssl,
+        cmd: libc::c_int,
+        larg: libc::c_long,
+        parg: *mut libc::c_void,
+    ) -> libc::c_long;
+    fn SSL_set1_host(ssl: *mut Ssl, hostname: *const libc::c_char) -> libc::c_int;
+    fn SSL_get0_param(ssl: *mut Ssl) -> *mut VerifyParam;
+    fn SSL_connect(ssl: *mut Ssl) -> libc::c_int;
+    fn SSL_read(ssl: *mut Ssl, buf: *mut libc::c_void, num: libc::c_int) -> libc::c_int;
+    fn SSL_write(ssl: *mut Ssl, buf: *const libc::c_void, num: libc::c_int) -> libc::c_int;
+    fn SSL_shutdown(ssl: *mut Ssl) -> libc::c_int;
+    fn SSL_get_error(ssl: *const Ssl, ret: libc::c_int) -> libc::c_int;
+    fn SSL_get_verify_result(ssl: *const Ssl) -> libc::c_long;
+}
+
+#[link(name = "crypto")]
+extern "C" {
+    fn X509_VERIFY_PARAM_set1_ip_asc(
+        param: *mut VerifyParam,
+        ip: *const libc::c_char,
+    ) -> libc::c_int;
+    fn X509_verify_cert_error_string(n: libc::c_long) -> *const libc::c_char;
+    fn ERR_get_error() -> libc::c_ulong;
+    fn ERR_error_string_n(e: libc::c_ulong, buf: *mut libc::c_char, len: libc::size_t);
+}
+
+pub struct Stream {
+    ctx: *mut SslCtx,
+    ssl: *mut Ssl,
+    /// Closed once the TLS session is freed.
+    _tcp: net::TcpStream,
+}
+
+// an SSL object may move between threads, used by one at a time
+unsafe impl Send for Stream {}
+
+impl Stream {
+    /// Runs the handshake on `tcp`, checking the certificate against `host`.
+    pub fn connect(tcp: net::TcpStream, host: &str) -> io::Result<Self> {
+        let host_c =
+            ffi::CString::new(host).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let ctx = unsafe { SSL_CTX_new(TLS_client_method()) };
+        if ctx.is_null() {
+            return Err(failed());
+        }
+        // freed with the stream from now on
+        let mut stream = Self {
+            ctx,
+            ssl: ptr::null_mut(),
+            _tcp: tcp,
+        };
+        unsafe {
+            if SSL_CTX_set_default_verify_paths(ctx) != 1 {
+                return Err(failed());
+            }
+            SSL_CTX_set_verify(ctx, SSL_VERIFY_PEER, ptr::null());
+            SSL_CTX_set_options(ctx, SSL_OP_IGNORE_UNEXPECTED_EOF);
+            stream.ssl = SSL_new(ctx);
+            if stream.ssl.is_null() {
+                return Err(failed());
+            }
+            let ssl = stream.ssl;
+            if SSL_set_fd(ssl, stream._tcp.as_raw_fd()) != 1 {
+                return Err(failed());
+            }
+            let checked = if host.parse::<net::IpAddr>().is_ok() {
+                X509_VERIFY_PARAM_set1_ip_asc(SSL_get0_param(ssl), host_c.as_ptr())
+            } else {
+                // SNI, which IP addresses may not be sent in
+                SSL_ctrl(
+                    ssl,
+                    SSL_CTRL_SET_TLSEXT_HOSTNAME,
+                    TLSEXT_NAMETYPE_HOST_NAME,
+                    host_c.as_ptr() as *mut libc::c_void,
+                );
+                SSL_set1_host(ssl, host_c.as_ptr())
+            };
+            if checked != 1 {
+                return Err(failed());
+            }
+            let ret = SSL_connect(ssl);
+            if ret != 1 {
+                let verified = SSL_get_verify_result(ssl);
+                if verified != X509_V_OK {
+                    let reason = ffi::CStr::from_ptr(X509_verify_cert_error_string(verified));
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "{}: certificate verification failed: {}",
+                            host,
+                            reason.to_string_lossy()
+                        ),
+                    ));
+                }
+                return Err(stream.error(ret));
+            }
+        }
+        Ok(stream)
+    }
+
+    /// The error of a call on the session returning `ret`.
+    fn error(&self, ret: libc::c_int) -> io::Error {
+        // errno first, before any other call changes it
+        let os = io::Error::last_os_error();
+        match unsafe { SSL_get_error(self.ssl, ret) } {
+            SSL_ERROR_ZERO_RETURN => io::ErrorKind::UnexpectedEof.into(),
+            // timeouts of the socket
+            SSL_ERROR_WANT_READ | SSL_ERROR_WANT_WRITE => io::ErrorKind::WouldBlock.into(),
+            SSL_ERROR_SYSCALL if os.raw_os_error().is_some_and(|e| e != 0) => os,
+            _ => failed(),
+        }
+    }
+}
+
+impl io::Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(libc::c_int::MAX as usize) as libc::c_int;
+        let ret = unsafe { SSL_read(self.ssl, buf.as_mut_ptr() as *mut libc::c_void, len) };
+        if ret > 0 {
+            return Ok(ret as usize);
+        }
+        match self.error(ret) {
+            ref e if e.kind() == io::ErrorKind::UnexpectedEof => Ok(0),
+            e => Err(e),
+        }
+    }
+}
+
+impl io::Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let len = buf.len().min(libc::c_int::MAX as usize) as libc::c_int;
+        let ret = unsafe { SSL_write(self.ssl, buf.as_ptr() as *const libc::c_void, len) };
+        if ret > 0 {
+            Ok(ret as usize)
+        } else {
+            Err(self.error(ret))
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.ssl.is_null() {
+                SSL_shutdown(self.ssl);
+                SSL_free(self.ssl);
+            }
+            SSL_CTX_free(self.ctx);
+        }
+    }
+}
+
+/// The last error of OpenSSL.
+fn failed() -> io::Error {
+    let code = unsafe { ERR_get_error() };
+    let mut buf = [0 as libc::c_char; 256];
+    unsafe { ERR_error_string_n(code, buf.as_mut_ptr(), buf.len()) };
+    let message = unsafe { ffi::CStr::from_ptr(buf.as_ptr()) };
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("TLS failed: {}", message.to_string_lossy()),
+    )
+}
@@ -0,0 +1,427 @@
+//! Tracker associating detections across frames, as SORT (Bewley et al.,
+//! 2016): each track predicts its box in the next frame with a Kalman filter,
+//! and detections are matched greedily with the predicted boxes by IoU.
+//! Without the filter (`Motion::None`), detections are matched with the last
+//! box of each track, as the IoU tracker (Bochinski et al., 2017).
+
+use std::cmp;
+
+use bb;
+
+use rect::Rect;
+
+#[derive(Clone, Debug)]
+pub struct Track {
+    pub id: u64,
+    /// The last matched detection.
+    pub bb: bb::Bb,
+    /// The box of the filter, with the label and score of `bb`: smoothed
+    /// after a match, or predicted after a miss. `bb` without a filter.
+    pub smoothed: bb::Bb,
+    /// The exponential moving average of the matched detections, with the
+    /// label and score of `bb` (see `Tracker::with_smoothing`).
+    pub average: bb::Bb,
+    pub hits: usize,
+    misses: usize,
+    filter: Option<Kalman>,
+}
+
+/// How tracks predict their box in the next frame.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Motion {
+    /// With a constant velocity Kalman filter.
+    #[default]
+    Kalman,
+    /// As the last matched box, for the devices too constrained for the
+    /// filter. Fast objects, moving by more than their size between frames,
+    /// are lost.
+    None,
+}
+
+pub struct Tracker {
+    iou: f32,
+    max_misses: usize,
+    motion: Motion,
+    alpha: f32,
+    next_id: u64,
+    tracks: Vec<Track>,
+}
+
+impl Tracker {
+    /// Detections are matched with tracks of the same label with an IoU of at
+    /// least `iou` with the predicted box. Tracks are ended after `max_misses` frames without matches.
+    pub fn new(iou: f32, max_misses: usize) -> Self {
+        Self {
+            iou,
+            max_misses,
+            motion: Motion::Kalman,
+            alpha: 1.,
+            next_id: 0,
+            tracks: Vec::new(),
+        }
+    }
+
+    pub fn with_motion(self, motion: Motion) -> Self {
+        Self { motion, ..self }
+    }
+
+    /// Averages the coordinates of the detections of each track into
+    /// `Track::average`, each detection weighing `alpha` in `(0, 1]`, for
+    /// steadier overlays than the detections themselves.
+    pub fn with_smoothing(self, alpha: f32) -> Self {
+        Self { alpha, ..self }
+    }
+
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    pub fn track(&self, id: u64) -> Option<&Track> {
+        self.tracks.iter().find(|t| t.id == id)
+    }
+
+    /// Returns the track IDs of `bbox`, in the same order, and the tracks
+    /// ended by this frame.
+    pub fn update(&mut self, bbox: &[bb::Bb]) -> (Vec<u64>, Vec<Track>) {
+        for track in self.tracks.iter_mut() {
+            if let Some(ref mut filter) = track.filter {
+                filter.predict();
+                track.smoothed = filter.bb(&track.bb);
+            }
+        }
+
+        let mut pairs = Vec::new();
+        for (t, track) in self.tracks.iter().enumerate() {
+            for (b, bb) in bbox.iter().enumerate() {
+                if bb.label == track.bb.label {
+                    let iou = bb.iou(&track.smoothed);
+                    if iou >= self.iou {
+                        pairs.push((iou, t, b));
+                    }
+                }
+            }
+        }
+        pairs.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(cmp::Ordering::Equal));
+
+        let mut track_matched = vec![false; self.tracks.len()];
+        let mut ids = vec![None; bbox.len()];
+        for (_, t, b) in pairs {
+            if track_matched[t] || ids[b].is_some() {
+                continue;
+            }
+            track_matched[t] = true;
+            ids[b] = Some(self.tracks[t].id);
+            let track = &mut self.tracks[t];
+            track.bb = bbox[b].clone();
+            track.smoothed = match track.filter {
+                Some(ref mut filter) => {
+                    filter.update(&track.bb);
+                    filter.bb(&track.bb)
+                }
+                None => track.bb.clone(),
+            };
+            track.average = average(&track.average, &track.bb, self.alpha);
+            track.hits += 1;
+            track.misses = 0;
+        }
+
+        let mut ended = Vec::new();
+        let mut tracks = Vec::with_capacity(self.tracks.len());
+        for (mut track, matched) in self.tracks.drain(..).zip(track_matched) {
+            if !matched {
+                track.misses += 1;
+            }
+            if track.misses > self.max_misses {
+                ended.push(track);
+            } else {
+                tracks.push(track);
+            }
+        }
+        self.tracks = tracks;
+
+        let ids = ids
+            .into_iter()
+            .zip(bbox.iter())
+            .map(|(id, bb)| match id {
+                Some(id) => id,
+                None => {
+                    let id = self.next_id;
+                    self.next_id += 1;
+                    let filter = match self.motion {
+                        Motion::Kalman => Some(Kalman::new(bb)),
+                        Motion::None => None,
+                    };
+                    self.tracks.push(Track {
+                        id,
+                        bb: bb.clone(),
+                        smoothed: bb.clone(),
+                        average: bb.clone(),
+                        hits: 1,
+                        misses: 0,
+                        filter,
+                    });
+                    id
+                }
+            })
+            .collect();
+        (ids, ended)
+    }
+
+    /// Ends all the tracks.
+    pub fn finish(&mut self) -> Vec<Track> {
+        self.tracks.drain(..).collect()
+    }
+}
+
+/// `bb` weighing `alpha` in the coordinates of `average`.
+fn average(average: &bb::Bb, bb: &bb::Bb, alpha: f32) -> bb::Bb {
+    let mix = |a: f32, b: f32| a + alpha * (b - a);
+    bb::Bb {
+        top: mix(average.top, bb.top),
+        left: mix(average.left, bb.left),
+        bottom: mix(average.bottom, bb.bottom),
+        right: mix(average.right, bb.right),
+        ..bb.clone()
+    }
+}
+
+/// Variances of the process noise, per state variable.
+const PROCESS_NOISE: [f32; 7] = [1., 1., 1., 1., 0.01, 0.01, 0.0001];
+/// Variances of the measurement noise: the area and the aspect ratio are the
+/// noisiest.
+const MEASUREMENT_NOISE: [f32; 4] = [1., 1., 10., 10.];
+
+/// Constant velocity Kalman filter of a box. The state is the center `x` and
+/// `y`, the area `s` and the aspect ratio `r` of the box, followed by the
+/// velocities of the first three; the measurement is the first four.
+#[derive(Clone, Debug)]
+struct Kalman {
+    x: [f32; 7],
+    p: [[f32; 7]; 7],
+}
+
+impl Kalman {
+    fn new(bb: &bb::Bb) -> Self {
+        let mut x = [0.; 7];
+        x[..4].copy_from_slice(&measure(bb));
+        // the velocities are unknown
+        let mut p = [[0.; 7]; 7];
+        for (i, row) in p.iter_mut().enumerate() {
+            row[i] = if i < 4 { 10. } else { 10000. };
+        }
+        Self { x, p }
+    }
+
+    fn predict(&mut self) {
+        if self.x[2] + self.x[6] <= 0. {
+            self.x[6] = 0.;
+        }
+        for i in 0..3 {
+            self.x[i] += self.x[i + 4];
+        }
+        // P = F P F^T + Q, where F adds the velocity i + 4 to i < 3
+        let mut fp = self.p;
+        for (row, velocity) in fp.iter_mut().zip(self.p[4..].iter()) {
+            for (p, v) in row.iter_mut().zip(velocity.iter()) {
+                *p += v;
+            }
+        }
+        let mut p = fp;
+        for row in p.iter_mut() {
+            for j in 0..3 {
+                row[j] += row[j + 4];
+            }
+        }
+        for (i, q) in PROCESS_NOISE.iter().enumerate() {
+            p[i][i] += q;
+        }
+        self.p = p;
+    }
+
+    fn update(&mut self, bb: &bb::Bb) {
+        // S = H P H^T + R, where H selects the first four variables
+        let mut s = [[0.; 4]; 4];
+        for (i, row) in s.iter_mut().enumerate() {
+            row.copy_from_slice(&self.p[i][..4]);
+            row[i] += MEASUREMENT_NOISE[i];
+        }
+        let s_inv = match invert(s) {
+            Some(s_inv) => s_inv,
+            None => return,
+        };
+        // K = P H^T S^-1
+        let mut k = [[0.; 4]; 7];
+        for (i, row) in k.iter_mut().enumerate() {
+            for (j, k) in row.iter_mut().enumerate() {
+                *k = (0..4).map(|l| self.p[i][l] * s_inv[l][j]).sum();
+            }
+        }
+        let z = measure(bb);
+        let y: Vec<_> = (0..4).map(|i| z[i] - self.x[i]).collect();
+        for (x, k) in self.x.iter_mut().zip(k.iter()) {
+            *x += (0..4).map(|j| k[j] * y[j]).sum::<f32>();
+        }
+        // P = (I - K H) P
+        let p = self.p;
+        for (i, row) in self.p.iter_mut().enumerate() {
+            for (j, p_ij) in row.iter_mut().enumerate() {
+                *p_ij -= (0..4).map(|l| k[i][l] * p[l][j]).sum::<f32>();
+            }
+        }
+    }
+
+    /// The box of the state, with the label and the score of `bb`.
+    fn bb(&self, bb: &bb::Bb) -> bb::Bb {
+        let (x, y) = (self.x[0], self.x[1]);
+        let w = (self.x[2].max(0.) * self.x[3].max(0.)).sqrt();
+        let h = if w > 0. { self.x[2] / w } else { 0. };
+        bb::Bb {
+            top: y - h / 2.,
+            left: x - w / 2.,
+            bottom: y + h / 2.,
+            right: x + w / 2.,
+            ..bb.clone()
+        }
+    }
+}
+
+fn measure(bb: &bb::Bb) -> [f32; 4] {
+    let (h, w) = (bb.height(), bb.width());
+    [
+        bb.left + w / 2.,
+        bb.top + h / 2.,
+        w * h,
+        if h > 0. { w / h } else { 1. },
+    ]
+}
+
+/// Inverts `m` by Gauss-Jordan elimination. `None` if it is singular.
+fn invert(mut m: [[f32; 4]; 4]) -> Option<[[f32; 4]; 4]> {
+    let mut inv = [[0.; 4]; 4];
+    for (i, row) in inv.iter_mut().enumerate() {
+        row[i] = 1.;
+    }
+    for c in 0..4 {
+        let pivot = (c..4).max_by(|&a, &b| {
+            m[a][c]
+                .abs()
+                .partial_cmp(&m[b][c].abs())
+                .unwrap_or(cmp::Ordering::Equal)
+        })?;
+        if m[pivot][c].abs() < 1e-12 {
+            return None;
+        }
+        m.swap(c, pivot);
+        inv.swap(c, pivot);
+        let d = m[c][c];
+        for j in 0..4 {
+            m[c][j] /= d;
+            inv[c][j] /= d;
+        }
+        for r in 0..4 {
+            if r != c {
+                let f = m[r][c];
+                for j in 0..4 {
+                    m[r][j] -= f * m[c][j];
+                    inv[r][j] -= f * inv[c][j];
+                }
+            }
+        }
+    }
+    Some(inv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bb(top: f32, left: f32, size: f32) -> bb::Bb {
+        bb::Bb {
+            top,
+            left,
+            bottom: top + size,
+            right: left + size,
+            label: 0,
+            score: 1.,
+            truncated: false,
+            sharpness: 0.,
+            provenance: None,
+        }
+    }
+
+    #[test]
+    fn inverts_matrices() {
+        let m = [
+            [4., 7., 2., 0.],
+            [3., 6., 1., 0.],
+            [2., 5., 3., 0.],
+            [0., 0., 0., 2.],
+        ];
+        let inv = invert(m).unwrap();
+        for (i, row) in m.iter().enumerate() {
+            for j in 0..4 {
+                let product: f32 = row.iter().zip(inv.iter()).map(|(a, b)| a * b[j]).sum();
+                let identity = if i == j { 1. } else { 0. };
+                assert!(
+                    (product - identity).abs() < 1e-5,
+                    "{} at {}, {}",
+                    product,
+                    i,
+                    j
+                );
+            }
+        }
+        assert!(invert([
+            [1., 2., 0., 0.],
+            [2., 4., 0., 0.],
+            [0., 0., 1., 0.],
+            [0., 0., 0., 1.]
+        ])
+        .is_none());
+    }
+
+    #[test]
+    fn keeps_a_still_box() {
+        let mut filter = Kalman::new(&bb(10., 20., 8.));
+        for _ in 0..10 {
+            filter.predict();
+            filter.update(&bb(10., 20., 8.));
+        }
+        let b = filter.bb(&bb(0., 0., 1.));
+        for (v, expected) in [b.top, b.left, b.bottom, b.right]
+            .iter()
+            .zip(&[10., 20., 18., 28.])
+        {
+            assert!((v - expected).abs() < 1e-3, "{:?}", b);
+        }
+    }
+
+    /// The filter learns the velocity of a box moving 4 pixels right per
+    /// frame, and predicts it across a missed frame.
+    #[test]
+    fn predicts_constant_velocity() {
+        let mut tracker = Tracker::new(0.3, 2);
+        for t in 0..20 {
+            let (ids, _) = tracker.update(&[bb(10., 4. * t as f32, 20.)]);
+            assert_eq!(ids, [0]);
+        }
+        tracker.update(&[]);
+        let predicted = &tracker.track(0).unwrap().smoothed;
+        assert!((predicted.left - 80.).abs() < 0.5, "{:?}", predicted);
+        assert!((predicted.top - 10.).abs() < 0.5, "{:?}", predicted);
+        assert!((predicted.width() - 20.).abs() < 0.5, "{:?}", predicted);
+        let (ids, _) = tracker.update(&[bb(10., 84., 20.)]);
+        assert_eq!(ids, [0]);
+    }
+
+    #[test]
+    fn loses_fast_boxes_without_the_filter() {
+        let mut tracker = Tracker::new(0.3, 2).with_motion(Motion::None);
+        tracker.update(&[bb(0., 0., 10.)]);
+        let (ids, _) = tracker.update(&[bb(0., 2., 10.)]);
+        assert_eq!(ids, [0]);
+        let (ids, _) = tracker.update(&[bb(0., 12., 10.)]);
+        assert_eq!(ids, [1]);
+    }
+}
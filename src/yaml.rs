@@ -0,0 +1,719 @@
+//! The subset of YAML that configuration files use, read into JSON values so
+//! that the serde definitions of the JSON files apply unchanged.
+//!
+//! Block mappings and sequences, flow collections (hence JSON), comments,
+//! plain, single-quoted and double-quoted scalars, and literal (`|`) and
+//! folded (`>`) block scalars are supported. Anchors, aliases, tags,
+//! complex keys, multi-line flow scalars and several documents are
+//! rejected. Plain scalars resolve as in the core schema of YAML 1.2: null,
+//! booleans, integers and floats, or strings.
+
+use serde_json;
+use std::error;
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub struct Error {
+    /// From 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl error::Error for Error {}
+
+type Result<T> = ::std::result::Result<T, Error>;
+
+/// The value of the document in `text`, null if it is empty.
+pub fn parse(text: &str) -> Result<serde_json::Value> {
+    let mut parser = Parser {
+        lines: text
+            .lines()
+            .enumerate()
+            .map(|(i, raw)| Line::new(i + 1, raw))
+            .collect::<Result<_>>()?,
+        pos: 0,
+    };
+    if let Some(line) = parser.peek() {
+        if line.text.starts_with('%') {
+            return Err(line.error("directives are not supported"));
+        }
+        if line.text == "---" {
+            parser.pos += 1;
+        }
+    }
+    let value = match parser.peek() {
+        Some(line) if line.text != "..." => parser.block()?,
+        _ => serde_json::Value::Null,
+    };
+    match parser.peek() {
+        Some(line) if line.text == "..." || line.text == "---" => {
+            let line = line.number;
+            parser.pos += 1;
+            match parser.peek() {
+                Some(line) => Err(line.error("several documents are not supported")),
+                None => Ok(value),
+            }
+            .map_err(|e| Error { line, ..e })
+        }
+        Some(line) => Err(line.error("unexpected content")),
+        None => Ok(value),
+    }
+}
+
+struct Line<'a> {
+    number: usize,
+    indent: usize,
+    /// Without the indentation and the comment.
+    text: &'a str,
+    raw: &'a str,
+}
+
+impl<'a> Line<'a> {
+    fn new(number: usize, raw: &'a str) -> Result<Self> {
+        let indent = raw.len() - raw.trim_start_matches(' ').len();
+        let mut line = Self {
+            number,
+            indent,
+            text: strip_comment(&raw[indent..]).trim_end(),
+            raw,
+        };
+        if line.text.starts_with('\t') {
+            line.text = line.text.trim_start();
+            if !line.text.is_empty() {
+                return Err(line.error("tabs cannot indent"));
+            }
+        }
+        Ok(line)
+    }
+
+    fn error(&self, message: &str) -> Error {
+        Error {
+            line: self.number,
+            message: message.to_owned(),
+        }
+    }
+}
+
+struct Parser<'a> {
+    lines: Vec<Line<'a>>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    /// The next line that is not blank.
+    fn peek(&mut self) -> Option<&Line<'a>> {
+        while self.lines.get(self.pos).is_some_and(|l| l.text.is_empty()) {
+            self.pos += 1;
+        }
+        self.lines.get(self.pos)
+    }
+
+    /// The node starting at the next line, at its indentation.
+    fn block(&mut self) -> Result<serde_json::Value> {
+        let line = self.peek().unwrap();
+        let (indent, text, number) = (line.indent, line.text, line.number);
+        if is_entry(text) {
+            self.sequence(indent)
+        } else if split_key(text).is_some() {
+            self.mapping(indent)
+        } else {
+            self.pos += 1;
+            self.inline(text, number)
+        }
+    }
+
+    /// The node of a key or an entry with nothing after it on its line, at
+    /// `indent`.
+    fn nested(&mut self, indent: usize) -> Result<serde_json::Value> {
+        match self.peek() {
+            Some(line) if line.indent > indent => self.block(),
+            _ => Ok(serde_json::Value::Null),
+        }
+    }
+
+    fn sequence(&mut self, indent: usize) -> Result<serde_json::Value> {
+        let mut items = Vec::new();
+        while let Some(line) = self.peek() {
+            if line.indent > indent {
+                return Err(line.error("unexpected indentation"));
+            }
+            if line.indent < indent || !is_entry(line.text) {
+                break;
+            }
+            let rest = line.text[1..].trim_start();
+            if rest.is_empty() {
+                self.pos += 1;
+                items.push(self.nested(indent)?);
+            } else {
+                // the rest of the line as a line of its own, at its column
+                let column = indent + line.text.len() - rest.len();
+                let line = &mut self.lines[self.pos];
+                line.indent = column;
+                line.text = rest;
+                items.push(self.block()?);
+            }
+        }
+        Ok(serde_json::Value::Array(items))
+    }
+
+    fn mapping(&mut self, indent: usize) -> Result<serde_json::Value> {
+        let mut map = serde_json::Map::new();
+        while let Some(line) = self.peek() {
+            if line.indent > indent {
+                return Err(line.error("unexpected indentation"));
+            }
+            if line.indent < indent || line.text == "..." || line.text == "---" {
+                break;
+            }
+            let (key, rest) = match split_key(line.text) {
+                Some(split) => split,
+                None => return Err(line.error("expected a key")),
+            };
+            let number = line.number;
+            let key = self.key(key, number)?;
+            self.pos += 1;
+            let value = if rest.is_empty() {
+                match self.peek() {
+                    // sequences may be as indented as their key
+                    Some(next) if next.indent == indent && is_entry(next.text) => {
+                        self.sequence(indent)?
+                    }
+                    _ => self.nested(indent)?,
+                }
+            } else if rest.starts_with('|') || rest.starts_with('>') {
+                self.block_scalar(rest, indent, number)?
+            } else {
+                self.inline(rest, number)?
+            };
+            if map.insert(key, value).is_some() {
+                return Err(Error {
+                    line: number,
+                    message: "duplicate key".to_owned(),
+                });
+            }
+        }
+        Ok(serde_json::Value::Object(map))
+    }
+
+    fn key(&self, key: &str, number: usize) -> Result<String> {
+        let error = |message: String| Error {
+            line: number,
+            message,
+        };
+        if key.starts_with('?') {
+            return Err(error("complex keys are not supported".to_owned()));
+        }
+        let value = if key.starts_with('"') || key.starts_with('\'') {
+            let (value, len) = quoted(key).map_err(error)?;
+            if len != key.len() {
+                return Err(error("unexpected content after the key".to_owned()));
+            }
+            serde_json::Value::String(value)
+        } else {
+            self.scalar(key, number)?
+        };
+        Ok(match value {
+            serde_json::Value::String(s) => s,
+            serde_json::Value::Null => "null".to_owned(),
+            value => value.to_string(),
+        })
+    }
+
+    /// A node written on the line of its key, or the line numbered `number`,
+    /// continuing on the next lines for flow collections.
+    fn inline(&mut self, text: &str, number: usize) -> Result<serde_json::Value> {
+        let error = |message: String| Error {
+            line: number,
+            message,
+        };
+        if text.starts_with('[') || text.starts_with('{') {
+            let mut source = text.to_owned();
+            while !is_closed(&source) {
+                match self.lines.get(self.pos) {
+                    Some(line) => source = source + " " + line.text.trim_start(),
+                    None => return Err(error("unterminated flow collection".to_owned())),
+                }
+                self.pos += 1;
+            }
+            let mut flow = Flow { s: &source, i: 0 };
+            let value = flow.value().map_err(error)?;
+            flow.skip_spaces();
+            if flow.i != source.len() {
+                return Err(error("unexpected content after the collection".to_owned()));
+            }
+            Ok(value)
+        } else if text.starts_with('"') || text.starts_with('\'') {
+            let (value, len) = quoted(text).map_err(error)?;
+            if len != text.len() {
+                return Err(error("unexpected content after the string".to_owned()));
+            }
+            Ok(serde_json::Value::String(value))
+        } else {
+            self.scalar(text, number)
+        }
+    }
+
+    fn scalar(&self, text: &str, number: usize) -> Result<serde_json::Value> {
+        if text.starts_with(['&', '*', '!']) {
+            return Err(Error {
+                line: number,
+                message: "anchors, aliases and tags are not supported".to_owned(),
+            });
+        }
+        Ok(resolve(text))
+    }
+
+    /// The block scalar of the header `header`, in a mapping at `indent`.
+    fn block_scalar(
+        &mut self,
+        header: &str,
+        indent: usize,
+        number: usize,
+    ) -> Result<serde_json::Value> {
+        let chomping = match &header[1..] {
+            "" => Chomping::Clip,
+            "-" => Chomping::Strip,
+            "+" => Chomping::Keep,
+            _ => {
+                return Err(Error {
+                    line: number,
+                    message: "unsupported block scalar header".to_owned(),
+                })
+            }
+        };
+        let mut body: Vec<&str> = Vec::new();
+        let mut content = None;
+        while let Some(line) = self.lines.get(self.pos) {
+            if line.raw.trim().is_empty() {
+                body.push("");
+            } else if line.indent <= indent {
+                break;
+            } else {
+                let content = *content.get_or_insert(line.indent);
+                if line.indent < content {
+                    return Err(line.error("block scalar less indented than its first line"));
+                }
+                body.push(&line.raw[content..]);
+            }
+            self.pos += 1;
+        }
+        let trailing = body.iter().rev().take_while(|l| l.is_empty()).count();
+        body.truncate(body.len() - trailing);
+        let mut value = String::new();
+        for (i, line) in body.iter().enumerate() {
+            if i > 0 {
+                let previous = body[i - 1];
+                let normal = |l: &str| !l.is_empty() && !l.starts_with(' ');
+                if !header.starts_with('>') {
+                    value.push('\n');
+                } else if normal(previous) && normal(line) {
+                    value.push(' ');
+                } else if !(normal(previous) && line.is_empty()) {
+                    value.push('\n');
+                }
+            }
+            value += line;
+        }
+        if !value.is_empty() {
+            match chomping {
+                Chomping::Strip => {}
+                Chomping::Clip => value.push('\n'),
+                Chomping::Keep => value += &"\n".repeat(trailing + 1),
+            }
+        } else if let Chomping::Keep = chomping {
+            value += &"\n".repeat(trailing);
+        }
+        Ok(serde_json::Value::String(value))
+    }
+}
+
+enum Chomping {
+    Clip,
+    Strip,
+    Keep,
+}
+
+/// Flow collections and their scalars, from `s[i..]`.
+struct Flow<'s> {
+    s: &'s str,
+    i: usize,
+}
+
+impl<'s> Flow<'s> {
+    fn rest(&self) -> &'s str {
+        &self.s[self.i..]
+    }
+
+    fn skip_spaces(&mut self) {
+        self.i = self.s.len() - self.rest().trim_start().len();
+    }
+
+    fn value(&mut self) -> ::std::result::Result<serde_json::Value, String> {
+        self.skip_spaces();
+        let rest = self.rest();
+        if rest.starts_with('[') {
+            self.i += 1;
+            let mut items = Vec::new();
+            loop {
+                self.skip_spaces();
+                if self.rest().starts_with(']') {
+                    self.i += 1;
+                    return Ok(serde_json::Value::Array(items));
+                }
+                items.push(self.value()?);
+                self.skip_spaces();
+                self.separator(']')?;
+            }
+        } else if rest.starts_with('{') {
+            self.i += 1;
+            let mut map = serde_json::Map::new();
+            loop {
+                self.skip_spaces();
+                if self.rest().starts_with('}') {
+                    self.i += 1;
+                    return Ok(serde_json::Value::Object(map));
+                }
+                let key = match self.scalar(true)? {
+                    serde_json::Value::String(s) => s,
+                    serde_json::Value::Null => "null".to_owned(),
+                    value => value.to_string(),
+                };
+                self.skip_spaces();
+                let value = if self.rest().starts_with(':') {
+                    self.i += 1;
+                    self.value()?
+                } else {
+                    serde_json::Value::Null
+                };
+                if map.insert(key, value).is_some() {
+                    return Err("duplicate key".to_owned());
+                }
+                self.skip_spaces();
+                self.separator('}')?;
+            }
+        } else {
+            self.scalar(false)
+        }
+    }
+
+    /// Skips the `,` after an item, but not the `end` of the collection.
+    fn separator(&mut self, end: char) -> ::std::result::Result<(), String> {
+        if self.rest().starts_with(',') {
+            self.i += 1;
+            Ok(())
+        } else if self.rest().starts_with(end) {
+            Ok(())
+        } else if self.rest().is_empty() {
+            Err("unterminated flow collection".to_owned())
+        } else {
+            Err(format!("expected `,` or `{}`", end))
+        }
+    }
+
+    /// A scalar ending at a flow indicator, or at `:` for keys.
+    fn scalar(&mut self, key: bool) -> ::std::result::Result<serde_json::Value, String> {
+        let rest = self.rest();
+        if rest.starts_with('"') || rest.starts_with('\'') {
+            let (value, len) = quoted(rest)?;
+            self.i += len;
+            return Ok(serde_json::Value::String(value));
+        }
+        if rest.starts_with(['&', '*', '!']) {
+            return Err("anchors, aliases and tags are not supported".to_owned());
+        }
+        let end = rest
+            .find(|c| c == ',' || c == '[' || c == ']' || c == '{' || c == '}' || key && c == ':')
+            .unwrap_or(rest.len());
+        self.i += end;
+        Ok(resolve(rest[..end].trim()))
+    }
+}
+
+/// Whether the text is a sequence entry.
+fn is_entry(text: &str) -> bool {
+    text == "-" || text.starts_with("- ")
+}
+
+/// The key and the rest of a mapping entry, if the text is one.
+fn split_key(text: &str) -> Option<(&str, &str)> {
+    let end = if text.starts_with('"') || text.starts_with('\'') {
+        quoted(text).ok()?.1
+    } else if text.starts_with('[') || text.starts_with('{') {
+        return None;
+    } else {
+        0
+    };
+    let colon = text[end..]
+        .match_indices(':')
+        .map(|(i, _)| end + i)
+        .find(|&i| text[i + 1..].is_empty() || text[i + 1..].starts_with(' '))?;
+    if end > 0 && text[end..colon].trim() != "" {
+        return None;
+    }
+    Some((text[..colon].trim_end(), text[colon + 1..].trim_start()))
+}
+
+/// The string of the quoted scalar starting `s`, with the length it takes.
+fn quoted(s: &str) -> ::std::result::Result<(String, usize), String> {
+    let quote = s.chars().next().unwrap();
+    let mut value = String::new();
+    let mut chars = s.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\'' if quote == '\'' => {
+                if s[i + 1..].starts_with('\'') {
+                    chars.next();
+                    value.push('\'');
+                } else {
+                    return Ok((value, i + 1));
+                }
+            }
+            '"' if quote == '"' => return Ok((value, i + 1)),
+            '\\' if quote == '"' => {
+                let escaped = match chars.next() {
+                    Some((_, c)) => c,
+                    None => break,
+                };
+                value.push(match escaped {
+                    '0' => '\0',
+                    'b' => '\u{8}',
+                    't' => '\t',
+                    'n' => '\n',
+                    'f' => '\u{c}',
+                    'r' => '\r',
+                    'e' => '\u{1b}',
+                    ' ' | '"' | '/' | '\\' => escaped,
+                    'x' | 'u' | 'U' => {
+                        let len = match escaped {
+                            'x' => 2,
+                            'u' => 4,
+                            _ => 8,
+                        };
+                        let digits: String =
+                            (0..len).filter_map(|_| chars.next()).map(|c| c.1).collect();
+                        let code = u32::from_str_radix(&digits, 16)
+                            .ok()
+                            .filter(|_| digits.len() == len)
+                            .ok_or_else(|| format!("malformed escape \\{}{}", escaped, digits))?;
+                        match code {
+                            // a surrogate pair, as JSON writes them
+                            0xd800..=0xdbff => {
+                                let low: String = chars.by_ref().take(6).map(|c| c.1).collect();
+                                low.strip_prefix("\\u")
+                                    .and_then(|l| u32::from_str_radix(l, 16).ok())
+                                    .filter(|l| (0xdc00..=0xdfff).contains(l))
+                                    .and_then(|l| {
+                                        ::std::char::from_u32(
+                                            0x10000 + ((code - 0xd800) << 10) + (l - 0xdc00),
+                                        )
+                                    })
+                                    .ok_or_else(|| "unpaired surrogate".to_owned())?
+                            }
+                            _ => ::std::char::from_u32(code)
+                                .ok_or_else(|| format!("invalid character {:x}", code))?,
+                        }
+                    }
+                    c => return Err(format!("unknown escape \\{}", c)),
+                });
+            }
+            c => value.push(c),
+        }
+    }
+    Err("unterminated string".to_owned())
+}
+
+/// Whether the brackets of a flow collection are all closed.
+fn is_closed(s: &str) -> bool {
+    let mut depth = 0;
+    let mut i = 0;
+    while i < s.len() {
+        let rest = &s[i..];
+        if rest.starts_with('"') || rest.starts_with('\'') {
+            match quoted(rest) {
+                Ok((_, len)) => i += len,
+                Err(_) => return false,
+            }
+            continue;
+        }
+        match rest.as_bytes()[0] {
+            b'[' | b'{' => depth += 1,
+            b']' | b'}' => depth -= 1,
+            _ => {}
+        }
+        i += rest.chars().next().unwrap().len_utf8();
+    }
+    depth <= 0
+}
+
+/// The text before the comment of a line, if any.
+fn strip_comment(text: &str) -> &str {
+    let mut i = 0;
+    let mut prev = ' ';
+    while i < text.len() {
+        let rest = &text[i..];
+        let c = rest.chars().next().unwrap();
+        if c == '#' && prev.is_whitespace() {
+            return &text[..i];
+        }
+        // quotes open scalars only where one can start
+        if (c == '"' || c == '\'') && " \t[{,:-".contains(prev) {
+            if let Ok((_, len)) = quoted(rest) {
+                i += len;
+                prev = c;
+                continue;
+            }
+        }
+        i += c.len_utf8();
+        prev = c;
+    }
+    text
+}
+
+/// The value of a plain scalar, by the core schema.
+fn resolve(text: &str) -> serde_json::Value {
+    match text {
+        "" | "~" | "null" | "Null" | "NULL" => return serde_json::Value::Null,
+        "true" | "True" | "TRUE" => return serde_json::Value::Bool(true),
+        "false" | "False" | "FALSE" => return serde_json::Value::Bool(false),
+        _ => {}
+    }
+    let unsigned = text.trim_start_matches(['-', '+']);
+    let numeric = unsigned.len() + 1 >= text.len()
+        && unsigned.starts_with(|c: char| c.is_ascii_digit() || c == '.')
+        && unsigned
+            .chars()
+            .all(|c| c.is_ascii_digit() || "._eE+-".contains(c));
+    if numeric {
+        if let Ok(n) = text.parse::<i64>() {
+            return n.into();
+        }
+        if let Ok(n) = text.parse::<u64>() {
+            return n.into();
+        }
+        if let Some(n) = text.parse().ok().and_then(serde_json::Number::from_f64) {
+            return serde_json::Value::Number(n);
+        }
+    }
+    let radix = |prefix: &str, radix: u32| {
+        text.strip_prefix(prefix)
+            .and_then(|digits| i64::from_str_radix(digits, radix).ok())
+    };
+    match radix("0x", 16).or_else(|| radix("0o", 8)) {
+        Some(n) => n.into(),
+        None => serde_json::Value::String(text.to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_blocks() {
+        let text = "\
+# a pipeline
+sources:
+  - http://192.168.0.10/video.mjpg   # the door
+  - 'frames/'
+detectors:
+- model: yolo_v2_voc0712.onnx
+  config: \"yolo_v2_voc0712.json\"
+  night: {model: night.onnx, after: 18}
+tracker:
+  iou: 0.3
+  ids: ~
+recording: {dir: clips, rules: [{classes: [person],
+  post: 10}]}
+";
+        assert_eq!(
+            parse(text),
+            Ok(json!({
+                "sources": ["http://192.168.0.10/video.mjpg", "frames/"],
+                "detectors": [{
+                    "model": "yolo_v2_voc0712.onnx",
+                    "config": "yolo_v2_voc0712.json",
+                    "night": {"model": "night.onnx", "after": 18},
+                }],
+                "tracker": {"iou": 0.3, "ids": null},
+                "recording": {"dir": "clips", "rules": [{"classes": ["person"], "post": 10}]},
+            }))
+        );
+    }
+
+    #[test]
+    fn parses_json() {
+        let text = r#"{"a": [1, -2.5e3, true, null], "b": {"c": "d\"\u00e9\ud83d\ude00"}}"#;
+        assert_eq!(
+            parse(text),
+            Ok(serde_json::from_str::<serde_json::Value>(text).unwrap())
+        );
+    }
+
+    #[test]
+    fn parses_scalars() {
+        let text = "\
+- 012
+- 0x1f
+- -.5
+- yes
+- it's # a comment
+- 'it''s'
+- \"a # b\"
+- a: b: c
+- literal: |
+    a
+      b
+
+  folded: >-
+    a
+    b
+
+    c
+- - nested
+";
+        assert_eq!(
+            parse(text),
+            Ok(json!([
+                12,
+                31,
+                -0.5,
+                "yes",
+                "it's",
+                "it's",
+                "a # b",
+                {"a": "b: c"},
+                {"literal": "a\n  b\n", "folded": "a b\nc"},
+                ["nested"],
+            ]))
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_syntax() {
+        let error = |text| parse(text).err().map(|e| (e.line, e.message));
+        assert_eq!(
+            error("a: &x 1\nb: *x"),
+            Some((1, "anchors, aliases and tags are not supported".to_owned()))
+        );
+        assert_eq!(error("a: 1\na: 2"), Some((2, "duplicate key".to_owned())));
+        assert_eq!(
+            error("a:\n  b: 1\n    c: 2"),
+            Some((3, "unexpected indentation".to_owned()))
+        );
+        assert_eq!(
+            error("a: [1, 2"),
+            Some((1, "unterminated flow collection".to_owned()))
+        );
+        assert_eq!(
+            error("a: 1\n---\nb: 2"),
+            Some((2, "several documents are not supported".to_owned()))
+        );
+        assert_eq!(error("---\na: 1\n...\n"), None);
+    }
+}
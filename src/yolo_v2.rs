@@ -2,27 +2,835 @@ use image;
 use menoh;
 use ndarray;
 use std::cmp;
+use std::collections;
+use std::fs;
+use std::io;
+use std::iter;
 use std::path;
+use std::time;
 
+use affinity;
+use backend;
 use bb;
+use color;
+use coords;
+use detector;
+use filter;
+use hook;
+use inputs;
+use labels;
+use memory;
+use nms;
+use onnx;
 use partial_cmp;
+use quality;
+use raw;
+use recorder;
+use remote;
+use scheduler;
+use severity;
+use sink;
+use summary;
+use tiling;
 
 use image::GenericImage;
 use model_ext::ModelExt;
-use rect::Rect;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 
 #[derive(Deserialize)]
 pub struct Config {
+    /// How the outputs are decoded. The other fields describe the input and
+    /// the labels, with `output` and `anchors` only used by YOLO.
+    #[serde(default)]
+    pub architecture: detector::Architecture,
     pub input: String,
+    #[serde(default)]
     pub output: String,
     pub insize: usize,
+    /// Sizes of the input of a model with dynamic axes, as a portrait and a
+    /// landscape one. Each image runs at the size that its aspect ratio fills
+    /// best, and batches at the best size of their first image. `insize` is
+    /// the only size when empty.
+    #[serde(default)]
+    pub input_sizes: Vec<InputSize>,
+    #[serde(default)]
     pub anchors: Vec<(f32, f32)>,
+    /// A list, a built-in set or a file (see `labels`).
+    #[serde(deserialize_with = "labels::deserialize")]
     pub label_names: Vec<String>,
+    /// Names shown instead of the labels in the drawings and the text
+    /// output, per language, as `{"ja": {"person": "人"}}` (`--lang`).
+    #[serde(default)]
+    pub display_names: collections::HashMap<String, collections::HashMap<String, String>>,
+    #[serde(flatten)]
+    pub params: Params,
+    /// Used when recording is enabled (`--record`).
+    #[serde(default)]
+    pub recording: Vec<recorder::Rule>,
+    #[serde(default)]
+    pub hooks: Vec<hook::Hook>,
+    /// Severities of the detections, carried into the results.
+    #[serde(default)]
+    pub severities: Vec<severity::Rule>,
+    /// Sentence describing the detections, for `--summary` and `{summary}`
+    /// in the hooks.
+    #[serde(default)]
+    pub summary: summary::Template,
+    #[serde(default)]
+    pub sinks: Vec<sink::Config>,
+    /// Longer edge, in pixels, that input images are scaled down to before
+    /// detection (see `downscale`).
+    #[serde(default)]
+    pub max_edge: Option<u32>,
+    #[serde(default = "default_class_activation")]
+    pub class_activation: ClassActivation,
+    /// Order of the location outputs of each cell, `yx` for `(y, x, h, w)`.
+    #[serde(default)]
+    pub cell_order: CellOrder,
+    /// Added to the centers of the boxes, in cells, for exports whose
+    /// centers are not offsets from the top left corner of their cell, e.g.
+    /// -0.5 for offsets from its center.
+    #[serde(default)]
+    pub cell_offset: f32,
+    /// The model has no class outputs, as some pruned or single-class models.
+    /// Boxes get the only label and the objectness as score.
+    #[serde(default)]
+    pub objectness_only: bool,
+    /// Further outputs at other scales, as in YOLOv3, after the one of
+    /// `output` and `anchors`. Boxes of all scales are merged before the
+    /// non-maximum suppression.
+    #[serde(default)]
+    pub scales: Vec<Scale>,
+    /// Encoding of the input, `linear` for networks trained on linear RGB.
+    #[serde(default)]
+    pub color_space: color::ColorSpace,
+    /// Filter resizing the images to the input.
+    #[serde(default)]
+    pub resize_filter: ResizeFilter,
+    /// Order of the dimensions of the input, `nhwc` for many TensorFlow
+    /// exports.
+    #[serde(default)]
+    pub layout: Layout,
+    /// Further inputs of the network, filled for every image.
+    #[serde(default)]
+    pub inputs: Vec<inputs::Input>,
+    /// Cores and priorities of the threads running the model.
+    #[serde(default)]
+    pub threads: affinity::Threads,
+    /// Detectors running the requests of `serve` side by side.
+    #[serde(default)]
+    pub pool: Option<scheduler::Config>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct InputSize {
+    pub height: usize,
+    pub width: usize,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Layout {
+    #[default]
+    Nchw,
+    Nhwc,
+}
+
+impl Layout {
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Nchw => "NCHW",
+            Layout::Nhwc => "NHWC",
+        }
+    }
+
+    /// Shape of a batch of `batch` images of `height * width` pixels.
+    pub fn dims(self, batch: usize, height: usize, width: usize) -> [usize; 4] {
+        match self {
+            Layout::Nchw => [batch, 3, height, width],
+            Layout::Nhwc => [batch, height, width, 3],
+        }
+    }
+
+    /// `(height, width)` of an image of shape `shape`, without the batch.
+    fn size(self, shape: &[usize]) -> (usize, usize) {
+        match self {
+            Layout::Nchw => {
+                assert_eq!(shape[0], 3);
+                (shape[1], shape[2])
+            }
+            Layout::Nhwc => {
+                assert_eq!(shape[2], 3);
+                (shape[0], shape[1])
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResizeFilter {
+    /// The fastest, sampling the pixels without copying the image.
+    #[default]
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Lanczos3,
+}
+
+impl ResizeFilter {
+    pub fn name(self) -> &'static str {
+        match self {
+            ResizeFilter::Nearest => "nearest",
+            ResizeFilter::Triangle => "triangle",
+            ResizeFilter::CatmullRom => "catmull_rom",
+            ResizeFilter::Lanczos3 => "lanczos3",
+        }
+    }
+
+    /// `None` for the nearest pixels, which are sampled directly.
+    fn filter_type(self) -> Option<image::FilterType> {
+        match self {
+            ResizeFilter::Nearest => None,
+            ResizeFilter::Triangle => Some(image::FilterType::Triangle),
+            ResizeFilter::CatmullRom => Some(image::FilterType::CatmullRom),
+            ResizeFilter::Lanczos3 => Some(image::FilterType::Lanczos3),
+        }
+    }
+}
+
+/// An output layer and its anchors, in cells of its own grid. The anchors of
+/// YOLOv3 are usually given in pixels, to divide by the stride of the layer.
+#[derive(Clone, Deserialize)]
+pub struct Scale {
+    pub output: String,
+    pub anchors: Vec<(f32, f32)>,
+}
+
+/// How class scores are computed from the class outputs. Either way, they are
+/// multiplied by the objectness.
+#[derive(Clone, Copy, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClassActivation {
+    /// Softmax over the classes, as in the original YOLOv2.
+    Softmax,
+    /// Independent sigmoid per class, for multi-label models.
+    Sigmoid,
+}
+
+fn default_class_activation() -> ClassActivation {
+    ClassActivation::Softmax
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CellOrder {
+    /// `(y, x, h, w)`, as in the original YOLOv2.
+    #[default]
+    Yx,
+    /// `(x, y, w, h)`.
+    Xy,
+}
+
+/// Post-processing parameters. Unlike the rest of `Config`, they can be
+/// changed on a built model with `YOLOv2::set_params`.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Params {
+    #[serde(default)]
+    pub thresholds: Thresholds,
+    #[serde(default)]
+    pub constraints: collections::HashMap<String, filter::Constraint>,
+    #[serde(default)]
+    pub border: filter::Border,
+    /// Labels to report. All labels are reported when omitted.
+    #[serde(default)]
+    pub classes: Option<Vec<String>>,
+    /// Score thresholds per label, replacing each of `thresholds` for the
+    /// boxes of the label, as well as the default `min_score` of the sinks
+    /// (see `Params::label_thresholds`).
+    #[serde(default)]
+    pub class_thresholds: collections::HashMap<String, f32>,
+    /// Corrections of the scores per label, applied as the boxes are
+    /// decoded, before the thresholds and the suppression.
+    #[serde(default)]
+    pub score_corrections: collections::HashMap<String, filter::ScoreCorrection>,
+    /// Boxes kept per label, highest scores first, after the other filters.
+    #[serde(default)]
+    pub max_per_class: Option<usize>,
+    /// Boxes kept in all, highest scores first, after `max_per_class`.
+    #[serde(default)]
+    pub max_detections: Option<usize>,
+    /// Records where each box was decoded from (see `bb::Provenance`).
+    #[serde(default)]
+    pub provenance: bool,
+    /// Seconds from the start of `predict` after which the remaining cells
+    /// are not decoded. Cells are decoded in descending objectness, so the
+    /// boxes found by then are the likeliest ones (see `YOLOv2::partial`).
+    /// The inference itself cannot be interrupted.
+    #[serde(default)]
+    pub deadline: Option<f32>,
+    #[serde(default)]
+    pub nms: Nms,
+    #[serde(default)]
+    pub nms_ties: Ties,
+    /// Images failing the gate are not run through the network, and have no
+    /// boxes (see `YOLOv2::low_quality`).
+    #[serde(default)]
+    pub quality: quality::Gate,
+    /// Snapping of the coordinates, once in the pixels of the image, so
+    /// that drawing and every exporter crop the same pixels.
+    #[serde(default)]
+    pub rounding: bb::Rounding,
+    /// Clips the boxes reaching past the image to it, before the other
+    /// filters (see `detector::Warning::Clipped`).
+    #[serde(default)]
+    pub clip: bool,
+    /// Raises the display threshold of the frames with too many boxes (see
+    /// `Params::adapt`).
+    #[serde(default)]
+    pub adaptive: Option<filter::Adaptive>,
+}
+
+impl Params {
+    /// The score of the boxes decoded, the lowest of the thresholds.
+    pub fn min_score(&self) -> f32 {
+        self.class_thresholds
+            .values()
+            .fold(self.thresholds.min(), |min, &t| min.min(t))
+    }
+
+    /// The threshold of each label of `label_names`, its class threshold or
+    /// `thresh`, one of `thresholds`, for the others.
+    pub fn label_thresholds(&self, thresh: f32, label_names: &[String]) -> Vec<f32> {
+        label_names
+            .iter()
+            .map(|l| self.class_thresholds.get(l).cloned().unwrap_or(thresh))
+            .collect()
+    }
+
+    /// The params with the filters of `detector::apply_filters` turned off,
+    /// for boxes filtered later, once merged or restored to the original
+    /// image.
+    pub fn without_filters(&self) -> Self {
+        Self {
+            constraints: collections::HashMap::new(),
+            border: filter::Border::default(),
+            classes: None,
+            max_per_class: None,
+            max_detections: None,
+            ..self.clone()
+        }
+    }
+
+    /// The score correction of each label of `label_names`.
+    pub fn corrections(&self, label_names: &[String]) -> Vec<filter::ScoreCorrection> {
+        label_names
+            .iter()
+            .map(|l| self.score_corrections.get(l).cloned().unwrap_or_default())
+            .collect()
+    }
+
+    /// Applies `adaptive` to the boxes of a whole image, returning the
+    /// effective display threshold when enabled. Raising it adds a warning
+    /// to `warnings`.
+    pub fn adapt(
+        &self,
+        bbox: &mut Vec<bb::Bb>,
+        warnings: &mut Vec<detector::Warning>,
+    ) -> Option<f32> {
+        let adaptive = self.adaptive.as_ref()?;
+        let from = self.thresholds.display;
+        let to = filter::adapt(bbox, adaptive, from);
+        if to > from {
+            warnings.push(detector::Warning::ThresholdAdjusted { from, to });
+        }
+        Some(to)
+    }
+}
+
+/// Suppression of the boxes overlapping a higher-scoring box of their label.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Nms {
+    /// Removes the boxes whose IoU with a kept box is above `iou`.
+    Greedy {
+        #[serde(default = "default_nms_iou")]
+        iou: f32,
+    },
+    /// Soft-NMS (Bodla et al., 2017): scores are multiplied by
+    /// `exp(-iou^2 / sigma)` instead, and boxes falling below the lowest
+    /// threshold are removed. Keeps more of heavily overlapping objects.
+    Soft {
+        #[serde(default = "default_nms_sigma")]
+        sigma: f32,
+    },
+}
+
+impl Default for Nms {
+    fn default() -> Self {
+        Nms::Greedy {
+            iou: default_nms_iou(),
+        }
+    }
+}
+
+/// Order of the boxes of nearly equal scores in the suppression, which
+/// otherwise follows the order of the cells.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Ties {
+    pub prefer: Prefer,
+    /// Scores are nearly equal within the same step of `margin`.
+    pub margin: f32,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Prefer {
+    Score,
+    Larger,
+    Smaller,
+}
+
+impl Default for Ties {
+    fn default() -> Self {
+        Self {
+            prefer: Prefer::Score,
+            margin: 0.01,
+        }
+    }
+}
+
+impl Ties {
+    /// Orders `a` before `b` if it is kept first.
+    pub fn cmp<B: nms::BoundingBox>(&self, a: &B, b: &B) -> cmp::Ordering {
+        let by_score = b
+            .score()
+            .partial_cmp(&a.score())
+            .unwrap_or(cmp::Ordering::Equal);
+        if self.prefer == Prefer::Score || self.margin <= 0. {
+            return by_score;
+        }
+        let step = |bb: &B| (bb.score() / self.margin).floor();
+        let by_area = match self.prefer {
+            Prefer::Larger => b.area().partial_cmp(&a.area()),
+            _ => a.area().partial_cmp(&b.area()),
+        };
+        step(b)
+            .partial_cmp(&step(a))
+            .unwrap_or(cmp::Ordering::Equal)
+            .then(by_area.unwrap_or(cmp::Ordering::Equal))
+            .then(by_score)
+    }
+}
+
+fn default_nms_iou() -> f32 {
+    0.45
+}
+
+fn default_nms_sigma() -> f32 {
+    0.5
+}
+
+/// Score thresholds of the consumers of the detections. `predict` decodes
+/// once with the lowest of them, and each consumer filters the result with its
+/// own threshold.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Thresholds {
+    pub track: f32,
+    pub display: f32,
+    pub alert: f32,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self {
+            track: 0.5,
+            display: 0.5,
+            alert: 0.5,
+        }
+    }
+}
+
+impl Thresholds {
+    pub fn min(&self) -> f32 {
+        self.track.min(self.display).min(self.alert)
+    }
+}
+
+/// Describes how `predict` turns an image into the input tensor, so that
+/// external pipelines can reproduce it exactly.
+#[derive(Serialize)]
+pub struct Preprocessing {
+    pub input: String,
+    pub dtype: &'static str,
+    pub layout: &'static str,
+    pub shape: [usize; 4],
+    pub channel_order: &'static str,
+    /// `linear` if the sRGB values are converted to linear light before the
+    /// normalization.
+    pub color_space: color::ColorSpace,
+    pub resize: Resize,
+    pub pad: Pad,
+    pub normalization: Normalization,
+}
+
+#[derive(Serialize)]
+pub struct Resize {
+    /// The image is scaled by `min(height / img_height, width / img_width)`,
+    /// keeping its aspect ratio. The longer side becomes exactly `height` or
+    /// `width`, and the other side is `floor(side * target / longer side)`.
+    pub mode: &'static str,
+    pub height: usize,
+    pub width: usize,
+    pub filter: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct Pad {
+    /// Value of the padded elements, after normalization.
+    pub value: f32,
+    /// The resized image is placed at `((height - h) / 2, (width - w) / 2)`
+    /// using integer division.
+    pub position: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct Normalization {
+    /// `(pixel * scale - mean) / std`
+    pub scale: f32,
+    pub mean: [f32; 3],
+    pub std: [f32; 3],
+}
+
+impl Config {
+    /// The variables of `inputs` for an image of `height * width` pixels.
+    pub fn input_image(&self, height: u32, width: u32, letterbox: Letterbox) -> inputs::Image {
+        inputs::Image {
+            height,
+            width,
+            input_height: letterbox.height,
+            input_width: letterbox.width,
+            scale: letterbox.scale,
+        }
+    }
+
+    /// The sizes of the input, `input_sizes` or `insize` by `insize`.
+    pub fn sizes(&self) -> Vec<InputSize> {
+        if self.input_sizes.is_empty() {
+            vec![InputSize {
+                height: self.insize,
+                width: self.insize,
+            }]
+        } else {
+            self.input_sizes.clone()
+        }
+    }
+
+    /// The first of `sizes`.
+    pub fn input_size(&self) -> InputSize {
+        self.sizes()[0]
+    }
+
+    /// The index in `sizes` of the size wasting the least of the input on
+    /// the padding of an image of `height * width` pixels, the first of
+    /// equal ones.
+    pub fn best_size(&self, height: u32, width: u32) -> usize {
+        let filled = |size: &InputSize| {
+            let (h, w) = (size.height as f32, size.width as f32);
+            let scale = partial_cmp::min(h / height as f32, w / width as f32).unwrap();
+            (height as f32 * scale) * (width as f32 * scale) / (h * w)
+        };
+        let sizes = self.sizes();
+        let mut best = 0;
+        for (i, size) in sizes.iter().enumerate().skip(1) {
+            if filled(size) > filled(&sizes[best]) {
+                best = i;
+            }
+        }
+        best
+    }
+
+    /// Shape of the input for `batch` images, at the first of `sizes`.
+    pub fn input_dims(&self, batch: usize) -> [usize; 4] {
+        let size = self.input_size();
+        self.layout.dims(batch, size.height, size.width)
+    }
+
+    /// Channels of the output expected from the anchors and the labels.
+    pub fn output_channels(&self) -> usize {
+        self.channels(self.anchors.len())
+    }
+
+    fn channels(&self, n_anchors: usize) -> usize {
+        n_anchors * (4 + 1 + self.n_class_outputs())
+    }
+
+    /// Sets `output`, when omitted, to the output of the graph with the
+    /// channels expected from the anchors and the labels. `outputs` are the
+    /// names and shapes of the outputs of the graph, with dynamic dimensions
+    /// as `-1`. Fails listing the candidates when none or several match.
+    pub fn find_output(&mut self, outputs: &[(String, Vec<i64>)]) -> Result<(), detector::Error> {
+        if !self.needs_output() {
+            return Ok(());
+        }
+        let channels = self.output_channels() as i64;
+        let grid = (self.insize / 32) as i64;
+        // the other heads of multi-scale models are named in `scales`
+        let outputs: Vec<_> = outputs
+            .iter()
+            .filter(|o| !self.scales.iter().any(|s| s.output == o.0))
+            .collect();
+        let mut candidates: Vec<_> = outputs
+            .iter()
+            .cloned()
+            .filter(|o| o.1.len() == 4 && o.1[1] == channels)
+            .collect();
+        if candidates.len() > 1 {
+            let coarsest: Vec<_> = candidates
+                .iter()
+                .cloned()
+                .filter(|o| o.1[2] == grid && o.1[3] == grid)
+                .collect();
+            if coarsest.len() == 1 {
+                candidates = coarsest;
+            }
+        }
+        // without a shape, the output is checked once the model is built
+        if candidates.is_empty() && outputs.len() == 1 && outputs[0].1.is_empty() {
+            candidates = outputs.clone();
+        }
+        match candidates.len() {
+            1 => {
+                self.output = candidates[0].0.clone();
+                Ok(())
+            }
+            0 => Err(detector::Error::Config(format!(
+                "no output has shape [_, {}, _, _] for {} anchors and {} labels; the outputs are {}",
+                channels,
+                self.anchors.len(),
+                self.label_names.len(),
+                describe_outputs(&outputs)
+            ))),
+            _ => Err(detector::Error::Config(format!(
+                "several outputs have shape [_, {}, _, _], set output to one of {}",
+                channels,
+                describe_outputs(&candidates)
+            ))),
+        }
+    }
+
+    /// `find_output` with the outputs of the ONNX model at `path`.
+    pub fn find_onnx_output<P>(&mut self, path: P) -> Result<(), detector::Error>
+    where
+        P: AsRef<path::Path>,
+    {
+        if !self.needs_output() {
+            return Ok(());
+        }
+        let outputs = onnx::outputs(path).map_err(backend_error)?;
+        self.find_output(&outputs)
+    }
+
+    /// `find_output` with the outputs in the metadata of the model served at
+    /// `url` (see `remote`).
+    pub fn find_remote_output(&mut self, url: &str) -> Result<(), detector::Error> {
+        if !self.needs_output() {
+            return Ok(());
+        }
+        let client = remote::Client::new(url, false).map_err(backend_error)?;
+        let outputs = client.outputs().map_err(backend_error)?;
+        self.find_output(&outputs)
+    }
+
+    fn needs_output(&self) -> bool {
+        self.output.is_empty() && matches!(self.architecture, detector::Architecture::Yolo)
+    }
+
+    /// The output layers with their anchors, `output` first.
+    pub fn outputs(&self) -> Vec<(&str, &[(f32, f32)])> {
+        iter::once((self.output.as_str(), &self.anchors[..]))
+            .chain(self.scales.iter().map(|s| (s.output.as_str(), &s.anchors[..])))
+            .collect()
+    }
+
+    /// The labels in `lang`, those without a name in `lang` as is. `None` if
+    /// the config has no names in `lang`.
+    pub fn display_names(&self, lang: &str) -> Option<Vec<String>> {
+        let names = self.display_names.get(lang)?;
+        Some(
+            self.label_names
+                .iter()
+                .map(|l| names.get(l).unwrap_or(l).clone())
+                .collect(),
+        )
+    }
+
+    pub fn n_class_outputs(&self) -> usize {
+        if self.objectness_only {
+            0
+        } else {
+            self.label_names.len()
+        }
+    }
+
+    pub fn preprocessing(&self) -> Preprocessing {
+        Preprocessing {
+            input: self.input.clone(),
+            dtype: "float32",
+            layout: self.layout.name(),
+            shape: self.input_dims(1),
+            channel_order: "RGB",
+            color_space: self.color_space,
+            resize: Resize {
+                mode: "letterbox",
+                height: self.input_size().height,
+                width: self.input_size().width,
+                filter: self.resize_filter.name(),
+            },
+            pad: Pad {
+                value: 0.5,
+                position: "center",
+            },
+            normalization: Normalization {
+                scale: 1. / 255.,
+                mean: [0., 0., 0.],
+                std: [1., 1., 1.],
+            },
+        }
+    }
+}
+
+/// Where `predict` placed the image in the input tensor. The network pixel
+/// of an image pixel `(x, y)` is `(x * scale + pad_x, y * scale + pad_y)`.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Letterbox {
+    pub scale: f32,
+    pub pad_x: usize,
+    pub pad_y: usize,
+    /// Size of the input tensor.
+    pub height: usize,
+    pub width: usize,
+}
+
+struct Remote {
+    client: remote::Client,
+    input: ndarray::ArrayD<f32>,
+    /// The values of `Config::inputs`.
+    aux: Vec<ndarray::ArrayD<f32>>,
+    outputs: Vec<(String, ndarray::ArrayD<f32>)>,
+}
+
+impl Remote {
+    fn output(&self, name: &str) -> Result<&ndarray::ArrayD<f32>, detector::Error> {
+        self.outputs
+            .iter()
+            .find(|o| o.0 == name)
+            .map(|o| &o.1)
+            .ok_or_else(|| {
+                detector::Error::Backend(menoh::Error::VariableNotFound(name.to_owned()))
+            })
+    }
+}
+
+enum Engine {
+    Menoh(menoh::Model),
+    Remote(Box<Remote>),
+}
+
+impl Engine {
+    fn dims(&self, name: &str, config: &Config) -> Result<Vec<usize>, detector::Error> {
+        match *self {
+            Engine::Menoh(ref model) => Ok(model.get_variable_dims(name)?),
+            Engine::Remote(ref r) if name == config.input => Ok(r.input.shape().to_vec()),
+            Engine::Remote(ref r) => r.output(name).map(|o| o.shape().to_vec()),
+        }
+    }
+
+    fn input<'b>(
+        &'b mut self,
+        config: &Config,
+    ) -> Result<ndarray::ArrayViewMutD<'b, f32>, detector::Error> {
+        match *self {
+            Engine::Menoh(ref mut model) => Ok(model.get_view_mut(&config.input)?),
+            Engine::Remote(ref mut r) => Ok(r.input.view_mut()),
+        }
+    }
+
+    /// Fills `Config::inputs` for the image `index` of the batch.
+    fn set_inputs(
+        &mut self,
+        config: &Config,
+        index: usize,
+        image: &inputs::Image,
+    ) -> Result<(), detector::Error> {
+        for (i, input) in config.inputs.iter().enumerate() {
+            let view = match *self {
+                Engine::Menoh(ref mut model) => model.get_view_mut(&input.name)?,
+                Engine::Remote(ref mut r) => r.aux[i].view_mut(),
+            };
+            input.fill(view, index, image);
+        }
+        Ok(())
+    }
+
+    fn run(&mut self, config: &Config) -> Result<(), detector::Error> {
+        match *self {
+            Engine::Menoh(ref mut model) => Ok(model.run()?),
+            Engine::Remote(ref mut r) => {
+                let names: Vec<_> = r.outputs.iter().map(|o| o.0.clone()).collect();
+                let names: Vec<_> = names.iter().map(|n| n.as_str()).collect();
+                let inputs: Vec<_> = iter::once((config.input.as_str(), r.input.view()))
+                    .chain(
+                        config
+                            .inputs
+                            .iter()
+                            .zip(r.aux.iter())
+                            .map(|(i, a)| (i.name.as_str(), a.view())),
+                    )
+                    .collect();
+                let outputs = r.client.infer(&inputs, &names).map_err(backend_error)?;
+                for (&mut (ref name, ref mut expected), output) in
+                    r.outputs.iter_mut().zip(outputs)
+                {
+                    if output.shape() != expected.shape() {
+                        return Err(detector::Error::Shape(format!(
+                            "output {} has shape {:?}, expected {:?}",
+                            name,
+                            output.shape(),
+                            expected.shape()
+                        )));
+                    }
+                    *expected = output;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn output<'b>(&'b self, name: &str) -> Result<ndarray::ArrayViewD<'b, f32>, detector::Error> {
+        match *self {
+            Engine::Menoh(ref model) => Ok(model.get_view(name)?),
+            Engine::Remote(ref r) => r.output(name).map(|o| o.view()),
+        }
+    }
 }
 
 pub struct YOLOv2<'a> {
-    model: menoh::Model,
+    /// One per size of the input (see `Config::sizes`).
+    engines: Vec<Engine>,
+    /// The engine of the last prediction.
+    current: usize,
     config: &'a Config,
+    params: Params,
+    weights_size: usize,
+    letterbox: Option<Letterbox>,
+    partial: bool,
+    low_quality: Option<quality::Issue>,
+    warnings: Vec<detector::Warning>,
+    batch: usize,
 }
 
 impl<'a> YOLOv2<'a> {
@@ -31,131 +839,852 @@ impl<'a> YOLOv2<'a> {
         config: &'a Config,
         backend: &str,
         backend_config: &str,
-    ) -> Result<Self, menoh::Error>
+    ) -> Result<Self, detector::Error>
+    where
+        P: AsRef<path::Path>,
+    {
+        Self::from_onnx_batch(path, config, backend, backend_config, 1)
+    }
+
+    /// `from_onnx` with an input of `batch` images, for `predict_batch`.
+    pub fn from_onnx_batch<P>(
+        path: P,
+        config: &'a Config,
+        backend: &str,
+        backend_config: &str,
+        batch: usize,
+    ) -> Result<Self, detector::Error>
     where
         P: AsRef<path::Path>,
     {
-        let model = menoh::Builder::from_onnx(path)?
-            .add_input::<f32>(&config.input, &[1, 3, config.insize, config.insize])?
-            .add_output(&config.output)?
-            .build(backend, backend_config)?;
-        Ok(Self { model, config })
+        let batch = batch.max(1);
+        let weights_size = fs::metadata(path.as_ref())
+            .map(|m| m.len() as usize)
+            .unwrap_or(0);
+        let backend_config = backend::validate(backend, backend_config)?;
+        let mut engines = Vec::new();
+        for size in config.sizes() {
+            let dims = config.layout.dims(batch, size.height, size.width);
+            let mut builder =
+                menoh::Builder::from_onnx(path.as_ref())?.add_input::<f32>(&config.input, &dims)?;
+            for input in config.inputs.iter() {
+                builder = builder.add_input::<f32>(&input.name, &input.shape(batch)?)?;
+            }
+            for (name, _) in config.outputs() {
+                builder = builder.add_output(name)?;
+            }
+            let model = builder
+                .build(backend, &backend_config)
+                .map_err(|e| with_graph_outputs(e, path.as_ref()))?;
+            for (name, anchors) in config.outputs() {
+                check_output_dims(config, name, anchors, &model.get_variable_dims(name)?)?;
+            }
+            engines.push(Engine::Menoh(model));
+        }
+        Ok(Self {
+            engines,
+            current: 0,
+            config,
+            params: config.params.clone(),
+            weights_size,
+            letterbox: None,
+            partial: false,
+            low_quality: None,
+            warnings: Vec::new(),
+            batch,
+        })
+    }
+
+    /// Runs the network on an inference server (see `remote`). The output
+    /// shapes are taken from the model metadata, with a batch of 1.
+    pub fn from_remote(
+        url: &str,
+        config: &'a Config,
+        binary: bool,
+    ) -> Result<Self, detector::Error> {
+        if config.input_sizes.len() > 1 {
+            return Err(detector::Error::Config(
+                "remote inference supports a single input size".to_owned(),
+            ));
+        }
+        let client = remote::Client::new(url, binary).map_err(backend_error)?;
+        let mut outputs = Vec::new();
+        for (name, anchors) in config.outputs() {
+            let shape = client.shape(name).map_err(backend_error)?;
+            // a dynamic batch is that of the requests, the others are unknown
+            // until the first response
+            if shape.iter().skip(1).any(|&d| d < 0) {
+                return Err(detector::Error::Shape(format!(
+                    "output {} has the dynamic shape {:?} in the model metadata; \
+                     only the batch dimension may be dynamic",
+                    name, shape
+                )));
+            }
+            let dims: Vec<_> = shape.iter().map(|&d| d.max(1) as usize).collect();
+            check_output_dims(config, name, anchors, &dims)?;
+            outputs.push((name.to_owned(), ndarray::ArrayD::zeros(dims)));
+        }
+        Ok(Self {
+            engines: vec![Engine::Remote(Box::new(Remote {
+                client,
+                input: ndarray::ArrayD::zeros(config.input_dims(1).to_vec()),
+                aux: config
+                    .inputs
+                    .iter()
+                    .map(|i| i.shape(1).map(ndarray::ArrayD::zeros))
+                    .collect::<Result<_, _>>()?,
+                outputs,
+            }))],
+            current: 0,
+            config,
+            params: config.params.clone(),
+            weights_size: 0,
+            letterbox: None,
+            partial: false,
+            low_quality: None,
+            warnings: Vec::new(),
+            batch: 1,
+        })
+    }
+
+    pub fn config(&self) -> &'a Config {
+        self.config
+    }
+
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
+    pub fn set_params(&mut self, params: Params) {
+        self.params = params;
+    }
+
+    /// The letterbox of the last prediction.
+    pub fn letterbox(&self) -> Option<Letterbox> {
+        self.letterbox
+    }
+
+    /// Images per run of the network.
+    pub fn batch(&self) -> usize {
+        self.batch
+    }
+
+    /// Whether the last prediction hit the deadline before decoding every
+    /// cell.
+    pub fn partial(&self) -> bool {
+        self.partial
+    }
+
+    /// The issue of the last image if it failed the quality gate of the
+    /// params.
+    pub fn low_quality(&self) -> Option<quality::Issue> {
+        self.low_quality
     }
 
-    pub fn predict(&mut self, img: &image::DynamicImage) -> Result<Vec<bb::Bb>, menoh::Error> {
-        let scale = set_image(
-            self.model
-                .get_view_mut(&self.config.input)?
-                .subview_mut(ndarray::Axis(0), 0),
-            img,
-        );
+    /// The issues of the last prediction.
+    pub fn warnings(&self) -> &[detector::Warning] {
+        &self.warnings
+    }
+
+    /// The coordinate transform of the last prediction.
+    pub fn transform(&self) -> Option<coords::CoordTransform> {
+        self.letterbox
+            .map(|l| coords::CoordTransform::new(l, l.height, l.width))
+    }
+
+    pub fn output_dims(&self) -> Result<Vec<usize>, detector::Error> {
+        self.engines[self.current].dims(&self.config.output, self.config)
+    }
+
+    /// The dims of every output, in the order of `Config::outputs`.
+    pub fn all_output_dims(&self) -> Result<Vec<Vec<usize>>, detector::Error> {
+        self.config
+            .outputs()
+            .into_iter()
+            .map(|(name, _)| self.engines[self.current].dims(name, self.config))
+            .collect()
+    }
+
+    pub fn memory_usage(&self) -> Result<memory::Usage, detector::Error> {
+        let mut buffers = 0;
+        let outputs = self.config.outputs();
+        let names = iter::once(self.config.input.as_str()).chain(outputs.iter().map(|o| o.0));
+        for engine in self.engines.iter() {
+            for name in names.clone() {
+                let dims = engine.dims(name, self.config)?;
+                buffers += dims.iter().product::<usize>() * 4;
+            }
+        }
+        Ok(memory::Usage {
+            // each engine has its own copy of the weights
+            weights: self.weights_size * self.engines.len(),
+            buffers,
+            queues: 0,
+        })
+    }
+
+    pub fn predict(&mut self, img: &image::DynamicImage) -> Result<Vec<bb::Bb>, detector::Error> {
+        Ok(self.predict_all(&[img])?.pop().unwrap())
+    }
+
+    /// `predict` on each of `imgs`, running the network once per `batch`
+    /// images. The letterbox is then the one of the last image, and the
+    /// prediction is partial if any image hit the deadline.
+    pub fn predict_batch(
+        &mut self,
+        imgs: &[image::DynamicImage],
+    ) -> Result<Vec<Vec<bb::Bb>>, detector::Error> {
+        let imgs: Vec<_> = imgs.iter().collect();
+        let mut bboxes = Vec::with_capacity(imgs.len());
+        let mut partial = false;
+        let mut warnings = Vec::new();
+        for chunk in imgs.chunks(self.batch) {
+            bboxes.extend(self.predict_all(chunk)?);
+            partial |= self.partial;
+            warnings.append(&mut self.warnings);
+        }
+        self.partial = partial;
+        self.warnings = warnings;
+        Ok(bboxes)
+    }
+
+    /// `predict` on overlapping crops of `img` of `tile_size` pixels, run
+    /// through `predict_batch` (see `tiling`).
+    pub fn predict_tiled(
+        &mut self,
+        img: &image::DynamicImage,
+        tile_size: u32,
+        overlap: u32,
+    ) -> Result<Vec<bb::Bb>, detector::Error> {
+        tiling::predict(self, img, tile_size, overlap)
+    }
+
+    /// `predict` on a raw frame (see `raw`).
+    pub fn predict_raw(&mut self, frame: &raw::Frame) -> Result<Vec<bb::Bb>, detector::Error> {
+        self.low_quality = self.params.quality.check(frame);
+        self.warnings.clear();
+        if self.low_quality.is_some() {
+            self.partial = false;
+            return Ok(Vec::new());
+        }
+        let deadline = self.deadline();
+        self.current = self.config.best_size(frame.height(), frame.width());
+        let engine = &mut self.engines[self.current];
+        let letterbox = {
+            let mut input = engine.input(self.config)?;
+            input.fill(0.5);
+            set_raw(input.subview_mut(ndarray::Axis(0), 0), frame, self.config)
+        };
+        self.letterbox = Some(letterbox);
+        let image = self.config.input_image(frame.height(), frame.width(), letterbox);
+        engine.set_inputs(self.config, 0, &image)?;
 
-        self.model.run()?;
+        engine.run(self.config)?;
 
-        let mut bbox = decode(
-            self.model
-                .get_view(&self.config.output)?
-                .subview(ndarray::Axis(0), 0),
-            &self.config.anchors,
-            self.config.label_names.len(),
-            0.5,
-        );
-        suppress(&mut bbox, 0.45);
+        let decoded = self.postprocess(0, frame, letterbox, deadline)?;
+        self.partial = decoded.partial;
+        self.warnings = decoded.warnings;
+        Ok(decoded.bbox)
+    }
+
+    fn deadline(&self) -> Option<time::Instant> {
+        self.params.deadline.map(|secs| {
+            time::Instant::now() + time::Duration::from_millis((secs.max(0.) * 1000.) as u64)
+        })
+    }
+
+    fn predict_all(
+        &mut self,
+        imgs: &[&image::DynamicImage],
+    ) -> Result<Vec<Vec<bb::Bb>>, detector::Error> {
+        let issues: Vec<_> = imgs
+            .iter()
+            .map(|img| self.params.quality.check(*img))
+            .collect();
+        self.low_quality = issues.last().cloned().unwrap_or(None);
+        let passed: Vec<_> = imgs
+            .iter()
+            .zip(issues.iter())
+            .filter(|&(_, issue)| issue.is_none())
+            .map(|(img, _)| *img)
+            .collect();
+        let mut bboxes = self.run_all(&passed)?.into_iter();
+        Ok(issues
+            .iter()
+            .map(|issue| match *issue {
+                Some(_) => Vec::new(),
+                None => bboxes.next().unwrap(),
+            })
+            .collect())
+    }
+
+    fn run_all(
+        &mut self,
+        imgs: &[&image::DynamicImage],
+    ) -> Result<Vec<Vec<bb::Bb>>, detector::Error> {
+        self.partial = false;
+        self.warnings.clear();
+        if imgs.is_empty() {
+            return Ok(Vec::new());
+        }
+        let deadline = self.deadline();
+        self.current = self.config.best_size(imgs[0].height(), imgs[0].width());
+        let engine = &mut self.engines[self.current];
+        let mut letterboxes = Vec::with_capacity(imgs.len());
+        {
+            let mut input = engine.input(self.config)?;
+            // unused slots of the last batch
+            input.fill(0.5);
+            for (i, img) in imgs.iter().enumerate() {
+                letterboxes.push(set_image(
+                    input.subview_mut(ndarray::Axis(0), i),
+                    img,
+                    self.config,
+                ));
+            }
+        }
+        self.letterbox = letterboxes.last().cloned();
+        for (i, (img, letterbox)) in imgs.iter().zip(letterboxes.iter()).enumerate() {
+            let image = self.config.input_image(img.height(), img.width(), *letterbox);
+            engine.set_inputs(self.config, i, &image)?;
+        }
 
-        let scale = self.config.insize as f32 / scale;
-        for bb in bbox.iter_mut() {
-            bb.top = (bb.top - 0.5) * scale + img.height() as f32 / 2.;
-            bb.left = (bb.left - 0.5) * scale + img.width() as f32 / 2.;
-            bb.bottom = (bb.bottom - 0.5) * scale + img.height() as f32 / 2.;
-            bb.right = (bb.right - 0.5) * scale + img.width() as f32 / 2.;
+        engine.run(self.config)?;
+
+        let mut bboxes = Vec::with_capacity(imgs.len());
+        for (i, (img, letterbox)) in imgs.iter().zip(letterboxes).enumerate() {
+            let decoded = self.postprocess(i, *img, letterbox, deadline)?;
+            self.partial |= decoded.partial;
+            self.warnings.extend(decoded.warnings);
+            bboxes.push(decoded.bbox);
         }
+        Ok(bboxes)
+    }
+
+    fn postprocess<I>(
+        &self,
+        index: usize,
+        img: &I,
+        letterbox: Letterbox,
+        deadline: Option<time::Instant>,
+    ) -> Result<Decoded, detector::Error>
+    where
+        I: quality::Luma + ?Sized,
+    {
+        let outputs = self
+            .config
+            .outputs()
+            .into_iter()
+            .map(|(name, _)| self.engines[self.current].output(name))
+            .collect::<Result<Vec<_>, _>>()?;
+        decode_outputs(&outputs, index, self.config, &self.params, img, letterbox, deadline)
+    }
 
-        Ok(bbox)
+    /// Runs the network on an input of `preprocess`, returning the outputs in
+    /// the order of `Config::outputs`, for `decode_outputs` apart from the
+    /// model (see `overlap`). The input runs on the engine of its size.
+    pub fn infer(
+        &mut self,
+        input: &ndarray::ArrayD<f32>,
+        image: &inputs::Image,
+    ) -> Result<Vec<ndarray::ArrayD<f32>>, detector::Error> {
+        let shape = &input.shape()[1..];
+        let config = self.config;
+        self.current = config
+            .sizes()
+            .iter()
+            .position(|size| config.layout.dims(1, size.height, size.width)[1..] == *shape)
+            .ok_or_else(|| {
+                detector::Error::Shape(format!("no input size for an input of {:?}", shape))
+            })?;
+        let engine = &mut self.engines[self.current];
+        {
+            let mut view = engine.input(config)?;
+            view.fill(0.5);
+            view.subview_mut(ndarray::Axis(0), 0)
+                .assign(&input.subview(ndarray::Axis(0), 0));
+        }
+        engine.set_inputs(config, 0, image)?;
+        engine.run(config)?;
+        config
+            .outputs()
+            .into_iter()
+            .map(|(name, _)| Ok(engine.output(name)?.to_owned()))
+            .collect()
     }
 }
 
-fn set_image(mut in_: ndarray::ArrayViewMutD<f32>, img: &image::DynamicImage) -> f32 {
-    assert_eq!(in_.shape()[0], 3);
+impl<'a> detector::Detector for YOLOv2<'a> {
+    fn predict(&mut self, img: &image::DynamicImage) -> Result<Vec<bb::Bb>, detector::Error> {
+        YOLOv2::predict(self, img)
+    }
+
+    fn predict_raw(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        format: raw::PixelFormat,
+    ) -> Result<Vec<bb::Bb>, detector::Error> {
+        YOLOv2::predict_raw(self, &raw::Frame::new(data, width, height, format)?)
+    }
 
-    let (in_h, in_w) = (in_.shape()[1], in_.shape()[2]);
+    fn predict_batch(
+        &mut self,
+        imgs: &[image::DynamicImage],
+    ) -> Result<Vec<Vec<bb::Bb>>, detector::Error> {
+        YOLOv2::predict_batch(self, imgs)
+    }
+
+    fn config(&self) -> &Config {
+        self.config
+    }
+
+    fn params(&self) -> &Params {
+        &self.params
+    }
+
+    fn set_params(&mut self, params: Params) {
+        self.params = params;
+    }
+
+    fn memory_usage(&self) -> Result<memory::Usage, detector::Error> {
+        YOLOv2::memory_usage(self)
+    }
+
+    fn partial(&self) -> bool {
+        self.partial
+    }
+
+    fn low_quality(&self) -> Option<quality::Issue> {
+        self.low_quality
+    }
+
+    fn warnings(&self) -> &[detector::Warning] {
+        &self.warnings
+    }
+}
+
+fn check_output_dims(
+    config: &Config,
+    name: &str,
+    anchors: &[(f32, f32)],
+    dims: &[usize],
+) -> Result<(), detector::Error> {
+    let channels = config.channels(anchors.len());
+    if dims.len() != 4 || dims[1] != channels {
+        return Err(detector::Error::Shape(format!(
+            "output {} has shape {:?}, expected [1, {}, _, _] for {} anchors and {} labels",
+            name,
+            dims,
+            channels,
+            anchors.len(),
+            config.label_names.len()
+        )));
+    }
+    Ok(())
+}
+
+fn describe_outputs(outputs: &[&(String, Vec<i64>)]) -> String {
+    let described: Vec<_> = outputs
+        .iter()
+        .map(|o| {
+            if o.1.is_empty() {
+                o.0.clone()
+            } else {
+                format!("{} {:?}", o.0, o.1)
+            }
+        })
+        .collect();
+    described.join(", ")
+}
+
+/// Lists the outputs of the graph in the errors of missing outputs, which are
+/// mostly misspelled names.
+fn with_graph_outputs(e: menoh::Error, path: &path::Path) -> menoh::Error {
+    let message = match e {
+        menoh::Error::VariableNotFound(ref m) | menoh::Error::OutputNotFoundError(ref m) => m,
+        _ => return e,
+    };
+    match onnx::outputs(path) {
+        Ok(outputs) => menoh::Error::OutputNotFoundError(format!(
+            "{}; the outputs of the graph are {}",
+            message,
+            describe_outputs(&outputs.iter().collect::<Vec<_>>())
+        )),
+        Err(_) => e,
+    }
+}
+
+fn backend_error(e: io::Error) -> menoh::Error {
+    menoh::Error::BackendError(e.to_string())
+}
+
+/// Letterboxes `img` into `in_`, of shape `[3, height, width]`, or
+/// `[height, width, 3]` with the `nhwc` layout (see `Config::preprocessing`).
+pub fn set_image(
+    in_: ndarray::ArrayViewMutD<f32>,
+    img: &image::DynamicImage,
+    config: &Config,
+) -> Letterbox {
+    let (in_h, in_w) = config.layout.size(in_.shape());
+    let (letterbox, (h, w)) = place((in_h, in_w), img.height() as usize, img.width() as usize);
+    let converted;
+    let buf = match config.resize_filter.filter_type() {
+        None => match *img {
+            image::ImageRgb8(ref buf) => buf,
+            _ => {
+                converted = img.to_rgb();
+                &converted
+            }
+        },
+        Some(filter) => {
+            converted = img.resize_exact(w as _, h as _, filter).to_rgb();
+            &converted
+        }
+    };
+    let frame = raw::Frame::new(buf, buf.width(), buf.height(), raw::PixelFormat::Rgb).unwrap();
+    fill(in_, config.layout, &frame, letterbox, (h, w), &config.color_space.table());
+    letterbox
+}
+
+/// `set_image` into a new input of a single image, at the best of the input
+/// sizes for it as `predict`, for preprocessing apart from the model (see
+/// `YOLOv2::infer`).
+pub fn preprocess(img: &image::DynamicImage, config: &Config) -> (ndarray::ArrayD<f32>, Letterbox) {
+    let size = config.sizes()[config.best_size(img.height(), img.width())];
+    let dims = config.layout.dims(1, size.height, size.width);
+    let mut input = ndarray::ArrayD::from_elem(dims.to_vec(), 0.5);
+    let letterbox = set_image(input.subview_mut(ndarray::Axis(0), 0), img, config);
+    (input, letterbox)
+}
+
+/// Renders the input of `img` back into an image, with the values of the
+/// tensor scaled from [0, 1] to [0, 255] as they are (in linear light with the
+/// `linear` color space), and the padding around the letterboxed image
+/// hatched in magenta.
+pub fn render_input(img: &image::DynamicImage, config: &Config) -> image::RgbImage {
+    let (input, letterbox) = preprocess(img, config);
+    let (in_h, in_w) = config.layout.size(&input.shape()[1..]);
+    let (_, (h, w)) = place((in_h, in_w), img.height() as usize, img.width() as usize);
+    let values = input.as_slice().unwrap();
+    let value = |x: usize, y: usize, c: usize| match config.layout {
+        Layout::Nchw => values[(c * in_h + y) * in_w + x],
+        Layout::Nhwc => values[(y * in_w + x) * 3 + c],
+    };
+    image::RgbImage::from_fn(in_w as u32, in_h as u32, |x, y| {
+        let (x, y) = (x as usize, y as usize);
+        let padded = x < letterbox.pad_x || x >= letterbox.pad_x + w
+            || y < letterbox.pad_y || y >= letterbox.pad_y + h;
+        if padded && (x + y) % 8 < 2 {
+            return image::Rgb([255, 0, 255]);
+        }
+        let channel = |c| (value(x, y, c).clamp(0., 1.) * 255.).round() as u8;
+        image::Rgb([channel(0), channel(1), channel(2)])
+    })
+}
+
+/// The boxes of an image, decoded by `decode_outputs`.
+pub struct Decoded {
+    pub bbox: Vec<bb::Bb>,
+    /// Whether the deadline was hit.
+    pub partial: bool,
+    pub warnings: Vec<detector::Warning>,
+}
+
+/// Decodes the image `index` of the outputs of the network, given in the
+/// order of `Config::outputs`, into the boxes of `img`.
+pub fn decode_outputs<I>(
+    outputs: &[ndarray::ArrayViewD<f32>],
+    index: usize,
+    config: &Config,
+    params: &Params,
+    img: &I,
+    letterbox: Letterbox,
+    deadline: Option<time::Instant>,
+) -> Result<Decoded, detector::Error>
+where
+    I: quality::Luma + ?Sized,
+{
+    let (mut bbox, partial) = decode(outputs, index, config, params, deadline)?;
+    nms::suppress(&mut bbox, params.nms, params.nms_ties, params.min_score());
+    let warnings = detector::finish(&mut bbox, config, params, img, letterbox);
+    Ok(Decoded {
+        bbox,
+        partial,
+        warnings,
+    })
+}
+
+/// `set_image` on a raw frame. The frame is only copied by the filters other
+/// than `nearest`, which resize it as an image.
+pub fn set_raw(
+    in_: ndarray::ArrayViewMutD<f32>,
+    frame: &raw::Frame,
+    config: &Config,
+) -> Letterbox {
+    let (in_h, in_w) = config.layout.size(in_.shape());
+    let (letterbox, (h, w)) = place((in_h, in_w), frame.height() as usize, frame.width() as usize);
+    let resized;
+    let frame = match config.resize_filter.filter_type() {
+        None => *frame,
+        Some(filter) => {
+            let img = image::ImageBuffer::from_fn(frame.width(), frame.height(), |x, y| {
+                image::Rgb(frame.rgb(x, y))
+            });
+            resized = image::imageops::resize(&img, w as _, h as _, filter);
+            raw::Frame::new(&resized, w as _, h as _, raw::PixelFormat::Rgb).unwrap()
+        }
+    };
+    fill(in_, config.layout, &frame, letterbox, (h, w), &config.color_space.table());
+    letterbox
+}
+
+/// Returns where an image of `src_h * src_w` pixels goes in an input of
+/// `in_h * in_w`, and its size there, as `image::DynamicImage::resize`.
+fn place((in_h, in_w): (usize, usize), src_h: usize, src_w: usize) -> (Letterbox, (usize, usize)) {
     let scale = partial_cmp::min(
-        (in_h as f32) / (img.height() as f32),
-        (in_w as f32) / (img.width() as f32),
+        (in_h as f32) / (src_h as f32),
+        (in_w as f32) / (src_w as f32),
     ).unwrap();
-    let img = img.resize(in_h as _, in_w as _, image::FilterType::Nearest);
-    let (h, w) = (img.height() as usize, img.width() as usize);
+    let (h, w) = if in_w * src_h <= src_w * in_h {
+        (src_h * in_w / src_w, in_w)
+    } else {
+        (in_h, src_w * in_h / src_h)
+    };
+    let letterbox = Letterbox {
+        scale,
+        pad_x: (in_w - w) / 2,
+        pad_y: (in_h - h) / 2,
+        height: in_h,
+        width: in_w,
+    };
+    (letterbox, (h, w))
+}
 
-    in_.fill(0.5);
-    for c in 0..3 {
-        for y in 0..h {
-            for x in 0..w {
-                in_[[c, y + (in_h - h) / 2, x + (in_w - w) / 2]] =
-                    (img.get_pixel(x as _, y as _).data[c] as f32) / 255.;
-            }
+/// Samples the nearest pixels of `frame`, resized to `h * w`, into `in_`,
+/// padding the rest.
+fn fill(
+    mut in_: ndarray::ArrayViewMutD<f32>,
+    layout: Layout,
+    frame: &raw::Frame,
+    letterbox: Letterbox,
+    size: (usize, usize),
+    table: &[f32; 256],
+) {
+    let (_, in_w) = layout.size(in_.shape());
+    let fill_slice = match layout {
+        Layout::Nchw => fill_planes,
+        Layout::Nhwc => fill_pixels,
+    };
+    match in_.as_slice_mut() {
+        Some(input) => fill_slice(input, in_w, frame, letterbox, size, table),
+        None => {
+            let mut input = ndarray::ArrayD::zeros(in_.shape());
+            fill_slice(input.as_slice_mut().unwrap(), in_w, frame, letterbox, size, table);
+            in_.assign(&input);
         }
     }
+}
+
+/// The columns of the frame sampled by the `w` columns of the resized image.
+fn sample_columns(frame: &raw::Frame, w: usize) -> Vec<u32> {
+    let src_w = frame.width() as usize;
+    (0..w).map(|x| ((x * 2 + 1) * src_w / (w * 2)) as u32).collect()
+}
 
-    scale
+/// The row of the frame sampled by the row `y` of the resized image of `h`
+/// rows.
+fn sample_row(frame: &raw::Frame, y: usize, h: usize) -> u32 {
+    ((y * 2 + 1) * frame.height() as usize / (h * 2)) as u32
 }
 
+fn fill_planes(
+    input: &mut [f32],
+    in_w: usize,
+    frame: &raw::Frame,
+    letterbox: Letterbox,
+    (h, w): (usize, usize),
+    table: &[f32; 256],
+) {
+    let Letterbox { pad_x, pad_y, .. } = letterbox;
+    let xs = sample_columns(frame, w);
+
+    let plane = input.len() / 3;
+    let (r, rest) = input.split_at_mut(plane);
+    let (g, b) = rest.split_at_mut(plane);
+    let row = |y: usize, r: &mut [f32], g: &mut [f32], b: &mut [f32]| {
+        if y < pad_y || y >= pad_y + h {
+            for row in [r, g, b] {
+                row.fill(0.5);
+            }
+            return;
+        }
+        for row in [&mut *r, &mut *g, &mut *b] {
+            row[..pad_x].fill(0.5);
+            row[pad_x + w..].fill(0.5);
+        }
+        let src_y = sample_row(frame, y - pad_y, h);
+        for (i, &src_x) in xs.iter().enumerate() {
+            let [pr, pg, pb] = frame.rgb(src_x, src_y);
+            r[pad_x + i] = table[pr as usize];
+            g[pad_x + i] = table[pg as usize];
+            b[pad_x + i] = table[pb as usize];
+        }
+    };
+    #[cfg(feature = "rayon")]
+    r.par_chunks_mut(in_w)
+        .zip(g.par_chunks_mut(in_w))
+        .zip(b.par_chunks_mut(in_w))
+        .enumerate()
+        .for_each(|(y, ((r, g), b))| row(y, r, g, b));
+    #[cfg(not(feature = "rayon"))]
+    r.chunks_mut(in_w)
+        .zip(g.chunks_mut(in_w))
+        .zip(b.chunks_mut(in_w))
+        .enumerate()
+        .for_each(|(y, ((r, g), b))| row(y, r, g, b));
+}
+
+fn fill_pixels(
+    input: &mut [f32],
+    in_w: usize,
+    frame: &raw::Frame,
+    letterbox: Letterbox,
+    (h, w): (usize, usize),
+    table: &[f32; 256],
+) {
+    let Letterbox { pad_x, pad_y, .. } = letterbox;
+    let xs = sample_columns(frame, w);
+
+    let row = |y: usize, row: &mut [f32]| {
+        if y < pad_y || y >= pad_y + h {
+            row.fill(0.5);
+            return;
+        }
+        row[..pad_x * 3].fill(0.5);
+        row[(pad_x + w) * 3..].fill(0.5);
+        let src_y = sample_row(frame, y - pad_y, h);
+        let pixels = row[pad_x * 3..(pad_x + w) * 3].chunks_mut(3);
+        for (p, &src_x) in pixels.zip(xs.iter()) {
+            for (v, &c) in p.iter_mut().zip(frame.rgb(src_x, src_y).iter()) {
+                *v = table[c as usize];
+            }
+        }
+    };
+    #[cfg(feature = "rayon")]
+    input
+        .par_chunks_mut(in_w * 3)
+        .enumerate()
+        .for_each(|(y, r)| row(y, r));
+    #[cfg(not(feature = "rayon"))]
+    input
+        .chunks_mut(in_w * 3)
+        .enumerate()
+        .for_each(|(y, r)| row(y, r));
+}
+
+/// Decodes the cells of all scales for the image `index` of the batch, in
+/// descending objectness, until `deadline`. Returns the boxes and whether the
+/// deadline was hit.
 fn decode(
-    out: ndarray::ArrayViewD<f32>,
-    anchors: &[(f32, f32)],
-    n_fg_class: usize,
-    thresh: f32,
-) -> Vec<bb::Bb> {
-    let (out_h, out_w) = (out.shape()[1], out.shape()[2]);
-    let out = out
-        .into_shape((anchors.len(), 4 + 1 + n_fg_class, out_h, out_w))
-        .unwrap();
+    views: &[ndarray::ArrayViewD<f32>],
+    index: usize,
+    config: &Config,
+    params: &Params,
+    deadline: Option<time::Instant>,
+) -> Result<(Vec<bb::Bb>, bool), detector::Error> {
+    let n_class_outputs = config.n_class_outputs();
+    let mut outputs = Vec::new();
+    for (out, (name, anchors)) in views.iter().zip(config.outputs()) {
+        let (out_h, out_w) = (out.shape()[2], out.shape()[3]);
+        let out = out
+            .view()
+            .into_subview(ndarray::Axis(0), index)
+            .into_shape((anchors.len(), 4 + 1 + n_class_outputs, out_h, out_w))
+            .map_err(|e| detector::Error::Shape(format!("output {}: {}", name, e)))?;
+        outputs.push((out, anchors));
+    }
+
+    // (objectness, scale, anchor, y, x), the objectness before the sigmoid
+    // being enough to order the cells
+    let mut cells = Vec::new();
+    for (scale, &(ref out, anchors)) in outputs.iter().enumerate() {
+        for a in 0..anchors.len() {
+            for ((y, x), &obj) in out.slice(s![a, 4, .., ..]).indexed_iter() {
+                cells.push((obj, scale, a, y, x));
+            }
+        }
+    }
+    if deadline.is_some() {
+        cells.sort_unstable_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(cmp::Ordering::Equal));
+    }
 
+    let thresh = params.min_score();
+    let corrections = params.corrections(&config.label_names);
     let mut bbox = Vec::new();
-    for y in 0..out_h {
-        for x in 0..out_w {
-            for a in 0..anchors.len() {
-                let loc = out.slice(s![a, ..4, y, x]);
-                let obj = out[[a, 4, y, x]];
-                let conf = out.slice(s![a, 4 + 1.., y, x]);
-
-                let y = y as f32 + sigmoid(loc[0]);
-                let x = x as f32 + sigmoid(loc[1]);
-                let h = anchors[a].0 * loc[2].exp();
-                let w = anchors[a].1 * loc[3].exp();
-
-                let obj = sigmoid(obj);
+    for (i, &(obj, scale, a, y, x)) in cells.iter().enumerate() {
+        // checking the clock is not free
+        if i % 64 == 0 && deadline.is_some_and(|d| time::Instant::now() >= d) {
+            return Ok((bbox, true));
+        }
+        let (ref out, anchors) = outputs[scale];
+        let (out_h, out_w) = (out.shape()[2], out.shape()[3]);
+        let loc = out.slice(s![a, ..4, y, x]);
+        let loc = match config.cell_order {
+            CellOrder::Yx => [loc[0], loc[1], loc[2], loc[3]],
+            CellOrder::Xy => [loc[1], loc[0], loc[3], loc[2]],
+        };
+        let conf = out.slice(s![a, 4 + 1.., y, x]);
+        let provenance = if params.provenance {
+            Some(bb::Provenance {
+                scale,
+                cell: (y, x),
+                anchor: a,
+                loc,
+                obj,
+            })
+        } else {
+            None
+        };
+
+        let y = y as f32 + sigmoid(loc[0]) + config.cell_offset;
+        let x = x as f32 + sigmoid(loc[1]) + config.cell_offset;
+        let h = anchors[a].0 * loc[2].exp();
+        let w = anchors[a].1 * loc[3].exp();
+
+        let obj = sigmoid(obj);
+        let score = match config.class_activation {
+            _ if n_class_outputs == 0 => ndarray::arr1(&[obj]),
+            ClassActivation::Softmax => {
                 let mut score = conf.map(|c| c.exp());
                 let sum = score.scalar_sum();
                 score.map_inplace(|s| *s *= obj / sum);
+                score
+            }
+            ClassActivation::Sigmoid => conf.map(|&c| sigmoid(c) * obj),
+        };
 
-                for lb in 0..n_fg_class {
-                    if score[lb] >= thresh {
-                        bbox.push(bb::Bb {
-                            top: (y - h / 2.) / out_h as f32,
-                            left: (x - w / 2.) / out_w as f32,
-                            bottom: (y + h / 2.) / out_h as f32,
-                            right: (x + w / 2.) / out_w as f32,
-                            label: lb,
-                            score: score[lb],
-                        });
-                    }
-                }
+        for lb in 0..score.len() {
+            let score = corrections[lb].apply(score[lb]);
+            if score >= thresh {
+                bbox.push(bb::Bb {
+                    top: (y - h / 2.) / out_h as f32,
+                    left: (x - w / 2.) / out_w as f32,
+                    bottom: (y + h / 2.) / out_h as f32,
+                    right: (x + w / 2.) / out_w as f32,
+                    label: lb,
+                    score,
+                    truncated: false,
+                    sharpness: 0.,
+                    provenance: provenance.clone(),
+                });
             }
         }
     }
-    bbox
+    Ok((bbox, false))
 }
 
 fn sigmoid(x: f32) -> f32 {
     1. / (1. + (-x).exp())
 }
-
-fn suppress(bbox: &mut Vec<bb::Bb>, thresh: f32) {
-    bbox.sort_unstable_by(|a, b| {
-        a.label.cmp(&b.label).then(
-            b.score
-                .partial_cmp(&a.score)
-                .unwrap_or(cmp::Ordering::Equal),
-        )
-    });
-    bbox.dedup_by(|a, b| a.label == b.label && a.iou(b) > thresh);
-}